@@ -271,6 +271,7 @@ async fn create_function(
             .set_memory_size(memory)
             .timeout(timeout)
             .set_tracing_config(config.tracing_config())
+            .set_logging_config(config.logging_config())
             .set_environment(config.lambda_environment()?)
             .set_layers(config.function_config.layer.clone())
             .set_tags(config.lambda_tags())
@@ -402,6 +403,21 @@ async fn update_function_config(
             }
         }
 
+        if let Some(logging_config) = config.logging_config() {
+            // The current configuration also includes the log group, so only the
+            // fields that we manage are compared.
+            let current = conf.logging_config.as_ref();
+            if current.and_then(|l| l.log_format.as_ref()) != logging_config.log_format.as_ref()
+                || current.and_then(|l| l.application_log_level.as_ref())
+                    != logging_config.application_log_level.as_ref()
+                || current.and_then(|l| l.system_log_level.as_ref())
+                    != logging_config.system_log_level.as_ref()
+            {
+                update_config = true;
+                builder = builder.logging_config(logging_config);
+            }
+        }
+
         if let Some(vpc) = &config.function_config.vpc {
             if vpc.should_update() {
                 update_config = true;
@@ -17,6 +17,10 @@ pub enum InvokeError {
     MissingPayload,
     #[error("invalid error payload {0}")]
     InvalidErrorPayload(#[from] serde_json::Error),
+    #[error("invalid invocation record in line {0}: {1}")]
+    InvalidRecord(usize, #[source] serde_json::Error),
+    #[error("{0} replayed invocations don't match the recorded responses")]
+    ReplayMismatch(usize),
 }
 
 #[derive(Debug, Deserialize)]
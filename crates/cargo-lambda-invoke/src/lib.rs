@@ -23,6 +23,7 @@ use tracing::debug;
 
 mod error;
 use error::*;
+mod replay;
 
 const EXAMPLES_URL: &str = "https://event-examples.cargo-lambda.info";
 
@@ -66,6 +67,19 @@ pub struct Invoke {
     #[arg(short = 'R', long)]
     remote: bool,
 
+    /// File with invocations recorded by `cargo lambda watch --record`.
+    /// Every invocation in the file is sent again to the local emulator
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["data_file", "data_ascii", "data_example", "remote"]
+    )]
+    replay: Option<PathBuf>,
+
+    /// Compare the responses of the replayed invocations with the recorded responses
+    #[arg(long, requires = "replay")]
+    diff: bool,
+
     #[command(flatten)]
     remote_config: RemoteConfig,
 
@@ -126,6 +140,10 @@ impl Invoke {
     pub async fn run(&self) -> Result<()> {
         tracing::trace!(options = ?self, "invoking function");
 
+        if let Some(path) = &self.replay {
+            return self.replay(path).await;
+        }
+
         let data = if let Some(file) = &self.data_file {
             read_to_string(file)
                 .into_diagnostic()
@@ -158,8 +176,14 @@ impl Invoke {
             self.invoke_local(&data).await?
         };
 
-        let text = match &self.output_format {
-            OutputFormat::Text => text,
+        println!("{}", self.format_output(text)?);
+
+        Ok(())
+    }
+
+    fn format_output(&self, text: String) -> Result<String> {
+        match &self.output_format {
+            OutputFormat::Text => Ok(text),
             OutputFormat::Json => {
                 let obj: Value = from_str(&text)
                     .into_diagnostic()
@@ -167,13 +191,9 @@ impl Invoke {
 
                 to_string_pretty(&obj)
                     .into_diagnostic()
-                    .wrap_err("failed to format json output")?
+                    .wrap_err("failed to format json output")
             }
-        };
-
-        println!("{text}");
-
-        Ok(())
+        }
     }
 
     async fn invoke_remote(&self, data: &str) -> Result<String> {
@@ -215,24 +235,7 @@ impl Invoke {
     }
 
     async fn invoke_local(&self, data: &str) -> Result<String> {
-        let host = parse_invoke_ip_address(&self.invoke_address)?;
-
-        let (protocol, client) = if self.tls_options.is_secure() {
-            let tls = self.tls_options.client_config()?;
-            let client = Client::builder()
-                .use_preconfigured_tls(tls)
-                .build()
-                .into_diagnostic()?;
-
-            ("https", client)
-        } else {
-            ("http", Client::new())
-        };
-
-        let url = format!(
-            "{}://{}:{}/2015-03-31/functions/{}/invocations",
-            protocol, &host, self.invoke_port, &self.function_name
-        );
+        let (client, url) = self.local_endpoint(&self.function_name)?;
 
         let mut req = client.post(url).body(data.to_string());
         if let Some(identity) = &self.cognito {
@@ -269,6 +272,30 @@ impl Invoke {
         }
     }
 
+    /// Client and URL to send invocations for a function to the local emulator.
+    fn local_endpoint(&self, function_name: &str) -> Result<(Client, String)> {
+        let host = parse_invoke_ip_address(&self.invoke_address)?;
+
+        let (protocol, client) = if self.tls_options.is_secure() {
+            let tls = self.tls_options.client_config()?;
+            let client = Client::builder()
+                .use_preconfigured_tls(tls)
+                .build()
+                .into_diagnostic()?;
+
+            ("https", client)
+        } else {
+            ("http", Client::new())
+        };
+
+        let url = format!(
+            "{}://{}:{}/2015-03-31/functions/{}/invocations",
+            protocol, &host, self.invoke_port, function_name
+        );
+
+        Ok((client, url))
+    }
+
     fn client_context(&self, encode: bool) -> Result<Option<String>> {
         let mut data = if let Some(file) = &self.client_context_file {
             read_to_string(file)
@@ -0,0 +1,253 @@
+use cargo_lambda_metadata::cargo::watch::record::InvocationRecord;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::{fmt, fs::read_to_string, path::Path};
+
+use crate::{Invoke, error::InvokeError};
+
+impl Invoke {
+    /// Send the invocations recorded by `cargo lambda watch --record`
+    /// to the local emulator again, in the same order they were recorded.
+    pub(crate) async fn replay(&self, path: &Path) -> Result<()> {
+        let content = read_to_string(path)
+            .into_diagnostic()
+            .wrap_err("error reading replay file")?;
+        let records = parse_records(&content)?;
+
+        let mut mismatches = 0;
+        for (index, recorded) in records.iter().enumerate() {
+            let replayed = self.replay_invocation(recorded).await?;
+            let name = format!("[{}] {}", index + 1, recorded.function_name);
+
+            if !self.diff {
+                let payload = replayed.error.or(replayed.response).unwrap_or_default();
+                let text = match payload {
+                    Value::String(text) => text,
+                    payload => payload.to_string(),
+                };
+                println!("{name}: {}", self.format_output(text)?);
+                continue;
+            }
+
+            let differences = compare(recorded, &replayed);
+            if differences.is_empty() {
+                println!("{name}: no differences");
+            } else {
+                mismatches += 1;
+                println!("{name}: {} differences", differences.len());
+                for difference in differences {
+                    println!("  {difference}");
+                }
+            }
+        }
+
+        if mismatches > 0 {
+            return Err(InvokeError::ReplayMismatch(mismatches).into());
+        }
+
+        Ok(())
+    }
+
+    async fn replay_invocation(&self, recorded: &InvocationRecord) -> Result<InvocationRecord> {
+        let (client, url) = self.local_endpoint(&recorded.function_name)?;
+
+        let mut req = client.post(url).body(recorded.event_payload());
+        for (name, value) in &recorded.headers {
+            req = req.header(name, value);
+        }
+
+        let resp = req
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err("error sending request to the runtime emulator")?;
+        let status = resp.status();
+
+        let payload = resp
+            .bytes()
+            .await
+            .into_diagnostic()
+            .wrap_err("error reading response body")?;
+        let payload = (!payload.is_empty()).then(|| InvocationRecord::decode_payload(&payload));
+
+        let mut replayed = InvocationRecord {
+            function_name: recorded.function_name.clone(),
+            status: status.as_u16(),
+            ..Default::default()
+        };
+        if status == StatusCode::OK {
+            replayed.response = payload;
+        } else {
+            replayed.error = payload;
+        }
+
+        Ok(replayed)
+    }
+}
+
+fn parse_records(content: &str) -> Result<Vec<InvocationRecord>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| InvokeError::InvalidRecord(index + 1, e).into())
+        })
+        .collect()
+}
+
+/// Value that changed between the recorded invocation and the replayed one.
+#[derive(Debug, PartialEq)]
+struct Difference {
+    path: String,
+    recorded: Option<Value>,
+    replayed: Option<Value>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let display = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "(missing)".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.path,
+            display(&self.recorded),
+            display(&self.replayed)
+        )
+    }
+}
+
+fn compare(recorded: &InvocationRecord, replayed: &InvocationRecord) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    if recorded.status != replayed.status {
+        differences.push(Difference {
+            path: "status".into(),
+            recorded: Some(recorded.status.into()),
+            replayed: Some(replayed.status.into()),
+        });
+    }
+
+    diff_values(
+        "response".into(),
+        recorded.response.as_ref(),
+        replayed.response.as_ref(),
+        &mut differences,
+    );
+    diff_values(
+        "error".into(),
+        recorded.error.as_ref(),
+        replayed.error.as_ref(),
+        &mut differences,
+    );
+
+    differences
+}
+
+fn diff_values(
+    path: String,
+    recorded: Option<&Value>,
+    replayed: Option<&Value>,
+    differences: &mut Vec<Difference>,
+) {
+    match (recorded, replayed) {
+        (Some(Value::Object(recorded)), Some(Value::Object(replayed))) => {
+            let mut keys = recorded.keys().chain(replayed.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                diff_values(
+                    format!("{path}.{key}"),
+                    recorded.get(key),
+                    replayed.get(key),
+                    differences,
+                );
+            }
+        }
+        (Some(Value::Array(recorded)), Some(Value::Array(replayed))) => {
+            for index in 0..recorded.len().max(replayed.len()) {
+                diff_values(
+                    format!("{path}[{index}]"),
+                    recorded.get(index),
+                    replayed.get(index),
+                    differences,
+                );
+            }
+        }
+        (recorded, replayed) if recorded != replayed => differences.push(Difference {
+            path,
+            recorded: recorded.cloned(),
+            replayed: replayed.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_records() {
+        let content = r#"{"function_name":"basic-lambda","timestamp":"2024-01-01T00:00:00.000Z","event":{"command":"hi"},"status":200,"response":"ok","duration_ms":3}
+
+{"function_name":"other-lambda","timestamp":"2024-01-01T00:00:01.000Z","event":{},"status":500,"error":{"errorType":"Oops"},"duration_ms":1}
+"#;
+        let records = parse_records(content).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].function_name, "basic-lambda");
+        assert_eq!(records[1].error, Some(json!({"errorType": "Oops"})));
+
+        let err = parse_records("{}\n").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<InvokeError>(),
+            Some(InvokeError::InvalidRecord(1, _))
+        ));
+    }
+
+    #[test]
+    fn test_compare() {
+        let recorded = InvocationRecord {
+            status: 200,
+            response: Some(json!({"message": "hi", "items": [1, 2], "same": true})),
+            ..Default::default()
+        };
+
+        assert!(compare(&recorded, &recorded).is_empty());
+
+        let replayed = InvocationRecord {
+            status: 200,
+            response: Some(json!({"message": "hello", "items": [1], "same": true, "new": null})),
+            ..Default::default()
+        };
+        let differences = compare(&recorded, &replayed)
+            .into_iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            differences,
+            vec![
+                "response.items[1]: 2 -> (missing)",
+                r#"response.message: "hi" -> "hello""#,
+                "response.new: (missing) -> null",
+            ]
+        );
+
+        let replayed = InvocationRecord {
+            status: 500,
+            error: Some(json!({"errorType": "Oops"})),
+            ..Default::default()
+        };
+        let differences = compare(&recorded, &replayed)
+            .into_iter()
+            .map(|d| d.path)
+            .collect::<Vec<_>>();
+        assert_eq!(differences, vec!["status", "response", "error"]);
+    }
+}
@@ -1,6 +1,6 @@
 use cargo_lambda_remote::{
     RemoteConfig,
-    aws_sdk_lambda::types::{Environment, TracingConfig},
+    aws_sdk_lambda::types::{Environment, LoggingConfig as LambdaLoggingConfig, TracingConfig},
 };
 use clap::{ArgAction, Args, ValueHint};
 use serde::{Deserialize, Serialize, ser::SerializeStruct};
@@ -10,7 +10,9 @@ use strum_macros::{Display, EnumString};
 use crate::{
     env::EnvOptions,
     error::MetadataError,
-    lambda::{Memory, MemoryValueParser, Timeout, Tracing},
+    lambda::{
+        ApplicationLogLevel, LogFormat, Memory, MemoryValueParser, SystemLogLevel, Timeout, Tracing,
+    },
 };
 
 use crate::cargo::deserialize_vec_or_map;
@@ -146,6 +148,26 @@ impl Deploy {
         )
     }
 
+    pub fn logging_config(&self) -> Option<LambdaLoggingConfig> {
+        let logging = self.function_config.logging.as_ref()?;
+        if !logging.should_update() {
+            return None;
+        }
+
+        let format = logging.log_format();
+        let mut builder =
+            LambdaLoggingConfig::builder().log_format(format.to_string().as_str().into());
+
+        // Lambda only accepts log levels for functions that log in JSON format.
+        if format == LogFormat::Json {
+            builder = builder
+                .application_log_level(logging.application_log_level().to_string().as_str().into())
+                .system_log_level(logging.system_log_level().to_string().as_str().into());
+        }
+
+        Some(builder.build())
+    }
+
     pub fn lambda_tags(&self) -> Option<HashMap<String, String>> {
         match &self.tag {
             None => None,
@@ -332,6 +354,10 @@ pub struct FunctionDeployConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub vpc: Option<VpcConfig>,
 
+    #[command(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LoggingConfig>,
+
     /// Choose a different Lambda runtime to deploy with.
     /// The only other option that might work is `provided.al2`.
     #[arg(long, default_value = DEFAULT_RUNTIME)]
@@ -364,6 +390,7 @@ impl FunctionDeployConfig {
             && self.tracing.is_none()
             && self.role.is_none()
             && self.vpc.is_none()
+            && self.logging.is_none()
             && self.description.is_none()
             && self.log_retention.is_none()
             && self.layer.is_none()
@@ -397,6 +424,7 @@ impl FunctionDeployConfig {
             + self.description.is_some() as usize
             + self.log_retention.is_some() as usize
             + self.vpc.is_some() as usize
+            + self.logging.is_some() as usize
             + self
                 .env_options
                 .as_ref()
@@ -456,6 +484,10 @@ impl FunctionDeployConfig {
             state.serialize_field("vpc", vpc)?;
         }
 
+        if let Some(logging) = &self.logging {
+            state.serialize_field("logging", logging)?;
+        }
+
         if let Some(env_options) = &self.env_options {
             env_options.serialize_fields::<S>(state)?;
         }
@@ -498,6 +530,57 @@ impl VpcConfig {
     }
 }
 
+#[derive(Args, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LoggingConfig {
+    /// Format of the logs that Lambda captures from the function: `Text` or `JSON`
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+
+    /// Minimum level of the logs that the function writes.
+    /// Only used with the `JSON` log format
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application_log_level: Option<ApplicationLogLevel>,
+
+    /// Minimum level of the logs that Lambda writes.
+    /// Only used with the `JSON` log format
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_log_level: Option<SystemLogLevel>,
+}
+
+impl LoggingConfig {
+    pub fn should_update(&self) -> bool {
+        self != &LoggingConfig::default()
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or_default()
+    }
+
+    pub fn application_log_level(&self) -> ApplicationLogLevel {
+        self.application_log_level.unwrap_or_default()
+    }
+
+    pub fn system_log_level(&self) -> SystemLogLevel {
+        self.system_log_level.unwrap_or_default()
+    }
+
+    /// Fill the fields that this configuration doesn't set with the values in `other`.
+    pub fn or(&self, other: Option<&LoggingConfig>) -> LoggingConfig {
+        let Some(other) = other else {
+            return self.clone();
+        };
+
+        LoggingConfig {
+            log_format: self.log_format.or(other.log_format),
+            application_log_level: self.application_log_level.or(other.application_log_level),
+            system_log_level: self.system_log_level.or(other.system_log_level),
+        }
+    }
+}
+
 fn extract_tags(tags: &Vec<String>) -> HashMap<String, String> {
     let mut map = HashMap::new();
 
@@ -650,4 +733,53 @@ mod tests {
 
         assert_eq!(config.deploy.function_config.log_retention, Some(14));
     }
+
+    #[test]
+    fn test_logging_config() {
+        let deploy = Deploy::default();
+        assert_eq!(deploy.logging_config(), None);
+
+        let config: FunctionDeployConfig = serde_json::from_value(serde_json::json!({
+            "logging": {
+                "log_format": "JSON",
+                "application_log_level": "WARN",
+            }
+        }))
+        .unwrap();
+        let logging = config.logging.as_ref().unwrap();
+        assert_eq!(logging.log_format(), LogFormat::Json);
+        assert_eq!(logging.system_log_level(), SystemLogLevel::Info);
+
+        let deploy = Deploy {
+            function_config: config,
+            ..Default::default()
+        };
+        let logging = deploy.logging_config().unwrap();
+        assert_eq!(logging.log_format().unwrap().as_str(), "JSON");
+        assert_eq!(logging.application_log_level().unwrap().as_str(), "WARN");
+        assert_eq!(logging.system_log_level().unwrap().as_str(), "INFO");
+
+        let flags = LoggingConfig {
+            application_log_level: Some(ApplicationLogLevel::Debug),
+            ..Default::default()
+        };
+        let merged = flags.or(deploy.function_config.logging.as_ref());
+        assert_eq!(merged.log_format(), LogFormat::Json);
+        assert_eq!(merged.application_log_level(), ApplicationLogLevel::Debug);
+        assert_eq!(flags.or(None), flags);
+
+        let deploy = Deploy {
+            function_config: FunctionDeployConfig {
+                logging: Some(LoggingConfig {
+                    log_format: Some(LogFormat::Text),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let logging = deploy.logging_config().unwrap();
+        assert_eq!(logging.log_format().unwrap().as_str(), "Text");
+        assert_eq!(logging.application_log_level(), None);
+    }
 }
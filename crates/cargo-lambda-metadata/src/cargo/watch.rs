@@ -1,5 +1,5 @@
 use cargo_options::Run;
-use clap::Args;
+use clap::{Args, ValueHint};
 use matchit::{InsertError, MatchError, Router};
 use serde::{
     Deserialize, Serialize,
@@ -7,10 +7,14 @@ use serde::{
     ser::SerializeSeq,
 };
 use serde_json::{Value, json};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+use strum_macros::{Display, EnumString};
 
 use crate::{
-    cargo::{count_common_options, serialize_common_options},
+    cargo::{count_common_options, deploy::LoggingConfig, serialize_common_options},
     env::{EnvOptions, Environment},
     error::MetadataError,
     lambda::Timeout,
@@ -18,6 +22,11 @@ use crate::{
 
 use cargo_lambda_remote::tls::TlsOptions;
 
+pub mod openapi;
+pub mod record;
+pub mod schedule;
+use schedule::ScheduleExpression;
+
 #[cfg(windows)]
 const DEFAULT_INVOKE_ADDRESS: &str = "127.0.0.1";
 
@@ -74,6 +83,81 @@ pub struct Watch {
     #[serde(default)]
     pub timeout: Option<Timeout>,
 
+    /// Number of execution environments to start for each function.
+    /// Invocations wait in a queue when all the environments are busy
+    #[arg(long)]
+    #[serde(default)]
+    pub concurrency: Option<u32>,
+
+    /// Maximum number of invocations that each function can process at the same time.
+    /// Invocations over this limit fail with a TooManyRequestsException error
+    #[arg(long)]
+    #[serde(default)]
+    pub reserved_concurrency: Option<u32>,
+
+    /// Stop functions that use more memory than their configured memory size,
+    /// and fail their invocations with a Runtime.OutOfMemory error. Only supported on Linux
+    #[arg(long)]
+    #[serde(default)]
+    pub enforce_memory: bool,
+
+    /// Maximum age, in seconds, of an asynchronous invocation.
+    /// Older invocations are not retried, and they are sent to the on-failure destination
+    #[arg(long)]
+    #[serde(default)]
+    pub max_event_age: Option<u32>,
+
+    /// Name of the function that receives asynchronous invocations that fail after all the retries
+    #[arg(long, conflicts_with = "on_failure_dir")]
+    #[serde(default)]
+    pub on_failure_function: Option<String>,
+
+    /// Directory where the emulator stores asynchronous invocations that fail after all the retries
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    #[serde(default)]
+    pub on_failure_dir: Option<PathBuf>,
+
+    /// Authentication type of the function URLs, acceptable values are [NONE, AWS_IAM].
+    /// With AWS_IAM, requests must be signed with SigV4 and local AWS credentials
+    #[arg(long)]
+    #[serde(default)]
+    pub function_url_auth: Option<AuthType>,
+
+    /// File where every invocation is recorded, one JSON line per invocation.
+    /// Use `cargo lambda invoke --replay` to send the recorded invocations again
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    #[serde(default)]
+    pub record: Option<PathBuf>,
+
+    /// Accept invocation payloads and responses of any size. By default, the emulator
+    /// enforces the same payload size limits that Lambda enforces
+    #[arg(long)]
+    #[serde(default)]
+    pub disable_payload_limits: bool,
+
+    /// Extension to start next to every function process, with the same runtime API.
+    /// It can be the name of a binary in the workspace, or the path to a prebuilt binary.
+    /// This flag can be used multiple times
+    #[arg(long = "extension", value_name = "BIN_OR_PATH")]
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
+    /// What happens to the invocations that a function is processing when it reloads
+    /// after code changes, acceptable values are [drain, requeue, fail]
+    #[arg(long)]
+    #[serde(default)]
+    pub reload_strategy: Option<ReloadStrategy>,
+
+    /// OpenAPI 3 document, in JSON or YAML, to generate the routes of the
+    /// emulator from. The routes in the `router` configuration take precedence
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    #[serde(default)]
+    pub router_from_openapi: Option<PathBuf>,
+
+    #[command(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LoggingConfig>,
+
     #[command(flatten)]
     #[serde(flatten)]
     pub cargo_opts: Run,
@@ -89,6 +173,22 @@ pub struct Watch {
     #[arg(skip)]
     #[serde(default, skip_serializing_if = "is_empty_router")]
     pub router: Option<FunctionRouter>,
+
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sqs_event_sources: Vec<SqsEventSource>,
+
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub schedule: BTreeMap<String, ScheduleExpression>,
+
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket: Option<WebSocketApi>,
+
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub functions: BTreeMap<String, FunctionCommand>,
 }
 
 impl Watch {
@@ -131,8 +231,25 @@ impl Serialize for Watch {
             + self.print_traces as usize
             + self.wait as usize
             + self.disable_cors as usize
+            + self.enforce_memory as usize
+            + self.disable_payload_limits as usize
+            + !self.extensions.is_empty() as usize
             + self.timeout.is_some() as usize
+            + self.concurrency.is_some() as usize
+            + self.reserved_concurrency.is_some() as usize
+            + self.max_event_age.is_some() as usize
+            + self.on_failure_function.is_some() as usize
+            + self.on_failure_dir.is_some() as usize
+            + self.function_url_auth.is_some() as usize
+            + self.reload_strategy.is_some() as usize
+            + self.router_from_openapi.is_some() as usize
+            + self.record.is_some() as usize
+            + self.logging.is_some() as usize
             + self.router.is_some() as usize
+            + !self.sqs_event_sources.is_empty() as usize
+            + !self.schedule.is_empty() as usize
+            + self.websocket.is_some() as usize
+            + !self.functions.is_empty() as usize
             + self.cargo_opts.manifest_path.is_some() as usize
             + self.cargo_opts.release as usize
             + self.cargo_opts.ignore_rust_version as usize
@@ -169,14 +286,65 @@ impl Serialize for Watch {
         if self.disable_cors {
             state.serialize_field("disable_cors", &true)?;
         }
+        if self.enforce_memory {
+            state.serialize_field("enforce_memory", &true)?;
+        }
+        if self.disable_payload_limits {
+            state.serialize_field("disable_payload_limits", &true)?;
+        }
+        if !self.extensions.is_empty() {
+            state.serialize_field("extensions", &self.extensions)?;
+        }
 
         // Only serialize Some values for Options
         if let Some(timeout) = &self.timeout {
             state.serialize_field("timeout", timeout)?;
         }
+        if let Some(concurrency) = &self.concurrency {
+            state.serialize_field("concurrency", concurrency)?;
+        }
+        if let Some(reserved_concurrency) = &self.reserved_concurrency {
+            state.serialize_field("reserved_concurrency", reserved_concurrency)?;
+        }
+        if let Some(max_event_age) = &self.max_event_age {
+            state.serialize_field("max_event_age", max_event_age)?;
+        }
+        if let Some(on_failure_function) = &self.on_failure_function {
+            state.serialize_field("on_failure_function", on_failure_function)?;
+        }
+        if let Some(on_failure_dir) = &self.on_failure_dir {
+            state.serialize_field("on_failure_dir", on_failure_dir)?;
+        }
+        if let Some(function_url_auth) = &self.function_url_auth {
+            state.serialize_field("function_url_auth", function_url_auth)?;
+        }
+        if let Some(reload_strategy) = &self.reload_strategy {
+            state.serialize_field("reload_strategy", reload_strategy)?;
+        }
+        if let Some(router_from_openapi) = &self.router_from_openapi {
+            state.serialize_field("router_from_openapi", router_from_openapi)?;
+        }
+        if let Some(record) = &self.record {
+            state.serialize_field("record", record)?;
+        }
+        if let Some(logging) = &self.logging {
+            state.serialize_field("logging", logging)?;
+        }
         if let Some(router) = &self.router {
             state.serialize_field("router", router)?;
         }
+        if !self.sqs_event_sources.is_empty() {
+            state.serialize_field("sqs_event_sources", &self.sqs_event_sources)?;
+        }
+        if !self.schedule.is_empty() {
+            state.serialize_field("schedule", &self.schedule)?;
+        }
+        if let Some(websocket) = &self.websocket {
+            state.serialize_field("websocket", websocket)?;
+        }
+        if !self.functions.is_empty() {
+            state.serialize_field("functions", &self.functions)?;
+        }
 
         // Flatten the fields from cargo_opts and env_options
         self.env_options.serialize_fields::<S>(&mut state)?;
@@ -220,6 +388,118 @@ fn default_invoke_port() -> u16 {
     DEFAULT_INVOKE_PORT
 }
 
+const DEFAULT_SQS_BATCH_SIZE: usize = 10;
+const DEFAULT_SQS_VISIBILITY_TIMEOUT: u64 = 30;
+
+/// Event source mapping that delivers the messages
+/// in a local SQS queue to a function.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SqsEventSource {
+    /// Name of the local queue
+    pub queue: String,
+    /// Name of the function that processes the messages
+    pub function: String,
+    /// Directory where every file is sent to the queue as a message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directory: Option<PathBuf>,
+    /// Maximum number of messages that the function receives in each batch
+    #[serde(default = "default_sqs_batch_size")]
+    pub batch_size: usize,
+    /// Maximum time, in seconds, to gather messages before invoking the function
+    #[serde(default)]
+    pub batching_window: u64,
+    /// Time, in seconds, that a message is hidden from the queue after it's delivered
+    #[serde(default = "default_sqs_visibility_timeout")]
+    pub visibility_timeout: u64,
+    /// Whether the function reports the messages that
+    /// it failed to process with `batchItemFailures`
+    #[serde(default)]
+    pub report_batch_item_failures: bool,
+}
+
+const DEFAULT_WEBSOCKET_PATH: &str = "/ws";
+const DEFAULT_ROUTE_SELECTION_EXPRESSION: &str = "$request.body.action";
+
+/// WebSocket API that the emulator serves, like API Gateway WebSocket APIs do.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WebSocketApi {
+    /// Path where clients open their connections
+    #[serde(default = "default_websocket_path")]
+    pub path: String,
+    /// Expression that selects the route of each message,
+    /// like `$request.body.action`
+    #[serde(default = "default_route_selection_expression")]
+    pub route_selection_expression: String,
+    /// Functions that handle each route key. Besides the keys that the route selection
+    /// expression selects, `$connect`, `$disconnect`, and `$default` are special keys.
+    #[serde(default)]
+    pub routes: BTreeMap<String, String>,
+}
+
+impl WebSocketApi {
+    /// Key of the route that handles a message, if any route handles it.
+    pub fn route_key(&self, message: &str) -> Option<&str> {
+        let selected = self
+            .route_selection_expression
+            .strip_prefix("$request.body.")
+            .and_then(|path| {
+                let body: Value = serde_json::from_str(message).ok()?;
+                let value = path.split('.').try_fold(&body, |v, key| v.get(key))?;
+                match value {
+                    Value::String(key) => Some(key.clone()),
+                    Value::Number(key) => Some(key.to_string()),
+                    Value::Bool(key) => Some(key.to_string()),
+                    _ => None,
+                }
+            });
+
+        match selected {
+            Some(key) if !key.starts_with('$') && self.routes.contains_key(&key) => {
+                self.routes.get_key_value(&key).map(|(k, _)| k.as_str())
+            }
+            _ => self
+                .routes
+                .get_key_value("$default")
+                .map(|(k, _)| k.as_str()),
+        }
+    }
+}
+
+/// Function that runs a command, like a script or a prebuilt binary,
+/// instead of a binary in the project.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct FunctionCommand {
+    /// Program to run. Relative paths start in the working directory,
+    /// other programs are looked up in the `PATH`
+    pub command: String,
+    /// Arguments for the program
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Directory where the command runs, relative to the project's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// Paths that restart the command when they change.
+    /// The command is not restarted when this list is empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watch: Vec<PathBuf>,
+}
+
+fn default_websocket_path() -> String {
+    DEFAULT_WEBSOCKET_PATH.to_string()
+}
+
+fn default_route_selection_expression() -> String {
+    DEFAULT_ROUTE_SELECTION_EXPRESSION.to_string()
+}
+
+fn default_sqs_batch_size() -> usize {
+    DEFAULT_SQS_BATCH_SIZE
+}
+
+fn default_sqs_visibility_timeout() -> u64 {
+    DEFAULT_SQS_VISIBILITY_TIMEOUT
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct WatchConfig {
     pub router: Option<FunctionRouter>,
@@ -228,9 +508,214 @@ pub struct WatchConfig {
 #[derive(Clone, Debug, Default)]
 pub struct FunctionRouter {
     inner: Router<FunctionRoutes>,
+    routes: Router<RouteInfo>,
     pub(crate) raw: Vec<Route>,
 }
 
+/// Payload format of the events that a route sends to its function.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// API Gateway HTTP APIs and function URLs, payload format version 2.0
+    #[default]
+    V2,
+    /// API Gateway REST APIs, payload format version 1.0
+    V1,
+    /// Application Load Balancer target groups
+    Alb,
+}
+
+/// Authentication type of a function URL.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Display, EnumString, Eq, Hash, PartialEq, Serialize,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuthType {
+    /// Anyone can invoke the function
+    #[default]
+    None,
+    /// Requests must be signed with AWS Signature Version 4
+    AwsIam,
+}
+
+/// Authorizer that checks the requests that a route receives before they reach its function.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Authorizer {
+    /// Validates JSON Web Tokens, like API Gateway JWT authorizers
+    Jwt(JwtAuthorizer),
+    /// Invokes a function to authorize requests, like API Gateway Lambda authorizers
+    Lambda(LambdaAuthorizer),
+}
+
+impl Authorizer {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Authorizer::Jwt(jwt) if jwt.jwks.is_none() && jwt.secret.is_none() => {
+                Err("JWT authorizers need a jwks file or a secret to validate tokens".into())
+            }
+            Authorizer::Lambda(lambda) if lambda.function.is_empty() => {
+                Err("Lambda authorizers need the name of a function to invoke".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Authorizer that validates JSON Web Tokens, and sends their claims to the function.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct JwtAuthorizer {
+    /// Path to a JSON Web Key Set file with the keys that sign the tokens.
+    /// Relative paths are resolved from the directory of the project's manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<PathBuf>,
+    /// Shared secret for tokens signed with HMAC algorithms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Value that the `iss` claim must have
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Values accepted in the `aud` or `client_id` claims
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audience: Vec<String>,
+    /// Scopes that the route requires, tokens must include at least one of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// Where the token is in the request, `$request.header.Authorization` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_source: Option<String>,
+}
+
+/// Authorizer that invokes a local function to decide if a request is allowed.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct LambdaAuthorizer {
+    /// Name of the function that authorizes the requests
+    pub function: String,
+    /// Format of the authorizer's responses.
+    /// `simple` by default for v2 routes, and `iam` for v1 routes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<AuthorizerResponseFormat>,
+    /// Parts of the request that identify the caller, `$request.header.Authorization` by default.
+    /// Requests without them are rejected, and they are the key of the cached responses.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identity_source: Vec<String>,
+    /// Seconds to cache the authorizer's responses. `0` disables the cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+}
+
+/// Format of the responses that a Lambda authorizer returns.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Display, EnumString, Eq, Hash, PartialEq, Serialize,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorizerResponseFormat {
+    /// A boolean `isAuthorized` field, and an optional context
+    #[default]
+    Simple,
+    /// An IAM policy that allows or denies the request
+    Iam,
+}
+
+/// What the emulator does with the invocations that a function is processing
+/// when the function reloads after code changes. Without a strategy, the function
+/// reloads right away and the invocations in flight don't receive a response.
+#[derive(Clone, Copy, Debug, Deserialize, Display, EnumString, Eq, Hash, PartialEq, Serialize)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReloadStrategy {
+    /// Wait for the invocations to complete before reloading the function
+    Drain,
+    /// Send the invocations again to the function after it reloads
+    Requeue,
+    /// Fail the invocations with an error that says that the function is reloading
+    Fail,
+}
+
+/// Route that matched a request path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRoute {
+    /// Path pattern of the route, like `/users/{id}`
+    pub path: String,
+    pub payload_format: PayloadFormat,
+    /// Authentication type of the route.
+    /// `None` uses the authentication type of the function.
+    pub auth_type: Option<AuthType>,
+    /// Host of the request, when the route matched it by host.
+    pub domain: Option<String>,
+    /// Authorizer that checks the requests before they reach the function
+    pub authorizer: Option<Authorizer>,
+}
+
+/// Request that the router matches with a route.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouteRequest<'a> {
+    pub path: &'a str,
+    pub method: &'a str,
+    /// Value of the Host header, without the port
+    pub host: Option<&'a str>,
+    /// Headers of the request, with lowercase names
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+/// Function that a request is routed to, with the route that matched it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestRoute {
+    pub function: String,
+    pub params: HashMap<String, String>,
+    pub route: MatchedRoute,
+}
+
+/// Options declared in a route, besides the function that it invokes.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+struct RouteOptions {
+    payload_format: Option<PayloadFormat>,
+    auth_type: Option<AuthType>,
+    authorizer: Option<Authorizer>,
+}
+
+impl RouteOptions {
+    fn is_empty(&self) -> bool {
+        self.payload_format.is_none() && self.auth_type.is_none() && self.authorizer.is_none()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct RouteInfo {
+    path: String,
+    /// Whether any route without host or header matchers uses this path.
+    routed: bool,
+    /// Route options by method. `None` applies to every method.
+    options: HashMap<Option<String>, RouteOptions>,
+    /// Routes that match requests by host or headers,
+    /// in the order that they were declared.
+    conditional: Vec<Route>,
+}
+
+impl RouteInfo {
+    fn matched_route(&self, method: &str) -> MatchedRoute {
+        let by_method = self.options.get(&Some(method.to_string()));
+        let by_path = self.options.get(&None);
+
+        MatchedRoute {
+            path: self.path.clone(),
+            payload_format: by_method
+                .and_then(|o| o.payload_format)
+                .or(by_path.and_then(|o| o.payload_format))
+                .unwrap_or_default(),
+            auth_type: by_method
+                .and_then(|o| o.auth_type)
+                .or(by_path.and_then(|o| o.auth_type)),
+            domain: None,
+            authorizer: by_method
+                .and_then(|o| o.authorizer.clone())
+                .or_else(|| by_path.and_then(|o| o.authorizer.clone())),
+        }
+    }
+}
+
 impl FunctionRouter {
     pub fn at(
         &self,
@@ -240,17 +725,65 @@ impl FunctionRouter {
         let matched = self.inner.at(path)?;
         let function = matched.value.at(method).ok_or(MatchError::NotFound)?;
 
-        let params = matched
-            .params
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
+        Ok((function.to_string(), route_params(&matched.params)))
+    }
+
+    /// Find the function that handles a request.
+    /// Routes with host or header matchers are checked first, in the order
+    /// that they were declared, before the routes that only match the path and method.
+    pub fn route(&self, request: &RouteRequest<'_>) -> Option<RequestRoute> {
+        let matched = self.routes.at(request.path).ok()?;
+        let info = matched.value;
+
+        if let Some(route) = info.conditional.iter().find(|r| r.matches(request)) {
+            return Some(RequestRoute {
+                function: route.function.clone(),
+                params: route_params(&matched.params),
+                route: MatchedRoute {
+                    path: route.path.clone(),
+                    payload_format: route.payload_format.unwrap_or_default(),
+                    auth_type: route.auth_type,
+                    authorizer: route.authorizer.clone(),
+                    domain: route
+                        .host
+                        .as_ref()
+                        .and(request.host)
+                        .map(|host| host.to_ascii_lowercase()),
+                },
+            });
+        }
+
+        if !info.routed {
+            return None;
+        }
+
+        let (function, params) = self.at(request.path, request.method).ok()?;
+        Some(RequestRoute {
+            function,
+            params,
+            route: info.matched_route(request.method),
+        })
+    }
 
-        Ok((function.to_string(), params))
+    /// Find the route that matches a path and method, with the payload format of its events
+    /// and its authentication type.
+    pub fn matched_route(&self, path: &str, method: &str) -> Option<MatchedRoute> {
+        let matched = self.routes.at(path).ok()?;
+        let info = matched.value;
+
+        info.routed.then(|| info.matched_route(method))
     }
 
     pub fn insert(&mut self, path: &str, routes: FunctionRoutes) -> Result<(), InsertError> {
-        self.inner.insert(path, routes)
+        self.inner.insert(path, routes)?;
+        self.routes.insert(
+            path,
+            RouteInfo {
+                path: path.to_string(),
+                routed: true,
+                ..Default::default()
+            },
+        )
     }
 
     pub fn is_empty(&self) -> bool {
@@ -258,6 +791,13 @@ impl FunctionRouter {
     }
 }
 
+fn route_params(params: &matchit::Params<'_, '_>) -> HashMap<String, String> {
+    params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
 #[allow(dead_code)]
 fn is_empty_router(router: &Option<FunctionRouter>) -> bool {
     router.is_none() || router.as_ref().is_some_and(|r| r.is_empty())
@@ -269,6 +809,72 @@ pub struct Route {
     #[serde(skip_serializing_if = "Option::is_none")]
     methods: Option<Vec<String>>,
     function: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_format: Option<PayloadFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth_type: Option<AuthType>,
+    /// Host that requests must be sent to, like `api.example.com`,
+    /// or `*.example.com` to match any subdomain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    /// Headers that requests must include, with their exact values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    authorizer: Option<Authorizer>,
+}
+
+impl Route {
+    fn options(&self) -> RouteOptions {
+        RouteOptions {
+            payload_format: self.payload_format,
+            auth_type: self.auth_type,
+            authorizer: self.authorizer.clone(),
+        }
+    }
+
+    /// Whether the route matches requests by host or headers, besides their path.
+    fn is_conditional(&self) -> bool {
+        self.host.is_some() || !self.headers.is_empty()
+    }
+
+    fn matches(&self, request: &RouteRequest<'_>) -> bool {
+        if let Some(methods) = &self.methods {
+            if !methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(request.method))
+            {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.host {
+            if !request.host.is_some_and(|host| host_matches(pattern, host)) {
+                return false;
+            }
+        }
+
+        self.headers.iter().all(|(name, value)| {
+            request
+                .headers
+                .iter()
+                .any(|(n, v)| n.eq_ignore_ascii_case(name) && v == value)
+        })
+    }
+}
+
+/// Match a host with an exact name, or with a `*.` pattern that matches any subdomain.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .and_then(|prefix| prefix.strip_suffix('.'))
+            .is_some_and(|prefix| !prefix.is_empty()),
+        None => host == pattern,
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -299,7 +905,7 @@ impl<'de> Visitor<'de> for FunctionRouterVisitor {
     where
         A: serde::de::MapAccess<'de>,
     {
-        let routes: HashMap<String, FunctionRoutes> =
+        let values: HashMap<String, Value> =
             Deserialize::deserialize(serde::de::value::MapAccessDeserializer::new(map))?;
 
         let mut inner = Router::new();
@@ -307,23 +913,36 @@ impl<'de> Visitor<'de> for FunctionRouterVisitor {
 
         let mut inverse = HashMap::new();
 
-        for (path, route) in &routes {
+        for (path, value) in &values {
+            let route = FunctionRoutes::deserialize(value).map_err(serde::de::Error::custom)?;
+            let options = decode_route_options(value);
+
             inner.insert(path, route.clone()).map_err(|e| {
                 serde::de::Error::custom(format!("Failed to insert route {path}: {e}"))
             })?;
 
             match route {
                 FunctionRoutes::Single(function) => {
+                    let options = options.get(&None).cloned().unwrap_or_default();
                     raw.push(Route {
                         path: path.clone(),
                         methods: None,
                         function: function.clone(),
+                        payload_format: options.payload_format,
+                        auth_type: options.auth_type,
+                        host: None,
+                        headers: BTreeMap::new(),
+                        authorizer: options.authorizer,
                     });
                 }
                 FunctionRoutes::Multiple(routes) => {
                     for (method, function) in routes {
+                        let options = options
+                            .get(&Some(method.clone()))
+                            .cloned()
+                            .unwrap_or_default();
                         inverse
-                            .entry((path.clone(), function.clone()))
+                            .entry((path.clone(), function.clone(), options.clone()))
                             .and_modify(|route: &mut Route| {
                                 let mut methods = route.methods.clone().unwrap_or_default();
                                 methods.push(method.clone());
@@ -333,6 +952,11 @@ impl<'de> Visitor<'de> for FunctionRouterVisitor {
                                 path: path.clone(),
                                 methods: Some(vec![method.clone()]),
                                 function: function.clone(),
+                                payload_format: options.payload_format,
+                                auth_type: options.auth_type,
+                                host: None,
+                                headers: BTreeMap::new(),
+                                authorizer: options.authorizer.clone(),
                             });
                     }
                 }
@@ -343,7 +967,8 @@ impl<'de> Visitor<'de> for FunctionRouterVisitor {
             raw.push(route);
         }
 
-        Ok(FunctionRouter { inner, raw })
+        let routes = route_infos(&raw).map_err(serde::de::Error::custom)?;
+        Ok(FunctionRouter { inner, routes, raw })
     }
 
     fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
@@ -353,28 +978,118 @@ impl<'de> Visitor<'de> for FunctionRouterVisitor {
         let routes: Vec<Route> =
             Deserialize::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))?;
 
+        FunctionRouter::from_routes(routes).map_err(serde::de::Error::custom)
+    }
+}
+
+impl FunctionRouter {
+    /// Build a router from routes declared as an array of route tables.
+    pub(crate) fn from_routes(routes: Vec<Route>) -> Result<FunctionRouter, String> {
         let mut inner = Router::new();
         let mut raw = Vec::new();
 
         let mut routes_by_path = HashMap::new();
 
         for route in &routes {
+            if let Some(authorizer) = &route.authorizer {
+                authorizer.validate()?;
+            }
+            raw.push(route.clone());
+
+            if route.is_conditional() {
+                continue;
+            }
+
             routes_by_path
                 .entry(route.path.clone())
                 .and_modify(|routes| merge_routes(routes, route))
                 .or_insert_with(|| decode_route(route));
-
-            raw.push(route.clone());
         }
 
         for (path, route) in &routes_by_path {
-            inner.insert(path, route.clone()).map_err(|e| {
-                serde::de::Error::custom(format!("Failed to insert route {path}: {e}"))
-            })?;
+            inner
+                .insert(path, route.clone())
+                .map_err(|e| format!("Failed to insert route {path}: {e}"))?;
+        }
+
+        let routes = route_infos(&raw)?;
+        Ok(FunctionRouter { inner, routes, raw })
+    }
+}
+
+/// Index the options of the routes, and the routes with host or header matchers, by path.
+/// Every path is indexed, so a request never matches
+/// a different pattern than the one that routed it.
+fn route_infos(raw: &[Route]) -> Result<Router<RouteInfo>, String> {
+    let mut infos: HashMap<&str, RouteInfo> = HashMap::new();
+
+    for route in raw {
+        let info = infos.entry(&route.path).or_insert_with(|| RouteInfo {
+            path: route.path.clone(),
+            ..Default::default()
+        });
+
+        if route.is_conditional() {
+            info.conditional.push(route.clone());
+            continue;
+        }
+        info.routed = true;
+
+        let options = route.options();
+        if options.is_empty() {
+            continue;
+        }
+        match &route.methods {
+            None => {
+                info.options.insert(None, options);
+            }
+            Some(methods) => {
+                for method in methods {
+                    info.options.insert(Some(method.clone()), options.clone());
+                }
+            }
         }
+    }
+
+    let mut routes = Router::new();
+    for (path, info) in infos {
+        routes
+            .insert(path, info)
+            .map_err(|e| format!("Failed to insert route {path}: {e}"))?;
+    }
+    Ok(routes)
+}
 
-        Ok(FunctionRouter { inner, raw })
+/// Extract the route options from a route declared as a table,
+/// or from the methods in a route declared as an array.
+fn decode_route_options(value: &Value) -> HashMap<Option<String>, RouteOptions> {
+    let decode = |obj: &serde_json::Map<String, Value>| RouteOptions {
+        payload_format: obj
+            .get("payload_format")
+            .and_then(|f| PayloadFormat::deserialize(f).ok()),
+        auth_type: obj
+            .get("auth_type")
+            .and_then(|a| AuthType::deserialize(a).ok()),
+        authorizer: obj
+            .get("authorizer")
+            .and_then(|a| Authorizer::deserialize(a).ok()),
+    };
+
+    let mut options = HashMap::new();
+    match value {
+        Value::Object(obj) => {
+            options.insert(None, decode(obj));
+        }
+        Value::Array(arr) => {
+            for obj in arr.iter().filter_map(|item| item.as_object()) {
+                if let Some(method) = obj.get("method").and_then(|m| m.as_str()) {
+                    options.insert(Some(method.to_string()), decode(obj));
+                }
+            }
+        }
+        _ => {}
     }
+    options
 }
 
 fn merge_routes(routes: &mut FunctionRoutes, route: &Route) {
@@ -440,12 +1155,22 @@ impl<'de> Deserialize<'de> for FunctionRoutes {
         let value = Value::deserialize(deserializer)?;
         match value {
             Value::String(s) => Ok(FunctionRoutes::Single(s)),
+            Value::Object(obj) => {
+                validate_route_options(&obj)?;
+                let function = obj
+                    .get("function")
+                    .and_then(|f| f.as_str())
+                    .ok_or_else(|| Error::custom("Missing or invalid function field"))?;
+
+                Ok(FunctionRoutes::Single(function.to_string()))
+            }
             Value::Array(arr) => {
                 let mut routes = HashMap::new();
                 for item in arr {
                     let obj = item.as_object().ok_or_else(|| {
                         Error::custom("Array items must be objects with method and function fields")
                     })?;
+                    validate_route_options(obj)?;
 
                     let method = obj
                         .get("method")
@@ -462,12 +1187,35 @@ impl<'de> Deserialize<'de> for FunctionRoutes {
                 Ok(FunctionRoutes::Multiple(routes))
             }
             _ => Err(Error::custom(
-                "Function routes must be either a string or an array of objects with method and function fields",
+                "Function routes must be either a string, a table with a function field, or an array of objects with method and function fields",
             )),
         }
     }
 }
 
+fn validate_route_options<E: Error>(obj: &serde_json::Map<String, Value>) -> Result<(), E> {
+    if let Some(format) = obj.get("payload_format") {
+        PayloadFormat::deserialize(format)
+            .map_err(|_| Error::custom("Invalid payload_format field, use v2, v1, or alb"))?;
+    }
+    if let Some(auth_type) = obj.get("auth_type") {
+        AuthType::deserialize(auth_type)
+            .map_err(|_| Error::custom("Invalid auth_type field, use NONE or AWS_IAM"))?;
+    }
+    if let Some(authorizer) = obj.get("authorizer") {
+        Authorizer::deserialize(authorizer)
+            .map_err(|e| Error::custom(format!("Invalid authorizer field: {e}")))?
+            .validate()
+            .map_err(Error::custom)?;
+    }
+    if obj.contains_key("host") || obj.contains_key("headers") {
+        return Err(Error::custom(
+            "Routes that match the host or headers must be declared as an array of route tables",
+        ));
+    }
+    Ok(())
+}
+
 impl Serialize for FunctionRoutes {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -621,6 +1369,447 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_router_payload_formats() {
+        let router: FunctionRouter = toml::from_str(
+            r#"
+            "/users/{id}" = [
+                { function = "get_user", method = "GET", payload_format = "v1" },
+                { function = "update_user", method = "PUT" }
+            ]
+            "/health" = { function = "health", payload_format = "alb" }
+            "/all" = "all_methods"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            router.matched_route("/users/1", "GET"),
+            Some(MatchedRoute {
+                path: "/users/{id}".into(),
+                payload_format: PayloadFormat::V1,
+                auth_type: None,
+                domain: None,
+                authorizer: None,
+            })
+        );
+        assert_eq!(
+            router
+                .matched_route("/users/1", "PUT")
+                .unwrap()
+                .payload_format,
+            PayloadFormat::V2
+        );
+        assert_eq!(
+            router
+                .matched_route("/health", "GET")
+                .unwrap()
+                .payload_format,
+            PayloadFormat::Alb
+        );
+        assert_eq!(
+            router.at("/health", "GET"),
+            Ok(("health".to_string(), HashMap::new()))
+        );
+        assert_eq!(
+            router.matched_route("/all", "POST").unwrap().payload_format,
+            PayloadFormat::V2
+        );
+        assert_eq!(router.matched_route("/missing", "GET"), None);
+
+        let json = serde_json::to_value(&router).unwrap();
+        let new_router: FunctionRouter = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            new_router.matched_route("/users/1", "GET"),
+            router.matched_route("/users/1", "GET")
+        );
+        assert_eq!(
+            new_router.matched_route("/health", "GET"),
+            router.matched_route("/health", "GET")
+        );
+
+        let err = toml::from_str::<FunctionRouter>(
+            r#"
+            "/users" = { function = "get_user", payload_format = "v3" }
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid payload_format"));
+    }
+
+    #[test]
+    fn test_router_auth_types() {
+        let router: FunctionRouter = toml::from_str(
+            r#"
+            "/admin" = [
+                { function = "admin", method = "POST", auth_type = "AWS_IAM", payload_format = "v1" },
+                { function = "admin", method = "GET" }
+            ]
+            "/public" = { function = "public", auth_type = "NONE" }
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            router.matched_route("/admin", "POST"),
+            Some(MatchedRoute {
+                path: "/admin".into(),
+                payload_format: PayloadFormat::V1,
+                auth_type: Some(AuthType::AwsIam),
+                domain: None,
+                authorizer: None,
+            })
+        );
+        assert_eq!(
+            router.matched_route("/admin", "GET").unwrap().auth_type,
+            None
+        );
+        assert_eq!(
+            router.matched_route("/public", "GET").unwrap().auth_type,
+            Some(AuthType::None)
+        );
+
+        let json = serde_json::to_value(&router).unwrap();
+        let new_router: FunctionRouter = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            new_router.matched_route("/admin", "POST"),
+            router.matched_route("/admin", "POST")
+        );
+
+        let err = toml::from_str::<FunctionRouter>(
+            r#"
+            "/users" = { function = "get_user", auth_type = "OAUTH" }
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid auth_type"));
+
+        assert_eq!("aws_iam".parse::<AuthType>().unwrap(), AuthType::AwsIam);
+        assert_eq!(AuthType::AwsIam.to_string(), "AWS_IAM");
+    }
+
+    #[test]
+    fn test_router_hosts_and_headers() {
+        let config: WatchConfig = toml::from_str(
+            r#"
+            [[router]]
+            path = "/users/{id}"
+            function = "admin_users"
+            host = "admin.example.com"
+
+            [[router]]
+            path = "/users/{id}"
+            methods = ["GET"]
+            function = "tenant_users"
+            host = "*.example.com"
+            payload_format = "v1"
+
+            [[router]]
+            path = "/users/{id}"
+            function = "beta_users"
+            headers = { x-beta = "true" }
+
+            [[router]]
+            path = "/users/{id}"
+            function = "users"
+        "#,
+        )
+        .unwrap();
+        let router = config.router.unwrap();
+
+        let route = |host, headers| {
+            router
+                .route(&RouteRequest {
+                    path: "/users/1",
+                    method: "GET",
+                    host,
+                    headers,
+                })
+                .unwrap()
+        };
+
+        let admin = route(Some("Admin.Example.com"), &[]);
+        assert_eq!(admin.function, "admin_users");
+        assert_eq!(admin.params.get("id").unwrap(), "1");
+        assert_eq!(admin.route.domain.as_deref(), Some("admin.example.com"));
+
+        let tenant = route(Some("acme.example.com"), &[]);
+        assert_eq!(tenant.function, "tenant_users");
+        assert_eq!(tenant.route.payload_format, PayloadFormat::V1);
+        assert_eq!(tenant.route.domain.as_deref(), Some("acme.example.com"));
+
+        let beta = route(Some("example.com"), &[("x-beta", "true")]);
+        assert_eq!(beta.function, "beta_users");
+        assert_eq!(beta.route.domain, None);
+
+        let fallback = route(Some("example.com"), &[("x-beta", "false")]);
+        assert_eq!(fallback.function, "users");
+        assert_eq!(fallback.route.path, "/users/{id}");
+        assert_eq!(route(None, &[]).function, "users");
+
+        let post = router
+            .route(&RouteRequest {
+                path: "/users/1",
+                method: "POST",
+                host: Some("acme.example.com"),
+                headers: &[],
+            })
+            .unwrap();
+        assert_eq!(post.function, "users");
+
+        let json = serde_json::to_value(&router).unwrap();
+        let new_router: FunctionRouter = serde_json::from_value(json).unwrap();
+        assert_eq!(new_router.raw, router.raw);
+
+        let router: FunctionRouter = serde_json::from_value(serde_json::json!([
+            { "path": "/admin", "function": "admin", "host": "admin.example.com" },
+            { "path": "/{*path}", "function": "catch_all" },
+        ]))
+        .unwrap();
+        let admin = RouteRequest {
+            path: "/admin",
+            method: "GET",
+            host: Some("example.com"),
+            headers: &[],
+        };
+        assert_eq!(router.route(&admin), None);
+        assert_eq!(router.matched_route("/admin", "GET"), None);
+
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "badexample.com"));
+
+        let err = toml::from_str::<FunctionRouter>(
+            r#"
+            "/users" = { function = "get_user", host = "api.example.com" }
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("array of route tables"));
+    }
+
+    #[test]
+    fn test_router_authorizers() {
+        let router: FunctionRouter = toml::from_str(
+            r#"
+            "/admin" = { function = "admin", authorizer = { type = "jwt", secret = "s3cr3t", audience = ["local"] } }
+            "/orders" = [
+                { function = "orders", method = "GET", authorizer = { type = "lambda", function = "authorizer", ttl = 60 } },
+                { function = "orders", method = "POST" }
+            ]
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            router.matched_route("/admin", "GET").unwrap().authorizer,
+            Some(Authorizer::Jwt(JwtAuthorizer {
+                secret: Some("s3cr3t".into()),
+                audience: vec!["local".into()],
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            router.matched_route("/orders", "GET").unwrap().authorizer,
+            Some(Authorizer::Lambda(LambdaAuthorizer {
+                function: "authorizer".into(),
+                ttl: Some(60),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            router.matched_route("/orders", "POST").unwrap().authorizer,
+            None
+        );
+
+        let json = serde_json::to_value(&router).unwrap();
+        let new_router: FunctionRouter = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            new_router.matched_route("/orders", "GET"),
+            router.matched_route("/orders", "GET")
+        );
+
+        let err = toml::from_str::<FunctionRouter>(
+            r#"
+            "/admin" = { function = "admin", authorizer = { type = "jwt" } }
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("jwks file or a secret"));
+
+        let err = toml::from_str::<FunctionRouter>(
+            r#"
+            "/admin" = { function = "admin", authorizer = { type = "cognito" } }
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid authorizer"));
+    }
+
+    #[test]
+    fn test_websocket_routes() {
+        let watch: Watch = toml::from_str(
+            r#"
+            [websocket]
+            route_selection_expression = "$request.body.message.type"
+
+            [websocket.routes]
+            "$connect" = "connect"
+            "$default" = "fallback"
+            "sendMessage" = "send-message"
+        "#,
+        )
+        .unwrap();
+        let websocket = watch.websocket.unwrap();
+        assert_eq!(websocket.path, "/ws");
+
+        assert_eq!(
+            websocket.route_key(r#"{"message":{"type":"sendMessage"}}"#),
+            Some("sendMessage")
+        );
+        assert_eq!(
+            websocket.route_key(r#"{"message":{"type":"unknown"}}"#),
+            Some("$default")
+        );
+        assert_eq!(
+            websocket.route_key(r#"{"message":{"type":"$connect"}}"#),
+            Some("$default")
+        );
+        assert_eq!(websocket.route_key("not json"), Some("$default"));
+
+        let websocket = WebSocketApi {
+            routes: BTreeMap::from([("ping".to_string(), "ping".to_string())]),
+            ..websocket
+        };
+        assert_eq!(websocket.route_key(r#"{"action":"pong"}"#), None);
+    }
+
+    #[test]
+    fn test_function_commands() {
+        let watch: Watch = toml::from_str(
+            r#"
+            [functions.go-handler]
+            command = "go"
+            args = ["run", "."]
+            working_dir = "handlers/go"
+            watch = ["handlers/go"]
+
+            [functions.bootstrap]
+            command = "./bin/bootstrap"
+        "#,
+        )
+        .unwrap();
+
+        let go = &watch.functions["go-handler"];
+        assert_eq!(go.command, "go");
+        assert_eq!(go.args, vec!["run", "."]);
+        assert_eq!(go.working_dir, Some(PathBuf::from("handlers/go")));
+        assert_eq!(go.watch, vec![PathBuf::from("handlers/go")]);
+
+        let bootstrap = &watch.functions["bootstrap"];
+        assert!(bootstrap.args.is_empty());
+        assert!(bootstrap.watch.is_empty());
+
+        let serialized = serde_json::to_value(&watch).unwrap();
+        assert_eq!(
+            serialized["functions"]["bootstrap"],
+            serde_json::json!({ "command": "./bin/bootstrap" })
+        );
+    }
+
+    #[test]
+    fn test_reload_strategy() {
+        let watch: Watch = toml::from_str(r#"reload_strategy = "requeue""#).unwrap();
+        assert_eq!(watch.reload_strategy, Some(ReloadStrategy::Requeue));
+
+        let watch: Watch = toml::from_str("").unwrap();
+        assert_eq!(watch.reload_strategy, None);
+
+        assert_eq!(
+            "FAIL".parse::<ReloadStrategy>().unwrap(),
+            ReloadStrategy::Fail
+        );
+        assert!(toml::from_str::<Watch>(r#"reload_strategy = "restart""#).is_err());
+    }
+
+    #[test]
+    fn test_sqs_event_sources_deserialize() {
+        let watch: Watch = toml::from_str(
+            r#"
+            [[sqs_event_sources]]
+            queue = "orders"
+            function = "orders-consumer"
+            directory = "events/orders"
+            batching_window = 5
+
+            [[sqs_event_sources]]
+            queue = "payments"
+            function = "payments-consumer"
+            batch_size = 1
+            report_batch_item_failures = true
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            watch.sqs_event_sources,
+            vec![
+                SqsEventSource {
+                    queue: "orders".into(),
+                    function: "orders-consumer".into(),
+                    directory: Some(PathBuf::from("events/orders")),
+                    batch_size: 10,
+                    batching_window: 5,
+                    visibility_timeout: 30,
+                    report_batch_item_failures: false,
+                },
+                SqsEventSource {
+                    queue: "payments".into(),
+                    function: "payments-consumer".into(),
+                    directory: None,
+                    batch_size: 1,
+                    batching_window: 0,
+                    visibility_timeout: 30,
+                    report_batch_item_failures: true,
+                },
+            ]
+        );
+
+        let json = serde_json::to_value(&watch).unwrap();
+        assert_eq!(json["sqs_event_sources"][0]["queue"], "orders");
+        assert_eq!(json["sqs_event_sources"][1]["batch_size"], 1);
+    }
+
+    #[test]
+    fn test_schedule_deserialize() {
+        let watch: Watch = toml::from_str(
+            r#"
+            [schedule]
+            nightly-report = "cron(0 2 * * ? *)"
+            cache-warmer = "rate(5 minutes)"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(2, watch.schedule.len());
+        assert_eq!(
+            "cron(0 2 * * ? *)",
+            watch.schedule["nightly-report"].to_string()
+        );
+
+        let json = serde_json::to_value(&watch).unwrap();
+        assert_eq!(json["schedule"]["cache-warmer"], "rate(5 minutes)");
+
+        let err = toml::from_str::<Watch>(
+            r#"
+            [schedule]
+            nightly-report = "cron(0 2 * *)"
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid schedule expression"));
+    }
+
     #[test]
     fn test_watch_serialization() {
         let watch = Watch {
@@ -665,12 +1854,20 @@ mod tests {
                 example: vec![],
                 args: vec![],
             },
+            concurrency: Some(4),
+            max_event_age: Some(60),
+            on_failure_dir: Some(PathBuf::from("/tmp/failed-events")),
             ..Default::default()
         };
 
         let json = serde_json::to_value(&watch).unwrap();
         assert_eq!(json["invoke_address"], "127.0.0.1");
         assert_eq!(json["invoke_port"], 9000);
+        assert_eq!(json["concurrency"], 4);
+        assert_eq!(json["reserved_concurrency"], Value::Null);
+        assert_eq!(json["max_event_age"], 60);
+        assert_eq!(json["on_failure_dir"], "/tmp/failed-events");
+        assert_eq!(json["on_failure_function"], Value::Null);
         assert_eq!(json["env_file"], "/tmp/env");
         assert_eq!(json["env_var"], json!(["FOO=BAR"]));
         assert_eq!(json["tls_cert"], "/tmp/cert.pem");
@@ -694,6 +1891,9 @@ mod tests {
 
         assert_eq!(deserialized.invoke_address, watch.invoke_address);
         assert_eq!(deserialized.invoke_port, watch.invoke_port);
+        assert_eq!(deserialized.concurrency, watch.concurrency);
+        assert_eq!(deserialized.max_event_age, watch.max_event_age);
+        assert_eq!(deserialized.on_failure_dir, watch.on_failure_dir);
         assert_eq!(
             deserialized.env_options.env_file,
             watch.env_options.env_file
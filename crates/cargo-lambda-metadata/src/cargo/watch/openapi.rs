@@ -0,0 +1,313 @@
+//! Routes generated from an OpenAPI 3 document.
+//! Each operation maps to a function through the `x-cargo-lambda-function`
+//! extension, or through its `operationId` or tags when they
+//! match the name of a function in the project.
+
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use super::{FunctionRouter, Route};
+use crate::error::MetadataError;
+
+/// Extension that sets the function of an operation, or of every operation in a path.
+const FUNCTION_EXTENSION: &str = "x-cargo-lambda-function";
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Load the routes of an OpenAPI document. The routes in `router`
+/// take precedence over the routes in the document, only for the methods that they declare.
+pub fn load_router(
+    path: &Path,
+    functions: &HashSet<String>,
+    router: Option<&FunctionRouter>,
+) -> Result<FunctionRouter, MetadataError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| MetadataError::InvalidOpenApiDocument(path.to_path_buf(), e.to_string()))?;
+
+    let is_json = path.extension().is_some_and(|ext| ext == "json");
+    let document: Value = if is_json {
+        serde_json::from_str(&content).map_err(|e| e.to_string())
+    } else {
+        serde_yaml_ng::from_str(&content).map_err(|e| e.to_string())
+    }
+    .map_err(|e| MetadataError::InvalidOpenApiDocument(path.to_path_buf(), e))?;
+
+    let mut routes = document_routes(&document, functions)
+        .map_err(|errors| MetadataError::InvalidOpenApiRoutes(path.to_path_buf(), errors))?;
+    if let Some(router) = router {
+        routes.retain(|route| !is_overridden(route, &router.raw));
+        routes.extend(router.raw.iter().cloned());
+    }
+
+    FunctionRouter::from_routes(routes)
+        .map_err(|e| MetadataError::InvalidOpenApiDocument(path.to_path_buf(), e))
+}
+
+/// Whether a route in the configuration handles the same path and method as a document route.
+/// Routes that match by host or headers don't override the document routes,
+/// because requests that they don't match fall back to them.
+fn is_overridden(route: &Route, config: &[Route]) -> bool {
+    config.iter().any(|r| {
+        !r.is_conditional()
+            && r.path == route.path
+            && match (&r.methods, &route.methods) {
+                (None, _) => true,
+                (Some(methods), Some(route_methods)) => route_methods
+                    .iter()
+                    .all(|m| methods.iter().any(|o| o.eq_ignore_ascii_case(m))),
+                (Some(_), None) => false,
+            }
+    })
+}
+
+/// Routes of the operations in a document. It returns every
+/// operation that doesn't map to a function as an error.
+fn document_routes(
+    document: &Value,
+    functions: &HashSet<String>,
+) -> Result<Vec<Route>, Vec<String>> {
+    let version = document
+        .get("openapi")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    if !version.starts_with("3.") {
+        return Err(vec![
+            "only OpenAPI 3 documents are supported, the `openapi` field must start with `3.`"
+                .to_string(),
+        ]);
+    }
+
+    let mut routes = Vec::new();
+    let mut errors = Vec::new();
+
+    let paths = document.get("paths").and_then(Value::as_object);
+    for (path, item) in paths.into_iter().flatten() {
+        let path_function = item.get(FUNCTION_EXTENSION).and_then(Value::as_str);
+
+        for method in METHODS {
+            let Some(operation) = item.get(method) else {
+                continue;
+            };
+            let method = method.to_uppercase();
+
+            match operation_function(operation, path_function, functions) {
+                Ok(function) => routes.push(Route {
+                    path: path.clone(),
+                    methods: Some(vec![method]),
+                    function,
+                    payload_format: None,
+                    auth_type: None,
+                    host: None,
+                    headers: BTreeMap::new(),
+                    authorizer: None,
+                }),
+                Err(error) => errors.push(format!("{method} {path}: {error}")),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(routes)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Function of an operation, from its extension, its `operationId`, or its tags, in that order.
+fn operation_function(
+    operation: &Value,
+    path_function: Option<&str>,
+    functions: &HashSet<String>,
+) -> Result<String, String> {
+    let extension = operation
+        .get(FUNCTION_EXTENSION)
+        .and_then(Value::as_str)
+        .or(path_function);
+    if let Some(function) = extension {
+        if functions.contains(function) {
+            return Ok(function.to_string());
+        }
+        return Err(format!(
+            "the function `{function}` doesn't exist, available functions: {functions:?}"
+        ));
+    }
+
+    let operation_id = operation.get("operationId").and_then(Value::as_str);
+    let tags = operation
+        .get("tags")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str);
+
+    operation_id
+        .into_iter()
+        .chain(tags)
+        .flat_map(|name| [name.to_string(), kebab_case(name)])
+        .find(|name| functions.contains(name))
+        .ok_or_else(|| {
+            format!(
+                "the operation doesn't map to any function, add the `{FUNCTION_EXTENSION}` extension, or use the name of a function as its operationId or tag"
+            )
+        })
+}
+
+/// Convert names like `listPets` and `list_pets` to `list-pets`,
+/// the usual name of binaries.
+fn kebab_case(name: &str) -> String {
+    let mut kebab = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 && !kebab.ends_with('-') {
+                kebab.push('-');
+            }
+            kebab.push(c.to_ascii_lowercase());
+        } else if c == '_' || c == ' ' {
+            kebab.push('-');
+        } else {
+            kebab.push(c);
+        }
+    }
+    kebab
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargo::watch::RouteRequest;
+
+    const DOCUMENT: &str = r#"
+openapi: 3.0.3
+info:
+  title: Pets
+  version: 1.0.0
+paths:
+  /pets:
+    get:
+      operationId: listPets
+      tags: [pets]
+    post:
+      x-cargo-lambda-function: create-pet
+  /pets/{petId}:
+    x-cargo-lambda-function: pets
+    get:
+      operationId: showPetById
+    delete:
+      tags: [admin]
+      x-cargo-lambda-function: admin
+"#;
+
+    fn functions(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn route(router: &FunctionRouter, path: &str, method: &str) -> Option<String> {
+        router
+            .route(&RouteRequest {
+                path,
+                method,
+                host: None,
+                headers: &[],
+            })
+            .map(|route| route.function)
+    }
+
+    #[test]
+    fn test_document_routes() {
+        let document: Value = serde_yaml_ng::from_str(DOCUMENT).unwrap();
+        let functions = functions(&["list-pets", "create-pet", "pets", "admin"]);
+        let routes = document_routes(&document, &functions).unwrap();
+        let router = FunctionRouter::from_routes(routes).unwrap();
+
+        assert_eq!(route(&router, "/pets", "GET").as_deref(), Some("list-pets"));
+        assert_eq!(
+            route(&router, "/pets", "POST").as_deref(),
+            Some("create-pet")
+        );
+        assert_eq!(route(&router, "/pets/1", "GET").as_deref(), Some("pets"));
+        assert_eq!(
+            route(&router, "/pets/1", "DELETE").as_deref(),
+            Some("admin")
+        );
+        assert_eq!(route(&router, "/pets/1", "PUT"), None);
+
+        let matched = router
+            .route(&RouteRequest {
+                path: "/pets/1",
+                method: "GET",
+                host: None,
+                headers: &[],
+            })
+            .unwrap();
+        assert_eq!(matched.params.get("petId").map(String::as_str), Some("1"));
+    }
+
+    #[test]
+    fn test_document_routes_errors() {
+        let document: Value = serde_yaml_ng::from_str(DOCUMENT).unwrap();
+        let functions = functions(&["admin"]);
+        let errors = document_routes(&document, &functions).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("GET /pets: the operation doesn't map to any function"));
+        assert!(errors[1].starts_with("POST /pets: the function `create-pet` doesn't exist"));
+        assert!(errors[2].starts_with("GET /pets/{petId}: the function `pets` doesn't exist"));
+
+        let document = serde_json::json!({ "swagger": "2.0", "paths": {} });
+        assert!(document_routes(&document, &functions).is_err());
+    }
+
+    #[test]
+    fn test_load_router_with_config_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("openapi.yaml");
+        std::fs::write(&path, DOCUMENT).unwrap();
+
+        let config: FunctionRouter =
+            serde_json::from_value(serde_json::json!({ "/pets": "all-pets" })).unwrap();
+        let functions = functions(&["list-pets", "create-pet", "pets", "admin"]);
+        let router = load_router(&path, &functions, Some(&config)).unwrap();
+
+        assert_eq!(route(&router, "/pets", "GET").as_deref(), Some("all-pets"));
+        assert_eq!(route(&router, "/pets/1", "GET").as_deref(), Some("pets"));
+    }
+
+    #[test]
+    fn test_load_router_with_config_methods() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("openapi.yaml");
+        std::fs::write(&path, DOCUMENT).unwrap();
+
+        let config: FunctionRouter = serde_json::from_value(serde_json::json!({
+            "/pets": [{ "method": "POST", "function": "all-pets" }],
+            "/pets/{petId}": [{ "method": "PUT", "function": "update-pet" }],
+        }))
+        .unwrap();
+        let functions = functions(&["list-pets", "create-pet", "pets", "admin"]);
+        let router = load_router(&path, &functions, Some(&config)).unwrap();
+
+        assert_eq!(route(&router, "/pets", "GET").as_deref(), Some("list-pets"));
+        assert_eq!(route(&router, "/pets", "POST").as_deref(), Some("all-pets"));
+        assert_eq!(route(&router, "/pets/1", "GET").as_deref(), Some("pets"));
+        assert_eq!(
+            route(&router, "/pets/1", "DELETE").as_deref(),
+            Some("admin")
+        );
+        assert_eq!(
+            route(&router, "/pets/1", "PUT").as_deref(),
+            Some("update-pet")
+        );
+    }
+
+    #[test]
+    fn test_kebab_case() {
+        assert_eq!(kebab_case("listPets"), "list-pets");
+        assert_eq!(kebab_case("ShowPetById"), "show-pet-by-id");
+        assert_eq!(kebab_case("list_pets"), "list-pets");
+        assert_eq!(kebab_case("pets"), "pets");
+    }
+}
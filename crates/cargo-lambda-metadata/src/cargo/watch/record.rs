@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Invocation recorded by `cargo lambda watch --record`, and
+/// resent by `cargo lambda invoke --replay`. Every line in a
+/// recording file is one of these records serialized as JSON.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct InvocationRecord {
+    /// Name of the function that processed the invocation
+    pub function_name: String,
+    /// Request id of the invocation, if the caller sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Time when the invocation was received, in RFC 3339 format
+    pub timestamp: String,
+    /// Event as it was sent to the function
+    pub event: Value,
+    /// Headers that the runtime forwards to the function, like the client context
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Status of the invocation, 200 if the function succeeded
+    pub status: u16,
+    /// Response that the function returned when it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// Error that the function returned when it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+    /// Time, in milliseconds, between the invocation and the end of the response
+    pub duration_ms: u64,
+}
+
+impl InvocationRecord {
+    /// Decode a payload as JSON. Payloads that are not valid JSON, and JSON strings,
+    /// are kept as their raw text, so they can be sent again exactly as they were.
+    pub fn decode_payload(payload: &[u8]) -> Value {
+        match serde_json::from_slice(payload) {
+            Ok(Value::String(_)) | Err(_) => {
+                Value::String(String::from_utf8_lossy(payload).to_string())
+            }
+            Ok(value) => value,
+        }
+    }
+
+    /// Raw payload of the invocation's event.
+    pub fn event_payload(&self) -> String {
+        match &self.event {
+            Value::String(raw) => raw.clone(),
+            event => event.to_string(),
+        }
+    }
+
+    /// Whether the function failed to process the invocation.
+    pub fn is_error(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_record_serialization() {
+        let record = InvocationRecord {
+            function_name: "basic-lambda".into(),
+            timestamp: "2024-01-01T00:00:00.000Z".into(),
+            event: InvocationRecord::decode_payload(br#"{"command":"hi"}"#),
+            status: 200,
+            response: Some(InvocationRecord::decode_payload(b"plain text")),
+            duration_ms: 12,
+            ..Default::default()
+        };
+
+        let line = serde_json::to_string(&record).unwrap();
+        assert!(!line.contains("request_id"));
+        assert!(!line.contains("headers"));
+        assert!(!line.contains("error"));
+
+        let decoded: InvocationRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.event, json!({"command": "hi"}));
+        assert_eq!(decoded.response, Some(json!("plain text")));
+        assert!(!decoded.is_error());
+    }
+
+    #[test]
+    fn test_event_payload() {
+        let record = InvocationRecord {
+            event: InvocationRecord::decode_payload(b"plain text"),
+            ..Default::default()
+        };
+        assert_eq!(record.event_payload(), "plain text");
+
+        let record = InvocationRecord {
+            event: InvocationRecord::decode_payload(br#""quoted""#),
+            ..Default::default()
+        };
+        assert_eq!(record.event_payload(), r#""quoted""#);
+
+        let record = InvocationRecord {
+            event: InvocationRecord::decode_payload(br#"{"command": "hi"}"#),
+            ..Default::default()
+        };
+        assert_eq!(record.event_payload(), r#"{"command":"hi"}"#);
+    }
+}
@@ -0,0 +1,477 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use crate::error::MetadataError;
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const DAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+const MIN_YEAR: u32 = 1970;
+const MAX_YEAR: u32 = 2199;
+
+/// EventBridge schedule expression, either `rate(value unit)` or
+/// `cron(minutes hours day-of-month month day-of-week year)`.
+/// See the syntax in the EventBridge documentation:
+/// https://docs.aws.amazon.com/eventbridge/latest/userguide/eb-scheduled-rule-pattern.html
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ScheduleExpression {
+    raw: String,
+    schedule: Schedule,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Schedule {
+    Rate(Duration),
+    Cron(Cron),
+}
+
+impl ScheduleExpression {
+    /// Next time that the schedule fires after `time`.
+    /// Rate expressions fire periodically from the time that the schedule starts,
+    /// so `time` must be the last time that the schedule fired.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match &self.schedule {
+            Schedule::Rate(interval) => Some(time + *interval),
+            Schedule::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+impl fmt::Display for ScheduleExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl From<ScheduleExpression> for String {
+    fn from(expression: ScheduleExpression) -> String {
+        expression.raw
+    }
+}
+
+impl TryFrom<String> for ScheduleExpression {
+    type Error = MetadataError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl FromStr for ScheduleExpression {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| MetadataError::InvalidScheduleExpression(s.into(), reason);
+
+        let schedule = if let Some(rate) = inner_expression(s, "rate") {
+            Schedule::Rate(parse_rate(rate).map_err(invalid)?)
+        } else if let Some(cron) = inner_expression(s, "cron") {
+            Schedule::Cron(cron.parse().map_err(invalid)?)
+        } else {
+            return Err(invalid(
+                "the expression must be `rate(...)` or `cron(...)`".into(),
+            ));
+        };
+
+        Ok(ScheduleExpression {
+            raw: s.into(),
+            schedule,
+        })
+    }
+}
+
+fn inner_expression<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.trim()
+        .strip_prefix(name)
+        .and_then(|s| s.strip_prefix('('))
+        .and_then(|s| s.strip_suffix(')'))
+        .map(str::trim)
+}
+
+fn parse_rate(rate: &str) -> Result<Duration, String> {
+    let (value, unit) = rate
+        .split_once(' ')
+        .ok_or_else(|| "the rate must have a value and a unit".to_string())?;
+    let value: i64 = value
+        .parse()
+        .map_err(|_| format!("invalid rate value `{value}`"))?;
+    if value <= 0 {
+        return Err("the rate value must be a positive number".into());
+    }
+
+    // EventBridge requires the singular unit when the value is 1, and the plural otherwise.
+    let unit = unit.trim();
+    let expected = |singular: &str| {
+        if value == 1 {
+            unit == singular
+        } else {
+            unit.strip_suffix('s') == Some(singular)
+        }
+    };
+
+    if expected("minute") {
+        Ok(Duration::minutes(value))
+    } else if expected("hour") {
+        Ok(Duration::hours(value))
+    } else if expected("day") {
+        Ok(Duration::days(value))
+    } else {
+        Err(format!(
+            "invalid rate unit `{unit}`, use minute, hour, or day, in plural when the value is greater than 1"
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cron {
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days_of_month: DaysOfMonth,
+    months: BTreeSet<u32>,
+    days_of_week: DaysOfWeek,
+    years: BTreeSet<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum DaysOfMonth {
+    /// `?` or `*`
+    Any,
+    Days(BTreeSet<u32>),
+    /// `L`, the last day of the month
+    Last,
+    /// `LW`, the last weekday of the month
+    LastWeekday,
+    /// `nW`, the weekday closest to the day
+    NearestWeekday(u32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum DaysOfWeek {
+    /// `?` or `*`
+    Any,
+    /// Days from 1 (Sunday) to 7 (Saturday)
+    Days(BTreeSet<u32>),
+    /// `nL`, the last occurrence of the day in the month
+    Last(u32),
+    /// `n#k`, the k-th occurrence of the day in the month
+    Nth(u32, u32),
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days_of_month, months, days_of_week, years] = fields[..] else {
+            return Err(format!(
+                "the cron expression must have 6 fields, found {}",
+                fields.len()
+            ));
+        };
+
+        if (days_of_month == "?") == (days_of_week == "?") {
+            return Err(
+                "you must use `?` in either the day-of-month or the day-of-week field, but not both"
+                    .into(),
+            );
+        }
+
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59, &[])?,
+            hours: parse_field(hours, 0, 23, &[])?,
+            days_of_month: parse_days_of_month(days_of_month)?,
+            months: parse_field(months, 1, 12, &MONTH_NAMES)?,
+            days_of_week: parse_days_of_week(days_of_week)?,
+            years: parse_field(years, MIN_YEAR, MAX_YEAR, &[])?,
+        })
+    }
+}
+
+impl Cron {
+    fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date_naive();
+
+        while date.year() as u32 <= MAX_YEAR {
+            if self.matches_date(date) {
+                for hour in &self.hours {
+                    for minute in &self.minutes {
+                        let candidate = date.and_hms_opt(*hour, *minute, 0)?;
+                        let candidate = Utc.from_utc_datetime(&candidate);
+                        if candidate >= start {
+                            return Some(candidate);
+                        }
+                    }
+                }
+            }
+
+            date = date.succ_opt()?;
+            if !self.years.contains(&(date.year() as u32)) {
+                let next_year = self.years.range(date.year() as u32..).next()?;
+                date = NaiveDate::from_ymd_opt(*next_year as i32, 1, 1)?;
+            }
+        }
+
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        self.years.contains(&(date.year() as u32))
+            && self.months.contains(&date.month())
+            && self.matches_day_of_month(date)
+            && self.matches_day_of_week(date)
+    }
+
+    fn matches_day_of_month(&self, date: NaiveDate) -> bool {
+        let last_day = last_day_of_month(date);
+        match &self.days_of_month {
+            DaysOfMonth::Any => true,
+            DaysOfMonth::Days(days) => days.contains(&date.day()),
+            DaysOfMonth::Last => date.day() == last_day,
+            DaysOfMonth::LastWeekday => date.day() == nearest_weekday(date, last_day),
+            DaysOfMonth::NearestWeekday(day) => {
+                *day <= last_day && date.day() == nearest_weekday(date, *day)
+            }
+        }
+    }
+
+    fn matches_day_of_week(&self, date: NaiveDate) -> bool {
+        let day_of_week = date.weekday().number_from_sunday();
+        match &self.days_of_week {
+            DaysOfWeek::Any => true,
+            DaysOfWeek::Days(days) => days.contains(&day_of_week),
+            DaysOfWeek::Last(day) => {
+                *day == day_of_week && date.day() + 7 > last_day_of_month(date)
+            }
+            DaysOfWeek::Nth(day, nth) => *day == day_of_week && (date.day() - 1) / 7 + 1 == *nth,
+        }
+    }
+}
+
+fn last_day_of_month(date: NaiveDate) -> u32 {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|d| d.pred_opt())
+        .map(|d| d.day())
+        .unwrap_or(28)
+}
+
+/// Weekday closest to a day in the same month as `date`.
+fn nearest_weekday(date: NaiveDate, day: u32) -> u32 {
+    let Some(target) = date.with_day(day) else {
+        return day;
+    };
+
+    match target.weekday().number_from_sunday() {
+        // Saturday moves to Friday, unless it's the first day of the month.
+        7 if day == 1 => day + 2,
+        7 => day - 1,
+        // Sunday moves to Monday, unless it's the last day of the month.
+        1 if day == last_day_of_month(date) => day - 2,
+        1 => day + 1,
+        _ => day,
+    }
+}
+
+fn parse_days_of_month(field: &str) -> Result<DaysOfMonth, String> {
+    match field {
+        "?" | "*" => Ok(DaysOfMonth::Any),
+        "L" => Ok(DaysOfMonth::Last),
+        "LW" => Ok(DaysOfMonth::LastWeekday),
+        _ => match field.strip_suffix('W') {
+            Some(day) => Ok(DaysOfMonth::NearestWeekday(parse_value(day, 1, 31, &[])?)),
+            None => parse_field(field, 1, 31, &[]).map(DaysOfMonth::Days),
+        },
+    }
+}
+
+fn parse_days_of_week(field: &str) -> Result<DaysOfWeek, String> {
+    if field == "?" || field == "*" {
+        return Ok(DaysOfWeek::Any);
+    }
+    if field == "L" {
+        return Ok(DaysOfWeek::Days(BTreeSet::from([7])));
+    }
+    if let Some(day) = field.strip_suffix('L') {
+        return Ok(DaysOfWeek::Last(parse_value(day, 1, 7, &DAY_NAMES)?));
+    }
+    if let Some((day, nth)) = field.split_once('#') {
+        let day = parse_value(day, 1, 7, &DAY_NAMES)?;
+        let nth = parse_value(nth, 1, 5, &[])?;
+        return Ok(DaysOfWeek::Nth(day, nth));
+    }
+
+    parse_field(field, 1, 7, &DAY_NAMES).map(DaysOfWeek::Days)
+}
+
+/// Parse a field with lists, ranges, wildcards, and increments, like `1,5-10,*/15`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<BTreeSet<u32>, String> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("invalid increment `{step}`"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    parse_value(start, min, max, names)?,
+                    parse_value(end, min, max, names)?,
+                ),
+                // `5/10` starts at 5 and increments until the end of the range.
+                None if step > 1 || part.contains('/') => {
+                    (parse_value(range, min, max, names)?, max)
+                }
+                None => {
+                    let value = parse_value(range, min, max, names)?;
+                    (value, value)
+                }
+            },
+        };
+
+        if start > end {
+            return Err(format!("invalid range `{range}`"));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    Ok(values)
+}
+
+fn parse_value(value: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, String> {
+    let parsed = match names.iter().position(|n| n.eq_ignore_ascii_case(value)) {
+        Some(idx) => idx as u32 + min,
+        None => value
+            .parse::<u32>()
+            .map_err(|_| format!("invalid value `{value}`"))?,
+    };
+
+    if parsed < min || parsed > max {
+        return Err(format!(
+            "the value `{value}` is out of range, it must be between {min} and {max}"
+        ));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn next(expression: &str, after: &str) -> String {
+        let expression: ScheduleExpression = expression.parse().unwrap();
+        expression
+            .next_after(time(after))
+            .unwrap()
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    }
+
+    #[test]
+    fn test_rate_expressions() {
+        assert_eq!(
+            "2024-01-01T00:05:00Z",
+            next("rate(5 minutes)", "2024-01-01T00:00:00Z")
+        );
+        assert_eq!(
+            "2024-01-01T01:00:00Z",
+            next("rate(1 hour)", "2024-01-01T00:00:00Z")
+        );
+        assert_eq!(
+            "2024-01-03T00:00:00Z",
+            next("rate(2 days)", "2024-01-01T00:00:00Z")
+        );
+
+        assert!("rate(1 minutes)".parse::<ScheduleExpression>().is_err());
+        assert!("rate(5 minute)".parse::<ScheduleExpression>().is_err());
+        assert!("rate(0 minutes)".parse::<ScheduleExpression>().is_err());
+        assert!("rate(5 weeks)".parse::<ScheduleExpression>().is_err());
+    }
+
+    #[test]
+    fn test_cron_expressions() {
+        // Every day at 10:15
+        assert_eq!(
+            "2024-01-01T10:15:00Z",
+            next("cron(15 10 * * ? *)", "2024-01-01T09:00:00Z")
+        );
+        assert_eq!(
+            "2024-01-02T10:15:00Z",
+            next("cron(15 10 * * ? *)", "2024-01-01T10:15:00Z")
+        );
+        // Every 10 minutes on weekdays
+        assert_eq!(
+            "2024-01-08T00:00:00Z",
+            next("cron(0/10 * ? * MON-FRI *)", "2024-01-05T23:55:00Z")
+        );
+        // Last day of the month
+        assert_eq!(
+            "2024-02-29T12:00:00Z",
+            next("cron(0 12 L * ? *)", "2024-02-01T00:00:00Z")
+        );
+        // Last weekday of the month, Sunday 2024-03-31
+        assert_eq!(
+            "2024-03-29T08:00:00Z",
+            next("cron(0 8 LW * ? *)", "2024-03-01T00:00:00Z")
+        );
+        // Second Tuesday of the month
+        assert_eq!(
+            "2024-01-09T18:00:00Z",
+            next("cron(0 18 ? * 3#2 *)", "2024-01-01T00:00:00Z")
+        );
+        // Last Friday of the month
+        assert_eq!(
+            "2024-01-26T18:00:00Z",
+            next("cron(0 18 ? * 6L *)", "2024-01-01T00:00:00Z")
+        );
+        // Only in a future year
+        assert_eq!(
+            "2030-06-01T00:00:00Z",
+            next("cron(0 0 1 JUN ? 2030)", "2024-01-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_invalid_cron_expressions() {
+        for expression in [
+            "cron(0 10 * * *)",
+            "cron(0 10 * * * *)",
+            "cron(0 10 ? * ? *)",
+            "cron(60 10 * * ? *)",
+            "cron(0 10 * FOO ? *)",
+            "every(5 minutes)",
+        ] {
+            assert!(
+                expression.parse::<ScheduleExpression>().is_err(),
+                "{expression} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn test_expired_cron_expression() {
+        let expression: ScheduleExpression = "cron(0 0 1 1 ? 2020)".parse().unwrap();
+        assert_eq!(None, expression.next_after(time("2024-01-01T00:00:00Z")));
+    }
+}
@@ -44,4 +44,13 @@ pub enum MetadataError {
     #[error(transparent)]
     #[diagnostic()]
     MergeError(#[from] MergeError),
+    #[error("invalid schedule expression `{0}`: {1}")]
+    #[diagnostic()]
+    InvalidScheduleExpression(String, String),
+    #[error("invalid OpenAPI document `{0}`: {1}")]
+    #[diagnostic()]
+    InvalidOpenApiDocument(PathBuf, String),
+    #[error("invalid routes in OpenAPI document `{0}`:\n{}", .1.join("\n"))]
+    #[diagnostic()]
+    InvalidOpenApiRoutes(PathBuf, Vec<String>),
 }
@@ -253,3 +253,71 @@ impl<'de> Deserialize<'de> for Tracing {
         deserializer.deserialize_string(TracingVisitor)
     }
 }
+
+/// Format of the logs that Lambda captures from the function.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Display, EnumString, Eq, Hash, PartialEq, Serialize,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum LogFormat {
+    /// Logs are captured as plain text
+    #[default]
+    Text,
+    /// Logs are captured as structured JSON records
+    #[strum(serialize = "JSON")]
+    #[serde(rename = "JSON", alias = "json", alias = "Json")]
+    Json,
+}
+
+/// Minimum level of the logs that the function writes, when the logs are in JSON format.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Display,
+    EnumString,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ApplicationLogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+/// Minimum level of the logs that Lambda writes, when the logs are in JSON format.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Display,
+    EnumString,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SystemLogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+}
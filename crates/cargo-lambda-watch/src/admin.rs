@@ -0,0 +1,340 @@
+use crate::{
+    RefRuntimeState,
+    error::ServerError,
+    state::{
+        CompletedInvocation, InFlightInvocation, RegisteredExtension, environment_function_name,
+    },
+};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Path, under the runtime emulator path, where the admin API is served.
+const ADMIN_PATH: &str = "/_admin";
+
+/// Number of completed invocations returned when the request doesn't set a limit.
+const DEFAULT_INVOCATIONS_LIMIT: usize = 20;
+
+/// Read-only endpoints to inspect the state of the emulator.
+pub(crate) fn routes() -> Router<RefRuntimeState> {
+    Router::new()
+        .route(ADMIN_PATH, get(overview))
+        .route(&format!("{ADMIN_PATH}/functions"), get(list_functions))
+        .route(
+            &format!("{ADMIN_PATH}/functions/:function_name"),
+            get(get_function),
+        )
+        .route(&format!("{ADMIN_PATH}/extensions"), get(list_extensions))
+        .route(&format!("{ADMIN_PATH}/invocations"), get(list_invocations))
+}
+
+/// State of the processes that run a function.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ProcessState {
+    /// The function doesn't have any process running
+    Stopped,
+    /// The function's processes are waiting for invocations
+    Idle,
+    /// At least one of the function's processes is processing an invocation
+    Busy,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvironmentStatus {
+    id: String,
+    state: ProcessState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_invocation: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionSettingsStatus {
+    timeout_secs: u64,
+    memory: u32,
+    concurrency: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserved_concurrency: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionStatus {
+    name: String,
+    state: ProcessState,
+    queue_depth: usize,
+    in_flight: Vec<String>,
+    environments: Vec<EnvironmentStatus>,
+    settings: FunctionSettingsStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvocationsStatus {
+    in_flight: Vec<InFlightInvocation>,
+    completed: Vec<CompletedInvocation>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Overview {
+    functions: Vec<FunctionStatus>,
+    extensions: Vec<RegisteredExtension>,
+    invocations: InvocationsStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct InvocationsQuery {
+    limit: Option<usize>,
+}
+
+async fn overview(
+    State(state): State<RefRuntimeState>,
+    Query(query): Query<InvocationsQuery>,
+) -> Result<Json<Overview>, ServerError> {
+    Ok(Json(Overview {
+        functions: functions_status(&state).await,
+        extensions: state.ext_cache.registered().await,
+        invocations: invocations_status(&state, query.limit).await,
+    }))
+}
+
+async fn list_functions(
+    State(state): State<RefRuntimeState>,
+) -> Result<Json<Vec<FunctionStatus>>, ServerError> {
+    Ok(Json(functions_status(&state).await))
+}
+
+async fn get_function(
+    State(state): State<RefRuntimeState>,
+    Path(function_name): Path<String>,
+) -> Result<Response, ServerError> {
+    let status = functions_status(&state)
+        .await
+        .into_iter()
+        .find(|f| f.name == function_name);
+
+    match status {
+        Some(status) => Ok(Json(status).into_response()),
+        None => Ok((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "message": format!("function `{function_name}` not found"),
+            })),
+        )
+            .into_response()),
+    }
+}
+
+async fn list_extensions(
+    State(state): State<RefRuntimeState>,
+) -> Result<Json<Vec<RegisteredExtension>>, ServerError> {
+    Ok(Json(state.ext_cache.registered().await))
+}
+
+async fn list_invocations(
+    State(state): State<RefRuntimeState>,
+    Query(query): Query<InvocationsQuery>,
+) -> Result<Json<InvocationsStatus>, ServerError> {
+    Ok(Json(invocations_status(&state, query.limit).await))
+}
+
+async fn invocations_status(state: &RefRuntimeState, limit: Option<usize>) -> InvocationsStatus {
+    InvocationsStatus {
+        in_flight: state.res_cache.in_flight().await,
+        completed: state
+            .res_cache
+            .history(limit.unwrap_or(DEFAULT_INVOCATIONS_LIMIT))
+            .await,
+    }
+}
+
+/// Status of every function that the emulator knows about: the functions in the project,
+/// and the functions that received invocations or started processes since the emulator started.
+async fn functions_status(state: &RefRuntimeState) -> Vec<FunctionStatus> {
+    let depths = state.req_cache.depths().await;
+    let in_flight = state.res_cache.in_flight().await;
+    let environments = state.processes.environments().await;
+
+    let mut names = BTreeSet::new();
+    names.extend(state.initial_functions.iter().cloned());
+    names.extend(depths.keys().cloned());
+    names.extend(state.functions.names().await);
+    names.extend(in_flight.iter().map(|i| i.function_name.clone()));
+    names.extend(
+        environments
+            .iter()
+            .map(|env| environment_function_name(env).to_string()),
+    );
+
+    let mut environments_by_function: BTreeMap<&str, Vec<EnvironmentStatus>> = BTreeMap::new();
+    for env in &environments {
+        let current_invocation = in_flight
+            .iter()
+            .find(|i| &i.environment == env)
+            .map(|i| i.request_id.clone());
+        let state = if current_invocation.is_some() {
+            ProcessState::Busy
+        } else {
+            ProcessState::Idle
+        };
+
+        environments_by_function
+            .entry(environment_function_name(env))
+            .or_default()
+            .push(EnvironmentStatus {
+                id: env.clone(),
+                state,
+                current_invocation,
+            });
+    }
+
+    let mut functions = Vec::with_capacity(names.len());
+    for name in names {
+        let settings = state.functions.get(&name).await;
+        let environments = environments_by_function
+            .remove(name.as_str())
+            .unwrap_or_default();
+
+        functions.push(FunctionStatus {
+            state: process_state(&environments),
+            queue_depth: depths.get(&name).copied().unwrap_or_default(),
+            in_flight: in_flight
+                .iter()
+                .filter(|i| i.function_name == name)
+                .map(|i| i.request_id.clone())
+                .collect(),
+            environments,
+            settings: FunctionSettingsStatus {
+                timeout_secs: settings.deadline().as_secs(),
+                memory: settings.memory,
+                concurrency: settings.concurrency,
+                reserved_concurrency: settings.reserved_concurrency,
+            },
+            name,
+        });
+    }
+
+    functions
+}
+
+fn process_state(environments: &[EnvironmentStatus]) -> ProcessState {
+    if environments.is_empty() {
+        ProcessState::Stopped
+    } else if environments.iter().any(|e| e.state == ProcessState::Busy) {
+        ProcessState::Busy
+    } else {
+        ProcessState::Idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{report::InvocationStatus, state::RuntimeState};
+    use axum::{body::Body, http::Request};
+    use http_body_util::BodyExt;
+    use std::{collections::HashSet, sync::Arc};
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_routes_with_runtime_api() {
+        let state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::from(["basic-lambda".to_string()]),
+            None,
+        );
+
+        // The admin routes share the runtime emulator path with the runtime API.
+        let router = crate::runtime::routes()
+            .merge(routes())
+            .with_state(Arc::new(state));
+
+        let req = Request::get(format!("{ADMIN_PATH}/functions/basic-lambda"))
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["name"], "basic-lambda");
+
+        let req = Request::post("/basic-lambda/2018-06-01/runtime/init/error")
+            .body(Body::from(r#"{"errorType":"Runtime.Oops"}"#))
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn test_functions_status() {
+        let state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::from(["basic-lambda".to_string()]),
+            None,
+        );
+        let state = Arc::new(state);
+
+        let (resp_tx, _resp_rx) = oneshot::channel();
+        state
+            .res_cache
+            .push(
+                "request-1",
+                "other-lambda@1",
+                resp_tx,
+                axum::http::Request::new(bytes::Bytes::new()),
+            )
+            .await;
+
+        let functions = functions_status(&state).await;
+        let names = functions
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["basic-lambda", "other-lambda"]);
+        assert_eq!(functions[0].state, ProcessState::Stopped);
+        assert!(functions[0].in_flight.is_empty());
+        assert_eq!(functions[1].in_flight, vec!["request-1"]);
+
+        let invocations = invocations_status(&state, None).await;
+        assert_eq!(invocations.in_flight.len(), 1);
+        assert_eq!(invocations.in_flight[0].function_name, "other-lambda");
+        assert!(invocations.completed.is_empty());
+
+        let status = InvocationStatus::Success;
+        assert!(
+            state
+                .complete_invocation("request-1", status.clone())
+                .await
+                .is_some()
+        );
+        assert!(
+            state
+                .complete_invocation("request-1", status)
+                .await
+                .is_none()
+        );
+        let invocations = invocations_status(&state, Some(1)).await;
+        assert!(invocations.in_flight.is_empty());
+        assert_eq!(invocations.completed.len(), 1);
+        assert_eq!(invocations.completed[0].request_id, "request-1");
+        assert_eq!(invocations.completed[0].environment, "other-lambda@1");
+        assert_eq!(invocations.completed[0].status, "success");
+        assert_eq!(invocations.completed[0].metrics.memory_size_mb, 4096);
+    }
+}
@@ -8,7 +8,7 @@ use miette::Diagnostic;
 use serde::Serialize;
 use thiserror::Error;
 
-use crate::requests::{Action, InvokeRequest, NextEvent};
+use crate::requests::{Action, InvokeRequest};
 
 #[derive(Debug, Diagnostic, Error)]
 pub enum ServerError {
@@ -62,7 +62,7 @@ pub enum ServerError {
 
     #[error("failed to run watcher")]
     #[diagnostic()]
-    WatcherError(#[from] watchexec::error::CriticalError),
+    WatcherError(#[from] Box<watchexec::error::CriticalError>),
 
     #[error("failed to load ignore files")]
     #[diagnostic()]
@@ -72,14 +72,14 @@ pub enum ServerError {
     #[diagnostic()]
     MissingExtensionIdHeader,
 
-    #[error("failed to send message to extension: {0}")]
-    #[diagnostic()]
-    SendEventMessage(#[from] Box<tokio::sync::mpsc::error::SendError<NextEvent>>),
-
     #[error("no extension event received")]
     #[diagnostic()]
     NoExtensionEvent,
 
+    #[error("unknown extension identifier: {0}")]
+    #[diagnostic()]
+    UnknownExtension(String),
+
     #[error(
         "client context cannot be longer than 3583 bytes after base64 encoding, the current size is {0}"
     )]
@@ -105,6 +105,30 @@ pub enum ServerError {
     #[error(transparent)]
     #[diagnostic()]
     TlsError(#[from] TlsError),
+
+    #[error(transparent)]
+    #[diagnostic()]
+    InvalidUri(#[from] hyper::http::uri::InvalidUri),
+
+    #[error("failed to encode an event stream message: {0}")]
+    #[diagnostic()]
+    EventStreamEncoding(#[from] aws_smithy_eventstream::error::Error),
+
+    #[error("failed to open the recording file {0:?}")]
+    #[diagnostic()]
+    OpenRecording(std::path::PathBuf, #[source] std::io::Error),
+
+    #[error("failed to start extension {0}")]
+    #[diagnostic()]
+    SpawnExtension(String, #[source] std::io::Error),
+
+    #[error("failed to build extension {0}")]
+    #[diagnostic()]
+    BuildExtension(String),
+
+    #[error("failed to build function: {0}")]
+    #[diagnostic()]
+    BuildFunction(String),
 }
 
 // Explicitly implement Send + Sync
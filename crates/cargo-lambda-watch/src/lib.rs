@@ -4,7 +4,8 @@ use cargo_lambda_metadata::{
     DEFAULT_PACKAGE_FUNCTION,
     cargo::{
         CargoMetadata, CargoPackage, filter_binary_targets_from_metadata, kind_bin_filter,
-        selected_bin_filter, watch::Watch,
+        selected_bin_filter,
+        watch::{Watch, openapi},
     },
     env::SystemEnvExtractor,
     lambda::Timeout,
@@ -12,7 +13,9 @@ use cargo_lambda_metadata::{
 use cargo_lambda_remote::tls::TlsOptions;
 use cargo_options::Run as CargoOptions;
 use http_body_util::{BodyExt, combinators::BoxBody};
-use hyper::{Request, Response, body::Incoming, client::conn::http1, service::service_fn};
+use hyper::{
+    Request, Response, StatusCode, body::Incoming, client::conn::http1, service::service_fn,
+};
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::conn::auto::Builder,
@@ -50,19 +53,28 @@ use tracing::{Subscriber, error, info};
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::registry::LookupSpan;
 
+mod admin;
 mod error;
+mod limits;
+mod logs;
+mod recorder;
+mod report;
 mod requests;
 mod runtime;
 
+mod schedule;
 mod scheduler;
 use scheduler::*;
+mod sqs;
 mod state;
 use state::*;
+mod telemetry;
 mod trigger_router;
 mod watcher;
-use watcher::WatcherConfig;
+use watcher::{WatcherConfig, extensions::ExtensionCommand};
+mod websocket;
 
-use crate::{error::ServerError, requests::Action};
+use crate::{error::ServerError, limits::PayloadLimits, requests::Action};
 
 pub(crate) const RUNTIME_EMULATOR_PATH: &str = "/.rt";
 
@@ -101,8 +113,11 @@ pub async fn run(
         selected_bin_filter(config.cargo_opts.bin.clone())
     };
 
-    let binary_packages =
+    let mut binary_packages =
         filter_binary_targets_from_metadata(metadata, binary_filter, package_filter);
+    // Extensions in the workspace run next to the functions, they are not functions themselves.
+    binary_packages.retain(|name| !config.extensions.contains(name));
+    binary_packages.extend(config.functions.keys().cloned());
 
     if binary_packages.is_empty() {
         Err(ServerError::NoBinaryPackages)?;
@@ -116,6 +131,12 @@ pub async fn run(
         only_lambda_apis: config.only_lambda_apis,
         manifest_path: manifest_path.clone(),
         wait: config.wait,
+        extensions: config
+            .extensions
+            .iter()
+            .map(|extension| ExtensionCommand::new(extension, &cargo_options))
+            .collect(),
+        reload_strategy: config.reload_strategy,
         ..Default::default()
     };
 
@@ -182,14 +203,42 @@ fn build_runtime_state(
     };
     let runtime_addr = SocketAddr::from((ip, runtime_port));
 
-    Ok(RuntimeState::new(
+    let router = match &config.router_from_openapi {
+        Some(path) => {
+            let path = manifest_path
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .join(path);
+            let router = openapi::load_router(&path, &binary_packages, config.router.as_ref())?;
+            info!(?path, "routes loaded from OpenAPI document");
+            Some(router)
+        }
+        None => config.router.clone(),
+    };
+
+    let mut state = RuntimeState::new(
         runtime_addr,
         proxy_addr,
         manifest_path.to_path_buf(),
         config.only_lambda_apis,
         binary_packages,
-        config.router.clone(),
-    ))
+        router,
+    );
+    state.functions = FunctionCache::new(FunctionSettings::from_watch(config));
+    state.sqs_queues = sqs::SqsQueues::new(&config.sqs_event_sources);
+    state.schedules = Arc::new(config.schedule.clone());
+    state.function_commands = Arc::new(config.functions.clone());
+    state.websocket_api = config.websocket.clone().map(Arc::new);
+    if config.disable_payload_limits {
+        state.payload_limits = PayloadLimits::unlimited();
+        info!("payload size limits are disabled");
+    }
+    if let Some(path) = &config.record {
+        state.recorder = Some(recorder::Recorder::new(path)?);
+        info!(?path, "recording invocations");
+    }
+
+    Ok(state)
 }
 
 async fn start_server(
@@ -207,6 +256,29 @@ async fn start_server(
 
     let (runtime_addr, proxy_addr, runtime_url) = runtime_state.addresses();
 
+    // The function is not started by the emulator with --only-lambda-apis,
+    // load its settings now so they apply to the invocations it receives.
+    let default_function_env = if only_lambda_apis {
+        let config = watcher::reload_config(&watcher_config.manifest_path, &None);
+        let settings = match &config {
+            Some(config) => {
+                runtime_state
+                    .functions
+                    .update(DEFAULT_PACKAGE_FUNCTION, config)
+                    .await
+            }
+            None => runtime_state.functions.get(DEFAULT_PACKAGE_FUNCTION).await,
+        };
+        Some(watcher::env::lambda_environment(
+            DEFAULT_PACKAGE_FUNCTION,
+            &settings,
+            config.as_ref(),
+            &watcher_config.base,
+        ))
+    } else {
+        None
+    };
+
     let x_request_id = HeaderName::from_static("lambda-runtime-aws-request-id");
     let req_tx = init_scheduler(
         &subsys,
@@ -216,11 +288,20 @@ async fn start_server(
     );
 
     let state_ref = Arc::new(runtime_state);
+    recorder::init_recorder(&subsys, state_ref.recorder.as_ref());
+    sqs::init_event_sources(&subsys, state_ref.clone(), req_tx.clone());
+    schedule::init_schedules(&subsys, state_ref.clone(), req_tx.clone());
+
     let mut app = Router::new()
+        .merge(sqs::api::routes().with_state(state_ref.clone()))
+        .merge(schedule::routes().with_state(state_ref.clone()))
+        .merge(websocket::routes(state_ref.websocket_api.as_deref()).with_state(state_ref.clone()))
         .merge(trigger_router::routes().with_state(state_ref.clone()))
         .nest(
             RUNTIME_EMULATOR_PATH,
-            runtime::routes().with_state(state_ref.clone()),
+            runtime::routes()
+                .merge(admin::routes())
+                .with_state(state_ref.clone()),
         )
         .layer(SetRequestIdLayer::new(
             x_request_id.clone(),
@@ -238,7 +319,7 @@ async fn start_server(
     }
     let app = app.with_state(state_ref);
 
-    if only_lambda_apis {
+    if let Some(lambda_env) = default_function_env {
         info!("");
         info!(
             "the flag --only_lambda_apis is active, the lambda function will not be started by Cargo Lambda"
@@ -247,10 +328,10 @@ async fn start_server(
         info!(
             "you MUST set these variables in the environment where you're running your function:"
         );
-        info!("AWS_LAMBDA_FUNCTION_VERSION=1");
-        info!("AWS_LAMBDA_FUNCTION_MEMORY_SIZE=4096");
+        for (key, value) in lambda_env {
+            info!("{key}={value}");
+        }
         info!("AWS_LAMBDA_RUNTIME_API={}", runtime_url);
-        info!("AWS_LAMBDA_FUNCTION_NAME={DEFAULT_PACKAGE_FUNCTION}");
     } else {
         let print_start_info = if init_default_function {
             // This call ignores any error sending the action.
@@ -341,7 +422,8 @@ async fn start_tls_proxy(
                 };
 
                 let builder = Builder::new(TokioExecutor::new());
-                let conn = builder.serve_connection(TokioIo::new(tls_stream), hyper_service);
+                let conn =
+                    builder.serve_connection_with_upgrades(TokioIo::new(tls_stream), hyper_service);
 
                 pin!(conn);
 
@@ -365,7 +447,7 @@ async fn start_tls_proxy(
 
 async fn proxy(
     connection_tracker: TaskTracker,
-    req: Request<Incoming>,
+    mut req: Request<Incoming>,
     addr: Arc<SocketAddr>,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
     let stream = TcpStream::connect(&*addr).await.unwrap();
@@ -378,11 +460,30 @@ async fn proxy(
         .await?;
 
     connection_tracker.spawn(async move {
-        if let Err(err) = conn.await {
+        if let Err(err) = conn.with_upgrades().await {
             println!("Connection failed: {:?}", err);
         }
     });
 
-    let resp = sender.send_request(req).await?;
+    // WebSocket connections upgrade both sides of the proxy,
+    // and copy the data between them after that.
+    let client_upgrade = hyper::upgrade::on(&mut req);
+    let mut resp = sender.send_request(req).await?;
+    if resp.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let server_upgrade = hyper::upgrade::on(&mut resp);
+        connection_tracker.spawn(async move {
+            let (client, server) = match tokio::try_join!(client_upgrade, server_upgrade) {
+                Ok(upgraded) => upgraded,
+                Err(error) => {
+                    error!(?error, "failed to upgrade the proxied connection");
+                    return;
+                }
+            };
+            let mut client = TokioIo::new(client);
+            let mut server = TokioIo::new(server);
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+        });
+    }
+
     Ok(resp.map(|b| b.boxed()))
 }
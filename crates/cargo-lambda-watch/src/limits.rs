@@ -0,0 +1,210 @@
+use crate::{
+    requests::FunctionError,
+    trigger_router::response_stream::{ERROR_BODY_TRAILER, ERROR_TYPE_TRAILER},
+};
+use axum::body::Body;
+use base64::{Engine as _, engine::general_purpose as b64};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Maximum size of the payload of a synchronous invocation.
+const SYNC_REQUEST_LIMIT: usize = 6_291_456;
+/// Maximum size of the payload of an asynchronous invocation.
+const ASYNC_REQUEST_LIMIT: usize = 262_144;
+/// Maximum size of a buffered response.
+const RESPONSE_LIMIT: usize = 6_291_556;
+/// Soft limit of the size of a streamed response.
+const STREAMED_RESPONSE_LIMIT: usize = 20_971_520;
+
+/// Error type that Lambda reports when a function's response is over the limit.
+pub(crate) const RESPONSE_TOO_LARGE_ERROR_TYPE: &str = "Function.ResponseSizeTooLarge";
+
+/// Payload size limits that the emulator enforces, like Lambda does.
+/// `None` means that the payload can be of any size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PayloadLimits {
+    pub sync_request: Option<usize>,
+    pub async_request: Option<usize>,
+    pub response: Option<usize>,
+    pub streamed_response: Option<usize>,
+}
+
+impl Default for PayloadLimits {
+    /// Limits that Lambda enforces:
+    /// https://docs.aws.amazon.com/lambda/latest/dg/gettingstarted-limits.html
+    fn default() -> Self {
+        PayloadLimits {
+            sync_request: Some(SYNC_REQUEST_LIMIT),
+            async_request: Some(ASYNC_REQUEST_LIMIT),
+            response: Some(RESPONSE_LIMIT),
+            streamed_response: Some(STREAMED_RESPONSE_LIMIT),
+        }
+    }
+}
+
+impl PayloadLimits {
+    /// Limits that accept payloads of any size.
+    pub(crate) fn unlimited() -> PayloadLimits {
+        PayloadLimits {
+            sync_request: None,
+            async_request: None,
+            response: None,
+            streamed_response: None,
+        }
+    }
+
+    /// Check the size of a buffered response.
+    /// It returns the error that fails the invocation when the response is too large.
+    pub(crate) fn check_response(&self, size: usize) -> Result<(), FunctionError> {
+        match self.response {
+            Some(limit) if size > limit => Err(response_too_large(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wrap the body of a streamed response, so the stream is interrupted
+    /// with a `Function.ResponseSizeTooLarge` error when it goes over the limit.
+    pub(crate) fn limit_stream(&self, body: Body) -> Body {
+        match self.streamed_response {
+            Some(limit) => Body::new(LimitedStream {
+                inner: body,
+                limit,
+                sent: 0,
+                done: false,
+            }),
+            None => body,
+        }
+    }
+}
+
+/// Check the size of an invocation's payload.
+/// It returns the limit that the payload goes over, if any.
+pub(crate) fn exceeded_limit(payload: &[u8], limit: Option<usize>) -> Option<usize> {
+    limit.filter(|limit| payload.len() > *limit)
+}
+
+fn response_too_large(limit: usize) -> FunctionError {
+    FunctionError::new(
+        RESPONSE_TOO_LARGE_ERROR_TYPE,
+        &format!("Response payload size exceeded maximum allowed payload size ({limit} bytes)."),
+    )
+}
+
+/// Response stream that ends with error trailers
+/// when the function sends more data than the limit allows.
+struct LimitedStream {
+    inner: Body,
+    limit: usize,
+    sent: usize,
+    done: bool,
+}
+
+impl HttpBody for LimitedStream {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let frame = match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(frame) => frame,
+        };
+
+        if let Some(data) = frame.as_ref().and_then(|f| f.as_ref().ok()?.data_ref()) {
+            this.sent += data.len();
+            if this.sent > this.limit {
+                this.done = true;
+                tracing::error!(
+                    limit = this.limit,
+                    "the function's response stream is too large"
+                );
+                return Poll::Ready(Some(Ok(Frame::trailers(error_trailers(this.limit)))));
+            }
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+fn error_trailers(limit: usize) -> HeaderMap {
+    let error = response_too_large(limit);
+    let body = serde_json::to_vec(&error).unwrap_or_default();
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert(
+        ERROR_TYPE_TRAILER,
+        HeaderValue::from_static(RESPONSE_TOO_LARGE_ERROR_TYPE),
+    );
+    if let Ok(body) = HeaderValue::try_from(b64::STANDARD.encode(body)) {
+        trailers.insert(ERROR_BODY_TRAILER, body);
+    }
+    trailers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use http_body_util::{BodyExt, StreamBody};
+
+    #[test]
+    fn test_exceeded_limit() {
+        let limits = PayloadLimits::default();
+        let payload = vec![0; ASYNC_REQUEST_LIMIT + 1];
+        assert_eq!(
+            exceeded_limit(&payload, limits.async_request),
+            Some(ASYNC_REQUEST_LIMIT)
+        );
+        assert_eq!(exceeded_limit(&payload, limits.sync_request), None);
+        assert_eq!(
+            exceeded_limit(&payload, PayloadLimits::unlimited().async_request),
+            None
+        );
+
+        let error = limits.check_response(RESPONSE_LIMIT + 1).unwrap_err();
+        assert_eq!(error.error_type, RESPONSE_TOO_LARGE_ERROR_TYPE);
+        assert!(limits.check_response(RESPONSE_LIMIT).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_limit_stream() {
+        let limits = PayloadLimits {
+            streamed_response: Some(8),
+            ..Default::default()
+        };
+
+        let body = Body::new(StreamBody::new(stream::iter(vec![
+            Ok::<_, axum::Error>(Frame::data(Bytes::from("hello"))),
+            Ok(Frame::data(Bytes::from("world"))),
+            Ok(Frame::data(Bytes::from("never sent"))),
+        ])));
+        let collected = limits.limit_stream(body).collect().await.unwrap();
+
+        let trailers = collected.trailers().cloned().unwrap();
+        assert_eq!(
+            trailers.get(ERROR_TYPE_TRAILER).unwrap(),
+            RESPONSE_TOO_LARGE_ERROR_TYPE
+        );
+        assert_eq!(collected.to_bytes(), Bytes::from("hello"));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::telemetry::{EventsApi, TelemetryEvent};
+use cargo_lambda_metadata::{
+    cargo::deploy::LoggingConfig,
+    lambda::{ApplicationLogLevel, LogFormat, SystemLogLevel},
+};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// Renders the output of a function, and the lines that the platform writes
+/// for every invocation, in the format of the function's logging configuration.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct LogFormatter {
+    format: LogFormat,
+    application_level: ApplicationLogLevel,
+    system_level: SystemLogLevel,
+}
+
+impl LogFormatter {
+    pub(crate) fn new(config: &LoggingConfig) -> LogFormatter {
+        LogFormatter {
+            format: config.log_format(),
+            application_level: config.application_log_level(),
+            system_level: config.system_log_level(),
+        }
+    }
+
+    pub(crate) fn is_text(&self) -> bool {
+        self.format == LogFormat::Text
+    }
+
+    /// Render a line that the function wrote to stdout or stderr.
+    ///
+    /// In text format, the line is returned as it is. In JSON format, JSON objects are
+    /// completed with the timestamp, level and request id, and any other line is wrapped
+    /// into a JSON record. It returns `None` when the line's level is below the
+    /// function's application log level.
+    pub(crate) fn function_line(&self, line: &str, request_id: Option<&str>) -> Option<String> {
+        if self.is_text() {
+            return Some(line.to_string());
+        }
+
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        let mut record = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(record)) => record,
+            _ => {
+                let mut record = Map::new();
+                record.insert("message".into(), Value::String(line.to_string()));
+                record
+            }
+        };
+
+        let level = record
+            .get("level")
+            .and_then(Value::as_str)
+            .and_then(parse_level);
+        if level.is_some_and(|level| level < self.application_level) {
+            return None;
+        }
+
+        if !record.contains_key("timestamp") {
+            record.insert("timestamp".into(), Value::String(timestamp()));
+        }
+        if !record.contains_key("level") {
+            record.insert("level".into(), Value::String("INFO".into()));
+        }
+        if let Some(request_id) = request_id {
+            if !record.contains_key("requestId") {
+                record.insert("requestId".into(), Value::String(request_id.into()));
+            }
+        }
+
+        Some(Value::Object(record).to_string())
+    }
+
+    /// Print the lines that the platform writes for an event, like `START` and `REPORT`.
+    ///
+    /// In JSON format, the event is printed as a single JSON record instead,
+    /// when the function's system log level includes `INFO` records.
+    pub(crate) fn print_platform(&self, event: &TelemetryEvent, text: &[String]) {
+        if self.is_text() {
+            for line in text {
+                println!("{line}");
+            }
+        } else if self.system_level <= SystemLogLevel::Info {
+            let record = event.record(EventsApi::Telemetry, &timestamp());
+            println!("{record}");
+        }
+    }
+}
+
+fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Level of a JSON log record. Besides Lambda's levels,
+/// it accepts the names that other logging libraries use.
+fn parse_level(level: &str) -> Option<ApplicationLogLevel> {
+    match level.to_ascii_uppercase().as_str() {
+        "WARNING" => Some(ApplicationLogLevel::Warn),
+        "CRITICAL" => Some(ApplicationLogLevel::Fatal),
+        level => ApplicationLogLevel::from_str(level).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_formatter(application_log_level: ApplicationLogLevel) -> LogFormatter {
+        LogFormatter::new(&LoggingConfig {
+            log_format: Some(LogFormat::Json),
+            application_log_level: Some(application_log_level),
+            system_log_level: None,
+        })
+    }
+
+    #[test]
+    fn test_text_lines() {
+        let formatter = LogFormatter::default();
+        assert_eq!(
+            formatter.function_line("hello world", Some("req-id")),
+            Some("hello world".into())
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let formatter = json_formatter(ApplicationLogLevel::Info);
+
+        let line = formatter
+            .function_line("hello world", Some("req-id"))
+            .unwrap();
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["message"], "hello world");
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["requestId"], "req-id");
+        assert!(record["timestamp"].is_string());
+
+        let line = formatter
+            .function_line(
+                r#"{"timestamp":"2024-01-01T00:00:00Z","level":"WARN","fields":{"message":"hi"}}"#,
+                None,
+            )
+            .unwrap();
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["timestamp"], "2024-01-01T00:00:00Z");
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["fields"]["message"], "hi");
+        assert!(record.get("requestId").is_none());
+
+        assert_eq!(formatter.function_line("", Some("req-id")), None);
+    }
+
+    #[test]
+    fn test_level_filtering() {
+        let formatter = json_formatter(ApplicationLogLevel::Warn);
+        assert_eq!(formatter.function_line(r#"{"level":"info"}"#, None), None);
+        assert_eq!(formatter.function_line(r#"{"level":"DEBUG"}"#, None), None);
+        assert!(
+            formatter
+                .function_line(r#"{"level":"warning"}"#, None)
+                .is_some()
+        );
+        assert!(
+            formatter
+                .function_line(r#"{"level":"ERROR"}"#, None)
+                .is_some()
+        );
+        // Lines with unknown levels are never filtered.
+        assert!(
+            formatter
+                .function_line(r#"{"level":"notice"}"#, None)
+                .is_some()
+        );
+    }
+}
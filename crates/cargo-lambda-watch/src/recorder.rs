@@ -0,0 +1,442 @@
+use crate::{
+    error::ServerError,
+    requests::LambdaResponse,
+    runtime::{
+        LAMBDA_RUNTIME_AWS_REQUEST_ID, LAMBDA_RUNTIME_CLIENT_CONTEXT,
+        LAMBDA_RUNTIME_COGNITO_IDENTITY,
+    },
+    trigger_router::response_stream::{ERROR_BODY_TRAILER, ERROR_TYPE_TRAILER},
+};
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode},
+};
+use base64::{Engine as _, engine::general_purpose as b64};
+use bytes::{Bytes, BytesMut};
+use cargo_lambda_metadata::cargo::watch::record::InvocationRecord;
+use chrono::{SecondsFormat, Utc};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use std::{
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+};
+use tokio_graceful_shutdown::{SubsystemBuilder, SubsystemHandle};
+use tracing::error;
+
+/// Headers that the runtime forwards to the function,
+/// and that need to be sent again to replay an invocation.
+const RECORDED_HEADERS: [&str; 2] = [
+    LAMBDA_RUNTIME_CLIENT_CONTEXT,
+    LAMBDA_RUNTIME_COGNITO_IDENTITY,
+];
+
+/// Recorder that appends every invocation that the emulator
+/// processes to a file, one JSON record per line.
+#[derive(Clone)]
+pub(crate) struct Recorder {
+    tx: UnboundedSender<InvocationRecord>,
+    /// Writer of the recording file, until the recorder subsystem starts.
+    writer: Arc<Mutex<Option<RecordWriter>>>,
+}
+
+impl Recorder {
+    /// Open the recording file. Records are appended if the file already exists,
+    /// and they are written after the recorder subsystem starts, see [`init_recorder`].
+    pub(crate) fn new(path: &Path) -> Result<Recorder, ServerError> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| ServerError::OpenRecording(path.to_path_buf(), e))?;
+
+        let (tx, rx) = unbounded_channel::<InvocationRecord>();
+        let writer = RecordWriter {
+            file: File::from_std(file),
+            rx,
+        };
+
+        Ok(Recorder {
+            tx,
+            writer: Arc::new(Mutex::new(Some(writer))),
+        })
+    }
+
+    /// Read the event and headers of an invocation before it's sent to the function.
+    /// The body of the request is buffered, and put back into the request.
+    pub(crate) async fn start(
+        &self,
+        function_name: &str,
+        req: Request<Body>,
+    ) -> Result<(PendingRecord, Request<Body>), ServerError> {
+        let (parts, body) = req.into_parts();
+        let payload = body
+            .collect()
+            .await
+            .map_err(ServerError::DataDeserialization)?
+            .to_bytes();
+
+        let request_id = parts
+            .headers
+            .get(LAMBDA_RUNTIME_AWS_REQUEST_ID)
+            .and_then(|h| h.to_str().ok())
+            .map(String::from);
+
+        let headers = RECORDED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = parts.headers.get(*name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        let record = InvocationRecord {
+            function_name: function_name.to_string(),
+            request_id,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            event: InvocationRecord::decode_payload(&payload),
+            headers,
+            ..Default::default()
+        };
+
+        let pending = PendingRecord {
+            record,
+            started_at: Instant::now(),
+            tx: self.tx.clone(),
+        };
+
+        Ok((pending, Request::from_parts(parts, Body::from(payload))))
+    }
+}
+
+/// Start the subsystem that writes the records into the recording file.
+pub(crate) fn init_recorder(subsys: &SubsystemHandle, recorder: Option<&Recorder>) {
+    let writer = recorder.and_then(|r| r.writer.lock().ok()?.take());
+    if let Some(writer) = writer {
+        subsys.start(SubsystemBuilder::new("invocation recorder", move |s| {
+            writer.run(s)
+        }));
+    }
+}
+
+struct RecordWriter {
+    file: File,
+    rx: UnboundedReceiver<InvocationRecord>,
+}
+
+impl RecordWriter {
+    async fn run(mut self, subsys: SubsystemHandle) -> Result<(), ServerError> {
+        loop {
+            tokio::select! {
+                Some(record) = self.rx.recv() => self.write(record).await,
+                _ = subsys.on_shutdown_requested() => break,
+            }
+        }
+
+        // Write the records that are still in the channel before the emulator stops.
+        self.rx.close();
+        while let Some(record) = self.rx.recv().await {
+            self.write(record).await;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, record: InvocationRecord) {
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(error) => {
+                error!(?error, "failed to serialize invocation record");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(error) = self.file.write_all(&line).await {
+            error!(?error, "failed to write invocation record");
+            return;
+        }
+        if let Err(error) = self.file.flush().await {
+            error!(?error, "failed to flush invocation record");
+        }
+    }
+}
+
+/// Invocation waiting for the function's response to be recorded.
+pub(crate) struct PendingRecord {
+    record: InvocationRecord,
+    started_at: Instant,
+    tx: UnboundedSender<InvocationRecord>,
+}
+
+impl PendingRecord {
+    /// Wrap the body of the function's response, so the
+    /// invocation is recorded when the body is consumed.
+    pub(crate) fn finish_with(self, resp: LambdaResponse) -> LambdaResponse {
+        let status = resp
+            .extensions()
+            .get::<StatusCode>()
+            .cloned()
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+        resp.map(|body| {
+            Body::new(RecordingBody {
+                inner: body,
+                status,
+                buffer: BytesMut::new(),
+                stream_error: None,
+                pending: Some(self),
+            })
+        })
+    }
+
+    fn write(mut self, status: StatusCode, payload: &[u8], stream_error: Option<Value>) {
+        let payload = (!payload.is_empty()).then(|| InvocationRecord::decode_payload(payload));
+
+        self.record.status = status.as_u16();
+        self.record.duration_ms = self.started_at.elapsed().as_millis() as u64;
+        if stream_error.is_some() {
+            self.record.error = stream_error;
+        } else if status == StatusCode::OK {
+            self.record.response = payload;
+        } else {
+            self.record.error = payload;
+        }
+
+        if self.tx.send(self.record).is_err() {
+            error!("failed to record invocation, the recorder is not running");
+        }
+    }
+}
+
+/// Response body that keeps a copy of the data that goes through it,
+/// and records the invocation when the body ends.
+struct RecordingBody {
+    inner: Body,
+    status: StatusCode,
+    buffer: BytesMut,
+    stream_error: Option<Value>,
+    pending: Option<PendingRecord>,
+}
+
+impl RecordingBody {
+    fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.write(self.status, &self.buffer, self.stream_error.take());
+        }
+    }
+}
+
+impl HttpBody for RecordingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        let frame = match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(frame) => frame,
+        };
+
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.buffer.extend_from_slice(data);
+                } else if let Some(trailers) = frame.trailers_ref() {
+                    this.stream_error = stream_error(trailers);
+                }
+            }
+            Some(Err(_)) | None => this.finish(),
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for RecordingBody {
+    fn drop(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+
+        // Nobody reads the rest of the response, like when the client disconnects.
+        // Keep reading it, so the invocation is recorded when the function completes it.
+        let rest = RecordingBody {
+            inner: std::mem::take(&mut self.inner),
+            status: self.status,
+            buffer: std::mem::take(&mut self.buffer),
+            stream_error: self.stream_error.take(),
+            pending: Some(pending),
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    let _ = rest.collect().await;
+                });
+            }
+            Err(_) => {
+                let mut rest = rest;
+                rest.finish();
+            }
+        }
+    }
+}
+
+/// Read the error that a function reported in the trailers of its stream, if any.
+fn stream_error(trailers: &HeaderMap) -> Option<Value> {
+    let error_type = trailers.get(ERROR_TYPE_TRAILER)?.to_str().ok()?;
+
+    let body = trailers
+        .get(ERROR_BODY_TRAILER)
+        .and_then(|b| b64::STANDARD.decode(b.as_bytes()).ok())
+        .filter(|b| !b.is_empty());
+
+    match body {
+        Some(body) => Some(InvocationRecord::decode_payload(&body)),
+        None => Some(json!({ "errorType": error_type })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use http_body_util::StreamBody;
+    use std::time::Duration;
+    use tokio_graceful_shutdown::Toplevel;
+
+    fn recorder() -> (Recorder, UnboundedReceiver<InvocationRecord>) {
+        let (tx, rx) = unbounded_channel();
+        let recorder = Recorder {
+            tx,
+            writer: Arc::default(),
+        };
+        (recorder, rx)
+    }
+
+    #[tokio::test]
+    async fn test_record_response() {
+        let (recorder, mut rx) = recorder();
+
+        let req = Request::builder()
+            .header(LAMBDA_RUNTIME_AWS_REQUEST_ID, "request-1")
+            .header(LAMBDA_RUNTIME_CLIENT_CONTEXT, r#"{"custom":{}}"#)
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"command":"hi"}"#))
+            .unwrap();
+
+        let (pending, req) = recorder.start("basic-lambda", req).await.unwrap();
+        let event = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(event, Bytes::from(r#"{"command":"hi"}"#));
+
+        let mut resp = Request::new(Body::from(r#"{"message":"hello"}"#));
+        resp.extensions_mut().insert(StatusCode::OK);
+
+        let body = pending.finish_with(resp).into_body();
+        let body = body.collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from(r#"{"message":"hello"}"#));
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.function_name, "basic-lambda");
+        assert_eq!(record.request_id.as_deref(), Some("request-1"));
+        assert_eq!(record.event, json!({"command": "hi"}));
+        assert_eq!(record.status, 200);
+        assert_eq!(record.response, Some(json!({"message": "hello"})));
+        assert_eq!(record.error, None);
+        assert_eq!(record.headers.len(), 1);
+        assert_eq!(
+            record.headers.get(LAMBDA_RUNTIME_CLIENT_CONTEXT).unwrap(),
+            r#"{"custom":{}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_errors() {
+        let (recorder, mut rx) = recorder();
+
+        let (pending, _) = recorder
+            .start("basic-lambda", Request::new(Body::from("{}")))
+            .await
+            .unwrap();
+        let mut resp = Request::new(Body::from(r#"{"errorType":"Oops"}"#));
+        resp.extensions_mut()
+            .insert(StatusCode::INTERNAL_SERVER_ERROR);
+
+        // Responses that nobody reads are recorded when the function completes them.
+        drop(pending.finish_with(resp));
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.status, 500);
+        assert_eq!(record.response, None);
+        assert_eq!(record.error, Some(json!({"errorType": "Oops"})));
+
+        let (pending, _) = recorder
+            .start("basic-lambda", Request::new(Body::from("{}")))
+            .await
+            .unwrap();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert(ERROR_TYPE_TRAILER, "Oops".parse().unwrap());
+        let body = Body::new(StreamBody::new(stream::iter(vec![
+            Ok::<_, axum::Error>(Frame::data(Bytes::from("partial"))),
+            Ok(Frame::trailers(trailers)),
+        ])));
+        let mut resp = Request::new(body);
+        resp.extensions_mut().insert(StatusCode::OK);
+
+        let _ = pending.finish_with(resp).into_body().collect().await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.status, 200);
+        assert_eq!(record.response, None);
+        assert_eq!(record.error, Some(json!({"errorType": "Oops"})));
+    }
+
+    #[tokio::test]
+    async fn test_write_records_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("invocations.jsonl");
+        let recorder = Recorder::new(&path).unwrap();
+
+        for request_id in ["request-1", "request-2"] {
+            let record = InvocationRecord {
+                request_id: Some(request_id.into()),
+                ..Default::default()
+            };
+            recorder.tx.send(record).unwrap();
+        }
+
+        Toplevel::new(move |s| async move {
+            init_recorder(&s, Some(&recorder));
+            s.request_shutdown();
+        })
+        .handle_shutdown_requests(Duration::from_secs(1))
+        .await
+        .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("request-2"));
+    }
+}
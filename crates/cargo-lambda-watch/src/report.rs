@@ -0,0 +1,157 @@
+use serde::Serialize;
+use std::{fmt::Write, time::Duration};
+
+/// How an invocation ended, as Lambda reports it in the `REPORT` line.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum InvocationStatus {
+    /// The function returned a response
+    Success,
+    /// The function returned an error, or the platform failed the invocation
+    /// with the given error type, like when the function runs out of memory
+    Error(Option<String>),
+    /// The function didn't respond before its timeout
+    Timeout,
+}
+
+impl InvocationStatus {
+    pub(crate) fn is_success(&self) -> bool {
+        matches!(self, Self::Success)
+    }
+
+    /// Status of the invocation in the `platform.report` events of the Telemetry API.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Error(_) => "error",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+/// Measurements that Lambda reports at the end of every invocation.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReportMetrics {
+    pub duration_ms: f64,
+    pub billed_duration_ms: u64,
+    #[serde(rename = "memorySizeMB")]
+    pub memory_size_mb: u32,
+    /// Peak memory used by the function's process. It's only
+    /// available on Linux, for processes started by the emulator.
+    #[serde(rename = "maxMemoryUsedMB", skip_serializing_if = "Option::is_none")]
+    pub max_memory_used_mb: Option<u64>,
+    /// Time that the function took to initialize. It's only reported
+    /// in the first invocation that an execution environment processes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_duration_ms: Option<f64>,
+}
+
+impl ReportMetrics {
+    pub(crate) fn new(
+        duration: Duration,
+        memory_size_mb: u32,
+        max_memory_used: Option<u64>,
+        init_duration: Option<Duration>,
+    ) -> ReportMetrics {
+        // Functions on custom runtimes are billed for their initialization too.
+        let billed = duration + init_duration.unwrap_or_default();
+
+        ReportMetrics {
+            duration_ms: millis(duration),
+            billed_duration_ms: millis(billed).ceil().max(1.0) as u64,
+            memory_size_mb,
+            max_memory_used_mb: max_memory_used.map(|bytes| bytes.div_ceil(1024 * 1024)),
+            init_duration_ms: init_duration.map(millis),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 100_000.0).round() / 100.0
+}
+
+pub(crate) fn start_line(request_id: &str) -> String {
+    format!("START RequestId: {request_id} Version: $LATEST")
+}
+
+pub(crate) fn end_line(request_id: &str) -> String {
+    format!("END RequestId: {request_id}")
+}
+
+/// `REPORT` line with the same fields, and separators, that Lambda writes in CloudWatch.
+pub(crate) fn report_line(
+    request_id: &str,
+    metrics: &ReportMetrics,
+    status: &InvocationStatus,
+) -> String {
+    let mut line = format!(
+        "REPORT RequestId: {request_id}\tDuration: {:.2} ms\tBilled Duration: {} ms\tMemory Size: {} MB\t",
+        metrics.duration_ms, metrics.billed_duration_ms, metrics.memory_size_mb
+    );
+
+    if let Some(used) = metrics.max_memory_used_mb {
+        let _ = write!(line, "Max Memory Used: {used} MB\t");
+    }
+    if let Some(init) = metrics.init_duration_ms {
+        let _ = write!(line, "Init Duration: {init:.2} ms\t");
+    }
+
+    match status {
+        InvocationStatus::Timeout => line.push_str("Status: timeout"),
+        InvocationStatus::Error(Some(error_type)) => {
+            let _ = write!(line, "Status: error\tError Type: {error_type}");
+        }
+        _ => {}
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_line() {
+        let metrics = ReportMetrics::new(
+            Duration::from_micros(12_345),
+            128,
+            Some(20 * 1024 * 1024 + 1),
+            Some(Duration::from_micros(100_250)),
+        );
+        assert_eq!(metrics.duration_ms, 12.35);
+        assert_eq!(metrics.billed_duration_ms, 113);
+        assert_eq!(metrics.max_memory_used_mb, Some(21));
+
+        assert_eq!(
+            report_line("req-id", &metrics, &InvocationStatus::Success),
+            "REPORT RequestId: req-id\tDuration: 12.35 ms\tBilled Duration: 113 ms\tMemory Size: 128 MB\tMax Memory Used: 21 MB\tInit Duration: 100.25 ms\t"
+        );
+
+        let metrics = ReportMetrics::new(Duration::from_secs(3), 128, None, None);
+        assert_eq!(
+            report_line("req-id", &metrics, &InvocationStatus::Timeout),
+            "REPORT RequestId: req-id\tDuration: 3000.00 ms\tBilled Duration: 3000 ms\tMemory Size: 128 MB\tStatus: timeout"
+        );
+
+        let status = InvocationStatus::Error(Some("Runtime.OutOfMemory".into()));
+        assert!(
+            report_line("req-id", &metrics, &status)
+                .ends_with("Status: error\tError Type: Runtime.OutOfMemory")
+        );
+    }
+
+    #[test]
+    fn test_metrics_json() {
+        let metrics = ReportMetrics::new(Duration::from_millis(5), 256, None, None);
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "durationMs": 5.0,
+                "billedDurationMs": 5,
+                "memorySizeMB": 256,
+            })
+        );
+    }
+}
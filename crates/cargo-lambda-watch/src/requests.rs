@@ -36,6 +36,15 @@ pub struct StreamingPrelude {
     pub cookies: Vec<String>,
 }
 
+/// Error that a function reports with the `init/error` endpoint,
+/// or that an extension reports with the `init/error` and `exit/error` endpoints.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorRequest {
+    pub error_message: Option<String>,
+    pub error_type: Option<String>,
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct EventsRequest {
     pub events: Vec<String>,
@@ -87,7 +96,7 @@ pub enum NextEvent {
 }
 
 impl NextEvent {
-    pub fn invoke(id: &str, event: &InvokeRequest) -> NextEvent {
+    pub fn invoke(id: &str, deadline_ms: u64, event: &InvokeRequest) -> NextEvent {
         let tracing_id = event
             .req
             .headers()
@@ -96,22 +105,22 @@ impl NextEvent {
             .unwrap_or_default();
 
         let e = InvokeEvent {
+            deadline_ms,
             request_id: id.to_string(),
             invoked_function_arn: event.function_name.clone(),
             tracing: Tracing {
                 r#type: AWS_XRAY_TRACE_HEADER.to_string(),
                 value: tracing_id.to_string(),
             },
-            ..Default::default()
         };
 
         NextEvent::Invoke(e)
     }
 
-    pub fn shutdown(reason: &str) -> NextEvent {
+    pub fn shutdown(reason: &str, deadline_ms: u64) -> NextEvent {
         NextEvent::Shutdown(ShutdownEvent {
             shutdown_reason: reason.into(),
-            ..Default::default()
+            deadline_ms,
         })
     }
 
@@ -123,7 +132,48 @@ impl NextEvent {
     }
 }
 
-#[allow(dead_code)]
+/// Reason of the SHUTDOWN events sent after an extension fails.
+pub const SHUTDOWN_REASON_FAILURE: &str = "FAILURE";
+
+/// Error type of the invocations that the function doesn't complete before its timeout.
+pub const TIMEOUT_ERROR_TYPE: &str = "Sandbox.Timedout";
+
+/// Error type of the invocations that fail because the function's process exits.
+pub const EXIT_ERROR_TYPE: &str = "Runtime.ExitError";
+
+/// Error type of the invocations that fail because the function
+/// reloads after code changes, with the `fail` reload strategy.
+pub const RELOADING_ERROR_TYPE: &str = "Runtime.FunctionReloading";
+
+/// Error that the emulator reports on behalf of a function,
+/// using the same format that the Lambda runtime uses.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionError {
+    pub error_type: String,
+    pub error_message: String,
+}
+
+impl FunctionError {
+    pub fn new(error_type: &str, error_message: &str) -> FunctionError {
+        FunctionError {
+            error_type: error_type.into(),
+            error_message: error_message.into(),
+        }
+    }
+
+    /// Convert the error into the response that the
+    /// function would send to the runtime emulator.
+    pub fn into_lambda_response(self) -> LambdaResponse {
+        let body = serde_json::to_vec(&self).unwrap_or_default();
+
+        let mut resp = Request::new(Body::from(body));
+        resp.extensions_mut()
+            .insert(StatusCode::INTERNAL_SERVER_ERROR);
+        resp
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct LogBuffering {
@@ -132,7 +182,18 @@ pub(crate) struct LogBuffering {
     pub max_items: usize,
 }
 
-#[allow(dead_code)]
+impl Default for LogBuffering {
+    /// Buffering configuration that Lambda uses
+    /// when the subscription doesn't include one.
+    fn default() -> Self {
+        LogBuffering {
+            timeout_ms: 1_000,
+            max_bytes: 262_144,
+            max_items: 10_000,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct EventsDestination {
     pub protocol: String,
@@ -1,12 +1,33 @@
-use crate::{RefRuntimeState, error::ServerError, requests::*};
-use axum::{Json, body::Body, extract::State, http::Request, response::Response};
+use crate::{
+    RefRuntimeState, error::ServerError, requests::*, state::environment_function_name,
+    telemetry::EventsApi, watcher::env::FUNCTION_HANDLER,
+};
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    http::Request,
+    response::{IntoResponse, Response},
+};
 use http_body_util::BodyExt;
-use hyper::HeaderMap;
+use hyper::{HeaderMap, StatusCode};
 use serde::{Serialize, de::DeserializeOwned};
-use tokio::sync::mpsc;
-use tracing::{debug, warn};
+use serde_json::json;
+use tracing::debug;
 
 const EXTENSION_ID_HEADER: &str = "Lambda-Extension-Identifier";
+const EXTENSION_NAME_HEADER: &str = "Lambda-Extension-Name";
+const EXTENSION_ERROR_TYPE_HEADER: &str = "Lambda-Extension-Function-Error-Type";
+
+/// Events that extensions can register for.
+const EXTENSION_EVENTS: [&str; 2] = ["INVOKE", "SHUTDOWN"];
+
+/// Phase of the extension's lifecycle when it reports an error.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ExtensionPhase {
+    Init,
+    Exit,
+}
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -21,18 +42,41 @@ pub(crate) async fn register_extension(
     State(state): State<RefRuntimeState>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
+    process_register_extension(&state, None, req).await
+}
+
+/// Register an extension that the emulator started next to a function's process.
+/// The extension only receives the INVOKE events of that execution environment.
+pub(crate) async fn register_environment_extension(
+    State(state): State<RefRuntimeState>,
+    Path(environment): Path<String>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    process_register_extension(&state, Some(environment), req).await
+}
+
+async fn process_register_extension(
+    state: &RefRuntimeState,
+    environment: Option<String>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    let default_function_name = environment
+        .as_deref()
+        .map(environment_function_name)
+        .unwrap_or("function-name");
+
     let response_body = serde_json::to_vec(&RegisterResponse {
         function_name: extract_header_with_default(
             req.headers(),
             "cargo-lambda-extension-function-name",
-            "function-name",
+            default_function_name,
         ),
         function_version: extract_header_with_default(
             req.headers(),
             "cargo-lambda-extension-function-version",
             "function-version",
         ),
-        handler: "bootstrap".to_string(),
+        handler: FUNCTION_HANDLER.to_string(),
         account_id: Some(extract_header_with_default(
             req.headers(),
             "cargo-lambda-extension-account-id",
@@ -40,10 +84,33 @@ pub(crate) async fn register_extension(
         )),
     })?;
 
+    let name = req
+        .headers()
+        .get(EXTENSION_NAME_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(String::from);
+
     let payload: EventsRequest = extract_json(req).await?;
-    debug!(?payload, "registering extension");
+    debug!(?name, ?environment, ?payload, "registering extension");
 
-    let extension_id = state.ext_cache.register(payload.events).await;
+    if let Some(event) = payload
+        .events
+        .iter()
+        .find(|e| !EXTENSION_EVENTS.contains(&e.as_str()))
+    {
+        return extension_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidEventType",
+            &format!(
+                "invalid event type `{event}`, extensions can register for INVOKE and SHUTDOWN"
+            ),
+        );
+    }
+
+    let extension_id = state
+        .ext_cache
+        .register(name, payload.events, environment)
+        .await;
     let resp = Response::builder()
         .status(200)
         .header(EXTENSION_ID_HEADER, extension_id)
@@ -55,40 +122,148 @@ pub(crate) async fn register_extension(
 pub(crate) async fn next_extension_event(
     State(state): State<RefRuntimeState>,
     req: Request<Body>,
-) -> Result<Json<NextEvent>, ServerError> {
+) -> Result<Response<Body>, ServerError> {
     let extension_id = match req.headers().get(EXTENSION_ID_HEADER) {
         None => Err(ServerError::MissingExtensionIdHeader)?,
-        Some(id) => id.to_str().unwrap(),
+        Some(id) => id.to_str().unwrap().to_string(),
     };
 
     debug!(%extension_id, "extension waiting for next event");
 
-    let (tx, mut rx) = mpsc::channel::<NextEvent>(100);
-    state.ext_cache.set_senders(extension_id, tx).await;
+    match state.ext_cache.next_event(&extension_id).await {
+        Ok(event) => Ok(Json(event).into_response()),
+        Err(ServerError::UnknownExtension(_)) => unknown_extension(&extension_id),
+        Err(error) => Err(error),
+    }
+}
+
+pub(crate) async fn extension_init_error(
+    State(state): State<RefRuntimeState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    report_extension_error(&state, req, ExtensionPhase::Init).await
+}
+
+pub(crate) async fn extension_exit_error(
+    State(state): State<RefRuntimeState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    report_extension_error(&state, req, ExtensionPhase::Exit).await
+}
 
-    match rx.recv().await {
-        None => Err(ServerError::NoExtensionEvent),
-        Some(event) => {
-            state.ext_cache.clear(extension_id).await;
-            Ok(Json(event))
+/// Fail the execution environments with the error that an extension reports.
+async fn report_extension_error(
+    state: &RefRuntimeState,
+    req: Request<Body>,
+    phase: ExtensionPhase,
+) -> Result<Response<Body>, ServerError> {
+    let extension_id = match req.headers().get(EXTENSION_ID_HEADER).map(|id| id.to_str()) {
+        None => Err(ServerError::MissingExtensionIdHeader)?,
+        Some(Ok(id)) => id.to_string(),
+        Some(Err(_)) => {
+            return extension_error(
+                StatusCode::BAD_REQUEST,
+                "InvalidRequestFormat",
+                &format!("invalid {EXTENSION_ID_HEADER} header"),
+            );
         }
+    };
+    if !state.ext_cache.is_registered(&extension_id).await {
+        return unknown_extension(&extension_id);
     }
+
+    let Some(error_type) = req
+        .headers()
+        .get(EXTENSION_ERROR_TYPE_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(String::from)
+    else {
+        return extension_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidRequestFormat",
+            &format!("missing {EXTENSION_ERROR_TYPE_HEADER} header"),
+        );
+    };
+
+    // The error body is optional, extensions can report only the error type.
+    let body = extract_json::<ErrorRequest>(req).await.unwrap_or_default();
+    let message = body.error_message.unwrap_or_else(|| match phase {
+        ExtensionPhase::Init => "the extension failed to initialize".into(),
+        ExtensionPhase::Exit => "the extension exited with an error".into(),
+    });
+    debug!(%extension_id, ?phase, %error_type, ?body.error_type, "extension reported an error");
+
+    let error = FunctionError::new(&error_type, &message);
+    state.fail_extension(&extension_id, error).await;
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::from(json!({ "status": "OK" }).to_string()))
+        .map_err(ServerError::ResponseBuild)
 }
 
-pub(crate) async fn subcribe_extension_events(
-    State(_state): State<RefRuntimeState>,
+fn unknown_extension(extension_id: &str) -> Result<Response<Body>, ServerError> {
+    extension_error(
+        StatusCode::FORBIDDEN,
+        "Extension.UnknownExtensionIdentifier",
+        &format!("unknown extension identifier {extension_id}"),
+    )
+}
+
+/// Error response with the format that the Extensions API uses.
+fn extension_error(
+    status: StatusCode,
+    error_type: &str,
+    message: &str,
+) -> Result<Response<Body>, ServerError> {
+    let body = json!({
+        "errorMessage": message,
+        "errorType": error_type,
+    });
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(body.to_string()))
+        .map_err(ServerError::ResponseBuild)
+}
+
+pub(crate) async fn subscribe_logs_api(
+    State(state): State<RefRuntimeState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    subcribe_extension_events(&state, req, EventsApi::Logs).await
+}
+
+pub(crate) async fn subscribe_telemetry_api(
+    State(state): State<RefRuntimeState>,
     req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    subcribe_extension_events(&state, req, EventsApi::Telemetry).await
+}
+
+async fn subcribe_extension_events(
+    state: &RefRuntimeState,
+    req: Request<Body>,
+    api: EventsApi,
 ) -> Result<Response<Body>, ServerError> {
     let extension_id = match req.headers().get(EXTENSION_ID_HEADER) {
         None => Err(ServerError::MissingExtensionIdHeader)?,
         Some(id) => id.to_str().unwrap().to_string(),
     };
+    let environment = match state.ext_cache.environment(&extension_id).await {
+        Ok(environment) => environment,
+        Err(ServerError::UnknownExtension(_)) => return unknown_extension(&extension_id),
+        Err(error) => return Err(error),
+    };
     let payload: SubcribeEvent = extract_json(req).await?;
 
-    debug!(%extension_id, ?payload.types, "received events subscription request");
-    warn!(%extension_id, ?payload.types, "!!! Events subcription is not supported at the moment !!!");
+    debug!(%extension_id, ?api, ?payload.types, ?payload.destination, "received events subscription request");
+    state
+        .telemetry
+        .subscribe(&extension_id, environment, api, payload)
+        .await;
 
-    Ok(Response::new(Body::empty()))
+    Ok(Response::new(Body::from("OK")))
 }
 
 /// Extract JSON manually instead of using Axum
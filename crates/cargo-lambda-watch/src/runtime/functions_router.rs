@@ -1,9 +1,12 @@
 use crate::{
     RefRuntimeState,
     error::ServerError,
+    logs::LogFormatter,
+    report::{self, InvocationStatus},
     requests::*,
     runtime::LAMBDA_RUNTIME_XRAY_TRACE_HEADER,
-    state::{RequestCache, ResponseCache},
+    state::{ProcessFailure, environment_function_name},
+    telemetry::TelemetryEvent,
 };
 use axum::{
     body::Body,
@@ -12,9 +15,13 @@ use axum::{
     response::Response,
 };
 use base64::{Engine as _, engine::general_purpose as b64};
-use cargo_lambda_metadata::DEFAULT_PACKAGE_FUNCTION;
+use cargo_lambda_metadata::{DEFAULT_PACKAGE_FUNCTION, lambda::Timeout};
+use chrono::{SecondsFormat, Utc};
 use http::request::Parts;
-use tracing::debug;
+use http_body_util::BodyExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::AbortHandle;
+use tracing::{debug, error};
 
 use super::LAMBDA_RUNTIME_AWS_REQUEST_ID;
 
@@ -22,13 +29,19 @@ pub(crate) const LAMBDA_RUNTIME_CLIENT_CONTEXT: &str = "lambda-runtime-client-co
 pub(crate) const LAMBDA_RUNTIME_COGNITO_IDENTITY: &str = "lambda-runtime-cognito-identity";
 pub(crate) const LAMBDA_RUNTIME_DEADLINE_MS: &str = "lambda-runtime-deadline-ms";
 pub(crate) const LAMBDA_RUNTIME_FUNCTION_ARN: &str = "lambda-runtime-invoked-function-arn";
+pub(crate) const LAMBDA_RUNTIME_FUNCTION_RESPONSE_MODE: &str =
+    "lambda-runtime-function-response-mode";
+const LAMBDA_RUNTIME_FUNCTION_ERROR_TYPE: &str = "lambda-runtime-function-error-type";
+
+/// Error type of the initialization errors that don't report their type.
+const UNKNOWN_ERROR_TYPE: &str = "Runtime.Unknown";
 
 pub(crate) async fn next_request(
     State(state): State<RefRuntimeState>,
-    Path(function_name): Path<String>,
+    Path(environment): Path<String>,
     parts: Parts,
 ) -> Result<Response<Body>, ServerError> {
-    process_next_request(&state, &function_name, parts).await
+    process_next_request(&state, &environment, parts).await
 }
 
 pub(crate) async fn bare_next_request(
@@ -40,14 +53,18 @@ pub(crate) async fn bare_next_request(
 
 pub(crate) async fn process_next_request(
     state: &RefRuntimeState,
-    function_name: &str,
+    environment: &str,
     parts: Parts,
 ) -> Result<Response<Body>, ServerError> {
-    let function_name = if function_name.is_empty() {
+    let environment = if environment.is_empty() {
         DEFAULT_PACKAGE_FUNCTION
     } else {
-        function_name
+        environment
     };
+    let function_name = environment_function_name(environment);
+
+    // The first request for an event means that the function finished its initialization.
+    state.processes.init_done(environment).await;
 
     let req_id = parts
         .headers
@@ -56,26 +73,67 @@ pub(crate) async fn process_next_request(
 
     let mut builder = Response::builder()
         .header(LAMBDA_RUNTIME_AWS_REQUEST_ID, req_id)
-        .header(LAMBDA_RUNTIME_DEADLINE_MS, 600_000_u32)
         .header(LAMBDA_RUNTIME_FUNCTION_ARN, "function-arn");
 
-    let resp = match state.req_cache.pop(function_name).await {
+    // Environments that drain their invocations before reloading don't pick up new ones.
+    let invoke = tokio::select! {
+        biased;
+        _ = state.processes.draining(environment) => {
+            state.processes.drained(environment).await;
+            None
+        }
+        invoke = state.req_cache.pop(function_name) => invoke,
+    };
+
+    let resp = match invoke {
         None => builder.status(StatusCode::NO_CONTENT).body(Body::empty()),
         Some(invoke) => {
             let req_id = req_id
                 .to_str()
                 .map_err(ServerError::InvalidRequestIdHeader)?;
 
-            debug!(req_id = ?req_id, function = ?function_name, "processing request");
-            let next_event = NextEvent::invoke(req_id, &invoke);
-            state.ext_cache.send_event(next_event).await?;
+            let settings = state.functions.get(function_name).await;
+            let deadline_ms = (SystemTime::now() + settings.deadline())
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            builder = builder.header(LAMBDA_RUNTIME_DEADLINE_MS, deadline_ms);
 
+            debug!(req_id = ?req_id, function = ?function_name, timeout = ?settings.timeout, "processing request");
+            let start = TelemetryEvent::PlatformStart {
+                request_id: req_id.to_string(),
+            };
+            LogFormatter::new(&settings.logging)
+                .print_platform(&start, &[report::start_line(req_id)]);
+            let next_event = NextEvent::invoke(req_id, deadline_ms, &invoke);
+            state.ext_cache.send_event(next_event, environment).await;
+            state.telemetry.send_event(start, environment).await;
+
+            // Keep a copy of the request, so it can be sent again if the function reloads.
             let (parts, body) = invoke.req.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(ServerError::DataDeserialization)?
+                .to_bytes();
+            let headers = parts.headers.clone();
+            let request = Request::from_parts(parts, body.clone());
 
             let resp_tx = invoke.resp_tx;
-            state.res_cache.push(req_id, resp_tx).await;
+            state
+                .res_cache
+                .push(req_id, environment, resp_tx, request)
+                .await;
+            state.processes.set_invocation(environment, req_id).await;
+
+            // Runtimes started outside the emulator can't be restarted.
+            if let Some(timeout) = settings.timeout.clone() {
+                if !timeout.is_zero() && !state.is_only_lambda_apis() {
+                    let timer = enforce_timeout(state.clone(), environment, req_id, timeout);
+                    state.res_cache.set_timeout(req_id, timer).await;
+                }
+            }
 
-            let headers = parts.headers;
             if let Some(h) = headers.get(LAMBDA_RUNTIME_CLIENT_CONTEXT) {
                 let ctx = b64::STANDARD.encode(h.as_bytes());
                 let ctx = ctx.as_bytes();
@@ -91,19 +149,48 @@ pub(crate) async fn process_next_request(
                 builder = builder.header(LAMBDA_RUNTIME_XRAY_TRACE_HEADER, h);
             }
 
-            builder.status(StatusCode::OK).body(body)
+            builder.status(StatusCode::OK).body(Body::from(body))
         }
     };
 
     resp.map_err(ServerError::ResponseBuild)
 }
 
+/// Fail the invocation if the function doesn't respond before the deadline,
+/// and restart the process of the environment that was running it, like Lambda does.
+fn enforce_timeout(
+    state: RefRuntimeState,
+    environment: &str,
+    req_id: &str,
+    timeout: Timeout,
+) -> AbortHandle {
+    let environment = environment.to_string();
+    let req_id = req_id.to_string();
+
+    let timer = tokio::spawn(async move {
+        tokio::time::sleep(timeout.duration()).await;
+
+        let message = format!(
+            "{} {req_id} Task timed out after {timeout}.00 seconds",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+        );
+        let error = FunctionError::new(TIMEOUT_ERROR_TYPE, &message);
+        if !state.fail_invocation(&req_id, error).await {
+            return;
+        }
+
+        error!(?environment, %req_id, %timeout, "function timed out");
+        state.restart_environment(&environment, "TIMEOUT").await;
+    });
+    timer.abort_handle()
+}
+
 pub(crate) async fn next_invocation_response(
     State(state): State<RefRuntimeState>,
     Path((_function_name, req_id)): Path<(String, String)>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    respond_to_next_invocation(&state.res_cache, &req_id, req, StatusCode::OK).await
+    respond_to_next_invocation(&state, &req_id, req, StatusCode::OK).await
 }
 
 pub(crate) async fn bare_next_invocation_response(
@@ -111,7 +198,7 @@ pub(crate) async fn bare_next_invocation_response(
     Path(req_id): Path<String>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    respond_to_next_invocation(&state.res_cache, &req_id, req, StatusCode::OK).await
+    respond_to_next_invocation(&state, &req_id, req, StatusCode::OK).await
 }
 
 pub(crate) async fn next_invocation_error(
@@ -119,13 +206,7 @@ pub(crate) async fn next_invocation_error(
     Path((_function_name, req_id)): Path<(String, String)>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    respond_to_next_invocation(
-        &state.res_cache,
-        &req_id,
-        req,
-        StatusCode::INTERNAL_SERVER_ERROR,
-    )
-    .await
+    respond_to_next_invocation(&state, &req_id, req, StatusCode::INTERNAL_SERVER_ERROR).await
 }
 
 pub(crate) async fn bare_next_invocation_error(
@@ -133,22 +214,57 @@ pub(crate) async fn bare_next_invocation_error(
     Path(req_id): Path<String>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    respond_to_next_invocation(
-        &state.res_cache,
-        &req_id,
-        req,
-        StatusCode::INTERNAL_SERVER_ERROR,
-    )
-    .await
+    respond_to_next_invocation(&state, &req_id, req, StatusCode::INTERNAL_SERVER_ERROR).await
 }
 
 async fn respond_to_next_invocation(
-    cache: &ResponseCache,
+    state: &RefRuntimeState,
     req_id: &str,
-    mut req: Request<Body>,
+    req: Request<Body>,
     response_status: StatusCode,
 ) -> Result<Response<Body>, ServerError> {
-    if let Some(resp_tx) = cache.pop(req_id).await {
+    let status = if response_status == StatusCode::OK {
+        InvocationStatus::Success
+    } else {
+        InvocationStatus::Error(None)
+    };
+
+    // Buffered responses are checked before the invocation completes,
+    // so responses over the limit fail the invocation instead.
+    let streaming = req
+        .headers()
+        .get(LAMBDA_RUNTIME_FUNCTION_RESPONSE_MODE)
+        .is_some_and(|mode| mode == "streaming");
+    let mut req = if streaming {
+        req.map(|body| state.payload_limits.limit_stream(body))
+    } else {
+        let (parts, body) = req.into_parts();
+        let payload = body
+            .collect()
+            .await
+            .map_err(ServerError::DataDeserialization)?
+            .to_bytes();
+
+        if let Err(error) = state.payload_limits.check_response(payload.len()) {
+            error!(%req_id, size = payload.len(), "the function's response is too large");
+            state.fail_invocation(req_id, error).await;
+
+            return Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(
+                    serde_json::json!({
+                        "errorMessage": "Exceeded maximum allowed payload size.",
+                        "errorType": "RequestEntityTooLarge",
+                    })
+                    .to_string(),
+                ))
+                .map_err(ServerError::ResponseBuild);
+        }
+
+        Request::from_parts(parts, Body::from(payload))
+    };
+
+    if let Some(resp_tx) = state.complete_invocation(req_id, status).await {
         req.extensions_mut().insert(response_status);
 
         resp_tx
@@ -161,36 +277,60 @@ async fn respond_to_next_invocation(
 
 pub(crate) async fn init_error(
     State(state): State<RefRuntimeState>,
-    Path(_function_name): Path<String>,
+    Path(environment): Path<String>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    respond_to_invocation(&state.req_cache, req, StatusCode::OK).await
+    report_init_error(&state, &environment, req).await
 }
 
 pub(crate) async fn bare_init_error(
     State(state): State<RefRuntimeState>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    respond_to_invocation(&state.req_cache, req, StatusCode::OK).await
+    report_init_error(&state, DEFAULT_PACKAGE_FUNCTION, req).await
 }
 
-async fn respond_to_invocation(
-    cache: &RequestCache,
-    mut req: Request<Body>,
-    response_status: StatusCode,
+/// Fail the invocations waiting for a function with the error
+/// that the function reports when it cannot initialize.
+async fn report_init_error(
+    state: &RefRuntimeState,
+    environment: &str,
+    req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    let keys = cache.keys().await;
+    let header_type = req
+        .headers()
+        .get(LAMBDA_RUNTIME_FUNCTION_ERROR_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(String::from);
 
-    if let Some(key) = keys.first() {
-        if let Some(invoke_request) = cache.pop(key).await {
-            req.extensions_mut().insert(response_status);
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(ServerError::DataDeserialization)?
+        .to_bytes();
+    let report = serde_json::from_slice::<ErrorRequest>(&body).unwrap_or_default();
 
-            invoke_request
-                .resp_tx
-                .send(req)
-                .map_err(|_| ServerError::SendFunctionMessage)?;
-        }
-    }
+    let error_type = report
+        .error_type
+        .or(header_type)
+        .unwrap_or_else(|| UNKNOWN_ERROR_TYPE.into());
+    let message = report
+        .error_message
+        .unwrap_or_else(|| "the function failed to initialize".into());
+    error!(environment, %error_type, %message, "function failed to initialize");
 
-    Ok(Response::new(Body::empty()))
+    let error = FunctionError::new(&error_type, &message);
+    state
+        .processes
+        .init_failed(environment, error.clone())
+        .await;
+    state
+        .fail_environment(environment, ProcessFailure::Init(error))
+        .await;
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::from(r#"{"status":"OK"}"#))
+        .map_err(ServerError::ResponseBuild)
 }
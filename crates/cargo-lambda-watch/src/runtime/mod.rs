@@ -9,6 +9,7 @@ use extensions_router::*;
 
 mod functions_router;
 use functions_router::*;
+pub(crate) use functions_router::{LAMBDA_RUNTIME_CLIENT_CONTEXT, LAMBDA_RUNTIME_COGNITO_IDENTITY};
 
 pub(crate) const LAMBDA_RUNTIME_AWS_REQUEST_ID: &str = "lambda-runtime-aws-request-id";
 pub(crate) const LAMBDA_RUNTIME_XRAY_TRACE_HEADER: &str = "lambda-runtime-trace-id";
@@ -20,8 +21,37 @@ pub(crate) fn routes() -> Router<RefRuntimeState> {
             "/2020-01-01/extension/event/next",
             get(next_extension_event),
         )
-        .route("/2020-08-15/logs", put(subcribe_extension_events))
-        .route("/2022-07-01/telemetry", put(subcribe_extension_events))
+        .route(
+            "/2020-01-01/extension/init/error",
+            post(extension_init_error),
+        )
+        .route(
+            "/2020-01-01/extension/exit/error",
+            post(extension_exit_error),
+        )
+        .route("/2020-08-15/logs", put(subscribe_logs_api))
+        .route("/2022-07-01/telemetry", put(subscribe_telemetry_api))
+        .route(
+            "/:function_name/2020-01-01/extension/register",
+            post(register_environment_extension),
+        )
+        .route(
+            "/:function_name/2020-01-01/extension/event/next",
+            get(next_extension_event),
+        )
+        .route(
+            "/:function_name/2020-01-01/extension/init/error",
+            post(extension_init_error),
+        )
+        .route(
+            "/:function_name/2020-01-01/extension/exit/error",
+            post(extension_exit_error),
+        )
+        .route("/:function_name/2020-08-15/logs", put(subscribe_logs_api))
+        .route(
+            "/:function_name/2022-07-01/telemetry",
+            put(subscribe_telemetry_api),
+        )
         .route(
             "/:function_name/2018-06-01/runtime/invocation/next",
             get(next_request),
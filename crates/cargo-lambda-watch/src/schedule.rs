@@ -0,0 +1,186 @@
+use crate::{
+    error::ServerError,
+    requests::Action,
+    state::RefRuntimeState,
+    trigger_router::async_invocation::{self, AsyncInvocation},
+    watcher::env::{LOCAL_ACCOUNT_ID, function_region},
+};
+use aws_lambda_events::eventbridge::EventBridgeEvent;
+use axum::{
+    Extension, Router,
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::Response,
+    routing::post,
+};
+use bytes::Bytes;
+use cargo_lambda_metadata::cargo::watch::schedule::ScheduleExpression;
+use chrono::{SubsecRound, Utc};
+use serde_json::Value;
+use tokio::{sync::mpsc::Sender, time::Instant};
+use tokio_graceful_shutdown::{SubsystemBuilder, SubsystemHandle};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+pub(crate) const SCHEDULE_API_PATH: &str = "/.schedule";
+
+/// Start a task for every scheduled function, that invokes
+/// the function every time its schedule expression fires.
+pub(crate) fn init_schedules(
+    subsys: &SubsystemHandle,
+    state: RefRuntimeState,
+    cmd_tx: Sender<Action>,
+) {
+    for (function_name, expression) in state.schedules.iter() {
+        let state = state.clone();
+        let cmd_tx = cmd_tx.clone();
+        let function_name = function_name.clone();
+        let expression = expression.clone();
+        let name = format!("Schedule {function_name}");
+
+        subsys.start(SubsystemBuilder::new(name, move |s| {
+            start_schedule(s, state, cmd_tx, function_name, expression)
+        }));
+    }
+}
+
+async fn start_schedule(
+    subsys: SubsystemHandle,
+    state: RefRuntimeState,
+    cmd_tx: Sender<Action>,
+    function_name: String,
+    expression: ScheduleExpression,
+) -> Result<(), ServerError> {
+    if let Err(binaries) = state.is_function_available(&function_name) {
+        error!(function = ?function_name, available_functions = ?binaries, "the scheduled function doesn't exist as a binary in your project");
+        return Ok(());
+    }
+
+    info!(function = ?function_name, %expression, "starting schedule");
+
+    let mut last_fired = Utc::now();
+    loop {
+        let Some(next) = expression.next_after(last_fired) else {
+            info!(function = ?function_name, %expression, "the schedule won't fire again");
+            return Ok(());
+        };
+        debug!(function = ?function_name, %next, "next scheduled invocation");
+
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = subsys.on_shutdown_requested() => {
+                info!(function = ?function_name, "terminating schedule");
+                return Ok(());
+            }
+        }
+
+        fire_schedule(&state, &cmd_tx, &function_name)?;
+        last_fired = next;
+    }
+}
+
+/// Invoke a function with a scheduled event. EventBridge invokes
+/// functions asynchronously, so the invocation is retried if it fails.
+fn fire_schedule(
+    state: &RefRuntimeState,
+    cmd_tx: &Sender<Action>,
+    function_name: &str,
+) -> Result<Bytes, ServerError> {
+    let event = scheduled_event(function_name);
+    let payload = Bytes::from(serde_json::to_vec(&event)?);
+
+    let uri = format!("/2015-03-31/functions/{function_name}/invocations");
+    let invocation = AsyncInvocation {
+        request_id: Uuid::new_v4().to_string(),
+        function_name: function_name.to_string(),
+        method: Method::POST,
+        uri: Uri::try_from(uri)?,
+        headers: HeaderMap::new(),
+        payload: payload.clone(),
+        received_at: Instant::now(),
+    };
+
+    debug!(function = ?function_name, request_id = ?invocation.request_id, "sending scheduled event");
+    async_invocation::spawn(state.clone(), cmd_tx.clone(), invocation);
+
+    Ok(payload)
+}
+
+/// Event that EventBridge sends to functions when a schedule fires:
+/// https://docs.aws.amazon.com/lambda/latest/dg/with-eventbridge-scheduler.html
+fn scheduled_event(function_name: &str) -> EventBridgeEvent {
+    let region = function_region(None);
+    let rule = format!("arn:aws:events:{region}:{LOCAL_ACCOUNT_ID}:rule/{function_name}-schedule");
+
+    EventBridgeEvent {
+        version: Some("0".into()),
+        id: Some(Uuid::new_v4().to_string()),
+        detail_type: "Scheduled Event".into(),
+        source: "aws.events".into(),
+        account: Some(LOCAL_ACCOUNT_ID.into()),
+        time: Some(Utc::now().trunc_subsecs(0)),
+        region: Some(region),
+        resources: Some(vec![rule]),
+        detail: Value::Object(Default::default()),
+    }
+}
+
+pub(crate) fn routes() -> Router<RefRuntimeState> {
+    Router::new().route(
+        &format!("{SCHEDULE_API_PATH}/:function_name"),
+        post(fire_now),
+    )
+}
+
+/// Fire a function's schedule right away, without waiting for its next invocation.
+async fn fire_now(
+    State(state): State<RefRuntimeState>,
+    Extension(cmd_tx): Extension<Sender<Action>>,
+    Path(function_name): Path<String>,
+) -> Result<Response<Body>, ServerError> {
+    if !state.schedules.contains_key(&function_name) {
+        let detail = format!("the function `{function_name}` doesn't have a schedule");
+        error!(function = ?function_name, "{detail}");
+
+        let body = serde_json::json!({
+            "title": "Missing schedule",
+            "detail": detail,
+        });
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(body.to_string()))
+            .map_err(ServerError::ResponseBuild);
+    }
+
+    let event = fire_schedule(&state, &cmd_tx, &function_name)?;
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header("content-type", "application/json")
+        .body(Body::from(event))
+        .map_err(ServerError::ResponseBuild)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduled_event() {
+        let event = serde_json::to_value(scheduled_event("nightly-report")).unwrap();
+
+        assert_eq!(event["detail-type"], "Scheduled Event");
+        assert_eq!(event["source"], "aws.events");
+        assert_eq!(event["account"], LOCAL_ACCOUNT_ID);
+        assert_eq!(event["detail"], serde_json::json!({}));
+        assert!(
+            event["resources"][0]
+                .as_str()
+                .unwrap()
+                .ends_with(":rule/nightly-report-schedule")
+        );
+        assert!(event["time"].as_str().unwrap().ends_with('Z'));
+    }
+}
@@ -1,15 +1,22 @@
 use crate::{
     error::ServerError,
-    requests::{Action, NextEvent},
-    state::{ExtensionCache, RuntimeState},
-    watcher::WatcherConfig,
+    requests::Action,
+    state::{RuntimeState, environment_id},
+    watcher::{WatcherConfig, build::FunctionBuild, reload_config},
 };
-use cargo_lambda_metadata::DEFAULT_PACKAGE_FUNCTION;
+use cargo_lambda_metadata::{DEFAULT_PACKAGE_FUNCTION, cargo::watch::FunctionCommand};
 use cargo_options::Run as CargoOptions;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinSet,
+};
 use tokio_graceful_shutdown::{SubsystemBuilder, SubsystemHandle};
 use tracing::{error, info};
-use watchexec::command::Command;
+use watchexec::{Watchexec, command::Command};
 
 pub(crate) fn init_scheduler(
     subsys: &SubsystemHandle,
@@ -51,12 +58,11 @@ async fn start_scheduler(
 
                 if watcher_config.start_function() {
                     if let Some(name) = start_function_name {
-                        let runtime_api = state.function_addr(&name);
                         let gc_tx = gc_tx.clone();
                         let cargo_options = cargo_options.clone();
                         let watcher_config = watcher_config.clone();
-                        let ext_cache = state.ext_cache.clone();
-                        subsys.start(SubsystemBuilder::new("lambda runtime", move |s| start_function(s, name, runtime_api, cargo_options, watcher_config, gc_tx, ext_cache)));
+                        let state = state.clone();
+                        subsys.start(SubsystemBuilder::new("lambda runtime", move |s| start_function(s, name, cargo_options, watcher_config, gc_tx, state)));
                     }
                 }
             }
@@ -74,29 +80,92 @@ async fn start_scheduler(
 async fn start_function(
     subsys: SubsystemHandle,
     name: String,
-    runtime_api: String,
     cargo_options: CargoOptions,
     mut watcher_config: WatcherConfig,
     gc_tx: Sender<String>,
-    ext_cache: ExtensionCache,
+    state: RuntimeState,
 ) -> Result<(), ServerError> {
-    let cmd = cargo_command(&name, &cargo_options)?;
-    info!(function = ?name, manifest = ?cargo_options.manifest_path, ?cmd, "starting lambda function");
-
     watcher_config.bin_name = if is_valid_bin_name(&name) {
         Some(name.clone())
     } else {
         None
     };
     watcher_config.name.clone_from(&name);
-    watcher_config.runtime_api = runtime_api;
 
-    let wx = crate::watcher::new(cmd, watcher_config, ext_cache.clone()).await?;
+    let cmd = match state.function_commands.get(&name) {
+        Some(function) => {
+            // Relative paths in the configuration start in the project's directory.
+            let root = watcher_config.base.join(state.manifest_dir());
+            let (cmd, working_dir) = custom_command(function, &root);
+            watcher_config.working_dir = Some(working_dir);
+            watcher_config.watch_paths =
+                Some(function.watch.iter().map(|p| root.join(p)).collect());
+            cmd
+        }
+        None => {
+            let build = FunctionBuild::new(watcher_config.bin_name.as_deref(), &cargo_options);
+            let cmd = build.command();
+            watcher_config.build = Some(build);
+            cmd
+        }
+    };
+
+    let settings = match reload_config(&watcher_config.manifest_path, &watcher_config.bin_name) {
+        Some(config) => state.functions.update(&name, &config).await,
+        None => state.functions.get(&name).await,
+    };
+    info!(function = ?name, manifest = ?cargo_options.manifest_path, ?cmd, concurrency = settings.concurrency, "starting lambda function");
+
+    // The first environment builds the function and watches its code. The other
+    // environments run the same binary, and they reload when the first one reloads.
+    let mut environments = JoinSet::new();
+    let wx = start_environment(&state, &watcher_config, cmd.clone(), 0).await?;
+    environments.spawn(async move { wx.main().await });
+
+    if settings.concurrency > 1 {
+        let state = state.clone();
+        let primary = environment_id(&name, 0);
+        let mut watcher_config = watcher_config.clone();
+        watcher_config.watch_paths = Some(Vec::new());
+        watcher_config.replica = true;
+
+        environments.spawn(async move {
+            let cmd = match watcher_config.build.take() {
+                Some(build) => {
+                    let binary = state.processes.built_binary(&primary).await;
+                    Command::Exec {
+                        prog: binary.to_string_lossy().to_string(),
+                        args: build.run_args,
+                    }
+                }
+                None => cmd,
+            };
+
+            let mut replicas = JoinSet::new();
+            for index in 1..settings.concurrency {
+                match start_environment(&state, &watcher_config, cmd.clone(), index).await {
+                    Ok(wx) => {
+                        replicas.spawn(async move { wx.main().await });
+                    }
+                    Err(error) => {
+                        error!(?error, function = ?watcher_config.name, index, "failed to start execution environment");
+                    }
+                }
+            }
+
+            match replicas.join_next().await {
+                Some(Ok(res)) => res,
+                Some(Err(error)) => Err(error),
+                // The first environment keeps processing the invocations.
+                None => std::future::pending().await,
+            }
+        });
+    }
 
     tokio::select! {
-        res = wx.main() => match res {
-            Ok(_) => {},
-            Err(error) => {
+        Some(res) = environments.join_next() => match res {
+            Ok(Ok(_)) => {},
+            Ok(Err(error)) | Err(error) => {
                 error!(?error, "failed to obtain the watchexec task");
                 if let Err(error) = gc_tx.send(name.clone()).await {
                     error!(%error, function = ?name, "failed to send message to cleanup dead function");
@@ -108,31 +177,95 @@ async fn start_function(
         }
     }
 
-    let event = NextEvent::shutdown(&format!("{name} function shutting down"));
-    ext_cache.send_event(event).await
+    for index in 0..settings.concurrency {
+        state.processes.remove(&environment_id(&name, index)).await;
+    }
+
+    state
+        .ext_cache
+        .shutdown(&format!("{name} function shutting down"))
+        .await;
+
+    Ok(())
+}
+
+/// Start the watcher of one of the function's execution environments.
+async fn start_environment(
+    state: &RuntimeState,
+    watcher_config: &WatcherConfig,
+    cmd: Command,
+    index: u32,
+) -> Result<Arc<Watchexec>, ServerError> {
+    let environment = environment_id(&watcher_config.name, index);
+
+    let mut watcher_config = watcher_config.clone();
+    watcher_config.runtime_api = state.function_addr(&environment);
+    watcher_config.environment.clone_from(&environment);
+
+    let wx = crate::watcher::new(cmd, watcher_config, state.clone()).await?;
+    state.processes.insert(&environment, wx.clone()).await;
+    Ok(wx)
 }
 
 fn is_valid_bin_name(name: &str) -> bool {
     !name.is_empty() && name != DEFAULT_PACKAGE_FUNCTION
 }
 
-fn cargo_command(
-    name: &str,
-    cargo_options: &CargoOptions,
-) -> Result<watchexec::command::Command, ServerError> {
-    let cmd = if is_valid_bin_name(name) {
-        let mut command_opts = cargo_options.clone();
-        command_opts.bin.push(name.to_string());
-        command_opts.command()
+/// Command of a function declared with a `command` in the watch configuration,
+/// and the directory where it runs.
+fn custom_command(function: &FunctionCommand, root: &Path) -> (Command, PathBuf) {
+    let working_dir = match &function.working_dir {
+        Some(dir) => root.join(dir),
+        None => root.to_path_buf(),
+    };
+
+    // Relative paths to programs start in the working directory,
+    // programs without a path are looked up in the PATH.
+    let program = Path::new(&function.command);
+    let prog = if program.is_relative() && program.components().count() > 1 {
+        working_dir.join(program).to_string_lossy().to_string()
     } else {
-        cargo_options.command()
+        function.command.clone()
     };
 
-    Ok(Command::Exec {
-        prog: cmd.get_program().to_string_lossy().to_string(),
-        args: cmd
-            .get_args()
-            .map(|arg| arg.to_string_lossy().to_string())
-            .collect(),
-    })
+    let cmd = Command::Exec {
+        prog,
+        args: function.args.clone(),
+    };
+    (cmd, working_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_command() {
+        let root = Path::new("/project");
+        let function = FunctionCommand {
+            command: "./bin/bootstrap".into(),
+            args: vec!["--port".into(), "8080".into()],
+            working_dir: Some("handlers".into()),
+            watch: Vec::new(),
+        };
+        let (cmd, working_dir) = custom_command(&function, root);
+        assert_eq!(working_dir, Path::new("/project/handlers"));
+        let Command::Exec { prog, args } = cmd else {
+            panic!("expected an exec command");
+        };
+        assert_eq!(
+            Path::new(&prog),
+            Path::new("/project/handlers/./bin/bootstrap")
+        );
+        assert_eq!(args, vec!["--port", "8080"]);
+
+        let function = FunctionCommand {
+            command: "go".into(),
+            args: vec!["run".into(), ".".into()],
+            ..Default::default()
+        };
+        let (cmd, working_dir) = custom_command(&function, root);
+        assert_eq!(working_dir, root);
+        assert!(matches!(cmd, Command::Exec { prog, .. } if prog == "go"));
+    }
 }
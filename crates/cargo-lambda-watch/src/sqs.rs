@@ -0,0 +1,534 @@
+use crate::{
+    error::ServerError,
+    requests::Action,
+    state::{RefRuntimeState, Reservation},
+    trigger_router::schedule_invocation,
+    watcher::env::{LOCAL_ACCOUNT_ID, function_region},
+};
+use aws_lambda_events::sqs::{BatchItemFailure, SqsEvent, SqsMessage, SqsMessageAttribute};
+use axum::{body::Body, http::Request};
+use cargo_lambda_metadata::cargo::watch::SqsEventSource;
+use chrono::Utc;
+use http::StatusCode;
+use http_body_util::BodyExt;
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{Mutex, Notify, mpsc::Sender},
+    time::Instant,
+};
+use tokio_graceful_shutdown::{SubsystemBuilder, SubsystemHandle};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+pub(crate) mod api;
+
+/// How often the queue and its directory are checked for new messages.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before trying again when the function is throttled.
+const THROTTLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Message waiting in a local queue.
+#[derive(Clone, Debug)]
+pub(crate) struct QueuedMessage {
+    pub id: String,
+    pub body: String,
+    pub attributes: HashMap<String, SqsMessageAttribute>,
+    sent_at: i64,
+    first_received_at: Option<i64>,
+    receive_count: u32,
+    visible_at: Instant,
+    /// File that the message was read from, if it came from a directory
+    path: Option<PathBuf>,
+}
+
+impl QueuedMessage {
+    pub fn new(
+        body: String,
+        attributes: HashMap<String, SqsMessageAttribute>,
+        delay: Duration,
+    ) -> QueuedMessage {
+        QueuedMessage {
+            id: Uuid::new_v4().to_string(),
+            body,
+            attributes,
+            sent_at: Utc::now().timestamp_millis(),
+            first_received_at: None,
+            receive_count: 0,
+            visible_at: Instant::now() + delay,
+            path: None,
+        }
+    }
+
+    fn into_event_message(self, queue_name: &str, region: &str) -> SqsMessage {
+        let mut attributes = HashMap::from([
+            (
+                "ApproximateReceiveCount".to_string(),
+                self.receive_count.to_string(),
+            ),
+            ("SentTimestamp".to_string(), self.sent_at.to_string()),
+            ("SenderId".to_string(), LOCAL_ACCOUNT_ID.to_string()),
+        ]);
+        if let Some(first_received_at) = self.first_received_at {
+            attributes.insert(
+                "ApproximateFirstReceiveTimestamp".to_string(),
+                first_received_at.to_string(),
+            );
+        }
+
+        SqsMessage {
+            message_id: Some(self.id.clone()),
+            receipt_handle: Some(self.id),
+            md5_of_body: Some(md5_hex(self.body.as_bytes())),
+            md5_of_message_attributes: attributes_md5(&self.attributes),
+            body: Some(self.body),
+            attributes,
+            message_attributes: self.attributes,
+            event_source_arn: Some(queue_arn(queue_name, region)),
+            event_source: Some("aws:sqs".into()),
+            aws_region: Some(region.into()),
+        }
+    }
+}
+
+/// Local queue that keeps its messages in memory.
+/// Messages that are delivered stay in the queue, hidden, until they're
+/// deleted, or until their visibility timeout expires and they can be delivered again.
+#[derive(Default)]
+pub(crate) struct SqsQueue {
+    messages: Mutex<Vec<QueuedMessage>>,
+    notify: Notify,
+}
+
+impl SqsQueue {
+    pub async fn send(&self, message: QueuedMessage) {
+        let mut messages = self.messages.lock().await;
+        messages.push(message);
+        self.notify.notify_one();
+    }
+
+    /// Take up to `max` visible messages, and hide them for the visibility timeout.
+    async fn receive(&self, max: usize, visibility_timeout: Duration) -> Vec<QueuedMessage> {
+        let now = Instant::now();
+        let timestamp = Utc::now().timestamp_millis();
+
+        let mut messages = self.messages.lock().await;
+        messages
+            .iter_mut()
+            .filter(|m| m.visible_at <= now)
+            .take(max)
+            .map(|m| {
+                m.visible_at = now + visibility_timeout;
+                m.receive_count += 1;
+                m.first_received_at.get_or_insert(timestamp);
+                m.clone()
+            })
+            .collect()
+    }
+
+    /// Make messages visible again, before their visibility timeout expires.
+    async fn release(&self, ids: &HashSet<String>) {
+        let now = Instant::now();
+        let mut messages = self.messages.lock().await;
+        for message in messages.iter_mut().filter(|m| ids.contains(&m.id)) {
+            message.visible_at = now;
+        }
+        self.notify.notify_one();
+    }
+
+    /// Remove messages from the queue. It returns the messages that were removed.
+    async fn delete(&self, ids: &HashSet<String>) -> Vec<QueuedMessage> {
+        let mut messages = self.messages.lock().await;
+        let (deleted, kept) = messages.drain(..).partition(|m| ids.contains(&m.id));
+        *messages = kept;
+        deleted
+    }
+}
+
+/// Local queues declared in the event source mappings.
+#[derive(Clone, Default)]
+pub(crate) struct SqsQueues {
+    queues: Arc<HashMap<String, Arc<SqsQueue>>>,
+    sources: Arc<Vec<SqsEventSource>>,
+}
+
+impl SqsQueues {
+    pub fn new(sources: &[SqsEventSource]) -> SqsQueues {
+        let queues = sources
+            .iter()
+            .map(|s| (s.queue.clone(), Arc::default()))
+            .collect();
+
+        SqsQueues {
+            queues: Arc::new(queues),
+            sources: Arc::new(sources.to_vec()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<SqsQueue>> {
+        self.queues.get(name).cloned()
+    }
+}
+
+/// Start a poller for every event source mapping,
+/// like Lambda does with SQS queues.
+pub(crate) fn init_event_sources(
+    subsys: &SubsystemHandle,
+    state: RefRuntimeState,
+    cmd_tx: Sender<Action>,
+) {
+    for source in state.sqs_queues.sources.iter().cloned() {
+        let Some(queue) = state.sqs_queues.get(&source.queue) else {
+            continue;
+        };
+        let state = state.clone();
+        let cmd_tx = cmd_tx.clone();
+        let name = format!("SQS event source {}", source.queue);
+
+        subsys.start(SubsystemBuilder::new(name, move |s| {
+            start_event_source(s, state, cmd_tx, queue, source)
+        }));
+    }
+}
+
+async fn start_event_source(
+    subsys: SubsystemHandle,
+    state: RefRuntimeState,
+    cmd_tx: Sender<Action>,
+    queue: Arc<SqsQueue>,
+    source: SqsEventSource,
+) -> Result<(), ServerError> {
+    if let Err(binaries) = state.is_function_available(&source.function) {
+        error!(function = ?source.function, queue = ?source.queue, available_functions = ?binaries, "the event source function doesn't exist as a binary in your project");
+        return Ok(());
+    }
+
+    info!(queue = ?source.queue, function = ?source.function, "starting SQS event source");
+
+    let mut ingested = HashSet::new();
+
+    loop {
+        if let Some(directory) = &source.directory {
+            read_directory(&queue, directory, &mut ingested).await;
+        }
+
+        tokio::select! {
+            _ = poll_queue(&state, &cmd_tx, &queue, &source, &mut ingested) => {}
+            _ = subsys.on_shutdown_requested() => {
+                info!(queue = ?source.queue, "terminating SQS event source");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Deliver one batch of messages to the function,
+/// or wait for new messages if the queue is empty.
+async fn poll_queue(
+    state: &RefRuntimeState,
+    cmd_tx: &Sender<Action>,
+    queue: &SqsQueue,
+    source: &SqsEventSource,
+    ingested: &mut HashSet<PathBuf>,
+) {
+    let batch_size = source.batch_size.max(1);
+    let visibility_timeout = Duration::from_secs(source.visibility_timeout);
+
+    let mut batch = queue.receive(batch_size, visibility_timeout).await;
+    if batch.is_empty() {
+        let _ = tokio::time::timeout(POLL_INTERVAL, queue.notify.notified()).await;
+        return;
+    }
+
+    let window = Instant::now() + Duration::from_secs(source.batching_window);
+    while batch.len() < batch_size && Instant::now() < window {
+        let wait = (window - Instant::now()).min(POLL_INTERVAL);
+        let _ = tokio::time::timeout(wait, queue.notify.notified()).await;
+
+        let more = queue
+            .receive(batch_size - batch.len(), visibility_timeout)
+            .await;
+        batch.extend(more);
+    }
+
+    let ids = batch.iter().map(|m| m.id.clone()).collect::<HashSet<_>>();
+
+    // Concurrency is reserved only for batches that the function is going to process,
+    // so idle queues don't take it from other invocations.
+    let _reservation = match state.functions.reserve(&source.function).await {
+        Reservation::Throttled => {
+            debug!(function = ?source.function, "SQS event source throttled, trying again later");
+            queue.release(&ids).await;
+            tokio::time::sleep(THROTTLE_DELAY).await;
+            return;
+        }
+        reservation => reservation,
+    };
+
+    let processed = match invoke_function(state, cmd_tx, source, batch).await {
+        Ok(failures) => ids.difference(&failures).cloned().collect(),
+        Err(reason) => {
+            warn!(function = ?source.function, queue = ?source.queue, %reason, "the function failed to process the batch, the messages will be delivered again after the visibility timeout");
+            HashSet::new()
+        }
+    };
+
+    for message in queue.delete(&processed).await {
+        if let Some(path) = message.path {
+            if let Err(error) = tokio::fs::remove_file(&path).await {
+                error!(?error, ?path, "failed to remove processed message file");
+            }
+            ingested.remove(&path);
+        }
+    }
+}
+
+/// Send a batch of messages to the function. It returns
+/// the ids of the messages that the function failed to process.
+async fn invoke_function(
+    state: &RefRuntimeState,
+    cmd_tx: &Sender<Action>,
+    source: &SqsEventSource,
+    batch: Vec<QueuedMessage>,
+) -> Result<HashSet<String>, String> {
+    let region = function_region(None);
+    let event = SqsEvent {
+        records: batch
+            .into_iter()
+            .map(|m| m.into_event_message(&source.queue, &region))
+            .collect(),
+    };
+    let ids = event
+        .records
+        .iter()
+        .filter_map(|r| r.message_id.clone())
+        .collect::<HashSet<_>>();
+
+    debug!(function = ?source.function, queue = ?source.queue, messages = ids.len(), "delivering SQS batch");
+
+    let event = serde_json::to_vec(&event).map_err(|e| e.to_string())?;
+    let req = Request::new(Body::from(event));
+
+    let resp = schedule_invocation(state, cmd_tx, source.function.clone(), req)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let status = resp
+        .extensions()
+        .get::<StatusCode>()
+        .cloned()
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if status != StatusCode::OK {
+        return Err(format!("function returned status {status}"));
+    }
+
+    if !source.report_batch_item_failures {
+        return Ok(HashSet::new());
+    }
+
+    let body = resp
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| e.to_string())?
+        .to_bytes();
+
+    batch_item_failures(&body, &ids)
+}
+
+/// Response of functions that report batch item failures. Like Lambda,
+/// `null`, an empty object, and an empty list mean that every message succeeded.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchResponse {
+    #[serde(default)]
+    batch_item_failures: Option<Vec<BatchItemFailure>>,
+}
+
+/// Extract the messages that failed from the function's response.
+/// Lambda considers the whole batch failed if it cannot parse the response,
+/// or if the response references a message that wasn't in the batch.
+fn batch_item_failures(body: &[u8], ids: &HashSet<String>) -> Result<HashSet<String>, String> {
+    let response = serde_json::from_slice::<Option<BatchResponse>>(body)
+        .map_err(|e| format!("the function's response is not a valid batch response: {e}"))?;
+
+    let failures = response
+        .and_then(|r| r.batch_item_failures)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| f.item_identifier)
+        .collect::<HashSet<_>>();
+
+    match failures.iter().find(|id| !ids.contains(*id)) {
+        None => Ok(failures),
+        Some(id) => Err(format!(
+            "the batch item failure `{id}` doesn't match any message in the batch"
+        )),
+    }
+}
+
+/// Send every new file in the directory to the queue.
+async fn read_directory(queue: &SqsQueue, directory: &PathBuf, ingested: &mut HashSet<PathBuf>) {
+    let mut entries = match tokio::fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        Err(error) => {
+            debug!(?error, ?directory, "failed to read SQS messages directory");
+            return;
+        }
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if hidden || !path.is_file() || ingested.contains(&path) {
+            continue;
+        }
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(body) => {
+                debug!(?path, "sending file to SQS queue");
+                let mut message = QueuedMessage::new(body, HashMap::new(), Duration::ZERO);
+                message.path = Some(path.clone());
+
+                queue.send(message).await;
+                ingested.insert(path);
+            }
+            Err(error) => error!(?error, ?path, "failed to read SQS message file"),
+        }
+    }
+}
+
+fn queue_arn(queue_name: &str, region: &str) -> String {
+    format!("arn:aws:sqs:{region}:{LOCAL_ACCOUNT_ID}:{queue_name}")
+}
+
+pub(crate) fn md5_hex(data: &[u8]) -> String {
+    format!("{:x}", Md5::digest(data))
+}
+
+/// MD5 digest of the message attributes, calculated with the algorithm that SQS uses:
+/// https://docs.aws.amazon.com/AWSSimpleQueueService/latest/SQSDeveloperGuide/sqs-message-metadata.html#sqs-attributes-md5-message-digest-calculation
+pub(crate) fn attributes_md5(attributes: &HashMap<String, SqsMessageAttribute>) -> Option<String> {
+    if attributes.is_empty() {
+        return None;
+    }
+
+    fn push_value(buffer: &mut Vec<u8>, value: &[u8]) {
+        buffer.extend((value.len() as u32).to_be_bytes());
+        buffer.extend(value);
+    }
+
+    let mut names = attributes.keys().collect::<Vec<_>>();
+    names.sort();
+
+    let mut buffer = Vec::new();
+    for name in names {
+        let attribute = &attributes[name];
+        let data_type = attribute.data_type.clone().unwrap_or_default();
+
+        push_value(&mut buffer, name.as_bytes());
+        push_value(&mut buffer, data_type.as_bytes());
+
+        if let Some(value) = &attribute.binary_value {
+            buffer.push(2);
+            push_value(&mut buffer, &value.0);
+        } else {
+            buffer.push(1);
+            push_value(
+                &mut buffer,
+                attribute
+                    .string_value
+                    .as_deref()
+                    .unwrap_or_default()
+                    .as_bytes(),
+            );
+        }
+    }
+
+    Some(md5_hex(&buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue_visibility_timeout() {
+        let queue = SqsQueue::default();
+        for body in ["one", "two", "three"] {
+            let message = QueuedMessage::new(body.into(), HashMap::new(), Duration::ZERO);
+            queue.send(message).await;
+        }
+
+        let batch = queue.receive(2, Duration::from_secs(30)).await;
+        assert_eq!(2, batch.len());
+        assert_eq!("one", batch[0].body);
+        assert_eq!(1, batch[0].receive_count);
+
+        let batch = queue.receive(10, Duration::from_secs(30)).await;
+        assert_eq!(1, batch.len());
+        assert_eq!("three", batch[0].body);
+
+        let ids = HashSet::from([batch[0].id.clone()]);
+        assert_eq!(1, queue.delete(&ids).await.len());
+
+        let batch = queue.receive(10, Duration::ZERO).await;
+        assert!(batch.is_empty());
+
+        let message = QueuedMessage::new("four".into(), HashMap::new(), Duration::ZERO);
+        queue.send(message).await;
+        let batch = queue.receive(10, Duration::from_secs(30)).await;
+        assert_eq!(1, batch.len());
+
+        queue.release(&HashSet::from([batch[0].id.clone()])).await;
+        let batch = queue.receive(10, Duration::from_secs(30)).await;
+        assert_eq!(1, batch.len());
+        assert_eq!("four", batch[0].body);
+    }
+
+    #[test]
+    fn test_batch_item_failures() {
+        let ids = HashSet::from(["one".to_string(), "two".to_string()]);
+
+        assert!(batch_item_failures(b"", &ids).is_err());
+        assert!(batch_item_failures(b"processed", &ids).is_err());
+
+        let failures = batch_item_failures(b"null", &ids).unwrap();
+        assert!(failures.is_empty());
+
+        let failures = batch_item_failures(b"{}", &ids).unwrap();
+        assert!(failures.is_empty());
+
+        let body = br#"{"batchItemFailures":[{"itemIdentifier":"two"}]}"#;
+        let failures = batch_item_failures(body, &ids).unwrap();
+        assert_eq!(HashSet::from(["two".to_string()]), failures);
+
+        let body = br#"{"batchItemFailures":[{"itemIdentifier":"three"}]}"#;
+        assert!(batch_item_failures(body, &ids).is_err());
+    }
+
+    #[test]
+    fn test_attributes_md5() {
+        assert_eq!(None, attributes_md5(&HashMap::new()));
+
+        let attributes = HashMap::from([(
+            "timestamp".to_string(),
+            SqsMessageAttribute {
+                string_value: Some("1493147359900".into()),
+                data_type: Some("Number".into()),
+                ..Default::default()
+            },
+        )]);
+        assert_eq!(
+            Some("235c5c510d26fb653d073faed50ae77c".to_string()),
+            attributes_md5(&attributes)
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{RefRuntimeState, error::ServerError};
+use aws_lambda_events::{encodings::Base64Data, sqs::SqsMessageAttribute};
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::post,
+};
+use base64::{Engine as _, engine::general_purpose as b64};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+use tracing::{debug, error};
+
+use super::{QueuedMessage, attributes_md5, md5_hex};
+
+pub(crate) const SQS_API_PATH: &str = "/.sqs";
+
+const TARGET_HEADER: &str = "x-amz-target";
+const SEND_MESSAGE_TARGET: &str = "AmazonSQS.SendMessage";
+
+/// Maximum delay that SQS accepts for a message.
+const MAX_DELAY_SECONDS: u64 = 900;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMessageRequest {
+    queue_url: String,
+    message_body: String,
+    #[serde(default)]
+    delay_seconds: Option<u64>,
+    #[serde(default)]
+    message_attributes: HashMap<String, MessageAttributeValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageAttributeValue {
+    data_type: String,
+    #[serde(default)]
+    string_value: Option<String>,
+    #[serde(default)]
+    binary_value: Option<String>,
+}
+
+impl MessageAttributeValue {
+    fn into_attribute(self) -> Result<SqsMessageAttribute, base64::DecodeError> {
+        let binary_value = self
+            .binary_value
+            .map(|v| b64::STANDARD.decode(v))
+            .transpose()?
+            .map(Base64Data);
+
+        Ok(SqsMessageAttribute {
+            string_value: self.string_value,
+            binary_value,
+            data_type: Some(self.data_type),
+            ..Default::default()
+        })
+    }
+}
+
+pub(crate) fn routes() -> Router<RefRuntimeState> {
+    Router::new()
+        .route(SQS_API_PATH, post(send_message))
+        .route(&format!("{SQS_API_PATH}/"), post(send_message))
+        .route(
+            &format!("{SQS_API_PATH}/:queue_name"),
+            post(send_raw_message),
+        )
+}
+
+/// Handle `SendMessage` requests sent with the AWS JSON protocol,
+/// like the ones that the AWS SDKs send.
+async fn send_message(
+    State(state): State<RefRuntimeState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, ServerError> {
+    let target = headers
+        .get(TARGET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if target != SEND_MESSAGE_TARGET {
+        return respond_with_error(
+            StatusCode::BAD_REQUEST,
+            "UnsupportedOperation",
+            "AWS.SimpleQueueService.UnsupportedOperation",
+            &format!("the operation `{target}` is not supported by the local SQS emulator"),
+        );
+    }
+
+    let request: SendMessageRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(error) => {
+            return respond_with_error(
+                StatusCode::BAD_REQUEST,
+                "InvalidParameterValue",
+                "InvalidParameterValue",
+                &error.to_string(),
+            );
+        }
+    };
+
+    let queue_name = request
+        .queue_url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    let delay = request.delay_seconds.unwrap_or_default();
+    if delay > MAX_DELAY_SECONDS {
+        return respond_with_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "InvalidParameterValue",
+            &format!(
+                "Value {delay} for parameter DelaySeconds is invalid. Reason: must be between 0 and {MAX_DELAY_SECONDS}."
+            ),
+        );
+    }
+
+    let mut attributes = HashMap::new();
+    for (name, value) in request.message_attributes {
+        match value.into_attribute() {
+            Ok(attribute) => attributes.insert(name, attribute),
+            Err(error) => {
+                return respond_with_error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidParameterValue",
+                    "InvalidParameterValue",
+                    &format!("invalid binary value for message attribute `{name}`: {error}"),
+                );
+            }
+        };
+    }
+
+    let message = QueuedMessage::new(request.message_body, attributes, Duration::from_secs(delay));
+    enqueue_message(&state, &queue_name, message).await
+}
+
+/// Send the raw request body as a message to the queue in the path.
+async fn send_raw_message(
+    State(state): State<RefRuntimeState>,
+    Path(queue_name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, ServerError> {
+    if headers.contains_key(TARGET_HEADER) {
+        return send_message(State(state), headers, body).await;
+    }
+
+    let body = String::from_utf8_lossy(&body).to_string();
+    let message = QueuedMessage::new(body, HashMap::new(), Duration::ZERO);
+    enqueue_message(&state, &queue_name, message).await
+}
+
+async fn enqueue_message(
+    state: &RefRuntimeState,
+    queue_name: &str,
+    message: QueuedMessage,
+) -> Result<Response<Body>, ServerError> {
+    let Some(queue) = state.sqs_queues.get(queue_name) else {
+        return respond_with_error(
+            StatusCode::BAD_REQUEST,
+            "QueueDoesNotExist",
+            "AWS.SimpleQueueService.NonExistentQueue",
+            &format!("The specified queue `{queue_name}` does not exist."),
+        );
+    };
+
+    let mut body = serde_json::json!({
+        "MessageId": message.id,
+        "MD5OfMessageBody": md5_hex(message.body.as_bytes()),
+    });
+    // SQS doesn't include the attributes' digest when the message has no attributes.
+    if let Some(md5) = attributes_md5(&message.attributes) {
+        body["MD5OfMessageAttributes"] = md5.into();
+    }
+
+    debug!(queue = ?queue_name, message_id = ?message.id, "message sent to SQS queue");
+    queue.send(message).await;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/x-amz-json-1.0")
+        .body(Body::from(body.to_string()))
+        .map_err(ServerError::ResponseBuild)
+}
+
+fn respond_with_error(
+    status: StatusCode,
+    error_type: &str,
+    query_error: &str,
+    message: &str,
+) -> Result<Response<Body>, ServerError> {
+    error!(error_type, message, "failed to send SQS message");
+
+    let body = serde_json::json!({
+        "__type": format!("com.amazonaws.sqs#{error_type}"),
+        "message": message,
+    });
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/x-amz-json-1.0")
+        .header("x-amzn-query-error", format!("{query_error};Sender"))
+        .body(Body::from(body.to_string()))
+        .map_err(ServerError::ResponseBuild)
+}
@@ -1,20 +1,55 @@
 use crate::{
     RUNTIME_EMULATOR_PATH,
     error::ServerError,
-    requests::{InvokeRequest, LambdaResponse, NextEvent},
+    limits::PayloadLimits,
+    logs::LogFormatter,
+    recorder::Recorder,
+    report::{self, InvocationStatus, ReportMetrics},
+    requests::{
+        EXIT_ERROR_TYPE, FunctionError, InvokeRequest, LambdaResponse, NextEvent,
+        RELOADING_ERROR_TYPE, SHUTDOWN_REASON_FAILURE, TIMEOUT_ERROR_TYPE,
+    },
+    sqs::SqsQueues,
+    telemetry::{TelemetryCache, TelemetryEvent},
+    trigger_router::{authorizers::AuthorizerCache, iam_auth::CredentialStore},
+    watcher::{memory, reload_config},
+    websocket::WebSocketConnections,
 };
-use cargo_lambda_metadata::cargo::{binary_targets, watch::FunctionRouter};
+use axum::{body::Body, http::Request};
+use bytes::Bytes;
+use cargo_lambda_metadata::{
+    DEFAULT_PACKAGE_FUNCTION,
+    cargo::{
+        binary_targets,
+        deploy::LoggingConfig,
+        watch::{
+            AuthType, FunctionCommand, FunctionRouter, ReloadStrategy, Watch, WebSocketApi,
+            schedule::ScheduleExpression,
+        },
+    },
+    config::Config,
+    lambda::Timeout,
+};
+use cargo_lambda_remote::RemoteConfig;
+use chrono::{SecondsFormat, Utc};
 use miette::Result;
 use mpsc::{Receiver, Sender, channel};
+use serde::Serialize;
 use std::{
-    collections::{HashMap, HashSet, hash_map::Entry},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque, hash_map::Entry},
     net::SocketAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    process::Child,
+    sync::{Mutex, Notify, OwnedSemaphorePermit, RwLock, Semaphore, mpsc, oneshot},
+    task::AbortHandle,
 };
-use tokio::sync::{Mutex, RwLock, mpsc, oneshot};
-use tracing::debug;
+use tracing::{debug, info};
 use uuid::Uuid;
+use watchexec::{Watchexec, event::Priority};
 
 #[derive(Clone)]
 pub(crate) struct RuntimeState {
@@ -28,6 +63,19 @@ pub(crate) struct RuntimeState {
     pub req_cache: RequestCache,
     pub res_cache: ResponseCache,
     pub ext_cache: ExtensionCache,
+    pub telemetry: TelemetryCache,
+    pub functions: FunctionCache,
+    pub processes: ProcessCache,
+    pub sqs_queues: SqsQueues,
+    pub schedules: Arc<BTreeMap<String, ScheduleExpression>>,
+    /// Functions that run a command instead of a binary in the project
+    pub function_commands: Arc<BTreeMap<String, FunctionCommand>>,
+    pub credentials: CredentialStore,
+    pub authorizers: AuthorizerCache,
+    pub websocket_api: Option<Arc<WebSocketApi>>,
+    pub websockets: WebSocketConnections,
+    pub recorder: Option<Recorder>,
+    pub payload_limits: PayloadLimits,
 }
 
 pub(crate) type RefRuntimeState = Arc<RuntimeState>;
@@ -52,9 +100,28 @@ impl RuntimeState {
             req_cache: RequestCache::new(),
             res_cache: ResponseCache::new(),
             ext_cache: ExtensionCache::default(),
+            telemetry: TelemetryCache::default(),
+            functions: FunctionCache::default(),
+            processes: ProcessCache::default(),
+            sqs_queues: SqsQueues::default(),
+            schedules: Arc::default(),
+            function_commands: Arc::default(),
+            credentials: CredentialStore::default(),
+            authorizers: AuthorizerCache::default(),
+            websocket_api: None,
+            websockets: WebSocketConnections::default(),
+            recorder: None,
+            payload_limits: PayloadLimits::default(),
         }
     }
 
+    /// Directory of the project's manifest, where relative paths in the configuration start.
+    pub(crate) fn manifest_dir(&self) -> &Path {
+        self.manifest_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+    }
+
     pub(crate) fn addresses(&self) -> (SocketAddr, Option<SocketAddr>, String) {
         (self.runtime_addr, self.proxy_addr, self.runtime_url.clone())
     }
@@ -63,6 +130,290 @@ impl RuntimeState {
         format!("{}/{}", &self.runtime_url, name)
     }
 
+    /// Respond to an invocation with an error on behalf of the function.
+    /// It returns false if the invocation was already completed.
+    pub(crate) async fn fail_invocation(&self, req_id: &str, error: FunctionError) -> bool {
+        let status = if error.error_type == TIMEOUT_ERROR_TYPE {
+            InvocationStatus::Timeout
+        } else {
+            InvocationStatus::Error(Some(error.error_type.clone()))
+        };
+
+        let Some(resp_tx) = self.complete_invocation(req_id, status).await else {
+            return false;
+        };
+
+        if resp_tx.send(error.into_lambda_response()).is_err() {
+            debug!(req_id, "the invocation was cancelled before it failed");
+        }
+
+        true
+    }
+
+    /// Remove an invocation from the cache when the function completes it,
+    /// and report its measurements like Lambda does, with the `END` and `REPORT` lines.
+    /// It returns the channel to send the function's response through,
+    /// or `None` if the invocation was already completed.
+    pub(crate) async fn complete_invocation(
+        &self,
+        req_id: &str,
+        status: InvocationStatus,
+    ) -> Option<oneshot::Sender<LambdaResponse>> {
+        let pending = self.res_cache.pop(req_id).await?;
+        let duration = pending.started_at.elapsed();
+
+        self.telemetry
+            .send_event(
+                TelemetryEvent::PlatformDone {
+                    request_id: req_id.to_string(),
+                    success: status.is_success(),
+                },
+                &pending.environment,
+            )
+            .await;
+
+        let function_name = environment_function_name(&pending.environment).to_string();
+        let settings = self.functions.get(&function_name).await;
+        let (max_memory_used, init_duration) =
+            self.processes.measurements(&pending.environment).await;
+        let metrics = ReportMetrics::new(duration, settings.memory, max_memory_used, init_duration);
+
+        let report = TelemetryEvent::PlatformReport {
+            request_id: req_id.to_string(),
+            status: status.as_str(),
+            metrics: metrics.clone(),
+        };
+        LogFormatter::new(&settings.logging).print_platform(
+            &report,
+            &[
+                report::end_line(req_id),
+                report::report_line(req_id, &metrics, &status),
+            ],
+        );
+        self.telemetry
+            .send_event(report, &pending.environment)
+            .await;
+
+        self.res_cache
+            .complete(CompletedInvocation {
+                request_id: req_id.to_string(),
+                function_name,
+                environment: pending.environment,
+                started_at: pending.timestamp,
+                status: status.as_str(),
+                metrics,
+            })
+            .await;
+
+        Some(pending.resp_tx)
+    }
+
+    /// Notify extensions that an execution environment is shutting down,
+    /// and restart the function's process in that environment.
+    pub(crate) async fn restart_environment(&self, environment: &str, reason: &str) {
+        self.ext_cache
+            .shutdown_environment(reason, Some(environment))
+            .await;
+
+        if let Err(error) = self.processes.restart(environment, reason).await {
+            tracing::error!(?error, environment, "failed to restart function process");
+        }
+    }
+
+    /// Handle the invocations that an execution environment is processing
+    /// before its process restarts to load the function's new code.
+    pub(crate) async fn prepare_reload(&self, environment: &str, strategy: ReloadStrategy) {
+        match strategy {
+            ReloadStrategy::Drain => {
+                self.processes.start_drain(environment).await;
+
+                let settings = self
+                    .functions
+                    .get(environment_function_name(environment))
+                    .await;
+                let drain = async {
+                    let mut logged = false;
+                    loop {
+                        let pending = self.res_cache.in_environment(environment).await;
+                        if pending.is_empty() {
+                            break;
+                        }
+                        if !logged {
+                            info!(
+                                environment,
+                                invocations = pending.len(),
+                                "waiting for the invocations in flight to complete before reloading the function"
+                            );
+                            logged = true;
+                        }
+                        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+                    }
+                };
+
+                if tokio::time::timeout(settings.deadline(), drain)
+                    .await
+                    .is_err()
+                {
+                    tracing::warn!(
+                        environment,
+                        "the invocations in flight didn't complete before the function's timeout, reloading the function"
+                    );
+                    self.fail_reloading(environment).await;
+                }
+            }
+            ReloadStrategy::Requeue => {
+                for req_id in self.res_cache.in_environment(environment).await {
+                    let Some(pending) = self.res_cache.pop(&req_id).await else {
+                        continue;
+                    };
+                    // The invocation starts a new timeout when the function picks it up again.
+                    if let Some(timeout) = pending.timeout {
+                        timeout.abort();
+                    }
+
+                    let (parts, body) = pending.request.into_parts();
+                    let req = InvokeRequest {
+                        function_name: environment_function_name(environment).into(),
+                        req: Request::from_parts(parts, Body::from(body)),
+                        resp_tx: pending.resp_tx,
+                    };
+                    info!(
+                        environment,
+                        req_id, "sending the invocation again after the function reloads"
+                    );
+                    if let Err(error) = self.req_cache.upsert(req).await {
+                        tracing::error!(?error, req_id, "failed to send the invocation again");
+                    }
+                }
+            }
+            ReloadStrategy::Fail => self.fail_reloading(environment).await,
+        }
+    }
+
+    /// Fail the invocations in flight in an execution environment that is reloading.
+    async fn fail_reloading(&self, environment: &str) {
+        for req_id in self.res_cache.in_environment(environment).await {
+            let error = FunctionError::new(
+                RELOADING_ERROR_TYPE,
+                "The function is reloading after code changes, invoke it again",
+            );
+            self.fail_invocation(&req_id, error).await;
+        }
+    }
+
+    /// Fail the invocations that an execution environment was processing when its process
+    /// failed. If none of the function's environments can process invocations, the
+    /// invocations waiting for the function fail too, instead of waiting forever.
+    ///
+    /// Processes that exit during an invocation are started again, like Lambda resets
+    /// the execution environment. Processes that fail during their initialization wait
+    /// for code changes instead, so they don't restart in a loop.
+    pub(crate) async fn fail_environment(&self, environment: &str, failure: ProcessFailure) {
+        if let ProcessFailure::Build(reason) = &failure {
+            tracing::error!(
+                environment,
+                %reason,
+                "the function failed to build, invocations wait until it builds again"
+            );
+            return;
+        }
+
+        let in_flight = self.res_cache.in_environment(environment).await;
+        for req_id in &in_flight {
+            self.fail_invocation(req_id, failure.error(Some(req_id)))
+                .await;
+        }
+
+        let function_name = environment_function_name(environment);
+        if self
+            .processes
+            .function_failure(function_name)
+            .await
+            .is_some()
+        {
+            for invoke in self.req_cache.drain(function_name).await {
+                let error = failure.error(None).into_lambda_response();
+                if invoke.resp_tx.send(error).is_err() {
+                    debug!(
+                        function_name,
+                        "the invocation was cancelled before it failed"
+                    );
+                }
+            }
+        }
+
+        if !in_flight.is_empty() {
+            self.restart_environment(environment, SHUTDOWN_REASON_FAILURE)
+                .await;
+        }
+    }
+
+    /// Fail the execution environment of an extension that reports an error, like Lambda does.
+    /// The invocations in flight in the environment fail with the extension's error, the other
+    /// extensions in the environment receive a SHUTDOWN event, and the function's process
+    /// is restarted. Extensions that don't run in a specific environment fail all of them.
+    pub(crate) async fn fail_extension(&self, extension_id: &str, error: FunctionError) {
+        self.telemetry.unsubscribe(extension_id).await;
+        let Some(failed) = self.ext_cache.fail(extension_id, error.clone()).await else {
+            debug!(
+                extension_id,
+                error_type = %error.error_type,
+                "extension reported an error while shutting down"
+            );
+            return;
+        };
+        tracing::error!(
+            extension_id,
+            extension = ?failed.name,
+            environment = ?failed.environment,
+            error_type = %error.error_type,
+            "extension failed, resetting execution environment"
+        );
+
+        let environments = match &failed.environment {
+            Some(environment) => vec![environment.clone()],
+            None => self.processes.environments().await,
+        };
+
+        for environment in &environments {
+            for req_id in self.res_cache.in_environment(environment).await {
+                self.fail_invocation(&req_id, error.clone()).await;
+            }
+        }
+
+        self.ext_cache
+            .shutdown_environment(SHUTDOWN_REASON_FAILURE, failed.environment.as_deref())
+            .await;
+        for environment in &environments {
+            if let Err(error) = self
+                .processes
+                .restart(environment, SHUTDOWN_REASON_FAILURE)
+                .await
+            {
+                tracing::error!(?error, environment, "failed to restart function process");
+            }
+        }
+    }
+
+    /// Settings for a function. If the function hasn't started yet,
+    /// the settings are loaded from the function's metadata.
+    pub(crate) async fn function_settings(&self, name: &str) -> FunctionSettings {
+        if let Some(settings) = self.functions.loaded(name).await {
+            return settings;
+        }
+
+        let bin_name = (name != DEFAULT_PACKAGE_FUNCTION).then(|| name.to_string());
+        match reload_config(&self.manifest_path, &bin_name) {
+            Some(config) => self.functions.update(name, &config).await,
+            None => self.functions.get(name).await,
+        }
+    }
+
+    /// Whether the functions' processes are started outside the emulator.
+    pub(crate) fn is_only_lambda_apis(&self) -> bool {
+        self.only_lambda_apis
+    }
+
     pub(crate) fn is_default_function_enabled(&self) -> bool {
         self.initial_functions.len() == 1 || self.only_lambda_apis
     }
@@ -110,6 +461,17 @@ impl RequestQueue {
             .await
             .map_err(|e| ServerError::SendInvokeMessage(Box::new(e)))
     }
+
+    /// Take the next invocation, if there is one, without waiting for it.
+    pub async fn try_pop(&self) -> Option<InvokeRequest> {
+        let mut rx = self.rx.lock().await;
+        rx.try_recv().ok()
+    }
+
+    /// Number of invocations waiting for the function to pick them up.
+    pub fn len(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -192,99 +554,1713 @@ impl RequestCache {
         debug!(function_name, "request stack cleaned");
     }
 
-    pub async fn keys(&self) -> Vec<String> {
+    /// Take all the invocations waiting for a function to pick them up.
+    pub async fn drain(&self, function_name: &str) -> Vec<InvokeRequest> {
+        let Some(stack) = self.inner.read().await.get(function_name).cloned() else {
+            return Vec::new();
+        };
+
+        let mut invocations = Vec::new();
+        while let Some(req) = stack.try_pop().await {
+            invocations.push(req);
+        }
+        invocations
+    }
+
+    /// Number of queued invocations for every function.
+    pub async fn depths(&self) -> HashMap<String, usize> {
         let inner = self.inner.read().await;
-        inner.keys().cloned().collect()
+        inner
+            .iter()
+            .map(|(name, queue)| (name.clone(), queue.len()))
+            .collect()
     }
 }
 
+/// Number of completed invocations that the emulator keeps in its history.
+const INVOCATION_HISTORY_SIZE: usize = 100;
+
+/// Invocation that a function picked up, and that it's still processing.
+pub(crate) struct PendingResponse {
+    pub resp_tx: oneshot::Sender<LambdaResponse>,
+    pub environment: String,
+    pub started_at: Instant,
+    pub timestamp: String,
+    /// Request that the function received, to send it again if the function reloads
+    pub request: Request<Bytes>,
+    /// Timer that fails the invocation when the function's timeout expires
+    pub timeout: Option<AbortHandle>,
+}
+
+/// Invocation that a function is processing.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InFlightInvocation {
+    pub request_id: String,
+    pub function_name: String,
+    pub environment: String,
+    pub started_at: String,
+    pub elapsed_ms: u64,
+}
+
+/// Invocation that a function already completed.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CompletedInvocation {
+    pub request_id: String,
+    pub function_name: String,
+    pub environment: String,
+    pub started_at: String,
+    pub status: &'static str,
+    pub metrics: ReportMetrics,
+}
+
 #[derive(Clone)]
 pub(crate) struct ResponseCache {
-    inner: Arc<Mutex<HashMap<String, oneshot::Sender<LambdaResponse>>>>,
+    inner: Arc<Mutex<HashMap<String, PendingResponse>>>,
+    history: Arc<Mutex<VecDeque<CompletedInvocation>>>,
 }
 
 impl ResponseCache {
     pub fn new() -> ResponseCache {
         ResponseCache {
             inner: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(Mutex::new(VecDeque::with_capacity(INVOCATION_HISTORY_SIZE))),
         }
     }
 
-    pub async fn pop(&self, req_id: &str) -> Option<oneshot::Sender<LambdaResponse>> {
+    pub async fn pop(&self, req_id: &str) -> Option<PendingResponse> {
         let mut cache = self.inner.lock().await;
         cache.remove(req_id)
     }
 
-    pub async fn push(&self, req_id: &str, resp_tx: oneshot::Sender<LambdaResponse>) {
+    pub async fn push(
+        &self,
+        req_id: &str,
+        environment: &str,
+        resp_tx: oneshot::Sender<LambdaResponse>,
+        request: Request<Bytes>,
+    ) {
+        let pending = PendingResponse {
+            resp_tx,
+            environment: environment.into(),
+            started_at: Instant::now(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            request,
+            timeout: None,
+        };
+
+        let mut cache = self.inner.lock().await;
+        cache.insert(req_id.into(), pending);
+    }
+
+    /// Keep the timer that enforces the timeout of an invocation.
+    pub async fn set_timeout(&self, req_id: &str, timeout: AbortHandle) {
         let mut cache = self.inner.lock().await;
-        cache.insert(req_id.into(), resp_tx);
+        if let Some(pending) = cache.get_mut(req_id) {
+            pending.timeout = Some(timeout);
+        }
+    }
+
+    /// Keep an invocation in the history of completed invocations.
+    pub async fn complete(&self, invocation: CompletedInvocation) {
+        let mut history = self.history.lock().await;
+        if history.len() == INVOCATION_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(invocation);
+    }
+
+    /// Ids of the invocations that an execution environment is processing.
+    pub async fn in_environment(&self, environment: &str) -> Vec<String> {
+        let cache = self.inner.lock().await;
+        cache
+            .iter()
+            .filter(|(_, pending)| pending.environment == environment)
+            .map(|(req_id, _)| req_id.clone())
+            .collect()
+    }
+
+    /// Invocations that functions are processing, oldest first.
+    pub async fn in_flight(&self) -> Vec<InFlightInvocation> {
+        let cache = self.inner.lock().await;
+        let mut invocations = cache
+            .iter()
+            .map(|(req_id, pending)| InFlightInvocation {
+                request_id: req_id.clone(),
+                function_name: environment_function_name(&pending.environment).into(),
+                environment: pending.environment.clone(),
+                started_at: pending.timestamp.clone(),
+                elapsed_ms: pending.started_at.elapsed().as_millis() as u64,
+            })
+            .collect::<Vec<_>>();
+        invocations.sort_by_key(|i| std::cmp::Reverse(i.elapsed_ms));
+        invocations
+    }
+
+    /// Last invocations that functions completed, most recent first.
+    pub async fn history(&self, limit: usize) -> Vec<CompletedInvocation> {
+        let history = self.history.lock().await;
+        history.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// Maximum number of events waiting for an extension to ask for them.
+/// The oldest events are dropped when an extension doesn't keep up.
+const EXTENSION_QUEUE_SIZE: usize = 100;
+
+/// How long extensions have to process a SHUTDOWN event
+/// before the function's processes are stopped.
+pub(crate) const EXTENSION_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Extension registered in the emulator, and the events waiting to be delivered to it.
+#[derive(Debug, Default)]
+struct ExtensionEntry {
+    name: Option<String>,
+    events: Vec<String>,
+    /// Execution environment of the extensions that the emulator starts next to
+    /// a function's process. Other extensions receive events from every environment
+    environment: Option<String>,
+    /// Channel of the `next` request that the extension is waiting on, if any
+    sender: Option<oneshot::Sender<NextEvent>>,
+    /// Events received while the extension was not waiting for them
+    queue: VecDeque<NextEvent>,
+    /// Whether the extension received a SHUTDOWN event,
+    /// and it didn't ask for the next event yet
+    shutting_down: bool,
+}
+
+impl ExtensionEntry {
+    fn deliver(&mut self, event: NextEvent) {
+        let event = match self.sender.take() {
+            None => event,
+            Some(tx) => {
+                let is_shutdown = matches!(event, NextEvent::Shutdown(_));
+                match tx.send(event) {
+                    Ok(()) => {
+                        self.shutting_down |= is_shutdown;
+                        return;
+                    }
+                    // The extension closed the connection, keep the event for its next request.
+                    Err(event) => event,
+                }
+            }
+        };
+
+        if self.queue.len() >= EXTENSION_QUEUE_SIZE {
+            tracing::warn!(extension = ?self.name, "dropping extension event, the queue is full");
+            self.queue.pop_front();
+        }
+        self.queue.push_back(event);
+    }
+
+    fn pending_shutdown(&self) -> bool {
+        self.shutting_down
+            || self
+                .queue
+                .iter()
+                .any(|e| matches!(e, NextEvent::Shutdown(_)))
     }
 }
 
 #[derive(Clone, Default)]
 pub(crate) struct ExtensionCache {
-    extensions: Arc<Mutex<HashMap<String, Vec<String>>>>,
-    events: Arc<Mutex<HashMap<String, Vec<String>>>>,
-    senders: Arc<Mutex<HashMap<String, mpsc::Sender<NextEvent>>>>,
+    extensions: Arc<Mutex<HashMap<String, ExtensionEntry>>>,
+    /// Notified when an extension finishes processing a SHUTDOWN event
+    shutdown_done: Arc<Notify>,
+    /// Errors reported by extensions, by the execution environment that they fail.
+    /// `None` is for the extensions that don't run in a specific environment,
+    /// their errors fail every environment
+    failures: Arc<Mutex<HashMap<Option<String>, FunctionError>>>,
+    /// Notified when an extension registers
+    registered: Arc<Notify>,
+}
+
+/// Extension that reported an error.
+#[derive(Debug)]
+pub(crate) struct FailedExtension {
+    pub name: Option<String>,
+    pub environment: Option<String>,
 }
 
 impl ExtensionCache {
-    pub async fn register(&self, events: Vec<String>) -> String {
+    pub async fn register(
+        &self,
+        name: Option<String>,
+        events: Vec<String>,
+        environment: Option<String>,
+    ) -> String {
         let mut extensions = self.extensions.lock().await;
-        let extension_id = Uuid::new_v4();
+        let extension_id = Uuid::new_v4().to_string();
 
-        extensions.insert(extension_id.to_string(), events.clone());
+        // An extension registering again starts a new execution environment.
+        self.failures.lock().await.remove(&environment);
 
-        let mut list = self.events.lock().await;
-        for event in events {
-            list.entry(event)
-                .and_modify(|e| e.push(extension_id.to_string()))
-                .or_insert(vec![extension_id.to_string()]);
-        }
+        extensions.insert(
+            extension_id.clone(),
+            ExtensionEntry {
+                name,
+                events,
+                environment,
+                ..Default::default()
+            },
+        );
+        self.registered.notify_waiters();
 
-        extension_id.to_string()
+        extension_id
     }
 
-    pub async fn set_senders(&self, extension_id: &str, sender: mpsc::Sender<NextEvent>) {
-        let extensions = self.extensions.lock().await;
-        if let Some(events) = extensions.get(extension_id) {
-            let mut senders = self.senders.lock().await;
-            for event in events {
-                let name = format!("{extension_id}_{event}");
-                senders.insert(name, sender.clone());
+    /// Wait until an execution environment has a number of registered extensions.
+    pub async fn wait_for_registration(&self, environment: &str, count: usize) {
+        loop {
+            let notified = self.registered.notified();
+            let registered = self
+                .extensions
+                .lock()
+                .await
+                .values()
+                .filter(|e| e.environment.as_deref() == Some(environment))
+                .count();
+            if registered >= count {
+                return;
             }
+            notified.await;
         }
     }
 
-    pub async fn send_event(&self, event: NextEvent) -> Result<(), ServerError> {
-        let events = self.events.lock().await;
+    pub async fn is_registered(&self, extension_id: &str) -> bool {
+        self.extensions.lock().await.contains_key(extension_id)
+    }
+
+    /// Execution environment of a registered extension,
+    /// `None` for the extensions that don't run in a specific environment.
+    pub async fn environment(&self, extension_id: &str) -> Result<Option<String>, ServerError> {
+        match self.extensions.lock().await.get(extension_id) {
+            Some(entry) => Ok(entry.environment.clone()),
+            None => Err(ServerError::UnknownExtension(extension_id.to_string())),
+        }
+    }
+
+    /// Wait for the next event that an extension subscribed to.
+    /// Asking for the next event also tells the emulator that
+    /// the extension finished processing the previous one.
+    pub async fn next_event(&self, extension_id: &str) -> Result<NextEvent, ServerError> {
+        let rx = {
+            let mut extensions = self.extensions.lock().await;
+            let Some(entry) = extensions.get_mut(extension_id) else {
+                return Err(ServerError::UnknownExtension(extension_id.to_string()));
+            };
+
+            if entry.shutting_down {
+                entry.shutting_down = false;
+                self.shutdown_done.notify_waiters();
+            }
+
+            if let Some(event) = entry.queue.pop_front() {
+                entry.shutting_down = matches!(event, NextEvent::Shutdown(_));
+                return Ok(event);
+            }
+
+            let (tx, rx) = oneshot::channel();
+            entry.sender = Some(tx);
+            rx
+        };
+
+        rx.await.map_err(|_| ServerError::NoExtensionEvent)
+    }
+
+    /// Send an event to the extensions that registered for its type,
+    /// and that run in the execution environment that the event comes from.
+    pub async fn send_event(&self, event: NextEvent, environment: &str) {
+        self.deliver(event, in_environment(Some(environment))).await;
+    }
+
+    /// Deliver an event to the extensions that registered for its type,
+    /// and that run in the execution environments that `filter` accepts.
+    /// The filter receives `None` for the extensions that don't run in a specific environment.
+    async fn deliver(&self, event: NextEvent, filter: impl Fn(Option<&str>) -> bool) {
+        let mut extensions = self.extensions.lock().await;
 
         let queue = event.type_queue();
+        for entry in extensions.values_mut() {
+            if filter(entry.environment.as_deref()) && entry.events.iter().any(|e| e == queue) {
+                entry.deliver(event.clone());
+            }
+        }
+    }
+
+    /// Remove the extensions started in an execution environment, when their processes stop.
+    pub async fn unregister_environment(&self, environment: &str) {
+        let mut extensions = self.extensions.lock().await;
+        extensions.retain(|_, entry| entry.environment.as_deref() != Some(environment));
+        self.shutdown_done.notify_waiters();
+    }
 
-        if let Some(ids) = events.get(queue) {
-            let senders = self.senders.lock().await;
+    /// Send a SHUTDOWN event to the extensions that registered for it, and wait
+    /// until they ask for their next event, or until the shutdown deadline passes.
+    pub async fn shutdown(&self, reason: &str) {
+        self.shutdown_environment(reason, None).await;
+    }
+
+    /// Send a SHUTDOWN event to the extensions in an execution environment, like `shutdown`.
+    /// When `environment` is `None`, the event goes to the extensions in every environment.
+    pub async fn shutdown_environment(&self, reason: &str, environment: Option<&str>) {
+        self.shutdown_matching(reason, in_environment(environment))
+            .await;
+    }
+
+    /// Send a SHUTDOWN event to the extensions in a function's execution environments,
+    /// and to the extensions that don't run in a specific environment, like `shutdown`.
+    pub async fn shutdown_function(&self, reason: &str, function_name: &str) {
+        self.shutdown_matching(reason, |environment| {
+            environment.is_none_or(|e| environment_function_name(e) == function_name)
+        })
+        .await;
+    }
+
+    async fn shutdown_matching(&self, reason: &str, filter: impl Fn(Option<&str>) -> bool) {
+        let deadline_ms = (SystemTime::now() + EXTENSION_SHUTDOWN_TIMEOUT)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.deliver(NextEvent::shutdown(reason, deadline_ms), filter)
+            .await;
 
-            for id in ids {
-                let name = format!("{id}_{queue}");
-                if let Some(tx) = senders.get(&name) {
-                    tx.send(event.clone())
-                        .await
-                        .map_err(|e| ServerError::SendEventMessage(Box::new(e)))?;
+        let wait = async {
+            loop {
+                let notified = self.shutdown_done.notified();
+                if !self.shutdown_pending().await {
+                    break;
                 }
+                notified.await;
+            }
+        };
+
+        if tokio::time::timeout(EXTENSION_SHUTDOWN_TIMEOUT, wait)
+            .await
+            .is_err()
+        {
+            let mut extensions = self.extensions.lock().await;
+            for (id, entry) in extensions.iter_mut().filter(|(_, e)| e.pending_shutdown()) {
+                tracing::warn!(extension_id = %id, name = ?entry.name, "extension didn't complete its shutdown before the deadline");
+                entry.shutting_down = false;
+                entry.queue.retain(|e| !matches!(e, NextEvent::Shutdown(_)));
             }
         }
+    }
 
-        Ok(())
+    async fn shutdown_pending(&self) -> bool {
+        let extensions = self.extensions.lock().await;
+        extensions.values().any(ExtensionEntry::pending_shutdown)
+    }
+
+    /// Remove an extension that reported an error, and keep the error to fail the
+    /// invocations in its execution environment until an extension registers again there.
+    /// Errors reported while the extension processes a SHUTDOWN event don't fail
+    /// the environment, which is already shutting down. It returns the extension
+    /// that failed the environment, if any.
+    pub async fn fail(&self, extension_id: &str, error: FunctionError) -> Option<FailedExtension> {
+        let entry = self.extensions.lock().await.remove(extension_id)?;
+        self.shutdown_done.notify_waiters();
+
+        if entry.pending_shutdown() {
+            return None;
+        }
+
+        self.failures
+            .lock()
+            .await
+            .insert(entry.environment.clone(), error);
+
+        Some(FailedExtension {
+            name: entry.name,
+            environment: entry.environment,
+        })
     }
 
-    pub async fn clear(&self, extension_id: &str) {
+    /// Error that failed a function's execution environments, if extensions reported one
+    /// that fails all of them.
+    pub async fn failure(&self, environments: &[String]) -> Option<FunctionError> {
+        let failures = self.failures.lock().await;
+        if let Some(error) = failures.get(&None) {
+            return Some(error.clone());
+        }
+
+        let mut failure = None;
+        for environment in environments {
+            failure = Some(failures.get(&Some(environment.clone()))?.clone());
+        }
+        failure
+    }
+
+    /// Extensions registered in the emulator, and the events they subscribed to.
+    pub async fn registered(&self) -> Vec<RegisteredExtension> {
         let extensions = self.extensions.lock().await;
-        if let Some(events) = extensions.get(extension_id) {
-            let mut senders = self.senders.lock().await;
-            for event in events {
-                let name = format!("{extension_id}_{event}");
-                senders.remove(&name);
+
+        let mut registered = extensions
+            .iter()
+            .map(|(id, entry)| RegisteredExtension {
+                id: id.clone(),
+                name: entry.name.clone(),
+                events: entry.events.clone(),
+                environment: entry.environment.clone(),
+            })
+            .collect::<Vec<_>>();
+        registered.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        registered
+    }
+}
+
+/// Filter for the extensions that receive the events of an execution environment.
+/// When `environment` is `None`, it accepts the extensions in every environment.
+fn in_environment(environment: Option<&str>) -> impl Fn(Option<&str>) -> bool + '_ {
+    move |entry_env| match (entry_env, environment) {
+        (Some(entry_env), Some(environment)) => entry_env == environment,
+        _ => true,
+    }
+}
+
+/// Extension registered in the emulator.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RegisteredExtension {
+    pub id: String,
+    pub name: Option<String>,
+    pub events: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+}
+
+/// Character that separates the function name from the number of
+/// the execution environment in the runtime API address of a process.
+const ENVIRONMENT_SEPARATOR: char = '@';
+
+/// Identifier of an execution environment for a function.
+/// The first environment uses the function name as identifier,
+/// so functions with one environment keep the same runtime API address.
+pub(crate) fn environment_id(function_name: &str, index: u32) -> String {
+    if index == 0 {
+        function_name.to_string()
+    } else {
+        format!("{function_name}{ENVIRONMENT_SEPARATOR}{index}")
+    }
+}
+
+/// Name of the function that an execution environment belongs to.
+pub(crate) fn environment_function_name(environment_id: &str) -> &str {
+    environment_id
+        .split_once(ENVIRONMENT_SEPARATOR)
+        .map(|(name, _)| name)
+        .unwrap_or(environment_id)
+}
+
+/// How often the emulator checks whether an execution environment
+/// completed its invocations, before reloading the function.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Memory size, in MB, for functions that don't configure one.
+pub(crate) const DEFAULT_MEMORY_SIZE: u32 = 4096;
+
+/// Maximum age, in seconds, of asynchronous invocations
+/// for functions that don't configure one.
+pub(crate) const DEFAULT_MAX_EVENT_AGE: u32 = 21600;
+
+/// Where the emulator sends asynchronous invocations
+/// that fail after all the retries.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum OnFailureDestination {
+    /// Invoke another function with the failed invocation
+    Function(String),
+    /// Store the failed invocation as a JSON file in a directory
+    Directory(PathBuf),
+}
+
+impl OnFailureDestination {
+    fn from_watch(config: &Watch) -> Option<OnFailureDestination> {
+        match (&config.on_failure_function, &config.on_failure_dir) {
+            (Some(function), _) => Some(OnFailureDestination::Function(function.clone())),
+            (None, Some(dir)) => Some(OnFailureDestination::Directory(dir.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Deadline of the invocations of functions without a timeout.
+/// The emulator doesn't stop these functions when they go over it.
+const DEFAULT_DEADLINE: Duration = Duration::from_secs(600);
+
+/// Settings that the emulator applies to a function,
+/// loaded from the project's metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct FunctionSettings {
+    /// How long the function can be running for an invocation.
+    /// Only enforced when the function's metadata, or the flags, include it
+    pub timeout: Option<Timeout>,
+    /// Number of execution environments started for the function
+    pub concurrency: u32,
+    /// Maximum number of invocations that the function can process at the same time
+    pub reserved_concurrency: Option<u32>,
+    /// Amount of memory, in MB, available to the function
+    pub memory: u32,
+    /// Whether the function's process is stopped when it uses more memory than it has available
+    pub enforce_memory: bool,
+    /// How long asynchronous invocations can wait to be processed
+    pub max_event_age: Duration,
+    /// Where asynchronous invocations go when they fail after all the retries
+    pub on_failure: Option<OnFailureDestination>,
+    /// Authentication type of the function's URL
+    pub auth_type: AuthType,
+    /// AWS configuration used to load the credentials that sign requests to the function's URL
+    pub remote_config: Option<RemoteConfig>,
+    /// Format and levels of the function's logs
+    pub logging: LoggingConfig,
+}
+
+impl Default for FunctionSettings {
+    fn default() -> Self {
+        FunctionSettings {
+            timeout: None,
+            concurrency: 1,
+            reserved_concurrency: None,
+            memory: DEFAULT_MEMORY_SIZE,
+            enforce_memory: false,
+            max_event_age: Duration::from_secs(DEFAULT_MAX_EVENT_AGE as u64),
+            on_failure: None,
+            auth_type: AuthType::default(),
+            remote_config: None,
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+impl FunctionSettings {
+    /// How long the function can take to process an invocation,
+    /// its timeout if it has one, or `DEFAULT_DEADLINE` otherwise.
+    pub fn deadline(&self) -> Duration {
+        self.timeout
+            .as_ref()
+            .map(Timeout::duration)
+            .unwrap_or(DEFAULT_DEADLINE)
+    }
+
+    /// Settings for every function, from the flags passed to the watch command.
+    pub fn from_watch(config: &Watch) -> FunctionSettings {
+        FunctionSettings {
+            timeout: config.timeout.clone(),
+            concurrency: config.concurrency.unwrap_or(1).max(1),
+            reserved_concurrency: config.reserved_concurrency,
+            enforce_memory: config.enforce_memory,
+            max_event_age: Duration::from_secs(
+                config.max_event_age.unwrap_or(DEFAULT_MAX_EVENT_AGE) as u64,
+            ),
+            on_failure: OnFailureDestination::from_watch(config),
+            auth_type: config.function_url_auth.unwrap_or_default(),
+            logging: config.logging.clone().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Settings for a specific function, from the function's metadata.
+    /// The values that the metadata doesn't include are taken from `defaults`.
+    pub fn from_config(config: &Config, defaults: &FunctionSettings) -> FunctionSettings {
+        FunctionSettings {
+            timeout: config
+                .watch
+                .timeout
+                .clone()
+                .or_else(|| config.deploy.function_config.timeout.clone())
+                .or_else(|| defaults.timeout.clone()),
+            concurrency: config
+                .watch
+                .concurrency
+                .map(|c| c.max(1))
+                .unwrap_or(defaults.concurrency),
+            reserved_concurrency: config
+                .watch
+                .reserved_concurrency
+                .or(defaults.reserved_concurrency),
+            memory: config
+                .deploy
+                .function_config
+                .memory
+                .as_ref()
+                .map(|m| i32::from(m) as u32)
+                .unwrap_or(DEFAULT_MEMORY_SIZE),
+            enforce_memory: config.watch.enforce_memory || defaults.enforce_memory,
+            max_event_age: config
+                .watch
+                .max_event_age
+                .map(|age| Duration::from_secs(age as u64))
+                .unwrap_or(defaults.max_event_age),
+            on_failure: OnFailureDestination::from_watch(&config.watch)
+                .or_else(|| defaults.on_failure.clone()),
+            auth_type: config.watch.function_url_auth.unwrap_or(defaults.auth_type),
+            remote_config: config.deploy.remote_config.clone(),
+            logging: config
+                .watch
+                .logging
+                .clone()
+                .unwrap_or_default()
+                .or(config.deploy.function_config.logging.as_ref())
+                .or(Some(&defaults.logging)),
+        }
+    }
+}
+
+/// Concurrency reserved for an invocation.
+#[derive(Debug)]
+pub(crate) enum Reservation {
+    /// The function doesn't have a reserved concurrency limit
+    Unlimited,
+    /// The invocation holds one of the function's reserved slots until it's dropped
+    Reserved(#[allow(dead_code)] OwnedSemaphorePermit),
+    /// The function is already processing as many invocations as its limit allows
+    Throttled,
+}
+
+#[derive(Debug)]
+struct FunctionEntry {
+    settings: FunctionSettings,
+    throttle: Option<Arc<Semaphore>>,
+    /// Whether the settings were loaded from the function's metadata
+    loaded: bool,
+}
+
+impl FunctionEntry {
+    fn new(settings: FunctionSettings, loaded: bool) -> FunctionEntry {
+        let throttle = settings
+            .reserved_concurrency
+            .map(|limit| Arc::new(Semaphore::new(limit as usize)));
+
+        FunctionEntry {
+            settings,
+            throttle,
+            loaded,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct FunctionCache {
+    defaults: FunctionSettings,
+    inner: Arc<RwLock<HashMap<String, FunctionEntry>>>,
+}
+
+impl FunctionCache {
+    pub fn new(defaults: FunctionSettings) -> FunctionCache {
+        FunctionCache {
+            defaults,
+            inner: Default::default(),
+        }
+    }
+
+    pub async fn get(&self, function_name: &str) -> FunctionSettings {
+        let inner = self.inner.read().await;
+        inner
+            .get(function_name)
+            .map(|entry| entry.settings.clone())
+            .unwrap_or_else(|| self.defaults.clone())
+    }
+
+    /// Names of the functions that have settings in the cache.
+    pub async fn names(&self) -> Vec<String> {
+        let inner = self.inner.read().await;
+        inner.keys().cloned().collect()
+    }
+
+    /// Settings for a function, if they were already loaded from its metadata.
+    pub async fn loaded(&self, function_name: &str) -> Option<FunctionSettings> {
+        let inner = self.inner.read().await;
+        inner
+            .get(function_name)
+            .filter(|entry| entry.loaded)
+            .map(|entry| entry.settings.clone())
+    }
+
+    /// Update the settings for a function with the values in its metadata.
+    pub async fn update(&self, function_name: &str, config: &Config) -> FunctionSettings {
+        let settings = FunctionSettings::from_config(config, &self.defaults);
+
+        let mut inner = self.inner.write().await;
+        match inner.entry(function_name.into()) {
+            Entry::Occupied(mut o)
+                if o.get().settings.reserved_concurrency == settings.reserved_concurrency =>
+            {
+                let entry = o.get_mut();
+                entry.settings = settings.clone();
+                entry.loaded = true;
+            }
+            Entry::Occupied(mut o) => {
+                o.insert(FunctionEntry::new(settings.clone(), true));
+            }
+            Entry::Vacant(v) => {
+                v.insert(FunctionEntry::new(settings.clone(), true));
+            }
+        }
+
+        settings
+    }
+
+    /// Reserve concurrency for an invocation. The reservation
+    /// is released when the invocation completes.
+    pub async fn reserve(&self, function_name: &str) -> Reservation {
+        let throttle = {
+            let inner = self.inner.read().await;
+            match inner.get(function_name) {
+                Some(entry) => entry.throttle.clone(),
+                None => {
+                    drop(inner);
+                    let mut inner = self.inner.write().await;
+                    let entry = inner
+                        .entry(function_name.into())
+                        .or_insert_with(|| FunctionEntry::new(self.defaults.clone(), false));
+                    entry.throttle.clone()
+                }
+            }
+        };
+
+        match throttle {
+            None => Reservation::Unlimited,
+            Some(throttle) => match throttle.try_acquire_owned() {
+                Ok(permit) => Reservation::Reserved(permit),
+                Err(_) => Reservation::Throttled,
+            },
+        }
+    }
+}
+
+/// Whether the process of an execution environment is running, or why it failed.
+#[derive(Debug, Default)]
+struct ProcessStatus {
+    running: bool,
+    /// Number of processes that the emulator stopped,
+    /// and that didn't report their completion yet
+    stopping: u32,
+    failure: Option<ProcessFailure>,
+}
+
+/// Reason why the process of an execution environment stopped working.
+#[derive(Clone, Debug)]
+pub(crate) enum ProcessFailure {
+    /// The function reported an error with the `init/error` endpoint
+    Init(FunctionError),
+    /// The process exited unexpectedly, with the reason that Lambda reports
+    Exit(String),
+    /// `cargo build` failed, and the function didn't start
+    Build(String),
+}
+
+impl ProcessFailure {
+    /// Error that fails an invocation, with the same message that Lambda reports.
+    pub(crate) fn error(&self, req_id: Option<&str>) -> FunctionError {
+        match (self, req_id) {
+            (ProcessFailure::Init(error), _) => error.clone(),
+            (ProcessFailure::Exit(reason) | ProcessFailure::Build(reason), Some(req_id)) => {
+                FunctionError::new(
+                    EXIT_ERROR_TYPE,
+                    &format!("RequestId: {req_id} Error: {reason}"),
+                )
+            }
+            (ProcessFailure::Exit(reason) | ProcessFailure::Build(reason), None) => {
+                FunctionError::new(EXIT_ERROR_TYPE, &format!("Error: {reason}"))
+            }
+        }
+    }
+}
+
+/// Initialization of the process running in an execution environment.
+#[derive(Debug, Default)]
+struct ProcessInit {
+    pid: u32,
+    /// When the function's binary started
+    started_at: Option<Instant>,
+    /// How long the function took to be ready for its first invocation
+    duration: Option<Duration>,
+    /// Whether the duration was already reported in an invocation
+    reported: bool,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct ProcessCache {
+    inner: Arc<Mutex<HashMap<String, Arc<Watchexec>>>>,
+    invocations: Arc<Mutex<HashMap<String, String>>>,
+    init: Arc<Mutex<HashMap<String, ProcessInit>>>,
+    /// Extension processes started next to the function in each execution environment
+    extensions: Arc<Mutex<HashMap<String, Vec<Child>>>>,
+    status: Arc<Mutex<HashMap<String, ProcessStatus>>>,
+    /// Execution environments that don't receive new invocations
+    /// until their process restarts with the function's new code
+    draining: Arc<Mutex<HashSet<String>>>,
+    drain_changed: Arc<Notify>,
+    /// Binaries that `cargo build` built in each execution environment
+    binaries: Arc<Mutex<HashMap<String, PathBuf>>>,
+    binary_built: Arc<Notify>,
+    /// Execution environments that reload the function's other environments
+    /// after they build the function again
+    outdated_replicas: Arc<Mutex<HashSet<String>>>,
+    /// Binaries of the extensions, by extension name.
+    /// `None` is for the extensions that failed to build
+    extension_binaries: Arc<Mutex<HashMap<String, Option<PathBuf>>>>,
+    extension_built: Arc<Notify>,
+}
+
+impl ProcessCache {
+    pub async fn insert(&self, environment: &str, wx: Arc<Watchexec>) {
+        let mut inner = self.inner.lock().await;
+        inner.insert(environment.into(), wx);
+    }
+
+    pub async fn remove(&self, environment: &str) {
+        let mut inner = self.inner.lock().await;
+        inner.remove(environment);
+
+        let mut invocations = self.invocations.lock().await;
+        invocations.remove(environment);
+
+        let mut init = self.init.lock().await;
+        init.remove(environment);
+
+        let mut status = self.status.lock().await;
+        status.remove(environment);
+
+        self.draining.lock().await.remove(environment);
+        self.drain_changed.notify_waiters();
+
+        self.binaries.lock().await.remove(environment);
+        self.outdated_replicas.lock().await.remove(environment);
+
+        self.stop_extensions(environment).await;
+    }
+
+    /// Stop sending new invocations to an execution environment until its process restarts.
+    pub async fn start_drain(&self, environment: &str) {
+        self.draining.lock().await.insert(environment.into());
+        self.drain_changed.notify_waiters();
+    }
+
+    /// Wait until an execution environment starts draining its invocations.
+    pub async fn draining(&self, environment: &str) {
+        self.wait_for_drain(environment, true).await
+    }
+
+    /// Wait until the process of a draining execution environment restarts.
+    pub async fn drained(&self, environment: &str) {
+        self.wait_for_drain(environment, false).await
+    }
+
+    async fn wait_for_drain(&self, environment: &str, draining: bool) {
+        loop {
+            let notified = self.drain_changed.notified();
+            if self.draining.lock().await.contains(environment) == draining {
+                return;
             }
+            notified.await;
+        }
+    }
+
+    /// Mark the process of an execution environment as stopped by the emulator,
+    /// so its completion is not reported as a failure.
+    pub async fn stopping(&self, environment: &str) {
+        let mut status = self.status.lock().await;
+        if let Some(status) = status.get_mut(environment).filter(|s| s.running) {
+            status.running = false;
+            status.stopping += 1;
+        }
+    }
+
+    /// Keep the error that a function reported during its initialization.
+    /// The process cannot process invocations after reporting the error.
+    pub async fn init_failed(&self, environment: &str, error: FunctionError) {
+        let mut status = self.status.lock().await;
+        let status = status.entry(environment.into()).or_default();
+        status.running = false;
+        status.failure = Some(ProcessFailure::Init(error));
+    }
+
+    /// Record that the process of an execution environment completed.
+    /// It returns the reason of the failure, or `None` if the emulator stopped the process.
+    /// The error that the function reported during its initialization takes precedence
+    /// over the process' exit reason.
+    pub async fn exited(&self, environment: &str, reason: String) -> Option<ProcessFailure> {
+        let mut status = self.status.lock().await;
+        let status = status.entry(environment.into()).or_default();
+        if status.stopping > 0 {
+            status.stopping -= 1;
+            return None;
         }
+
+        status.running = false;
+        Some(
+            status
+                .failure
+                .get_or_insert(ProcessFailure::Exit(reason))
+                .clone(),
+        )
+    }
+
+    /// Record that the function failed to build in an execution environment,
+    /// so its process didn't start.
+    pub async fn build_failed(&self, environment: &str, reason: String) -> ProcessFailure {
+        let mut status = self.status.lock().await;
+        let status = status.entry(environment.into()).or_default();
+        status.running = false;
+
+        let failure = ProcessFailure::Build(reason);
+        status.failure = Some(failure.clone());
+        failure
+    }
+
+    /// Failure of a function when none of its execution environments can process invocations.
+    /// Build failures are not function failures, the invocations wait in the queue until
+    /// the code changes and the function builds again.
+    pub async fn function_failure(&self, function_name: &str) -> Option<ProcessFailure> {
+        let status = self.status.lock().await;
+
+        let mut failure = None;
+        for (_, status) in status
+            .iter()
+            .filter(|(e, _)| environment_function_name(e) == function_name)
+        {
+            match status {
+                ProcessStatus {
+                    running: false,
+                    failure: Some(f),
+                    ..
+                } if !matches!(f, ProcessFailure::Build(_)) => failure = Some(f.clone()),
+                _ => return None,
+            }
+        }
+        failure
+    }
+
+    /// Keep track of the extension processes started in an execution environment.
+    pub async fn set_extensions(&self, environment: &str, processes: Vec<Child>) {
+        let mut extensions = self.extensions.lock().await;
+        extensions.insert(environment.into(), processes);
+    }
+
+    /// Keep the binary that an extension runs, or `None` if the extension failed to build.
+    pub async fn set_extension_binary(&self, name: &str, binary: Option<PathBuf>) {
+        self.extension_binaries
+            .lock()
+            .await
+            .insert(name.into(), binary);
+        self.extension_built.notify_waiters();
+    }
+
+    pub async fn has_extension_binary(&self, name: &str) -> bool {
+        self.extension_binaries.lock().await.contains_key(name)
+    }
+
+    /// Wait until an extension is built, and return its binary.
+    pub async fn extension_binary(&self, name: &str) -> Option<PathBuf> {
+        loop {
+            let notified = self.extension_built.notified();
+            if let Some(binary) = self.extension_binaries.lock().await.get(name) {
+                return binary.clone();
+            }
+            notified.await;
+        }
+    }
+
+    /// Build the extensions again the next time that a function starts, after code changes.
+    pub async fn clear_extension_binaries(&self) {
+        self.extension_binaries.lock().await.clear();
+    }
+
+    /// Kill the extension processes running in an execution environment.
+    pub async fn stop_extensions(&self, environment: &str) {
+        let processes = self.extensions.lock().await.remove(environment);
+        for mut process in processes.unwrap_or_default() {
+            if let Err(error) = process.kill().await {
+                debug!(?error, environment, "failed to stop extension process");
+            }
+        }
+    }
+
+    /// Keep track of a new process started in an execution environment.
+    pub async fn spawned(&self, environment: &str, pid: u32) {
+        let mut status = self.status.lock().await;
+        let status = status.entry(environment.into()).or_default();
+        status.running = true;
+        status.failure = None;
+
+        let mut init = self.init.lock().await;
+        init.insert(
+            environment.into(),
+            ProcessInit {
+                pid,
+                ..Default::default()
+            },
+        );
+
+        self.draining.lock().await.remove(environment);
+        self.drain_changed.notify_waiters();
+    }
+
+    /// Keep the binary that an execution environment built for the function.
+    /// The function's other environments reload with the new binary
+    /// if the build followed code changes.
+    pub async fn build_finished(&self, environment: &str, binary: PathBuf) {
+        self.binaries
+            .lock()
+            .await
+            .insert(environment.into(), binary);
+        self.binary_built.notify_waiters();
+
+        if self.outdated_replicas.lock().await.remove(environment) {
+            self.reload_replicas(environment).await;
+        }
+    }
+
+    /// Wait until an execution environment builds the function's binary.
+    pub async fn built_binary(&self, environment: &str) -> PathBuf {
+        loop {
+            let notified = self.binary_built.notified();
+            if let Some(binary) = self.binaries.lock().await.get(environment) {
+                return binary.clone();
+            }
+            notified.await;
+        }
+    }
+
+    /// Reload the function's other execution environments after
+    /// the next build in an execution environment.
+    pub async fn reload_after_build(&self, environment: &str) {
+        self.outdated_replicas
+            .lock()
+            .await
+            .insert(environment.into());
+    }
+
+    /// Reload the processes of the function's other execution environments,
+    /// that run the code that an execution environment builds.
+    pub async fn reload_replicas(&self, environment: &str) {
+        let function_name = environment_function_name(environment);
+        let replicas = self
+            .inner
+            .lock()
+            .await
+            .iter()
+            .filter(|(e, _)| *e != environment && environment_function_name(e) == function_name)
+            .map(|(e, wx)| (e.clone(), wx.clone()))
+            .collect::<Vec<_>>();
+
+        for (replica, wx) in replicas {
+            debug!(environment = replica, "reloading function process");
+            if let Err(error) = wx
+                .send_event(crate::watcher::reload_event(), Priority::Normal)
+                .await
+            {
+                tracing::error!(
+                    ?error,
+                    environment = replica,
+                    "failed to reload function process"
+                );
+            }
+        }
+    }
+
+    /// Mark the moment when the function's binary started in an execution environment.
+    pub async fn init_started(&self, environment: &str, pid: u32) {
+        let mut init = self.init.lock().await;
+        if let Some(init) = init.get_mut(environment).filter(|i| i.pid == pid) {
+            init.started_at = Some(Instant::now());
+        }
+    }
+
+    /// Mark the end of the initialization in an execution environment.
+    /// Only the first call after the process starts changes the init duration.
+    pub async fn init_done(&self, environment: &str) {
+        let mut init = self.init.lock().await;
+        if let Some(init) = init.get_mut(environment) {
+            if init.duration.is_none() {
+                init.duration = init.started_at.map(|started_at| started_at.elapsed());
+            }
+        }
+    }
+
+    /// Peak memory used by the process in an execution environment,
+    /// and its init duration if it has not been reported yet.
+    pub async fn measurements(&self, environment: &str) -> (Option<u64>, Option<Duration>) {
+        let mut init = self.init.lock().await;
+        let Some(init) = init.get_mut(environment) else {
+            return (None, None);
+        };
+
+        let init_duration = if init.reported {
+            None
+        } else {
+            init.reported = true;
+            init.duration
+        };
+
+        (memory::max_memory_used(init.pid), init_duration)
+    }
+
+    /// Keep track of the last invocation that an execution environment received.
+    pub async fn set_invocation(&self, environment: &str, req_id: &str) {
+        let mut invocations = self.invocations.lock().await;
+        invocations.insert(environment.into(), req_id.into());
+    }
+
+    pub async fn invocation(&self, environment: &str) -> Option<String> {
+        let invocations = self.invocations.lock().await;
+        invocations.get(environment).cloned()
+    }
+
+    /// Execution environments with a running process.
+    pub async fn environments(&self) -> Vec<String> {
+        let inner = self.inner.lock().await;
+        let mut environments = inner.keys().cloned().collect::<Vec<_>>();
+        environments.sort();
+        environments
+    }
+
+    /// Stop the process running in an execution environment
+    /// and start it again, without recompiling the function.
+    pub async fn restart(&self, environment: &str, reason: &str) -> Result<(), ServerError> {
+        let wx = self.inner.lock().await.get(environment).cloned();
+
+        if let Some(wx) = wx {
+            debug!(environment, reason, "restarting function process");
+            wx.send_event(crate::watcher::restart_event(reason), Priority::Urgent)
+                .await
+                .map_err(|e| ServerError::WatcherError(Box::new(e)))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[test]
+    fn test_function_timeout() {
+        let defaults = FunctionSettings::default();
+        let mut config = Config::default();
+        let settings = FunctionSettings::from_config(&config, &defaults);
+        assert_eq!(None, settings.timeout);
+        assert_eq!(DEFAULT_DEADLINE, settings.deadline());
+
+        config.deploy.function_config.timeout = Some(Timeout::new(60));
+        let settings = FunctionSettings::from_config(&config, &defaults);
+        assert_eq!(Duration::from_secs(60), settings.deadline());
+
+        config.watch.timeout = Some(Timeout::new(5));
+        let settings = FunctionSettings::from_config(&config, &defaults);
+        assert_eq!(Duration::from_secs(5), settings.deadline());
+    }
+
+    #[test]
+    fn test_function_logging() {
+        use cargo_lambda_metadata::lambda::{ApplicationLogLevel, LogFormat, SystemLogLevel};
+
+        let defaults = FunctionSettings {
+            logging: LoggingConfig {
+                log_format: Some(LogFormat::Text),
+                application_log_level: Some(ApplicationLogLevel::Warn),
+                system_log_level: Some(SystemLogLevel::Warn),
+            },
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        let settings = FunctionSettings::from_config(&config, &defaults);
+        assert_eq!(defaults.logging, settings.logging);
+
+        config.deploy.function_config.logging = Some(LoggingConfig {
+            log_format: Some(LogFormat::Json),
+            application_log_level: Some(ApplicationLogLevel::Debug),
+            system_log_level: None,
+        });
+        let settings = FunctionSettings::from_config(&config, &defaults);
+        assert_eq!(Some(LogFormat::Json), settings.logging.log_format);
+        assert_eq!(
+            Some(ApplicationLogLevel::Debug),
+            settings.logging.application_log_level
+        );
+        assert_eq!(
+            Some(SystemLogLevel::Warn),
+            settings.logging.system_log_level
+        );
+
+        // The package's watch settings win over its deploy settings, field by field.
+        config.watch.logging = Some(LoggingConfig {
+            application_log_level: Some(ApplicationLogLevel::Error),
+            ..Default::default()
+        });
+        let settings = FunctionSettings::from_config(&config, &defaults);
+        assert_eq!(Some(LogFormat::Json), settings.logging.log_format);
+        assert_eq!(
+            Some(ApplicationLogLevel::Error),
+            settings.logging.application_log_level
+        );
+        assert_eq!(
+            Some(SystemLogLevel::Warn),
+            settings.logging.system_log_level
+        );
+    }
+
+    #[test]
+    fn test_environment_id() {
+        assert_eq!("basic-lambda", environment_id("basic-lambda", 0));
+        assert_eq!("basic-lambda@2", environment_id("basic-lambda", 2));
+
+        assert_eq!("basic-lambda", environment_function_name("basic-lambda"));
+        assert_eq!("basic-lambda", environment_function_name("basic-lambda@2"));
+    }
+
+    #[tokio::test]
+    async fn test_prepare_reload() {
+        let state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::new(),
+            None,
+        );
+
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let request = Request::new(Bytes::from(r#"{"command":"hi"}"#));
+        state
+            .res_cache
+            .push("req-1", "basic-lambda", resp_tx, request)
+            .await;
+        let (other_tx, _other_rx) = oneshot::channel();
+        state
+            .res_cache
+            .push(
+                "req-2",
+                "basic-lambda@1",
+                other_tx,
+                Request::new(Bytes::new()),
+            )
+            .await;
+        let timer = tokio::spawn(std::future::pending::<()>());
+        state
+            .res_cache
+            .set_timeout("req-1", timer.abort_handle())
+            .await;
+
+        // Requeued invocations wait for the function again, with the same payload,
+        // and their first timeout doesn't fail them anymore.
+        state
+            .prepare_reload("basic-lambda", ReloadStrategy::Requeue)
+            .await;
+        assert!(timer.await.unwrap_err().is_cancelled());
+        assert!(
+            state
+                .res_cache
+                .in_environment("basic-lambda")
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            state.res_cache.in_environment("basic-lambda@1").await,
+            vec!["req-2".to_string()]
+        );
+
+        let invoke = state.req_cache.pop("basic-lambda").await.unwrap();
+        let body = invoke.req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from(r#"{"command":"hi"}"#));
+
+        // Failed invocations are completed with the reloading error.
+        state
+            .res_cache
+            .push("req-3", "basic-lambda", invoke.resp_tx, Request::new(body))
+            .await;
+        state
+            .prepare_reload("basic-lambda", ReloadStrategy::Fail)
+            .await;
+        assert!(
+            state
+                .res_cache
+                .in_environment("basic-lambda")
+                .await
+                .is_empty()
+        );
+
+        let resp = resp_rx.await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["errorType"], RELOADING_ERROR_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_drain_reload() {
+        let state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::new(),
+            None,
+        );
+        let mut config = Config::default();
+        config.watch.timeout = Some(Timeout::new(1));
+        state.functions.update("basic-lambda", &config).await;
+        state.processes.spawned("basic-lambda", 1).await;
+
+        let (resp_tx, resp_rx) = oneshot::channel();
+        state
+            .res_cache
+            .push("req-1", "basic-lambda", resp_tx, Request::new(Bytes::new()))
+            .await;
+
+        // The environment stops picking up invocations while it drains,
+        // and the invocations that don't complete before the timeout fail.
+        let reload = state.prepare_reload("basic-lambda", ReloadStrategy::Drain);
+        let waiting = state.processes.draining("basic-lambda");
+        let (_, ()) = tokio::join!(reload, waiting);
+        assert!(
+            state
+                .res_cache
+                .in_environment("basic-lambda")
+                .await
+                .is_empty()
+        );
+
+        let resp = resp_rx.await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["errorType"], RELOADING_ERROR_TYPE);
+
+        // The environment picks up invocations again when its process restarts.
+        let drained = tokio::spawn({
+            let processes = state.processes.clone();
+            async move { processes.drained("basic-lambda").await }
+        });
+        state.processes.spawned("basic-lambda", 2).await;
+        drained.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_built_binary() {
+        let processes = ProcessCache::default();
+
+        // The function's other environments start when the first one builds the binary.
+        let built = tokio::spawn({
+            let processes = processes.clone();
+            async move { processes.built_binary("basic-lambda").await }
+        });
+        processes
+            .build_failed("basic-lambda", "cargo exited with exit status: 101".into())
+            .await;
+        tokio::task::yield_now().await;
+        assert!(!built.is_finished());
+
+        let binary = PathBuf::from("target/debug/basic-lambda");
+        processes
+            .build_finished("basic-lambda", binary.clone())
+            .await;
+        assert_eq!(binary, built.await.unwrap());
+
+        // Only the builds that follow code changes reload the other environments.
+        processes.reload_after_build("basic-lambda").await;
+        assert!(
+            processes
+                .outdated_replicas
+                .lock()
+                .await
+                .contains("basic-lambda")
+        );
+        processes.build_finished("basic-lambda", binary).await;
+        assert!(processes.outdated_replicas.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_failures() {
+        let processes = ProcessCache::default();
+        processes.spawned("basic-lambda", 1).await;
+        processes.spawned("basic-lambda@1", 2).await;
+
+        // Processes that the emulator stops are not failures.
+        processes.stopping("basic-lambda").await;
+        assert!(
+            processes
+                .exited("basic-lambda", "killed".into())
+                .await
+                .is_none()
+        );
+
+        processes.spawned("basic-lambda", 3).await;
+        let failure = processes
+            .exited(
+                "basic-lambda",
+                "Runtime exited with error: exit status 1".into(),
+            )
+            .await
+            .unwrap();
+        let error = failure.error(Some("req-1"));
+        assert_eq!(error.error_type, EXIT_ERROR_TYPE);
+        assert_eq!(
+            error.error_message,
+            "RequestId: req-1 Error: Runtime exited with error: exit status 1"
+        );
+
+        // The function can process invocations while one of its environments is running.
+        assert!(processes.function_failure("basic-lambda").await.is_none());
+
+        let init_error = FunctionError::new("Runtime.InitError", "missing configuration");
+        processes.init_failed("basic-lambda@1", init_error).await;
+        let failure = processes
+            .exited(
+                "basic-lambda@1",
+                "Runtime exited without providing a reason".into(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(failure.error(Some("req-2")).error_type, "Runtime.InitError");
+        assert!(processes.function_failure("basic-lambda").await.is_some());
+
+        processes.spawned("basic-lambda@1", 4).await;
+        assert!(processes.function_failure("basic-lambda").await.is_none());
+
+        // Builds that fail keep the invocations in the queue.
+        let failure = processes
+            .build_failed("basic-lambda", "cargo exited with exit status: 101".into())
+            .await;
+        assert!(matches!(failure, ProcessFailure::Build(_)));
+        let failure = processes
+            .build_failed(
+                "basic-lambda@1",
+                "cargo exited with exit status: 101".into(),
+            )
+            .await;
+        assert!(matches!(failure, ProcessFailure::Build(_)));
+        assert!(processes.function_failure("basic-lambda").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fail_environment() {
+        let state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::new(),
+            None,
+        );
+        state.processes.spawned("basic-lambda", 1).await;
+
+        let (resp_tx, resp_rx) = oneshot::channel();
+        state
+            .req_cache
+            .upsert(InvokeRequest {
+                function_name: "basic-lambda".into(),
+                req: Request::new(Body::empty()),
+                resp_tx,
+            })
+            .await
+            .unwrap();
+
+        let error = FunctionError::new("Runtime.InitError", "missing configuration");
+        state
+            .processes
+            .init_failed("basic-lambda", error.clone())
+            .await;
+        state
+            .fail_environment("basic-lambda", ProcessFailure::Init(error))
+            .await;
+
+        let resp = resp_rx.await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["errorType"], "Runtime.InitError");
+        assert_eq!(error["errorMessage"], "missing configuration");
+    }
+
+    fn invoke_event(request_id: &str) -> NextEvent {
+        NextEvent::Invoke(crate::requests::InvokeEvent {
+            request_id: request_id.into(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_registration() {
+        let cache = ExtensionCache::default();
+        cache.register(Some("global".into()), vec![], None).await;
+
+        // Only the extensions in the environment count.
+        let waiting_cache = cache.clone();
+        let waiting =
+            tokio::spawn(
+                async move { waiting_cache.wait_for_registration("basic-lambda", 1).await },
+            );
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        cache
+            .register(
+                Some("logs".into()),
+                vec!["INVOKE".into()],
+                Some("basic-lambda".into()),
+            )
+            .await;
+        waiting.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extension_events_filtering() {
+        let cache = ExtensionCache::default();
+        let invoke_id = cache
+            .register(Some("invoke".into()), vec!["INVOKE".into()], None)
+            .await;
+        let shutdown_id = cache
+            .register(Some("shutdown".into()), vec!["SHUTDOWN".into()], None)
+            .await;
+
+        // Events are queued until the extensions ask for them.
+        cache
+            .send_event(invoke_event("request-1"), "basic-lambda")
+            .await;
+        cache
+            .send_event(invoke_event("request-2"), "basic-lambda")
+            .await;
+
+        let event = cache.next_event(&invoke_id).await.unwrap();
+        assert!(matches!(event, NextEvent::Invoke(e) if e.request_id == "request-1"));
+        let event = cache.next_event(&invoke_id).await.unwrap();
+        assert!(matches!(event, NextEvent::Invoke(e) if e.request_id == "request-2"));
+
+        let shutdown_cache = cache.clone();
+        let next = tokio::spawn(async move { shutdown_cache.next_event(&shutdown_id).await });
+        tokio::task::yield_now().await;
+        assert!(!next.is_finished());
+
+        cache.shutdown("SPINDOWN").await;
+        let event = next.await.unwrap().unwrap();
+        assert!(
+            matches!(event, NextEvent::Shutdown(e) if e.shutdown_reason == "SPINDOWN" && e.deadline_ms > 0)
+        );
+
+        let err = cache.next_event("unknown").await.unwrap_err();
+        assert!(matches!(err, ServerError::UnknownExtension(_)));
+    }
+
+    #[tokio::test]
+    async fn test_extension_environment() {
+        let cache = ExtensionCache::default();
+        let external_id = cache.register(None, vec![], None).await;
+        let env_id = cache
+            .register(None, vec![], Some("basic-lambda@0".into()))
+            .await;
+
+        assert_eq!(None, cache.environment(&external_id).await.unwrap());
+        assert_eq!(
+            Some("basic-lambda@0".to_string()),
+            cache.environment(&env_id).await.unwrap()
+        );
+
+        cache.unregister_environment("basic-lambda@0").await;
+        let err = cache.environment(&env_id).await.unwrap_err();
+        assert!(matches!(err, ServerError::UnknownExtension(_)));
+    }
+
+    #[tokio::test]
+    async fn test_extension_shutdown_acknowledgement() {
+        let cache = ExtensionCache::default();
+        let id = cache.register(None, vec!["SHUTDOWN".into()], None).await;
+
+        let ext_cache = cache.clone();
+        let extension = tokio::spawn(async move {
+            let event = ext_cache.next_event(&id).await.unwrap();
+            assert!(matches!(event, NextEvent::Shutdown(_)));
+            // Asking for the next event completes the shutdown.
+            ext_cache.next_event(&id).await
+        });
+        tokio::task::yield_now().await;
+
+        let started = Instant::now();
+        cache.shutdown("SPINDOWN").await;
+        assert!(started.elapsed() < EXTENSION_SHUTDOWN_TIMEOUT);
+        extension.abort();
+    }
+
+    #[tokio::test]
+    async fn test_extension_shutdown_function() {
+        let cache = ExtensionCache::default();
+        let external_id = cache.register(None, vec!["SHUTDOWN".into()], None).await;
+        let replica_id = cache
+            .register(None, vec!["SHUTDOWN".into()], Some("basic-lambda@1".into()))
+            .await;
+        let other_id = cache
+            .register(None, vec!["SHUTDOWN".into()], Some("other-lambda".into()))
+            .await;
+
+        let shutdown_cache = cache.clone();
+        let shutdown = tokio::spawn(async move {
+            shutdown_cache
+                .shutdown_function("recompiling function", "basic-lambda")
+                .await
+        });
+        tokio::task::yield_now().await;
+
+        for id in [&external_id, &replica_id] {
+            let event = cache.next_event(id).await.unwrap();
+            assert!(matches!(event, NextEvent::Shutdown(_)));
+        }
+
+        // Extensions in other functions' environments keep running.
+        let other_cache = cache.clone();
+        let other = tokio::spawn(async move { other_cache.next_event(&other_id).await });
+        tokio::task::yield_now().await;
+        assert!(!other.is_finished());
+        other.abort();
+        shutdown.abort();
+    }
+
+    #[tokio::test]
+    async fn test_extension_failure() {
+        let cache = ExtensionCache::default();
+        let environments = vec!["basic-lambda".to_string(), "basic-lambda@1".to_string()];
+        let id = cache.register(None, vec!["INVOKE".into()], None).await;
+        assert!(cache.failure(&environments).await.is_none());
+
+        let error = FunctionError::new("Extension.Crash", "boom");
+        let failed = cache.fail(&id, error).await.unwrap();
+        assert_eq!(None, failed.environment);
+        assert!(!cache.is_registered(&id).await);
+        let failure = cache.failure(&environments).await.unwrap();
+        assert_eq!(failure.error_type, "Extension.Crash");
+
+        cache.register(None, vec!["INVOKE".into()], None).await;
+        assert!(cache.failure(&environments).await.is_none());
+
+        // Extensions in an environment only fail that environment.
+        let id = cache
+            .register(None, vec!["INVOKE".into()], Some("basic-lambda".into()))
+            .await;
+        let error = FunctionError::new("Extension.Crash", "boom");
+        let failed = cache.fail(&id, error).await.unwrap();
+        assert_eq!(Some("basic-lambda".to_string()), failed.environment);
+        assert!(cache.failure(&environments).await.is_none());
+        assert!(cache.failure(&environments[..1]).await.is_some());
+
+        cache
+            .register(None, vec!["INVOKE".into()], Some("basic-lambda".into()))
+            .await;
+        assert!(cache.failure(&environments[..1]).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_extension_failure_during_shutdown() {
+        let cache = ExtensionCache::default();
+        let id = cache
+            .register(None, vec!["SHUTDOWN".into()], Some("basic-lambda".into()))
+            .await;
+
+        let ext_cache = cache.clone();
+        let shutdown = tokio::spawn(async move { ext_cache.shutdown("SPINDOWN").await });
+        tokio::task::yield_now().await;
+
+        let error = FunctionError::new("Extension.ExitError", "exited");
+        assert!(cache.fail(&id, error).await.is_none());
+        shutdown.await.unwrap();
+        assert!(cache.failure(&["basic-lambda".to_string()]).await.is_none());
     }
 }
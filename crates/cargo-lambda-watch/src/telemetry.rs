@@ -0,0 +1,632 @@
+use crate::{
+    logs::LogFormatter,
+    report::ReportMetrics,
+    requests::{EventsDestination, LogBuffering, SubcribeEvent},
+    state::ProcessCache,
+};
+use chrono::{SecondsFormat, Utc};
+use os_pipe::PipeReader;
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{Mutex, mpsc},
+    time::{Instant, sleep_until},
+};
+use tracing::{debug, error, warn};
+
+/// Hostname that Lambda extensions use to expose their listeners.
+/// It only resolves inside the Lambda sandbox.
+const SANDBOX_HOSTNAME: &str = "sandbox.localdomain";
+
+/// Maximum number of records waiting to be buffered for each subscription.
+/// Lambda drops records when extensions cannot keep up, and so do we.
+const SUBSCRIPTION_QUEUE_SIZE: usize = 10_000;
+
+/// Lambda APIs that extensions can use to subscribe to events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EventsApi {
+    /// Logs API, `/2020-08-15/logs`
+    Logs,
+    /// Telemetry API, `/2022-07-01/telemetry`
+    Telemetry,
+}
+
+/// Event produced by the function's execution environment
+/// that can be delivered to subscribed extensions.
+#[derive(Clone, Debug)]
+pub(crate) enum TelemetryEvent {
+    /// A line that the function wrote to stdout or stderr
+    Function(String),
+    /// A line that an extension wrote to stdout or stderr
+    Extension(String),
+    /// The runtime started processing an invocation
+    PlatformStart { request_id: String },
+    /// The runtime sent the response, or the error, for an invocation
+    PlatformDone { request_id: String, success: bool },
+    /// Measurements of an invocation, sent after the invocation completes
+    PlatformReport {
+        request_id: String,
+        status: &'static str,
+        metrics: ReportMetrics,
+    },
+}
+
+impl TelemetryEvent {
+    fn event_type(&self) -> &str {
+        match self {
+            Self::Function(_) => "function",
+            Self::Extension(_) => "extension",
+            Self::PlatformStart { .. }
+            | Self::PlatformDone { .. }
+            | Self::PlatformReport { .. } => "platform",
+        }
+    }
+
+    pub(crate) fn record(&self, api: EventsApi, time: &str) -> Value {
+        match self {
+            Self::Function(line) => json!({
+                "time": time,
+                "type": "function",
+                "record": line,
+            }),
+            Self::Extension(line) => json!({
+                "time": time,
+                "type": "extension",
+                "record": line,
+            }),
+            Self::PlatformStart { request_id } => json!({
+                "time": time,
+                "type": "platform.start",
+                "record": {
+                    "requestId": request_id,
+                    "version": "$LATEST",
+                },
+            }),
+            Self::PlatformDone {
+                request_id,
+                success,
+            } if api == EventsApi::Telemetry => json!({
+                "time": time,
+                "type": "platform.runtimeDone",
+                "record": {
+                    "requestId": request_id,
+                    "status": if *success { "success" } else { "error" },
+                },
+            }),
+            Self::PlatformDone { request_id, .. } => json!({
+                "time": time,
+                "type": "platform.end",
+                "record": {
+                    "requestId": request_id,
+                },
+            }),
+            Self::PlatformReport {
+                request_id,
+                status,
+                metrics,
+            } => json!({
+                "time": time,
+                "type": "platform.report",
+                "record": {
+                    "requestId": request_id,
+                    "status": status,
+                    "metrics": metrics,
+                },
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Subscription {
+    api: EventsApi,
+    types: Vec<String>,
+    /// Execution environment of the extension, `None` for
+    /// the extensions that receive events from every environment
+    environment: Option<String>,
+    tx: mpsc::Sender<Value>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TelemetryCache {
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+}
+
+impl TelemetryCache {
+    /// Register a new subscription for an extension.
+    /// A second subscription from the same extension replaces the first one.
+    pub async fn subscribe(
+        &self,
+        extension_id: &str,
+        environment: Option<String>,
+        api: EventsApi,
+        request: SubcribeEvent,
+    ) {
+        let buffering = request.buffering.unwrap_or_default();
+        let (tx, rx) = mpsc::channel::<Value>(SUBSCRIPTION_QUEUE_SIZE);
+
+        tokio::spawn(deliver_events(request.destination, buffering, rx));
+
+        let subscription = Subscription {
+            api,
+            types: request.types,
+            environment,
+            tx,
+        };
+
+        let mut subscriptions = self.subscriptions.lock().await;
+        subscriptions.insert(extension_id.to_string(), subscription);
+    }
+
+    /// Remove the subscription of an extension that is no longer registered.
+    /// The records that it buffered are still delivered.
+    pub async fn unsubscribe(&self, extension_id: &str) {
+        self.subscriptions.lock().await.remove(extension_id);
+    }
+
+    /// Remove the subscriptions of the extensions started in an execution environment,
+    /// when their processes stop.
+    pub async fn unsubscribe_environment(&self, environment: &str) {
+        let mut subscriptions = self.subscriptions.lock().await;
+        subscriptions.retain(|_, s| s.environment.as_deref() != Some(environment));
+    }
+
+    /// Send an event to the extensions that subscribed to its type,
+    /// and that run in the execution environment that the event comes from.
+    pub async fn send_event(&self, event: TelemetryEvent, environment: &str) {
+        let subscriptions = self.subscriptions.lock().await;
+        if subscriptions.is_empty() {
+            return;
+        }
+
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let event_type = event.event_type();
+
+        for (extension_id, subscription) in subscriptions.iter() {
+            if subscription
+                .environment
+                .as_deref()
+                .is_some_and(|e| e != environment)
+            {
+                continue;
+            }
+            if !subscription.types.iter().any(|t| t == event_type) {
+                continue;
+            }
+
+            let record = event.record(subscription.api, &time);
+            if let Err(error) = subscription.tx.try_send(record) {
+                warn!(%extension_id, %error, "dropping telemetry record");
+            }
+        }
+    }
+}
+
+/// Buffer records for a subscription, and send them to the extension's
+/// destination when any of the buffering limits is reached.
+async fn deliver_events(
+    destination: EventsDestination,
+    buffering: LogBuffering,
+    mut rx: mpsc::Receiver<Value>,
+) {
+    let client = reqwest::Client::new();
+    let uri = local_destination_uri(&destination.uri);
+    let timeout = Duration::from_millis(buffering.timeout_ms as u64);
+
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            record = rx.recv() => match record {
+                None => {
+                    flush_events(&client, &destination, &uri, &mut batch).await;
+                    return;
+                }
+                Some(record) => {
+                    batch_bytes += record.to_string().len();
+                    batch.push(record);
+                    deadline.get_or_insert_with(|| Instant::now() + timeout);
+
+                    if batch.len() >= buffering.max_items || batch_bytes >= buffering.max_bytes {
+                        flush_events(&client, &destination, &uri, &mut batch).await;
+                        batch_bytes = 0;
+                        deadline = None;
+                    }
+                }
+            },
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                flush_events(&client, &destination, &uri, &mut batch).await;
+                batch_bytes = 0;
+                deadline = None;
+            }
+        }
+    }
+}
+
+async fn flush_events(
+    client: &reqwest::Client,
+    destination: &EventsDestination,
+    uri: &str,
+    batch: &mut Vec<Value>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let records = std::mem::take(batch);
+
+    if !destination.protocol.eq_ignore_ascii_case("HTTP") {
+        warn!(protocol = %destination.protocol, "only HTTP destinations are supported, dropping telemetry records");
+        return;
+    }
+
+    let body = match serde_json::to_vec(&records) {
+        Ok(body) => body,
+        Err(error) => {
+            error!(?error, "failed to serialize telemetry records");
+            return;
+        }
+    };
+
+    debug!(%uri, records = records.len(), "sending telemetry records");
+    let result = client
+        .post(uri)
+        .header("content-type", "application/json")
+        .body(body)
+        .send()
+        .await;
+
+    if let Err(error) = result {
+        error!(%uri, ?error, "failed to send telemetry records to extension");
+    }
+}
+
+/// Replace the sandbox hostname with the loopback address, so extensions
+/// can use the same destination that they'd use in Lambda.
+fn local_destination_uri(uri: &str) -> String {
+    uri.replacen(SANDBOX_HOSTNAME, "127.0.0.1", 1)
+}
+
+/// Redirect the output of the function's process through pipes, so every line
+/// can be rendered with the function's logging configuration, and forwarded
+/// to the subscribed extensions. The output is still printed in the terminal
+/// as the process writes it.
+pub(crate) fn capture_output(
+    command: &mut tokio::process::Command,
+    telemetry: &TelemetryCache,
+    processes: &ProcessCache,
+    environment: &str,
+    formatter: LogFormatter,
+) -> std::io::Result<()> {
+    let (stdout_reader, stdout_writer) = os_pipe::pipe()?;
+    let (stderr_reader, stderr_writer) = os_pipe::pipe()?;
+
+    command.stdout(stdout_writer).stderr(stderr_writer);
+
+    let output = FunctionOutput {
+        telemetry: telemetry.clone(),
+        processes: processes.clone(),
+        environment: environment.to_string(),
+        formatter,
+    };
+    forward_output(stdout_reader, std::io::stdout, output.clone());
+    forward_output(stderr_reader, std::io::stderr, output);
+
+    Ok(())
+}
+
+/// Destinations of the output of a function's process.
+#[derive(Clone)]
+struct FunctionOutput {
+    telemetry: TelemetryCache,
+    processes: ProcessCache,
+    environment: String,
+    formatter: LogFormatter,
+}
+
+/// Render and forward the lines that the function's process writes to a pipe.
+fn forward_output<W: Write + 'static>(
+    reader: PipeReader,
+    writer: fn() -> W,
+    output: FunctionOutput,
+) {
+    let mut rx = read_lines(reader);
+
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']);
+
+            let request_id = output.processes.invocation(&output.environment).await;
+            let Some(record) = output.formatter.function_line(text, request_id.as_deref()) else {
+                continue;
+            };
+
+            {
+                let mut out = writer();
+                if output.formatter.is_text() {
+                    let _ = out.write_all(&line);
+                } else {
+                    let _ = writeln!(out, "{record}");
+                }
+                let _ = out.flush();
+            }
+
+            output
+                .telemetry
+                .send_event(TelemetryEvent::Function(record), &output.environment)
+                .await;
+        }
+    });
+}
+
+/// Redirect the output of an extension's process through pipes, so every line
+/// is forwarded to the subscribed extensions. The output is still printed
+/// in the terminal as the process writes it.
+pub(crate) fn capture_extension_output(
+    command: &mut tokio::process::Command,
+    telemetry: &TelemetryCache,
+    environment: &str,
+) -> std::io::Result<()> {
+    let (stdout_reader, stdout_writer) = os_pipe::pipe()?;
+    let (stderr_reader, stderr_writer) = os_pipe::pipe()?;
+
+    command.stdout(stdout_writer).stderr(stderr_writer);
+
+    forward_extension_output(
+        stdout_reader,
+        std::io::stdout,
+        telemetry.clone(),
+        environment.to_string(),
+    );
+    forward_extension_output(
+        stderr_reader,
+        std::io::stderr,
+        telemetry.clone(),
+        environment.to_string(),
+    );
+
+    Ok(())
+}
+
+fn forward_extension_output<W: Write + 'static>(
+    reader: PipeReader,
+    writer: fn() -> W,
+    telemetry: TelemetryCache,
+    environment: String,
+) {
+    let mut rx = read_lines(reader);
+
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            {
+                let mut out = writer();
+                let _ = out.write_all(&line);
+                let _ = out.flush();
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']).to_string();
+            telemetry
+                .send_event(TelemetryEvent::Extension(text), &environment)
+                .await;
+        }
+    });
+}
+
+/// Read the lines that a process writes to a pipe in a blocking thread,
+/// and send them through a channel.
+fn read_lines(reader: PipeReader) -> mpsc::UnboundedReceiver<Vec<u8>> {
+    let (tx, rx) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+
+        loop {
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::State, routing::post};
+    use tokio::{net::TcpListener, time::timeout};
+
+    type Batches = mpsc::Receiver<Vec<Value>>;
+
+    /// Start a destination that sends every batch it receives to the returned channel,
+    /// and a subscription that delivers records to it.
+    async fn subscription(buffering: LogBuffering) -> (mpsc::Sender<Value>, Batches) {
+        let (batch_tx, batch_rx) = mpsc::channel::<Vec<Value>>(10);
+        let app =
+            Router::new()
+                .route(
+                    "/telemetry",
+                    post(
+                        |State(tx): State<mpsc::Sender<Vec<Value>>>,
+                         Json(batch): Json<Vec<Value>>| async move {
+                            tx.send(batch).await.unwrap();
+                        },
+                    ),
+                )
+                .with_state(batch_tx);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let destination = EventsDestination {
+            protocol: "HTTP".into(),
+            uri: format!("http://sandbox.localdomain:{}/telemetry", addr.port()),
+        };
+        let (tx, rx) = mpsc::channel::<Value>(10);
+        tokio::spawn(deliver_events(destination, buffering, rx));
+
+        (tx, batch_rx)
+    }
+
+    async fn next_batch(rx: &mut Batches) -> Vec<Value> {
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("timed out waiting for a batch")
+            .expect("destination stopped")
+    }
+
+    #[test]
+    fn test_local_destination_uri() {
+        assert_eq!(
+            "http://127.0.0.1:8080/logs",
+            local_destination_uri("http://sandbox.localdomain:8080/logs")
+        );
+        assert_eq!(
+            "http://localhost:8080/logs",
+            local_destination_uri("http://localhost:8080/logs")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_event_in_environment() {
+        let cache = TelemetryCache::default();
+        let mut receivers = HashMap::new();
+        for (extension_id, environment) in [
+            ("in-env", Some("basic-lambda@0")),
+            ("other-env", Some("basic-lambda@1")),
+            ("external", None),
+        ] {
+            let (tx, rx) = mpsc::channel::<Value>(10);
+            cache.subscriptions.lock().await.insert(
+                extension_id.to_string(),
+                Subscription {
+                    api: EventsApi::Telemetry,
+                    types: vec!["function".into()],
+                    environment: environment.map(String::from),
+                    tx,
+                },
+            );
+            receivers.insert(extension_id, rx);
+        }
+
+        cache
+            .send_event(TelemetryEvent::Function("hello".into()), "basic-lambda@0")
+            .await;
+        assert!(receivers.get_mut("in-env").unwrap().try_recv().is_ok());
+        assert!(receivers.get_mut("external").unwrap().try_recv().is_ok());
+        assert!(receivers.get_mut("other-env").unwrap().try_recv().is_err());
+
+        cache.unsubscribe_environment("basic-lambda@0").await;
+        assert!(receivers.get_mut("in-env").unwrap().recv().await.is_none());
+
+        cache.unsubscribe("external").await;
+        assert!(
+            receivers
+                .get_mut("external")
+                .unwrap()
+                .recv()
+                .await
+                .is_none()
+        );
+        assert_eq!(
+            vec!["other-env"],
+            cache
+                .subscriptions
+                .lock()
+                .await
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_platform_done_record() {
+        let event = TelemetryEvent::PlatformDone {
+            request_id: "req-id".into(),
+            success: false,
+        };
+
+        let record = event.record(EventsApi::Telemetry, "2022-10-12T00:00:00.000Z");
+        assert_eq!("platform.runtimeDone", record["type"]);
+        assert_eq!("error", record["record"]["status"]);
+
+        let record = event.record(EventsApi::Logs, "2022-10-12T00:00:00.000Z");
+        assert_eq!("platform.end", record["type"]);
+        assert_eq!("req-id", record["record"]["requestId"]);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_events_on_timeout() {
+        let (tx, mut rx) = subscription(LogBuffering {
+            timeout_ms: 50,
+            max_bytes: 262_144,
+            max_items: 10_000,
+        })
+        .await;
+
+        tx.send(json!("first")).await.unwrap();
+        tx.send(json!("second")).await.unwrap();
+
+        let batch = next_batch(&mut rx).await;
+        assert_eq!(vec![json!("first"), json!("second")], batch);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_events_on_max_items() {
+        let (tx, mut rx) = subscription(LogBuffering {
+            timeout_ms: 60_000,
+            max_bytes: 262_144,
+            max_items: 2,
+        })
+        .await;
+
+        tx.send(json!("first")).await.unwrap();
+        tx.send(json!("second")).await.unwrap();
+        tx.send(json!("third")).await.unwrap();
+
+        let batch = next_batch(&mut rx).await;
+        assert_eq!(vec![json!("first"), json!("second")], batch);
+
+        drop(tx);
+        let batch = next_batch(&mut rx).await;
+        assert_eq!(vec![json!("third")], batch);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_events_on_max_bytes() {
+        let (tx, mut rx) = subscription(LogBuffering {
+            timeout_ms: 60_000,
+            max_bytes: 10,
+            max_items: 10_000,
+        })
+        .await;
+
+        tx.send(json!("1234")).await.unwrap();
+        tx.send(json!("5678")).await.unwrap();
+        tx.send(json!("9")).await.unwrap();
+
+        let batch = next_batch(&mut rx).await;
+        assert_eq!(vec![json!("1234"), json!("5678")], batch);
+
+        drop(tx);
+        let batch = next_batch(&mut rx).await;
+        assert_eq!(vec![json!("9")], batch);
+    }
+}
@@ -1,16 +1,12 @@
 use crate::{
     RefRuntimeState,
     error::ServerError,
+    limits,
     requests::*,
     runtime::{LAMBDA_RUNTIME_AWS_REQUEST_ID, LAMBDA_RUNTIME_XRAY_TRACE_HEADER},
+    state::{Reservation, RuntimeState, environment_id},
 };
-use aws_lambda_events::{
-    apigw::{
-        ApiGatewayV2httpRequest, ApiGatewayV2httpRequestContext,
-        ApiGatewayV2httpRequestContextHttpDescription, ApiGatewayV2httpResponse,
-    },
-    encodings::Body as LambdaBody,
-};
+use aws_lambda_events::encodings::Body as LambdaBody;
 use axum::{
     Router,
     body::Body,
@@ -20,8 +16,10 @@ use axum::{
     routing::{any, post},
 };
 use base64::{Engine as _, engine::general_purpose as b64};
-use cargo_lambda_metadata::DEFAULT_PACKAGE_FUNCTION;
-use chrono::Utc;
+use cargo_lambda_metadata::{
+    DEFAULT_PACKAGE_FUNCTION,
+    cargo::watch::{AuthType, PayloadFormat, RequestRoute, RouteRequest},
+};
 use http::Method;
 use http_body_util::BodyExt;
 use hyper::{HeaderMap, StatusCode, header};
@@ -30,18 +28,80 @@ use opentelemetry::{
     Context, KeyValue, global,
     trace::{TraceContextExt, Tracer},
 };
-use query_map::QueryMap;
-use std::collections::{HashMap, HashSet};
-use tokio::sync::{mpsc::Sender, oneshot};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+use tokio::{
+    sync::{mpsc::Sender, oneshot},
+    time::Instant,
+};
+
+pub(crate) mod async_invocation;
+use async_invocation::AsyncInvocation;
+pub(crate) mod authorizers;
+use authorizers::AuthorizerRequest;
+pub(crate) mod iam_auth;
+mod payload_format;
+use payload_format::HttpEvent;
+pub(crate) mod response_stream;
 
 const LAMBDA_URL_PREFIX: &str = "lambda-url";
 
+const INVOCATION_TYPE_HEADER: &str = "x-amz-invocation-type";
+/// Header that Lambda sets in the responses of the invocations that fail.
+const FUNCTION_ERROR_HEADER: &str = "x-amz-function-error";
+
+/// Invocation types that `Invoke` accepts.
+const INVOCATION_TYPES: &str = "[Event, RequestResponse, DryRun]";
+/// Invocation types that `InvokeWithResponseStream` accepts.
+const STREAMING_INVOCATION_TYPES: &str = "[RequestResponse, DryRun]";
+
+/// How the caller wants the function to be invoked,
+/// as set in the `X-Amz-Invocation-Type` header.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum InvocationType {
+    /// Wait for the function to process the invocation
+    #[default]
+    RequestResponse,
+    /// Process the invocation in the background
+    Event,
+    /// Only validate that the function exists
+    DryRun,
+}
+
+impl FromStr for InvocationType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "RequestResponse" => Ok(InvocationType::RequestResponse),
+            "Event" => Ok(InvocationType::Event),
+            "DryRun" => Ok(InvocationType::DryRun),
+            other => Err(other.to_string()),
+        }
+    }
+}
+
+impl InvocationType {
+    fn from_headers(headers: &HeaderMap) -> Result<Self, String> {
+        match headers.get(INVOCATION_TYPE_HEADER) {
+            None => Ok(InvocationType::default()),
+            Some(value) => value.to_str().unwrap_or_default().parse(),
+        }
+    }
+}
+
 pub(crate) fn routes() -> Router<RefRuntimeState> {
     Router::new()
         .route(
             "/2015-03-31/functions/:function_name/invocations",
             post(invoke_handler),
         )
+        .route(
+            "/2021-11-15/functions/:function_name/response-streaming-invocations",
+            post(response_streaming_handler),
+        )
         .route("/lambda-url/:function_name/*path", any(furls_handler))
         .fallback(furls_handler)
 }
@@ -56,8 +116,9 @@ async fn furls_handler(
     let (parts, body) = req.into_parts();
     let uri = &parts.uri;
 
+    let request_route = route_request(uri.path(), &parts.method, &parts.headers, &state);
     let (function_name, mut path, path_parameters) =
-        extract_path_parameters(uri.path(), &parts.method, &state);
+        extract_path_parameters(uri.path(), request_route.as_ref());
     tracing::trace!(%function_name, %path, "received request in furls handler");
 
     if function_name == DEFAULT_PACKAGE_FUNCTION && !state.is_default_function_enabled() {
@@ -77,6 +138,27 @@ async fn furls_handler(
         .await
         .map_err(ServerError::DataDeserialization)?
         .to_bytes();
+    if let Some(limit) = limits::exceeded_limit(&body, state.payload_limits.sync_request) {
+        return respond_with_request_too_large(&function_name, limit);
+    }
+
+    let route = request_route.map(|r| r.route);
+    let settings = state.function_settings(&function_name).await;
+    let auth_type = route
+        .as_ref()
+        .and_then(|r| r.auth_type)
+        .unwrap_or(settings.auth_type);
+
+    let iam_identity = match auth_type {
+        AuthType::None => None,
+        AuthType::AwsIam => {
+            let remote_config = settings.remote_config.as_ref();
+            match iam_auth::verify(&state.credentials, remote_config, &parts, &body).await {
+                Ok(identity) => Some(identity),
+                Err(error) => return respond_with_forbidden(&function_name, error),
+            }
+        }
+    };
     let text_content_type = match headers.get("content-type") {
         None => true,
         Some(c) => {
@@ -96,71 +178,67 @@ async fn furls_handler(
         (Some(body), true)
     };
 
-    let query_string_parameters = uri
-        .query()
-        .unwrap_or_default()
-        .parse::<QueryMap>()
-        .unwrap_or_default();
-
-    let cookies = headers.get("cookie").map(|c| {
-        c.to_str()
-            .unwrap_or_default()
-            .split("; ")
-            .map(|s| s.trim().to_string())
-            .collect()
-    });
-
     let req_id = headers
         .get(LAMBDA_RUNTIME_AWS_REQUEST_ID)
         .expect("missing request id")
         .to_str()
         .expect("invalid request id format");
 
-    let time = Utc::now();
-
     if !path.starts_with('/') {
         path = format!("/{path}");
     }
 
-    let request_context = ApiGatewayV2httpRequestContext {
-        stage: Some("$default".into()),
-        route_key: Some("$default".into()),
-        request_id: Some(req_id.into()),
-        domain_name: Some("localhost".into()),
-        domain_prefix: Some(function_name.clone()),
-        http: ApiGatewayV2httpRequestContextHttpDescription {
-            method: parts.method.clone(),
-            path: Some(path.clone()),
-            protocol: Some("http".into()),
-            source_ip: Some("127.0.0.1".into()),
-            user_agent: Some("cargo-lambda".into()),
-        },
-        time: Some(time.format("%d/%b/%Y:%T %z").to_string()),
-        time_epoch: time.timestamp(),
-        account_id: None,
-        authorizer: None,
-        authentication: None,
-        apiid: None,
+    let authorizer = match route
+        .as_ref()
+        .and_then(|r| Some((r, r.authorizer.as_ref()?)))
+    {
+        None => None,
+        Some((route, authorizer)) => {
+            let request = AuthorizerRequest {
+                parts: &parts,
+                request_id: req_id,
+                path: &path,
+                resource: &route.path,
+                path_parameters: &path_parameters,
+                payload_format: route.payload_format,
+            };
+            match authorizers::authorize(&state, &cmd_tx, authorizer, &request).await {
+                Ok(context) => Some(context),
+                Err(error) => return respond_with_unauthorized(&function_name, error),
+            }
+        }
     };
 
-    let event = ApiGatewayV2httpRequest {
-        version: Some("2.0".into()),
-        route_key: Some("$default".into()),
-        raw_path: Some(path),
-        raw_query_string: uri.query().map(String::from),
-        headers: headers.clone(),
-        body,
-        request_context,
-        cookies,
-        query_string_parameters,
-        is_base64_encoded,
-        path_parameters,
-        ..Default::default()
+    let (payload_format, resource, domain) = match route {
+        Some(route) => (route.payload_format, Some(route.path), route.domain),
+        None => (PayloadFormat::default(), None, None),
+    };
+    tracing::trace!(?payload_format, ?resource, "building http event");
+
+    let event = payload_format::build_event(
+        payload_format,
+        HttpEvent {
+            parts: &parts,
+            function_name: &function_name,
+            request_id: req_id,
+            path,
+            resource,
+            domain,
+            path_parameters,
+            body,
+            is_base64_encoded,
+            iam_identity,
+            authorizer,
+        },
+    )?;
+
+    let _reservation = match state.functions.reserve(&function_name).await {
+        Reservation::Throttled => return respond_with_throttled_function(&function_name),
+        reservation => reservation,
     };
-    let event = serde_json::to_string(&event).map_err(ServerError::SerializationError)?;
 
     let req = Request::from_parts(parts, event.into());
-    let resp = schedule_invocation(&cmd_tx, function_name, req).await?;
+    let resp = schedule_invocation(&state, &cmd_tx, function_name, req).await?;
     let status_code = resp
         .extensions()
         .get::<StatusCode>()
@@ -177,7 +255,8 @@ async fn furls_handler(
 
             builder.status(status).body(body)
         } else {
-            let (status, body) = create_buffered_response(&mut builder, &mut body).await?;
+            let (status, body) =
+                create_buffered_response(&mut builder, &mut body, payload_format).await?;
 
             builder.status(status).body(body)
         }
@@ -194,7 +273,11 @@ async fn invoke_handler(
     Path(function_name): Path<String>,
     req: Request<Body>,
 ) -> Result<Response<Body>, ServerError> {
-    tracing::debug!(%function_name, "invocation received");
+    let invocation_type = match InvocationType::from_headers(req.headers()) {
+        Ok(invocation_type) => invocation_type,
+        Err(value) => return respond_with_invalid_invocation_type(&value, INVOCATION_TYPES),
+    };
+    tracing::debug!(%function_name, ?invocation_type, "invocation received");
 
     if function_name == DEFAULT_PACKAGE_FUNCTION && !state.is_default_function_enabled() {
         tracing::error!(available_functions = ?state.initial_functions, "the default function route is disabled, use /lambda-url/:function_name to trigger a function call");
@@ -207,7 +290,64 @@ async fn invoke_handler(
         }
     }
 
-    let resp = schedule_invocation(&cmd_tx, function_name, req).await?;
+    match invocation_type {
+        InvocationType::DryRun => {
+            return Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .map_err(ServerError::ResponseBuild);
+        }
+        InvocationType::Event => {
+            let (parts, body) = req.into_parts();
+            let payload = body
+                .collect()
+                .await
+                .map_err(ServerError::DataDeserialization)?
+                .to_bytes();
+            if let Some(limit) =
+                limits::exceeded_limit(&payload, state.payload_limits.async_request)
+            {
+                return respond_with_request_too_large(&function_name, limit);
+            }
+
+            let request_id = parts
+                .headers
+                .get(LAMBDA_RUNTIME_AWS_REQUEST_ID)
+                .expect("missing request id")
+                .to_str()
+                .map_err(ServerError::InvalidRequestIdHeader)?
+                .to_string();
+
+            let invocation = AsyncInvocation {
+                request_id,
+                function_name,
+                method: parts.method,
+                uri: parts.uri,
+                headers: parts.headers,
+                payload,
+                received_at: Instant::now(),
+            };
+            async_invocation::spawn(state, cmd_tx, invocation);
+
+            return Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::empty())
+                .map_err(ServerError::ResponseBuild);
+        }
+        InvocationType::RequestResponse => {}
+    }
+
+    let req = match read_sync_payload(&state, req).await? {
+        Ok(req) => req,
+        Err(limit) => return respond_with_request_too_large(&function_name, limit),
+    };
+
+    let _reservation = match state.functions.reserve(&function_name).await {
+        Reservation::Throttled => return respond_with_throttled_function(&function_name),
+        reservation => reservation,
+    };
+
+    let resp = schedule_invocation(&state, &cmd_tx, function_name, req).await?;
     let status_code = resp
         .extensions()
         .get::<StatusCode>()
@@ -223,10 +363,108 @@ async fn invoke_handler(
         builder = builder.status(status);
     }
 
+    if status_code != StatusCode::OK {
+        builder = builder.header(FUNCTION_ERROR_HEADER, "Unhandled");
+    }
+
     builder.body(body).map_err(ServerError::ResponseBuild)
 }
 
-async fn schedule_invocation(
+/// Invoke a function, and send its response back as an event stream,
+/// like `InvokeWithResponseStream` does.
+async fn response_streaming_handler(
+    State(state): State<RefRuntimeState>,
+    Extension(cmd_tx): Extension<Sender<Action>>,
+    Path(function_name): Path<String>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ServerError> {
+    let invocation_type = match InvocationType::from_headers(req.headers()) {
+        Ok(InvocationType::Event) => {
+            return respond_with_invalid_invocation_type("Event", STREAMING_INVOCATION_TYPES);
+        }
+        Ok(invocation_type) => invocation_type,
+        Err(value) => {
+            return respond_with_invalid_invocation_type(&value, STREAMING_INVOCATION_TYPES);
+        }
+    };
+    tracing::debug!(%function_name, ?invocation_type, "streaming invocation received");
+
+    if function_name == DEFAULT_PACKAGE_FUNCTION && !state.is_default_function_enabled() {
+        return respond_with_disabled_default_function(&state, true);
+    }
+
+    if function_name != DEFAULT_PACKAGE_FUNCTION {
+        if let Err(binaries) = state.is_function_available(&function_name) {
+            return respond_with_missing_function(&binaries);
+        }
+    }
+
+    if invocation_type == InvocationType::DryRun {
+        return Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .map_err(ServerError::ResponseBuild);
+    }
+
+    let req = match read_sync_payload(&state, req).await? {
+        Ok(req) => req,
+        Err(limit) => return respond_with_request_too_large(&function_name, limit),
+    };
+
+    let reservation = match state.functions.reserve(&function_name).await {
+        Reservation::Throttled => return respond_with_throttled_function(&function_name),
+        reservation => reservation,
+    };
+
+    let resp = schedule_invocation(&state, &cmd_tx, function_name, req).await?;
+    let status_code = resp
+        .extensions()
+        .get::<StatusCode>()
+        .cloned()
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+    let (info, body) = resp.into_parts();
+
+    // The SDKs read the content type of the function's stream from this header.
+    let content_type = info
+        .headers
+        .get(header::CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header("x-amz-executed-version", "$LATEST")
+        .body(response_stream::event_stream(
+            status_code,
+            body,
+            reservation,
+        ))
+        .map_err(ServerError::ResponseBuild)
+}
+
+/// Buffer the payload of a synchronous invocation, and check that it's within the
+/// payload size limit. It returns the limit that the payload goes over, if any.
+async fn read_sync_payload(
+    state: &RuntimeState,
+    req: Request<Body>,
+) -> Result<Result<Request<Body>, usize>, ServerError> {
+    let (parts, body) = req.into_parts();
+    let payload = body
+        .collect()
+        .await
+        .map_err(ServerError::DataDeserialization)?
+        .to_bytes();
+
+    match limits::exceeded_limit(&payload, state.payload_limits.sync_request) {
+        Some(limit) => Ok(Err(limit)),
+        None => Ok(Ok(Request::from_parts(parts, Body::from(payload)))),
+    }
+}
+
+pub(crate) async fn schedule_invocation(
+    state: &RuntimeState,
     cmd_tx: &Sender<Action>,
     function_name: String,
     mut req: Request<Body>,
@@ -254,6 +492,32 @@ async fn schedule_invocation(
         function_name
     };
 
+    // Execution environments stay failed after an extension reports
+    // an error, until the extension registers again.
+    let concurrency = state.functions.get(&function_name).await.concurrency;
+    let environments = (0..concurrency)
+        .map(|index| environment_id(&function_name, index))
+        .collect::<Vec<_>>();
+    if let Some(error) = state.ext_cache.failure(&environments).await {
+        tracing::error!(function = ?function_name, error_type = %error.error_type, "failing invocation, an extension reported an error");
+        return Ok(error.into_lambda_response());
+    }
+
+    // Functions that crashed don't pick up invocations until they restart.
+    if let Some(failure) = state.processes.function_failure(&function_name).await {
+        let error = failure.error(None);
+        tracing::error!(function = ?function_name, error_type = %error.error_type, "failing invocation, the function's process failed");
+        return Ok(error.into_lambda_response());
+    }
+
+    let (pending, req) = match &state.recorder {
+        Some(recorder) => {
+            let (pending, req) = recorder.start(&function_name, req).await?;
+            (Some(pending), req)
+        }
+        None => (None, req),
+    };
+
     let req = InvokeRequest {
         function_name,
         req,
@@ -274,13 +538,17 @@ async fn schedule_invocation(
         );
     }
 
-    Ok(resp)
+    match pending {
+        Some(pending) => Ok(pending.finish_with(resp)),
+        None => Ok(resp),
+    }
 }
 
+/// Function that handles a request, the path that it receives, and the path parameters.
+/// Requests sent to the `/lambda-url` prefix go to the function in the prefix.
 fn extract_path_parameters(
     path: &str,
-    method: &Method,
-    state: &RefRuntimeState,
+    route: Option<&RequestRoute>,
 ) -> (String, String, HashMap<String, String>) {
     let mut comp = path.split('/');
     comp.next(); // skip the first empty string
@@ -302,11 +570,12 @@ fn extract_path_parameters(
         }
     }
 
-    tracing::trace!(?state.function_router, "checking function router");
-    if let Some(router) = &state.function_router {
-        if let Ok((route, params)) = router.at(path, method.to_string().as_str()) {
-            return (route.to_string(), path.to_string(), params);
-        }
+    if let Some(route) = route {
+        return (
+            route.function.clone(),
+            path.to_string(),
+            route.params.clone(),
+        );
     }
 
     (
@@ -316,6 +585,45 @@ fn extract_path_parameters(
     )
 }
 
+/// Match a request with the function router,
+/// using its Host header and headers for the routes that match them.
+/// Requests sent to the `/lambda-url` prefix don't use the router.
+fn route_request(
+    path: &str,
+    method: &Method,
+    headers: &HeaderMap,
+    state: &RefRuntimeState,
+) -> Option<RequestRoute> {
+    if path.starts_with(&format!("/{LAMBDA_URL_PREFIX}/")) {
+        return None;
+    }
+
+    let router = state.function_router.as_ref()?;
+
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(host_without_port);
+    let headers = headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect::<Vec<_>>();
+
+    router.route(&RouteRequest {
+        path,
+        method: method.as_str(),
+        host,
+        headers: &headers,
+    })
+}
+
+fn host_without_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.split_inclusive(']').next().unwrap_or(host);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
 async fn create_streaming_response(
     builder: &mut Builder,
     body: &mut Body,
@@ -374,14 +682,14 @@ fn is_streaming_response(headers: &HeaderMap) -> bool {
 async fn create_buffered_response(
     builder: &mut Builder,
     body: &mut Body,
+    payload_format: PayloadFormat,
 ) -> Result<(StatusCode, Body), ServerError> {
     let body = body
         .collect()
         .await
         .map_err(ServerError::DataDeserialization)?
         .to_bytes();
-    let resp_event: ApiGatewayV2httpResponse =
-        serde_json::from_slice(&body).map_err(ServerError::SerializationError)?;
+    let resp_event = payload_format::decode_response(payload_format, &body)?;
 
     let is_base64_encoded = resp_event.is_base64_encoded;
     let resp_body = match resp_event.body.unwrap_or(LambdaBody::Empty) {
@@ -455,18 +763,126 @@ fn respond_with_missing_function(
         .map_err(ServerError::ResponseBuild)
 }
 
+fn respond_with_invalid_invocation_type(
+    value: &str,
+    allowed: &str,
+) -> Result<Response<Body>, ServerError> {
+    let detail = format!(
+        "1 validation error detected: Value '{value}' at 'invocationType' failed to satisfy constraint: Member must satisfy enum value set: {allowed}"
+    );
+    tracing::error!(invocation_type = ?value, "invalid invocation type");
+
+    let body = Body::from(
+        serde_json::json!({
+            "title": "ValidationException",
+            "detail": detail,
+            "Type": "User",
+            "message": detail,
+        })
+        .to_string(),
+    );
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("x-amzn-errortype", "ValidationException")
+        .body(body)
+        .map_err(ServerError::ResponseBuild)
+}
+
+fn respond_with_throttled_function(function_name: &str) -> Result<Response<Body>, ServerError> {
+    let detail = "Rate Exceeded.";
+    tracing::error!(
+        function = ?function_name,
+        "the function reached its reserved concurrency limit, throttling invocation"
+    );
+
+    let body = Body::from(
+        serde_json::json!({
+            "title": "TooManyRequestsException",
+            "detail": detail,
+            "Reason": "ReservedFunctionConcurrentInvocationLimitExceeded",
+            "Type": "User",
+            "message": detail,
+        })
+        .to_string(),
+    );
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("x-amzn-errortype", "TooManyRequestsException")
+        .body(body)
+        .map_err(ServerError::ResponseBuild)
+}
+
+fn respond_with_request_too_large(
+    function_name: &str,
+    limit: usize,
+) -> Result<Response<Body>, ServerError> {
+    let detail =
+        format!("Request must be smaller than {limit} bytes for the InvokeFunction operation");
+    tracing::error!(function = ?function_name, limit, "the invocation payload is too large");
+
+    let body = Body::from(
+        serde_json::json!({
+            "title": "RequestEntityTooLargeException",
+            "detail": detail,
+            "Type": "User",
+            "message": detail,
+        })
+        .to_string(),
+    );
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .header("x-amzn-errortype", "RequestEntityTooLargeException")
+        .body(body)
+        .map_err(ServerError::ResponseBuild)
+}
+
+fn respond_with_forbidden(
+    function_name: &str,
+    error: iam_auth::AuthError,
+) -> Result<Response<Body>, ServerError> {
+    tracing::error!(function = ?function_name, %error, "rejecting request without a valid AWS_IAM signature");
+
+    let body = Body::from(serde_json::json!({ "Message": "Forbidden" }).to_string());
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header("content-type", "application/json")
+        .header("x-amzn-errortype", "AccessDeniedException")
+        .body(body)
+        .map_err(ServerError::ResponseBuild)
+}
+
+fn respond_with_unauthorized(
+    function_name: &str,
+    error: authorizers::AuthorizerError,
+) -> Result<Response<Body>, ServerError> {
+    let status = error.status_code();
+    let message = match status {
+        StatusCode::UNAUTHORIZED => "Unauthorized",
+        StatusCode::FORBIDDEN => "Forbidden",
+        _ => "Internal Server Error",
+    };
+    tracing::error!(function = ?function_name, %error, "rejecting request, the route's authorizer didn't authorize it");
+
+    let body = Body::from(serde_json::json!({ "message": message }).to_string());
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body)
+        .map_err(ServerError::ResponseBuild)
+}
+
 #[cfg(test)]
 mod test {
     use std::{
-        collections::HashSet,
+        collections::{HashMap, HashSet},
         net::{IpAddr, Ipv4Addr, SocketAddr},
         path::PathBuf,
         sync::Arc,
     };
 
-    use crate::RuntimeState;
+    use crate::{RefRuntimeState, RuntimeState};
 
-    use super::extract_path_parameters;
+    use super::{extract_path_parameters, host_without_port, route_request};
     use cargo_lambda_metadata::{
         DEFAULT_PACKAGE_FUNCTION,
         cargo::{
@@ -475,7 +891,17 @@ mod test {
         },
         config::{ConfigOptions, load_config_without_cli_flags},
     };
-    use http::Method;
+    use http::{HeaderMap, HeaderValue, Method};
+
+    fn extract(
+        path: &str,
+        method: &Method,
+        headers: &HeaderMap,
+        state: &RefRuntimeState,
+    ) -> (String, String, HashMap<String, String>) {
+        let route = route_request(path, method, headers, state);
+        extract_path_parameters(path, route.as_ref())
+    }
 
     #[test]
     fn test_extract_path_parameters() {
@@ -488,39 +914,55 @@ mod test {
             None,
         ));
 
-        let (func, path, _) = extract_path_parameters("", &Method::GET, &state);
+        let (func, path, _) = extract("", &Method::GET, &HeaderMap::new(), &state);
         assert_eq!(DEFAULT_PACKAGE_FUNCTION, func);
         assert_eq!("", path);
 
-        let (func, path, _) = extract_path_parameters("/", &Method::GET, &state);
+        let (func, path, _) = extract("/", &Method::GET, &HeaderMap::new(), &state);
         assert_eq!(DEFAULT_PACKAGE_FUNCTION, func);
         assert_eq!("/", path);
 
-        let (func, path, _) = extract_path_parameters("/foo", &Method::GET, &state);
+        let (func, path, _) = extract("/foo", &Method::GET, &HeaderMap::new(), &state);
         assert_eq!(DEFAULT_PACKAGE_FUNCTION, func);
         assert_eq!("/foo", path);
 
-        let (func, path, _) = extract_path_parameters("/foo/", &Method::GET, &state);
+        let (func, path, _) = extract("/foo/", &Method::GET, &HeaderMap::new(), &state);
         assert_eq!(DEFAULT_PACKAGE_FUNCTION, func);
         assert_eq!("/foo/", path);
 
-        let (func, path, _) =
-            extract_path_parameters("/lambda-url/func-name", &Method::GET, &state);
+        let (func, path, _) = extract(
+            "/lambda-url/func-name",
+            &Method::GET,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!("func-name", func);
         assert_eq!("/", path);
 
-        let (func, path, _) =
-            extract_path_parameters("/lambda-url/func-name/", &Method::GET, &state);
+        let (func, path, _) = extract(
+            "/lambda-url/func-name/",
+            &Method::GET,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!("func-name", func);
         assert_eq!("/", path);
 
-        let (func, path, _) =
-            extract_path_parameters("/lambda-url/func-name/foo", &Method::GET, &state);
+        let (func, path, _) = extract(
+            "/lambda-url/func-name/foo",
+            &Method::GET,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!("func-name", func);
         assert_eq!("/foo", path);
 
-        let (func, path, _) =
-            extract_path_parameters("/lambda-url/func-name/foo/", &Method::GET, &state);
+        let (func, path, _) = extract(
+            "/lambda-url/func-name/foo/",
+            &Method::GET,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!("func-name", func);
         assert_eq!("/foo/", path);
 
@@ -537,7 +979,7 @@ mod test {
             Some(new_router),
         ));
 
-        let (func, path, _) = extract_path_parameters("/foo", &Method::GET, &state);
+        let (func, path, _) = extract("/foo", &Method::GET, &HeaderMap::new(), &state);
         assert_eq!("bar", func);
         assert_eq!("/foo", path);
     }
@@ -562,15 +1004,20 @@ mod test {
         ));
 
         // Test with path parameters
-        let (func, path, params) =
-            extract_path_parameters("/users/123/posts/456", &Method::GET, &state);
+        let (func, path, params) = extract(
+            "/users/123/posts/456",
+            &Method::GET,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!("user-posts", func);
         assert_eq!("/users/123/posts/456", path);
         assert_eq!(params.get("user_id").unwrap(), "123");
         assert_eq!(params.get("post_id").unwrap(), "456");
 
         // Test with non-matching path
-        let (func, path, params) = extract_path_parameters("/invalid/path", &Method::GET, &state);
+        let (func, path, params) =
+            extract("/invalid/path", &Method::GET, &HeaderMap::new(), &state);
         assert_eq!(DEFAULT_PACKAGE_FUNCTION, func);
         assert_eq!("/invalid/path", path);
         assert!(params.is_empty());
@@ -591,18 +1038,64 @@ mod test {
         ));
 
         // Test with path parameters and method
-        let (func, path, params) =
-            extract_path_parameters("/users/123/posts/456", &Method::GET, &state);
+        let (func, path, params) = extract(
+            "/users/123/posts/456",
+            &Method::GET,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!("crate-3", func);
         assert_eq!("/users/123/posts/456", path);
         assert_eq!(params.get("user_id").unwrap(), "123");
         assert_eq!(params.get("post_id").unwrap(), "456");
 
         // Test with non-matching path and method
-        let (func, path, params) =
-            extract_path_parameters("/orgs/123/posts/456", &Method::POST, &state);
+        let (func, path, params) = extract(
+            "/orgs/123/posts/456",
+            &Method::POST,
+            &HeaderMap::new(),
+            &state,
+        );
         assert_eq!(DEFAULT_PACKAGE_FUNCTION, func);
         assert_eq!("/orgs/123/posts/456", path);
         assert!(params.is_empty());
     }
+
+    #[test]
+    fn test_extract_path_parameters_with_host_and_headers() {
+        let router: FunctionRouter = serde_json::from_value(serde_json::json!([
+            { "path": "/users/{id}", "function": "tenant-users", "host": "*.example.com" },
+            { "path": "/users/{id}", "function": "beta-users", "headers": { "x-beta": "true" } },
+            { "path": "/users/{id}", "function": "users" },
+        ]))
+        .unwrap();
+
+        let state = Arc::new(RuntimeState::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            None,
+            PathBuf::new(),
+            false,
+            HashSet::new(),
+            Some(router),
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_static("acme.example.com:9000"));
+        let (func, _, params) = extract("/users/1", &Method::GET, &headers, &state);
+        assert_eq!("tenant-users", func);
+        assert_eq!(params.get("id").unwrap(), "1");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_static("localhost:9000"));
+        headers.insert("x-beta", HeaderValue::from_static("true"));
+        let (func, _, _) = extract("/users/1", &Method::GET, &headers, &state);
+        assert_eq!("beta-users", func);
+
+        let (func, _, _) = extract("/users/1", &Method::GET, &HeaderMap::new(), &state);
+        assert_eq!("users", func);
+
+        assert_eq!("localhost", host_without_port("localhost:9000"));
+        assert_eq!("[::1]", host_without_port("[::1]:9000"));
+        assert_eq!("example.com", host_without_port("example.com"));
+    }
 }
@@ -0,0 +1,329 @@
+use crate::{
+    RefRuntimeState,
+    requests::Action,
+    state::{OnFailureDestination, Reservation},
+};
+use axum::{
+    body::Body,
+    http::{HeaderMap, Method, Request, StatusCode, Uri},
+};
+use bytes::Bytes;
+use chrono::{SecondsFormat, Utc};
+use http_body_util::BodyExt;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::{sync::mpsc::Sender, time::Instant};
+use tracing::{debug, error, warn};
+
+use super::schedule_invocation;
+
+/// Number of times that Lambda retries an asynchronous
+/// invocation when the function returns an error.
+const MAX_RETRY_ATTEMPTS: usize = 2;
+
+/// How long Lambda waits before each retry.
+const RETRY_DELAYS: [Duration; MAX_RETRY_ATTEMPTS] =
+    [Duration::from_secs(60), Duration::from_secs(120)];
+
+/// How long to wait before trying again when the function is throttled.
+/// Throttled attempts don't count towards the retries.
+const THROTTLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Invocation that the function processes in the background,
+/// after the emulator has responded to the caller.
+pub(crate) struct AsyncInvocation {
+    pub request_id: String,
+    pub function_name: String,
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub payload: Bytes,
+    pub received_at: Instant,
+}
+
+impl AsyncInvocation {
+    fn request(&self) -> Request<Body> {
+        let mut req = Request::new(Body::from(self.payload.clone()));
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.headers_mut() = self.headers.clone();
+        req
+    }
+}
+
+/// Reason why an asynchronous invocation was sent to the on-failure destination.
+#[derive(Clone, Copy, Debug, Serialize)]
+enum FailureCondition {
+    RetriesExhausted,
+    EventAgeExceeded,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DestinationRecord {
+    version: &'static str,
+    timestamp: String,
+    request_context: RecordRequestContext,
+    request_payload: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_context: Option<RecordResponseContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_payload: Option<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordRequestContext {
+    request_id: String,
+    function_arn: String,
+    condition: FailureCondition,
+    approximate_invoke_count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordResponseContext {
+    status_code: u16,
+    executed_version: &'static str,
+    function_error: &'static str,
+}
+
+/// Process an invocation in the background, retrying it
+/// when the function fails, like Lambda does with Event invocations.
+pub(crate) fn spawn(state: RefRuntimeState, cmd_tx: Sender<Action>, invocation: AsyncInvocation) {
+    tokio::spawn(async move {
+        process_invocation(&state, &cmd_tx, invocation).await;
+    });
+}
+
+async fn process_invocation(
+    state: &RefRuntimeState,
+    cmd_tx: &Sender<Action>,
+    invocation: AsyncInvocation,
+) {
+    let function_name = &invocation.function_name;
+    let request_id = &invocation.request_id;
+    let mut attempts = 0;
+    let mut last_response = None;
+
+    loop {
+        let settings = state.functions.get(function_name).await;
+        if invocation.received_at.elapsed() > settings.max_event_age {
+            error!(function = ?function_name, %request_id, "the asynchronous invocation is older than the maximum event age");
+            let condition = FailureCondition::EventAgeExceeded;
+            send_to_destination(
+                state,
+                cmd_tx,
+                &invocation,
+                condition,
+                attempts,
+                last_response,
+            )
+            .await;
+            return;
+        }
+
+        let reservation = state.functions.reserve(function_name).await;
+        if let Reservation::Throttled = reservation {
+            debug!(function = ?function_name, %request_id, "asynchronous invocation throttled, trying again later");
+            tokio::time::sleep(THROTTLE_DELAY).await;
+            continue;
+        }
+
+        attempts += 1;
+        let response = match schedule_invocation(
+            state,
+            cmd_tx,
+            function_name.clone(),
+            invocation.request(),
+        )
+        .await
+        {
+            Ok(resp) => resp,
+            Err(error) => {
+                error!(?error, function = ?function_name, %request_id, "failed to process asynchronous invocation");
+                return;
+            }
+        };
+        drop(reservation);
+
+        let status = response
+            .extensions()
+            .get::<StatusCode>()
+            .cloned()
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let payload = match response.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(error) => {
+                error!(?error, function = ?function_name, %request_id, "failed to read the function response");
+                Bytes::new()
+            }
+        };
+
+        if status == StatusCode::OK {
+            debug!(function = ?function_name, %request_id, attempts, "asynchronous invocation completed");
+            return;
+        }
+        last_response = Some(payload);
+
+        if attempts > MAX_RETRY_ATTEMPTS {
+            error!(function = ?function_name, %request_id, attempts, "the asynchronous invocation failed after all the retries");
+            let condition = FailureCondition::RetriesExhausted;
+            send_to_destination(
+                state,
+                cmd_tx,
+                &invocation,
+                condition,
+                attempts,
+                last_response,
+            )
+            .await;
+            return;
+        }
+
+        let delay = RETRY_DELAYS[attempts - 1];
+        warn!(function = ?function_name, %request_id, ?delay, "the asynchronous invocation failed, retrying");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn send_to_destination(
+    state: &RefRuntimeState,
+    cmd_tx: &Sender<Action>,
+    invocation: &AsyncInvocation,
+    condition: FailureCondition,
+    attempts: usize,
+    response: Option<Bytes>,
+) {
+    let settings = state.functions.get(&invocation.function_name).await;
+    let Some(destination) = settings.on_failure else {
+        return;
+    };
+
+    let record = destination_record(invocation, condition, attempts, response);
+    let record = match serde_json::to_vec_pretty(&record) {
+        Ok(record) => record,
+        Err(error) => {
+            error!(
+                ?error,
+                "failed to serialize the on-failure destination record"
+            );
+            return;
+        }
+    };
+
+    match destination {
+        OnFailureDestination::Directory(dir) => {
+            let path = dir.join(format!("{}.json", invocation.request_id));
+            let result = match tokio::fs::create_dir_all(&dir).await {
+                Ok(_) => tokio::fs::write(&path, record).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(_) => debug!(
+                    ?path,
+                    "failed invocation stored in the on-failure destination"
+                ),
+                Err(error) => {
+                    error!(
+                        ?error,
+                        ?path,
+                        "failed to store the invocation in the on-failure destination"
+                    )
+                }
+            }
+        }
+        OnFailureDestination::Function(function_name) => {
+            let req = Request::new(Body::from(record));
+
+            // Lambda doesn't retry invocations to the on-failure destination.
+            if let Err(error) = schedule_invocation(state, cmd_tx, function_name.clone(), req).await
+            {
+                error!(?error, function = ?function_name, "failed to invoke the on-failure destination");
+            }
+        }
+    }
+}
+
+fn destination_record(
+    invocation: &AsyncInvocation,
+    condition: FailureCondition,
+    attempts: usize,
+    response: Option<Bytes>,
+) -> DestinationRecord {
+    let (response_context, response_payload) = match response {
+        None => (None, None),
+        Some(response) => (
+            Some(RecordResponseContext {
+                status_code: StatusCode::OK.as_u16(),
+                executed_version: "$LATEST",
+                function_error: "Unhandled",
+            }),
+            Some(json_payload(&response)),
+        ),
+    };
+
+    DestinationRecord {
+        version: "1.0",
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        request_context: RecordRequestContext {
+            request_id: invocation.request_id.clone(),
+            function_arn: invocation.function_name.clone(),
+            condition,
+            approximate_invoke_count: attempts,
+        },
+        request_payload: json_payload(&invocation.payload),
+        response_context,
+        response_payload,
+    }
+}
+
+fn json_payload(payload: &Bytes) -> Value {
+    serde_json::from_slice(payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_destination_record() {
+        let invocation = AsyncInvocation {
+            request_id: "request-id".into(),
+            function_name: "basic-lambda".into(),
+            method: Method::POST,
+            uri: Uri::from_static("/2015-03-31/functions/basic-lambda/invocations"),
+            headers: HeaderMap::new(),
+            payload: Bytes::from_static(br#"{"command":"hi"}"#),
+            received_at: Instant::now(),
+        };
+        let response = Bytes::from_static(br#"{"errorType":"Boom","errorMessage":"boom"}"#);
+
+        let record = destination_record(
+            &invocation,
+            FailureCondition::RetriesExhausted,
+            3,
+            Some(response),
+        );
+        let record = serde_json::to_value(record).unwrap();
+
+        assert_eq!(record["requestContext"]["requestId"], "request-id");
+        assert_eq!(record["requestContext"]["functionArn"], "basic-lambda");
+        assert_eq!(record["requestContext"]["condition"], "RetriesExhausted");
+        assert_eq!(record["requestContext"]["approximateInvokeCount"], 3);
+        assert_eq!(record["requestPayload"], json!({"command": "hi"}));
+        assert_eq!(record["responseContext"]["statusCode"], 200);
+        assert_eq!(record["responseContext"]["functionError"], "Unhandled");
+        assert_eq!(record["responsePayload"]["errorType"], "Boom");
+
+        let record = destination_record(&invocation, FailureCondition::EventAgeExceeded, 0, None);
+        let record = serde_json::to_value(record).unwrap();
+
+        assert_eq!(record["requestContext"]["condition"], "EventAgeExceeded");
+        assert_eq!(record.get("responseContext"), None);
+    }
+}
@@ -0,0 +1,989 @@
+//! Emulation of the authorizers that API Gateway runs before it invokes a function:
+//! https://docs.aws.amazon.com/apigateway/latest/developerguide/http-api-access-control.html
+
+use super::{payload_format::query_string_parameters, schedule_invocation};
+use crate::{
+    requests::Action,
+    state::RuntimeState,
+    watcher::env::{LOCAL_ACCOUNT_ID, function_region},
+};
+use aws_lambda_events::{
+    apigw::{
+        ApiGatewayCustomAuthorizerRequestTypeRequest,
+        ApiGatewayCustomAuthorizerRequestTypeRequestContext,
+        ApiGatewayV2CustomAuthorizerIamPolicyResponse, ApiGatewayV2CustomAuthorizerSimpleResponse,
+        ApiGatewayV2CustomAuthorizerV2Request, ApiGatewayV2httpRequestContext,
+        ApiGatewayV2httpRequestContextHttpDescription,
+    },
+    iam::IamPolicyEffect,
+};
+use axum::{body::Body, http::request::Parts};
+use base64::{Engine as _, engine::general_purpose as b64};
+use cargo_lambda_metadata::cargo::watch::{
+    Authorizer, AuthorizerResponseFormat, JwtAuthorizer, LambdaAuthorizer, PayloadFormat,
+};
+use chrono::Utc;
+use http::Request;
+use http_body_util::BodyExt;
+use hyper::StatusCode;
+use ring::{hmac, signature};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{Mutex, mpsc::Sender};
+
+/// Identity source that authorizers read when routes don't set one.
+const DEFAULT_IDENTITY_SOURCE: &str = "$request.header.Authorization";
+/// Seconds that Lambda authorizer responses are cached when routes don't set a TTL.
+const DEFAULT_TTL: u64 = 300;
+/// Id of the API in the ARNs that Lambda authorizers receive.
+const LOCAL_API_ID: &str = "local";
+/// Action that IAM policies must allow for a request to reach the function.
+const INVOKE_ACTION: &str = "execute-api:Invoke";
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub(crate) enum AuthorizerError {
+    #[error("the request doesn't include the identity source `{0}`")]
+    MissingIdentity(String),
+    #[error("the token is malformed: {0}")]
+    MalformedToken(&'static str),
+    #[error("the token is signed with the unsupported algorithm `{0}`")]
+    UnsupportedAlgorithm(String),
+    #[error("there are no keys to verify the token's signature")]
+    MissingKey,
+    #[error("the token's signature doesn't match any of the authorizer's keys")]
+    InvalidSignature,
+    #[error("the token expired")]
+    ExpiredToken,
+    #[error("the token is not valid yet")]
+    ImmatureToken,
+    #[error("the token's issuer is not `{0}`")]
+    InvalidIssuer(String),
+    #[error("the token's audience is not accepted by the authorizer")]
+    InvalidAudience,
+    #[error("the token doesn't include any of the route's scopes")]
+    MissingScope,
+    #[error("the authorizer denied the request")]
+    Denied,
+    #[error("the authorizer failed: {0}")]
+    Failed(String),
+}
+
+impl AuthorizerError {
+    /// Status code that API Gateway responds with when an authorizer rejects a request.
+    pub(crate) fn status_code(&self) -> StatusCode {
+        match self {
+            AuthorizerError::Denied | AuthorizerError::MissingScope => StatusCode::FORBIDDEN,
+            AuthorizerError::Failed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+/// Information that an authorizer adds to the request context of the events.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum AuthorizerContext {
+    Jwt {
+        claims: Map<String, Value>,
+        scopes: Option<Vec<String>>,
+    },
+    Lambda {
+        principal_id: Option<String>,
+        context: HashMap<String, Value>,
+    },
+}
+
+/// Request that an authorizer checks.
+pub(crate) struct AuthorizerRequest<'a> {
+    pub parts: &'a Parts,
+    pub request_id: &'a str,
+    pub path: &'a str,
+    /// Path pattern of the route that matched the request
+    pub resource: &'a str,
+    pub path_parameters: &'a HashMap<String, String>,
+    pub payload_format: PayloadFormat,
+}
+
+/// Key sets loaded from disk, with the modification time of their files.
+type JwksFiles = HashMap<PathBuf, (SystemTime, Arc<Jwks>)>;
+
+/// Responses of Lambda authorizers, by authorizer function and identity,
+/// and the key sets of JWT authorizers, by path.
+#[derive(Clone, Default)]
+pub(crate) struct AuthorizerCache {
+    inner: Arc<Mutex<HashMap<String, (Instant, Value)>>>,
+    jwks: Arc<Mutex<JwksFiles>>,
+}
+
+impl AuthorizerCache {
+    async fn get(&self, key: &str) -> Option<Value> {
+        let mut inner = self.inner.lock().await;
+        match inner.get(key) {
+            Some((expires_at, response)) if *expires_at > Instant::now() => Some(response.clone()),
+            Some(_) => {
+                inner.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn insert(&self, key: String, response: Value, ttl: Duration) {
+        let mut inner = self.inner.lock().await;
+        inner.insert(key, (Instant::now() + ttl, response));
+    }
+
+    /// Load a JSON Web Key Set. The file is only read again when it changes.
+    async fn jwks(&self, path: &Path) -> Result<Arc<Jwks>, AuthorizerError> {
+        let read_error = |e: std::io::Error| {
+            AuthorizerError::Failed(format!("failed to read JWKS file {}: {e}", path.display()))
+        };
+        let modified = tokio::fs::metadata(path)
+            .await
+            .and_then(|m| m.modified())
+            .map_err(read_error)?;
+
+        let mut jwks = self.jwks.lock().await;
+        if let Some((cached_at, keys)) = jwks.get(path) {
+            if *cached_at == modified {
+                return Ok(keys.clone());
+            }
+        }
+
+        let content = tokio::fs::read(path).await.map_err(read_error)?;
+        let keys: Jwks = serde_json::from_slice(&content).map_err(|e| {
+            AuthorizerError::Failed(format!("invalid JWKS file {}: {e}", path.display()))
+        })?;
+        let keys = Arc::new(keys);
+        jwks.insert(path.to_path_buf(), (modified, keys.clone()));
+        Ok(keys)
+    }
+}
+
+/// Run a route's authorizer. It returns the context that the
+/// authorizer adds to the event, or the reason to reject the request.
+pub(crate) async fn authorize(
+    state: &RuntimeState,
+    cmd_tx: &Sender<Action>,
+    authorizer: &Authorizer,
+    request: &AuthorizerRequest<'_>,
+) -> Result<AuthorizerContext, AuthorizerError> {
+    match authorizer {
+        Authorizer::Jwt(jwt) => {
+            let jwks = match &jwt.jwks {
+                Some(path) => {
+                    let path = state.manifest_dir().join(path);
+                    Some(state.authorizers.jwks(&path).await?)
+                }
+                None => None,
+            };
+            verify_jwt(jwt, jwks.as_deref(), request.parts, Utc::now().timestamp())
+        }
+        Authorizer::Lambda(lambda) => invoke_authorizer(state, cmd_tx, lambda, request).await,
+    }
+}
+
+fn verify_jwt(
+    authorizer: &JwtAuthorizer,
+    jwks: Option<&Jwks>,
+    parts: &Parts,
+    now: i64,
+) -> Result<AuthorizerContext, AuthorizerError> {
+    let source = authorizer
+        .identity_source
+        .as_deref()
+        .unwrap_or(DEFAULT_IDENTITY_SOURCE);
+    let value = identity_value(source, parts)
+        .ok_or_else(|| AuthorizerError::MissingIdentity(source.to_string()))?;
+
+    let token = match value.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ => value.trim(),
+    };
+    let token = Token::decode(token)?;
+    token.verify(authorizer.secret.as_deref(), jwks)?;
+
+    let claims = token.claims;
+    if claims
+        .get("exp")
+        .and_then(Value::as_i64)
+        .is_some_and(|exp| exp <= now)
+    {
+        return Err(AuthorizerError::ExpiredToken);
+    }
+    if claims
+        .get("nbf")
+        .and_then(Value::as_i64)
+        .is_some_and(|nbf| nbf > now)
+    {
+        return Err(AuthorizerError::ImmatureToken);
+    }
+
+    if let Some(issuer) = &authorizer.issuer {
+        if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+            return Err(AuthorizerError::InvalidIssuer(issuer.clone()));
+        }
+    }
+
+    if !authorizer.audience.is_empty() {
+        let mut audience = string_values(claims.get("aud"));
+        audience.extend(string_values(claims.get("client_id")));
+        if !audience.iter().any(|aud| authorizer.audience.contains(aud)) {
+            return Err(AuthorizerError::InvalidAudience);
+        }
+    }
+
+    let scopes = match claims.get("scope") {
+        Some(Value::String(scope)) => Some(scope.split_whitespace().map(String::from).collect()),
+        _ => claims
+            .get("scp")
+            .map(|scp| string_values(Some(scp)))
+            .filter(|scp| !scp.is_empty()),
+    };
+    if !authorizer.scopes.is_empty() {
+        let granted = scopes.as_deref().unwrap_or_default();
+        if !authorizer.scopes.iter().any(|s| granted.contains(s)) {
+            return Err(AuthorizerError::MissingScope);
+        }
+    }
+
+    Ok(AuthorizerContext::Jwt { claims, scopes })
+}
+
+/// Strings in a claim that can be a single string or an array of strings.
+fn string_values(claim: Option<&Value>) -> Vec<String> {
+    match claim {
+        Some(Value::String(value)) => vec![value.clone()],
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// JSON Web Token, decoded but not verified yet.
+struct Token<'a> {
+    alg: String,
+    kid: Option<String>,
+    claims: Map<String, Value>,
+    signed: &'a str,
+    signature: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct TokenHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+impl<'a> Token<'a> {
+    fn decode(token: &'a str) -> Result<Token<'a>, AuthorizerError> {
+        let (signed, signature) = token
+            .rsplit_once('.')
+            .ok_or(AuthorizerError::MalformedToken("missing signature"))?;
+        let (header, claims) = signed
+            .split_once('.')
+            .ok_or(AuthorizerError::MalformedToken("missing claims"))?;
+
+        let header: TokenHeader = serde_json::from_slice(&decode_segment(header)?)
+            .map_err(|_| AuthorizerError::MalformedToken("invalid header"))?;
+        let claims: Map<String, Value> = serde_json::from_slice(&decode_segment(claims)?)
+            .map_err(|_| AuthorizerError::MalformedToken("invalid claims"))?;
+
+        Ok(Token {
+            alg: header.alg,
+            kid: header.kid,
+            claims,
+            signed,
+            signature: decode_segment(signature)?,
+        })
+    }
+
+    /// Verify the token's signature with the shared secret,
+    /// or with any of the keys in the key set.
+    fn verify(&self, secret: Option<&str>, jwks: Option<&Jwks>) -> Result<(), AuthorizerError> {
+        let message = self.signed.as_bytes();
+
+        let hmac_algorithm = match self.alg.as_str() {
+            "HS256" => Some(hmac::HMAC_SHA256),
+            "HS384" => Some(hmac::HMAC_SHA384),
+            "HS512" => Some(hmac::HMAC_SHA512),
+            _ => None,
+        };
+        if let Some(algorithm) = hmac_algorithm {
+            let secret = secret.ok_or(AuthorizerError::MissingKey)?;
+            let key = hmac::Key::new(algorithm, secret.as_bytes());
+            return hmac::verify(&key, message, &self.signature)
+                .map_err(|_| AuthorizerError::InvalidSignature);
+        }
+
+        let keys = jwks
+            .map(|jwks| jwks.keys.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter(|key| self.kid.is_none() || key.kid == self.kid)
+            .collect::<Vec<_>>();
+
+        let mut verified = None;
+        for key in keys {
+            let result = match (self.alg.as_str(), key.kty.as_str()) {
+                ("RS256", "RSA") => key.verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA256, self),
+                ("RS384", "RSA") => key.verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA384, self),
+                ("RS512", "RSA") => key.verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA512, self),
+                ("ES256", "EC") => key.verify_ec(&signature::ECDSA_P256_SHA256_FIXED, self),
+                ("ES384", "EC") => key.verify_ec(&signature::ECDSA_P384_SHA384_FIXED, self),
+                ("RS256" | "RS384" | "RS512" | "ES256" | "ES384", _) => continue,
+                (alg, _) => return Err(AuthorizerError::UnsupportedAlgorithm(alg.to_string())),
+            };
+            if result.is_ok() {
+                return Ok(());
+            }
+            verified = Some(result);
+        }
+
+        match verified {
+            Some(result) => result,
+            None if matches!(
+                self.alg.as_str(),
+                "RS256" | "RS384" | "RS512" | "ES256" | "ES384"
+            ) =>
+            {
+                Err(AuthorizerError::MissingKey)
+            }
+            None => Err(AuthorizerError::UnsupportedAlgorithm(self.alg.clone())),
+        }
+    }
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>, AuthorizerError> {
+    b64::URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .map_err(|_| AuthorizerError::MalformedToken("invalid base64 encoding"))
+}
+
+/// JSON Web Key Set with the public keys that sign the tokens.
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+impl Jwk {
+    fn verify_rsa(
+        &self,
+        params: &'static signature::RsaParameters,
+        token: &Token<'_>,
+    ) -> Result<(), AuthorizerError> {
+        let (Some(n), Some(e)) = (&self.n, &self.e) else {
+            return Err(AuthorizerError::MissingKey);
+        };
+        let key = signature::RsaPublicKeyComponents {
+            n: decode_segment(n)?,
+            e: decode_segment(e)?,
+        };
+        key.verify(params, token.signed.as_bytes(), &token.signature)
+            .map_err(|_| AuthorizerError::InvalidSignature)
+    }
+
+    fn verify_ec(
+        &self,
+        algorithm: &'static signature::EcdsaVerificationAlgorithm,
+        token: &Token<'_>,
+    ) -> Result<(), AuthorizerError> {
+        let (Some(x), Some(y)) = (&self.x, &self.y) else {
+            return Err(AuthorizerError::MissingKey);
+        };
+        // Uncompressed point encoding of the public key.
+        let mut key = vec![0x04];
+        key.extend(decode_segment(x)?);
+        key.extend(decode_segment(y)?);
+
+        signature::UnparsedPublicKey::new(algorithm, key)
+            .verify(token.signed.as_bytes(), &token.signature)
+            .map_err(|_| AuthorizerError::InvalidSignature)
+    }
+}
+
+/// Value of an identity source, like `$request.header.Authorization`
+/// or `$request.querystring.token`. Plain names are read from the headers.
+fn identity_value(source: &str, parts: &Parts) -> Option<String> {
+    if let Some(name) = source
+        .strip_prefix("$request.querystring.")
+        .or_else(|| source.strip_prefix("method.request.querystring."))
+    {
+        return query_string_parameters(parts)
+            .first(name)
+            .filter(|v| !v.is_empty())
+            .map(String::from);
+    }
+
+    let name = source
+        .strip_prefix("$request.header.")
+        .or_else(|| source.strip_prefix("method.request.header."))
+        .unwrap_or(source);
+    parts
+        .headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
+
+/// Invoke a Lambda authorizer, or reuse its cached response for the same identity.
+async fn invoke_authorizer(
+    state: &RuntimeState,
+    cmd_tx: &Sender<Action>,
+    authorizer: &LambdaAuthorizer,
+    request: &AuthorizerRequest<'_>,
+) -> Result<AuthorizerContext, AuthorizerError> {
+    let default_sources = [DEFAULT_IDENTITY_SOURCE.to_string()];
+    let sources = if authorizer.identity_source.is_empty() {
+        &default_sources[..]
+    } else {
+        &authorizer.identity_source[..]
+    };
+    let identity = sources
+        .iter()
+        .map(|source| {
+            identity_value(source, request.parts)
+                .ok_or_else(|| AuthorizerError::MissingIdentity(source.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let format = authorizer
+        .response_format
+        .unwrap_or(match request.payload_format {
+            PayloadFormat::V1 => AuthorizerResponseFormat::Iam,
+            _ => AuthorizerResponseFormat::Simple,
+        });
+    let method_arn = method_arn(request);
+
+    let ttl = authorizer.ttl.unwrap_or(DEFAULT_TTL);
+    let key = format!("{}\n{}", authorizer.function, identity.join("\n"));
+    if let Some(response) = state.authorizers.get(&key).await {
+        tracing::debug!(function = ?authorizer.function, "using cached authorizer response");
+        return evaluate_response(format, response, &method_arn);
+    }
+
+    if let Err(binaries) = state.is_function_available(&authorizer.function) {
+        return Err(AuthorizerError::Failed(format!(
+            "the authorizer function `{}` doesn't exist, available functions: {binaries:?}",
+            authorizer.function
+        )));
+    }
+
+    let event = match request.payload_format {
+        PayloadFormat::V1 => serde_json::to_vec(&v1_request(request, &method_arn)),
+        _ => serde_json::to_vec(&v2_request(request, &method_arn, identity)),
+    }
+    .map_err(|e| AuthorizerError::Failed(e.to_string()))?;
+
+    let resp = schedule_invocation(
+        state,
+        cmd_tx,
+        authorizer.function.clone(),
+        Request::new(Body::from(event)),
+    )
+    .await
+    .map_err(|e| AuthorizerError::Failed(e.to_string()))?;
+
+    let status = resp
+        .extensions()
+        .get::<StatusCode>()
+        .cloned()
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if status != StatusCode::OK {
+        return Err(AuthorizerError::Failed(format!(
+            "the authorizer function returned status {status}"
+        )));
+    }
+
+    let body = resp
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| AuthorizerError::Failed(e.to_string()))?
+        .to_bytes();
+    let response: Value = serde_json::from_slice(&body)
+        .map_err(|e| AuthorizerError::Failed(format!("invalid authorizer response: {e}")))?;
+
+    if ttl > 0 {
+        state
+            .authorizers
+            .insert(key, response.clone(), Duration::from_secs(ttl))
+            .await;
+    }
+
+    evaluate_response(format, response, &method_arn)
+}
+
+/// ARN of the route that the request invokes, that IAM policies allow or deny.
+fn method_arn(request: &AuthorizerRequest<'_>) -> String {
+    format!(
+        "arn:aws:execute-api:{}:{LOCAL_ACCOUNT_ID}:{LOCAL_API_ID}/$default/{}{}",
+        function_region(None),
+        request.parts.method,
+        request.path
+    )
+}
+
+/// Event that REST APIs send to `REQUEST` authorizers.
+fn v1_request(
+    request: &AuthorizerRequest<'_>,
+    method_arn: &str,
+) -> ApiGatewayCustomAuthorizerRequestTypeRequest {
+    let parts = request.parts;
+    let query_string_parameters = query_string_parameters(parts);
+
+    ApiGatewayCustomAuthorizerRequestTypeRequest {
+        type_: Some("REQUEST".into()),
+        method_arn: Some(method_arn.into()),
+        resource: Some(request.resource.into()),
+        path: Some(request.path.into()),
+        http_method: Some(parts.method.clone()),
+        headers: parts.headers.clone(),
+        multi_value_headers: parts.headers.clone(),
+        query_string_parameters: query_string_parameters.clone(),
+        multi_value_query_string_parameters: query_string_parameters,
+        path_parameters: request.path_parameters.clone(),
+        stage_variables: HashMap::new(),
+        request_context: ApiGatewayCustomAuthorizerRequestTypeRequestContext {
+            path: Some(request.path.into()),
+            account_id: Some(LOCAL_ACCOUNT_ID.into()),
+            stage: Some("$default".into()),
+            request_id: Some(request.request_id.into()),
+            resource_path: Some(request.resource.into()),
+            http_method: Some(parts.method.clone()),
+            apiid: Some(LOCAL_API_ID.into()),
+            ..Default::default()
+        },
+    }
+}
+
+/// Event that HTTP APIs send to Lambda authorizers, with the payload format version 2.0.
+fn v2_request(
+    request: &AuthorizerRequest<'_>,
+    route_arn: &str,
+    identity: Vec<String>,
+) -> ApiGatewayV2CustomAuthorizerV2Request {
+    let parts = request.parts;
+    let time = Utc::now();
+
+    let cookies = parts
+        .headers
+        .get("cookie")
+        .and_then(|c| c.to_str().ok())
+        .map(|c| c.split("; ").map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default();
+    let query_string_parameters = query_string_parameters(parts)
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    ApiGatewayV2CustomAuthorizerV2Request {
+        version: Some("2.0".into()),
+        type_: Some("REQUEST".into()),
+        route_arn: Some(route_arn.into()),
+        identity_source: Some(identity),
+        route_key: Some(format!("{} {}", parts.method, request.resource)),
+        raw_path: Some(request.path.into()),
+        raw_query_string: parts.uri.query().map(String::from),
+        cookies,
+        headers: parts.headers.clone(),
+        query_string_parameters,
+        path_parameters: request.path_parameters.clone(),
+        stage_variables: HashMap::new(),
+        request_context: ApiGatewayV2httpRequestContext {
+            stage: Some("$default".into()),
+            route_key: Some(format!("{} {}", parts.method, request.resource)),
+            request_id: Some(request.request_id.into()),
+            account_id: Some(LOCAL_ACCOUNT_ID.into()),
+            apiid: Some(LOCAL_API_ID.into()),
+            http: ApiGatewayV2httpRequestContextHttpDescription {
+                method: parts.method.clone(),
+                path: Some(request.path.into()),
+                protocol: Some("http".into()),
+                source_ip: Some("127.0.0.1".into()),
+                user_agent: Some("cargo-lambda".into()),
+            },
+            time: Some(time.format("%d/%b/%Y:%T %z").to_string()),
+            time_epoch: time.timestamp(),
+            ..Default::default()
+        },
+    }
+}
+
+/// Decide if a request is allowed from an authorizer's response.
+fn evaluate_response(
+    format: AuthorizerResponseFormat,
+    response: Value,
+    method_arn: &str,
+) -> Result<AuthorizerContext, AuthorizerError> {
+    let invalid = |e: serde_json::Error| {
+        AuthorizerError::Failed(format!("invalid {format} authorizer response: {e}"))
+    };
+
+    match format {
+        AuthorizerResponseFormat::Simple => {
+            let response: ApiGatewayV2CustomAuthorizerSimpleResponse<
+                Option<HashMap<String, Value>>,
+            > = serde_json::from_value(response).map_err(invalid)?;
+            if !response.is_authorized {
+                return Err(AuthorizerError::Denied);
+            }
+            Ok(AuthorizerContext::Lambda {
+                principal_id: None,
+                context: response.context.unwrap_or_default(),
+            })
+        }
+        AuthorizerResponseFormat::Iam => {
+            let response: ApiGatewayV2CustomAuthorizerIamPolicyResponse<
+                Option<HashMap<String, Value>>,
+            > = serde_json::from_value(response).map_err(invalid)?;
+
+            let matching = response
+                .policy_document
+                .statement
+                .iter()
+                .filter(|s| {
+                    // Action names are case insensitive in IAM, resources are not.
+                    s.action.iter().any(|a| {
+                        wildcard_match(&a.to_ascii_lowercase(), &INVOKE_ACTION.to_ascii_lowercase())
+                    })
+                })
+                .filter(|s| s.resource.iter().any(|r| wildcard_match(r, method_arn)))
+                .collect::<Vec<_>>();
+
+            let denied = matching.iter().any(|s| s.effect == IamPolicyEffect::Deny);
+            let allowed = matching.iter().any(|s| s.effect == IamPolicyEffect::Allow);
+            if denied || !allowed {
+                return Err(AuthorizerError::Denied);
+            }
+
+            Ok(AuthorizerContext::Lambda {
+                principal_id: response.principal_id,
+                context: response.context.unwrap_or_default(),
+            })
+        }
+    }
+}
+
+/// Match a value with an IAM pattern, where `*` matches any
+/// sequence of characters and `?` matches any single character.
+/// The rest of the characters must match exactly, including their case.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let value = value.as_bytes();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bv)) => {
+                    p = bp + 1;
+                    v = bv + 1;
+                    backtrack = Some((bp, bv + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+    use serde_json::json;
+
+    fn request_parts(authorization: &str) -> Parts {
+        let (parts, _) = Request::get("/orders/1")
+            .header("authorization", authorization)
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts
+    }
+
+    fn encode(value: &Value) -> String {
+        b64::URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).unwrap())
+    }
+
+    fn hs256_token(claims: Value, secret: &str) -> String {
+        let signed = format!(
+            "{}.{}",
+            encode(&json!({"alg": "HS256", "typ": "JWT"})),
+            encode(&claims)
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = hmac::sign(&key, signed.as_bytes());
+        format!("{signed}.{}", b64::URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    #[test]
+    fn test_verify_jwt_with_secret() {
+        let authorizer = JwtAuthorizer {
+            secret: Some("s3cr3t".into()),
+            issuer: Some("https://issuer.local".into()),
+            audience: vec!["orders".into()],
+            scopes: vec!["orders:read".into()],
+            ..Default::default()
+        };
+        let claims = json!({
+            "sub": "user-1",
+            "iss": "https://issuer.local",
+            "aud": ["orders"],
+            "scope": "orders:read orders:write",
+            "exp": 2000,
+        });
+
+        let token = hs256_token(claims.clone(), "s3cr3t");
+        let parts = request_parts(&format!("Bearer {token}"));
+        let context = verify_jwt(&authorizer, None, &parts, 1000).unwrap();
+        let AuthorizerContext::Jwt {
+            claims: decoded,
+            scopes,
+        } = context
+        else {
+            panic!("unexpected authorizer context");
+        };
+        assert_eq!(decoded["sub"], "user-1");
+        assert_eq!(
+            scopes,
+            Some(vec!["orders:read".to_string(), "orders:write".to_string()])
+        );
+
+        assert_eq!(
+            verify_jwt(&authorizer, None, &parts, 3000),
+            Err(AuthorizerError::ExpiredToken)
+        );
+
+        let token = hs256_token(claims_with(&claims, "aud", json!("billing")), "s3cr3t");
+        assert_eq!(
+            verify_jwt(&authorizer, None, &request_parts(&token), 1000),
+            Err(AuthorizerError::InvalidAudience)
+        );
+
+        let token = hs256_token(claims_with(&claims, "scope", json!("profile")), "s3cr3t");
+        let error = verify_jwt(&authorizer, None, &request_parts(&token), 1000).unwrap_err();
+        assert_eq!(error, AuthorizerError::MissingScope);
+        assert_eq!(error.status_code(), StatusCode::FORBIDDEN);
+
+        let token = hs256_token(claims.clone(), "other");
+        assert_eq!(
+            verify_jwt(&authorizer, None, &request_parts(&token), 1000),
+            Err(AuthorizerError::InvalidSignature)
+        );
+
+        let (parts, _) = Request::get("/orders/1").body(()).unwrap().into_parts();
+        let error = verify_jwt(&authorizer, None, &parts, 1000).unwrap_err();
+        assert_eq!(
+            error,
+            AuthorizerError::MissingIdentity(DEFAULT_IDENTITY_SOURCE.into())
+        );
+        assert_eq!(error.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn claims_with(claims: &Value, name: &str, value: Value) -> Value {
+        let mut claims = claims.clone();
+        claims[name] = value;
+        claims
+    }
+
+    #[test]
+    fn test_verify_jwt_with_jwks() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+
+        let public_key = key_pair.public_key().as_ref();
+        let jwks: Jwks = serde_json::from_value(json!({
+            "keys": [{
+                "kty": "EC",
+                "kid": "key-1",
+                "crv": "P-256",
+                "x": b64::URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+                "y": b64::URL_SAFE_NO_PAD.encode(&public_key[33..]),
+            }]
+        }))
+        .unwrap();
+
+        let signed = format!(
+            "{}.{}",
+            encode(&json!({"alg": "ES256", "kid": "key-1"})),
+            encode(&json!({"sub": "user-1", "client_id": "orders"}))
+        );
+        let signature = key_pair.sign(&rng, signed.as_bytes()).unwrap();
+        let token = format!("{signed}.{}", b64::URL_SAFE_NO_PAD.encode(signature));
+
+        let authorizer = JwtAuthorizer {
+            jwks: Some("jwks.json".into()),
+            audience: vec!["orders".into()],
+            ..Default::default()
+        };
+        let parts = request_parts(&format!("Bearer {token}"));
+        assert!(verify_jwt(&authorizer, Some(&jwks), &parts, 1000).is_ok());
+
+        assert_eq!(
+            verify_jwt(&authorizer, None, &parts, 1000),
+            Err(AuthorizerError::MissingKey)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_responses() {
+        let arn = "arn:aws:execute-api:us-east-1:000000000000:local/$default/GET/orders/1";
+
+        let context = evaluate_response(
+            AuthorizerResponseFormat::Simple,
+            json!({"isAuthorized": true, "context": {"tenant": "acme"}}),
+            arn,
+        )
+        .unwrap();
+        assert_eq!(
+            context,
+            AuthorizerContext::Lambda {
+                principal_id: None,
+                context: HashMap::from([("tenant".into(), json!("acme"))]),
+            }
+        );
+        assert_eq!(
+            evaluate_response(
+                AuthorizerResponseFormat::Simple,
+                json!({"isAuthorized": false}),
+                arn
+            ),
+            Err(AuthorizerError::Denied)
+        );
+
+        let policy = |effect: &str, resource: &str| {
+            json!({
+                "principalId": "user-1",
+                "policyDocument": {
+                    "Version": "2012-10-17",
+                    "Statement": [{
+                        "Action": "execute-api:Invoke",
+                        "Effect": effect,
+                        "Resource": resource,
+                    }]
+                }
+            })
+        };
+        let context = evaluate_response(
+            AuthorizerResponseFormat::Iam,
+            policy("Allow", "arn:aws:execute-api:*:*:*/*/GET/orders/*"),
+            arn,
+        )
+        .unwrap();
+        assert!(matches!(
+            context,
+            AuthorizerContext::Lambda { principal_id: Some(ref id), .. } if id == "user-1"
+        ));
+        assert_eq!(
+            evaluate_response(
+                AuthorizerResponseFormat::Iam,
+                policy("Allow", "arn:aws:execute-api:*:*:*/*/POST/orders/*"),
+                arn
+            ),
+            Err(AuthorizerError::Denied)
+        );
+        assert_eq!(
+            evaluate_response(AuthorizerResponseFormat::Iam, policy("Deny", "*"), arn),
+            Err(AuthorizerError::Denied)
+        );
+
+        // Resources match with their case, like paths in API Gateway.
+        let admin_arn = "arn:aws:execute-api:us-east-1:000000000000:local/$default/GET/admin";
+        assert_eq!(
+            evaluate_response(
+                AuthorizerResponseFormat::Iam,
+                policy("Allow", "arn:aws:execute-api:*:*:*/*/GET/Admin"),
+                admin_arn
+            ),
+            Err(AuthorizerError::Denied)
+        );
+        let mut invoke = policy("Allow", "arn:aws:execute-api:*:*:*/*/GET/admin");
+        invoke["policyDocument"]["Statement"][0]["Action"] = json!("Execute-API:invoke");
+        assert!(evaluate_response(AuthorizerResponseFormat::Iam, invoke, admin_arn).is_ok());
+
+        let error =
+            evaluate_response(AuthorizerResponseFormat::Simple, json!("yes"), arn).unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("execute-api:*", "execute-api:Invoke"));
+        assert!(wildcard_match("a*c?e", "abbbcde"));
+        assert!(!wildcard_match("a*c?e", "abbbce"));
+        assert!(!wildcard_match("GET/orders", "GET/orders/1"));
+        assert!(!wildcard_match("GET/Admin", "GET/admin"));
+    }
+
+    #[tokio::test]
+    async fn test_authorizer_cache() {
+        let cache = AuthorizerCache::default();
+        cache
+            .insert("key".into(), json!(true), Duration::from_secs(60))
+            .await;
+        assert_eq!(cache.get("key").await, Some(json!(true)));
+
+        cache
+            .insert("expired".into(), json!(true), Duration::ZERO)
+            .await;
+        assert_eq!(cache.get("expired").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_jwks_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jwks.json");
+        std::fs::write(&path, r#"{"keys": []}"#).unwrap();
+
+        let cache = AuthorizerCache::default();
+        let keys = cache.jwks(&path).await.unwrap();
+        assert!(keys.keys.is_empty());
+        assert!(Arc::ptr_eq(&keys, &cache.jwks(&path).await.unwrap()));
+
+        std::fs::write(&path, "invalid").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(matches!(
+            cache.jwks(&path).await,
+            Err(AuthorizerError::Failed(_))
+        ));
+    }
+}
@@ -0,0 +1,754 @@
+//! Verification of the SigV4 signatures in requests to
+//! function URLs that use the `AWS_IAM` authentication type:
+//! https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+
+use crate::watcher::env::LOCAL_ACCOUNT_ID;
+use aws_lambda_events::apigw::ApiGatewayRequestAuthorizerIamDescription;
+use axum::http::request::Parts;
+use cargo_lambda_remote::{RemoteConfig, aws_sdk_lambda::config::ProvideCredentials};
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use hyper::HeaderMap;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use subtle::ConstantTimeEq;
+use tokio::sync::{Mutex, RwLock};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SERVICE: &str = "lambda";
+const DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Value of the `x-amz-content-sha256` header when the client doesn't sign the body.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Maximum difference between the time in a request's signature
+/// and the time that the request is received.
+const MAX_CLOCK_SKEW: TimeDelta = TimeDelta::minutes(15);
+
+/// Minimum time between reloads of the credentials,
+/// when requests are signed with access keys that the store doesn't know.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Characters that SigV4 doesn't percent-encode.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub(crate) enum AuthError {
+    #[error("the request is not signed")]
+    MissingSignature,
+    #[error("the request signature is malformed: {0}")]
+    MalformedSignature(&'static str),
+    #[error("the signature was created at {0}, which is more than 15 minutes away from now")]
+    ExpiredSignature(String),
+    #[error("the access key `{0}` is not in your local AWS credentials")]
+    UnknownAccessKey(String),
+    #[error("the security token doesn't match the one in the local credentials")]
+    InvalidSecurityToken,
+    #[error("the signature doesn't match the signature calculated with the local credentials")]
+    SignatureMismatch,
+    #[error("the x-amz-content-sha256 header doesn't match the hash of the request body")]
+    PayloadHashMismatch,
+}
+
+/// Credentials that the emulator uses to verify signatures.
+#[derive(Clone, Debug, PartialEq)]
+struct LocalCredentials {
+    secret_access_key: String,
+    session_token: Option<String>,
+    account_id: Option<String>,
+    /// Name of the profile, or provider, that the credentials come from
+    source: String,
+}
+
+/// Local AWS credentials, indexed by access key id.
+#[derive(Clone, Default)]
+pub(crate) struct CredentialStore {
+    inner: Arc<RwLock<HashMap<String, LocalCredentials>>>,
+    /// When the credentials were last loaded, by the profile in the function's configuration
+    loaded_at: Arc<Mutex<HashMap<Option<String>, Instant>>>,
+}
+
+impl CredentialStore {
+    async fn find(
+        &self,
+        access_key_id: &str,
+        remote_config: Option<&RemoteConfig>,
+    ) -> Option<LocalCredentials> {
+        if let Some(credentials) = self.inner.read().await.get(access_key_id) {
+            return Some(credentials.clone());
+        }
+
+        // The key is not in the cache, reload the credentials
+        // in case they changed since the last reload.
+        // Concurrent requests wait for the same reload.
+        let profile = remote_config.and_then(|c| c.profile.clone());
+        let mut loaded_at = self.loaded_at.lock().await;
+        if !should_reload(loaded_at.get(&profile), Instant::now()) {
+            return self.inner.read().await.get(access_key_id).cloned();
+        }
+        loaded_at.insert(profile, Instant::now());
+
+        let mut loaded = load_shared_credentials();
+        match load_remote_credentials(remote_config).await {
+            Some((access_key_id, credentials)) => {
+                loaded.insert(access_key_id, credentials);
+            }
+            None => tracing::debug!("no credentials available in the AWS configuration"),
+        }
+
+        let mut inner = self.inner.write().await;
+        inner.extend(loaded);
+        inner.get(access_key_id).cloned()
+    }
+}
+
+fn should_reload(loaded_at: Option<&Instant>, now: Instant) -> bool {
+    loaded_at.is_none_or(|loaded_at| now.duration_since(*loaded_at) >= RELOAD_INTERVAL)
+}
+
+/// Read the credentials in the shared credentials file, `~/.aws/credentials` by default.
+fn load_shared_credentials() -> HashMap<String, LocalCredentials> {
+    let path = match std::env::var("AWS_SHARED_CREDENTIALS_FILE") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => match dirs::home_dir() {
+            Some(home) => home.join(".aws").join("credentials"),
+            None => return HashMap::new(),
+        },
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) => parse_credentials_file(&content),
+        Err(error) => {
+            tracing::debug!(?path, ?error, "failed to read the shared credentials file");
+            HashMap::new()
+        }
+    }
+}
+
+/// Load the credentials from the AWS configuration of the function,
+/// or from the default credentials chain.
+async fn load_remote_credentials(
+    remote_config: Option<&RemoteConfig>,
+) -> Option<(String, LocalCredentials)> {
+    let sdk_config = remote_config
+        .cloned()
+        .unwrap_or_default()
+        .sdk_config(None)
+        .await;
+    let credentials = sdk_config
+        .credentials_provider()?
+        .provide_credentials()
+        .await
+        .inspect_err(|error| tracing::debug!(?error, "failed to load AWS credentials"))
+        .ok()?;
+
+    let source = remote_config
+        .and_then(|c| c.profile.clone())
+        .unwrap_or_else(|| "default".into());
+
+    let local = LocalCredentials {
+        secret_access_key: credentials.secret_access_key().to_string(),
+        session_token: credentials.session_token().map(String::from),
+        account_id: None,
+        source,
+    };
+    Some((credentials.access_key_id().to_string(), local))
+}
+
+fn parse_credentials_file(content: &str) -> HashMap<String, LocalCredentials> {
+    let mut profiles: Vec<(String, HashMap<String, String>)> = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            profiles.push((name.trim().to_string(), HashMap::new()));
+        } else if let (Some((key, value)), Some((_, properties))) =
+            (line.split_once('='), profiles.last_mut())
+        {
+            properties.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    profiles
+        .into_iter()
+        .filter_map(|(source, mut properties)| {
+            let access_key_id = properties.remove("aws_access_key_id")?;
+            let credentials = LocalCredentials {
+                secret_access_key: properties.remove("aws_secret_access_key")?,
+                session_token: properties.remove("aws_session_token"),
+                account_id: properties.remove("aws_account_id"),
+                source,
+            };
+            Some((access_key_id, credentials))
+        })
+        .collect()
+}
+
+/// Signature in the `Authorization` header, or in the query string of a presigned URL.
+#[derive(Debug, PartialEq)]
+struct Signature {
+    access_key_id: String,
+    date: String,
+    /// Date, region, and service that the signature is scoped to
+    scope: String,
+    region: String,
+    signed_headers: Vec<String>,
+    signature: String,
+    /// Seconds that a presigned URL is valid for.
+    /// `None` when the request is signed with the `Authorization` header
+    expires: Option<i64>,
+}
+
+impl Signature {
+    fn from_request(parts: &Parts) -> Result<Signature, AuthError> {
+        if let Some(authorization) = header_value(&parts.headers, "authorization") {
+            let date = header_value(&parts.headers, "x-amz-date")
+                .ok_or(AuthError::MalformedSignature("missing X-Amz-Date header"))?;
+            return Signature::from_header(authorization, date);
+        }
+
+        let query = parse_query(parts.uri.query().unwrap_or_default());
+        if query.iter().any(|(k, _)| k == "X-Amz-Signature") {
+            return Signature::from_query(&query);
+        }
+
+        Err(AuthError::MissingSignature)
+    }
+
+    fn from_header(authorization: &str, date: &str) -> Result<Signature, AuthError> {
+        let components =
+            authorization
+                .strip_prefix(ALGORITHM)
+                .ok_or(AuthError::MalformedSignature(
+                    "unsupported signing algorithm",
+                ))?;
+
+        let mut credential = None;
+        let mut signed_headers = None;
+        let mut signature = None;
+        for component in components.split(',') {
+            match component.trim().split_once('=') {
+                Some(("Credential", value)) => credential = Some(value),
+                Some(("SignedHeaders", value)) => signed_headers = Some(value),
+                Some(("Signature", value)) => signature = Some(value),
+                _ => {}
+            }
+        }
+
+        Signature::new(
+            credential.ok_or(AuthError::MalformedSignature("missing Credential"))?,
+            date,
+            signed_headers.ok_or(AuthError::MalformedSignature("missing SignedHeaders"))?,
+            signature.ok_or(AuthError::MalformedSignature("missing Signature"))?,
+            None,
+        )
+    }
+
+    fn from_query(query: &[(String, String)]) -> Result<Signature, AuthError> {
+        let param = |name: &'static str| {
+            query
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+                .ok_or(AuthError::MalformedSignature(name))
+        };
+
+        if param("X-Amz-Algorithm")? != ALGORITHM {
+            return Err(AuthError::MalformedSignature(
+                "unsupported signing algorithm",
+            ));
+        }
+
+        let expires = param("X-Amz-Expires")?
+            .parse()
+            .map_err(|_| AuthError::MalformedSignature("X-Amz-Expires"))?;
+
+        Signature::new(
+            param("X-Amz-Credential")?,
+            param("X-Amz-Date")?,
+            param("X-Amz-SignedHeaders")?,
+            param("X-Amz-Signature")?,
+            Some(expires),
+        )
+    }
+
+    fn new(
+        credential: &str,
+        date: &str,
+        signed_headers: &str,
+        signature: &str,
+        expires: Option<i64>,
+    ) -> Result<Signature, AuthError> {
+        // The credential has the format `AKID/20240101/us-east-1/lambda/aws4_request`
+        let (access_key_id, scope) = credential
+            .split_once('/')
+            .ok_or(AuthError::MalformedSignature("invalid Credential"))?;
+
+        let region = match scope.split('/').collect::<Vec<_>>().as_slice() {
+            [day, region, SERVICE, "aws4_request"] if date.starts_with(day) => region.to_string(),
+            _ => return Err(AuthError::MalformedSignature("invalid credential scope")),
+        };
+
+        Ok(Signature {
+            access_key_id: access_key_id.to_string(),
+            date: date.to_string(),
+            scope: scope.to_string(),
+            region,
+            signed_headers: signed_headers.split(';').map(String::from).collect(),
+            signature: signature.to_string(),
+            expires,
+        })
+    }
+
+    /// Check that the signature was created recently enough
+    /// for the request to be accepted.
+    fn check_date(&self, now: DateTime<Utc>) -> Result<(), AuthError> {
+        let date = NaiveDateTime::parse_from_str(&self.date, DATE_FORMAT)
+            .map_err(|_| AuthError::MalformedSignature("invalid date"))?
+            .and_utc();
+
+        let valid = match self.expires {
+            Some(expires) => {
+                date - MAX_CLOCK_SKEW <= now && now <= date + TimeDelta::seconds(expires)
+            }
+            None => (now - date).abs() <= MAX_CLOCK_SKEW,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(AuthError::ExpiredSignature(self.date.clone()))
+        }
+    }
+}
+
+/// Verify the SigV4 signature in a request, and return the identity of the caller.
+pub(crate) async fn verify(
+    store: &CredentialStore,
+    remote_config: Option<&RemoteConfig>,
+    parts: &Parts,
+    body: &[u8],
+) -> Result<ApiGatewayRequestAuthorizerIamDescription, AuthError> {
+    let signature = Signature::from_request(parts)?;
+    signature.check_date(Utc::now())?;
+
+    let credentials = store
+        .find(&signature.access_key_id, remote_config)
+        .await
+        .ok_or_else(|| AuthError::UnknownAccessKey(signature.access_key_id.clone()))?;
+
+    if let Some(session_token) = &credentials.session_token {
+        let token = header_value(&parts.headers, "x-amz-security-token").or_else(|| {
+            let query = parts.uri.query().unwrap_or_default();
+            query
+                .split('&')
+                .find_map(|p| p.strip_prefix("X-Amz-Security-Token="))
+        });
+        let token = token.map(|t| percent_decode_str(t).decode_utf8_lossy());
+        if token.as_deref() != Some(session_token) {
+            return Err(AuthError::InvalidSecurityToken);
+        }
+    }
+
+    let expected = calculate_signature(&signature, &credentials.secret_access_key, parts, body)?;
+    if !bool::from(expected.as_bytes().ct_eq(signature.signature.as_bytes())) {
+        return Err(AuthError::SignatureMismatch);
+    }
+
+    let account_id = credentials
+        .account_id
+        .unwrap_or_else(|| LOCAL_ACCOUNT_ID.to_string());
+    let user_arn = format!("arn:aws:iam::{account_id}:user/{}", credentials.source);
+
+    Ok(ApiGatewayRequestAuthorizerIamDescription {
+        access_key: Some(signature.access_key_id.clone()),
+        account_id: Some(account_id),
+        caller_id: Some(signature.access_key_id.clone()),
+        user_arn: Some(user_arn),
+        user_id: Some(signature.access_key_id),
+        ..Default::default()
+    })
+}
+
+fn calculate_signature(
+    signature: &Signature,
+    secret_access_key: &str,
+    parts: &Parts,
+    body: &[u8],
+) -> Result<String, AuthError> {
+    let canonical_request = canonical_request(signature, parts, body)?;
+    tracing::trace!(%canonical_request, "verifying request signature");
+
+    let string_to_sign = format!(
+        "{ALGORITHM}\n{}\n{}\n{}",
+        signature.date,
+        signature.scope,
+        hex::encode(Sha256::digest(canonical_request))
+    );
+
+    let day = &signature.date[..8];
+    let key = hmac(format!("AWS4{secret_access_key}").as_bytes(), day);
+    let key = hmac(&key, &signature.region);
+    let key = hmac(&key, SERVICE);
+    let key = hmac(&key, "aws4_request");
+
+    Ok(hex::encode(hmac(&key, &string_to_sign)))
+}
+
+fn canonical_request(
+    signature: &Signature,
+    parts: &Parts,
+    body: &[u8],
+) -> Result<String, AuthError> {
+    let path = parts.uri.path();
+    let canonical_uri = if path.is_empty() {
+        "/".to_string()
+    } else {
+        // Lambda expects every path segment to be encoded twice.
+        // The path in the request is already encoded once.
+        path.split('/')
+            .map(|segment| utf8_percent_encode(segment, UNRESERVED).to_string())
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+
+    let mut query = parse_query(parts.uri.query().unwrap_or_default())
+        .into_iter()
+        .filter(|(k, _)| k != "X-Amz-Signature")
+        .map(|(k, v)| {
+            (
+                utf8_percent_encode(&k, UNRESERVED).to_string(),
+                utf8_percent_encode(&v, UNRESERVED).to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut canonical_headers = String::new();
+    for name in &signature.signed_headers {
+        let values = parts
+            .headers
+            .get_all(name.as_str())
+            .iter()
+            .map(|v| {
+                let value = String::from_utf8_lossy(v.as_bytes());
+                value.split_whitespace().collect::<Vec<_>>().join(" ")
+            })
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return Err(AuthError::MalformedSignature("missing signed header"));
+        }
+        canonical_headers.push_str(&format!("{name}:{}\n", values.join(",")));
+    }
+
+    // The signature only covers the hash in the header, so the hash must match the body
+    // that the emulator received, unless the client didn't sign the payload.
+    let body_hash = hex::encode(Sha256::digest(body));
+    let payload_hash = match header_value(&parts.headers, "x-amz-content-sha256") {
+        Some(UNSIGNED_PAYLOAD) => UNSIGNED_PAYLOAD.to_string(),
+        Some(hash) if hash != body_hash => return Err(AuthError::PayloadHashMismatch),
+        _ => body_hash,
+    };
+
+    Ok(format!(
+        "{}\n{canonical_uri}\n{canonical_query}\n{canonical_headers}\n{}\n{payload_hash}",
+        parts.method,
+        signature.signed_headers.join(";"),
+    ))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Decode the parameters in a query string, the same way that the AWS SDKs do before signing them.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .to_string()
+    };
+
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (decode(k), decode(v))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    const ACCESS_KEY_ID: &str = "AKIDEXAMPLE";
+    const SECRET_ACCESS_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+
+    fn credentials() -> LocalCredentials {
+        LocalCredentials {
+            secret_access_key: SECRET_ACCESS_KEY.into(),
+            session_token: None,
+            account_id: None,
+            source: "test".into(),
+        }
+    }
+
+    fn signed_request(date: &str, body: &str) -> Parts {
+        let (mut parts, _) = Request::post("/lambda-url/my-function/users?name=hello%20world&a=1")
+            .header("host", "localhost:9000")
+            .header("x-amz-date", date)
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let credential = format!(
+            "{ACCESS_KEY_ID}/{}/us-east-1/lambda/aws4_request",
+            &date[..8]
+        );
+        let unsigned =
+            Signature::new(&credential, date, "content-type;host;x-amz-date", "", None).unwrap();
+        let signature =
+            calculate_signature(&unsigned, SECRET_ACCESS_KEY, &parts, body.as_bytes()).unwrap();
+
+        let authorization = format!(
+            "{ALGORITHM} Credential={credential}, SignedHeaders=content-type;host;x-amz-date, Signature={signature}"
+        );
+        parts
+            .headers
+            .insert("authorization", authorization.parse().unwrap());
+        parts
+    }
+
+    async fn store() -> CredentialStore {
+        let store = CredentialStore::default();
+        store
+            .inner
+            .write()
+            .await
+            .insert(ACCESS_KEY_ID.into(), credentials());
+        store
+    }
+
+    #[test]
+    fn test_canonical_request() {
+        let parts = signed_request("20240101T000000Z", "{}");
+        let signature = Signature::from_request(&parts).unwrap();
+        assert_eq!(signature.region, "us-east-1");
+        assert_eq!(signature.scope, "20240101/us-east-1/lambda/aws4_request");
+
+        let request = canonical_request(&signature, &parts, b"{}").unwrap();
+        let lines = request.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "POST");
+        assert_eq!(lines[1], "/lambda-url/my-function/users");
+        assert_eq!(lines[2], "a=1&name=hello%20world");
+        assert_eq!(lines[3], "content-type:application/json");
+        assert_eq!(lines[7], "content-type;host;x-amz-date");
+        assert_eq!(lines[8], hex::encode(Sha256::digest(b"{}")));
+    }
+
+    #[tokio::test]
+    async fn test_verify_signature() {
+        let store = store().await;
+        let date = Utc::now().format(DATE_FORMAT).to_string();
+
+        let parts = signed_request(&date, "{}");
+        let identity = verify(&store, None, &parts, b"{}").await.unwrap();
+        assert_eq!(identity.access_key.as_deref(), Some(ACCESS_KEY_ID));
+        assert_eq!(identity.account_id.as_deref(), Some(LOCAL_ACCOUNT_ID));
+        assert_eq!(
+            identity.user_arn.as_deref(),
+            Some("arn:aws:iam::000000000000:user/test")
+        );
+
+        let err = verify(&store, None, &parts, b"{\"tampered\":true}").await;
+        assert_eq!(err, Err(AuthError::SignatureMismatch));
+
+        let parts = signed_request("20240101T000000Z", "{}");
+        let err = verify(&store, None, &parts, b"{}").await;
+        assert!(matches!(err, Err(AuthError::ExpiredSignature(_))));
+
+        let (parts, _) = Request::get("/").body(()).unwrap().into_parts();
+        let err = verify(&store, None, &parts, b"").await;
+        assert_eq!(err, Err(AuthError::MissingSignature));
+    }
+
+    #[tokio::test]
+    async fn test_verify_sdk_signature() {
+        use aws_credential_types::Credentials;
+        use aws_sigv4::{
+            http_request::{SignableBody, SignableRequest, SigningSettings, sign},
+            sign::v4,
+        };
+        use std::time::SystemTime;
+
+        let body = r#"{"id":"1"}"#;
+        let mut req = Request::put("http://localhost:9000/users/a%20b/?name=hello+world&id=1")
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap();
+
+        let identity =
+            Credentials::new(ACCESS_KEY_ID, SECRET_ACCESS_KEY, None, None, "test").into();
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region("eu-west-1")
+            .name("lambda")
+            .time(SystemTime::now())
+            .settings(SigningSettings::default())
+            .build()
+            .unwrap()
+            .into();
+        let signable = SignableRequest::new(
+            req.method().as_str(),
+            req.uri().to_string(),
+            req.headers()
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.to_str().unwrap())),
+            SignableBody::Bytes(body.as_bytes()),
+        )
+        .unwrap();
+        let (instructions, _) = sign(signable, &params).unwrap().into_parts();
+        instructions.apply_to_request_http1x(&mut req);
+
+        let (mut parts, _) = req.into_parts();
+        parts
+            .headers
+            .insert("host", "localhost:9000".parse().unwrap());
+        parts.uri = "/users/a%20b/?name=hello+world&id=1".parse().unwrap();
+
+        let identity = verify(&store().await, None, &parts, body.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(identity.access_key.as_deref(), Some(ACCESS_KEY_ID));
+    }
+
+    #[tokio::test]
+    async fn test_verify_signed_payload_hash() {
+        use aws_credential_types::Credentials;
+        use aws_sigv4::{
+            http_request::{
+                PayloadChecksumKind, SignableBody, SignableRequest, SigningSettings, sign,
+            },
+            sign::v4,
+        };
+        use std::time::SystemTime;
+
+        let sign_body = |body: SignableBody<'_>| {
+            let mut req = Request::post("http://localhost:9000/users")
+                .header("content-type", "application/json")
+                .body(())
+                .unwrap();
+
+            let identity =
+                Credentials::new(ACCESS_KEY_ID, SECRET_ACCESS_KEY, None, None, "test").into();
+            let mut settings = SigningSettings::default();
+            settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+            let params = v4::SigningParams::builder()
+                .identity(&identity)
+                .region("eu-west-1")
+                .name("lambda")
+                .time(SystemTime::now())
+                .settings(settings)
+                .build()
+                .unwrap()
+                .into();
+            let signable = SignableRequest::new(
+                req.method().as_str(),
+                req.uri().to_string(),
+                req.headers()
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.to_str().unwrap())),
+                body,
+            )
+            .unwrap();
+            let (instructions, _) = sign(signable, &params).unwrap().into_parts();
+            instructions.apply_to_request_http1x(&mut req);
+
+            let (mut parts, _) = req.into_parts();
+            parts
+                .headers
+                .insert("host", "localhost:9000".parse().unwrap());
+            parts.uri = "/users".parse().unwrap();
+            parts
+        };
+
+        let store = store().await;
+        let parts = sign_body(SignableBody::Bytes(br#"{"id":"1"}"#));
+        assert!(verify(&store, None, &parts, br#"{"id":"1"}"#).await.is_ok());
+
+        let err = verify(&store, None, &parts, br#"{"id":"2"}"#).await;
+        assert_eq!(err, Err(AuthError::PayloadHashMismatch));
+
+        let parts = sign_body(SignableBody::UnsignedPayload);
+        assert!(verify(&store, None, &parts, br#"{"id":"2"}"#).await.is_ok());
+    }
+
+    #[test]
+    fn test_should_reload() {
+        let now = Instant::now();
+        assert!(should_reload(None, now));
+        assert!(!should_reload(Some(&now), now + Duration::from_secs(1)));
+        assert!(should_reload(Some(&now), now + RELOAD_INTERVAL));
+    }
+
+    #[test]
+    fn test_parse_credentials_file() {
+        let credentials = parse_credentials_file(
+            r#"
+            [default]
+            aws_access_key_id = AKIDEXAMPLE
+            aws_secret_access_key = wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY
+
+            # profile without keys
+            [sso]
+            sso_session = my-sso
+
+            [dev]
+            aws_access_key_id=AKIDDEV
+            aws_secret_access_key=secret
+            aws_session_token=token
+            aws_account_id=123456789012
+        "#,
+        );
+
+        assert_eq!(credentials.len(), 2);
+        assert_eq!(credentials[ACCESS_KEY_ID].source, "default");
+        assert_eq!(
+            credentials[ACCESS_KEY_ID].secret_access_key,
+            SECRET_ACCESS_KEY
+        );
+
+        let dev = &credentials["AKIDDEV"];
+        assert_eq!(dev.session_token.as_deref(), Some("token"));
+        assert_eq!(dev.account_id.as_deref(), Some("123456789012"));
+    }
+}
@@ -0,0 +1,451 @@
+use super::authorizers::AuthorizerContext;
+use crate::{
+    error::ServerError,
+    watcher::env::{LOCAL_ACCOUNT_ID, function_region},
+};
+use aws_lambda_events::{
+    alb::{
+        AlbTargetGroupRequest, AlbTargetGroupRequestContext, AlbTargetGroupResponse, ElbContext,
+    },
+    apigw::{
+        ApiGatewayProxyRequest, ApiGatewayProxyRequestContext, ApiGatewayProxyResponse,
+        ApiGatewayRequestAuthorizer, ApiGatewayRequestAuthorizerIamDescription,
+        ApiGatewayRequestAuthorizerJwtDescription, ApiGatewayRequestIdentity,
+        ApiGatewayV2httpRequest, ApiGatewayV2httpRequestContext,
+        ApiGatewayV2httpRequestContextHttpDescription, ApiGatewayV2httpResponse,
+    },
+    encodings::Body as LambdaBody,
+};
+use axum::http::request::Parts;
+use cargo_lambda_metadata::cargo::watch::PayloadFormat;
+use chrono::Utc;
+use hyper::HeaderMap;
+use query_map::QueryMap;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// HTTP request that the trigger router sends to a function.
+pub(super) struct HttpEvent<'a> {
+    pub parts: &'a Parts,
+    pub function_name: &'a str,
+    pub request_id: &'a str,
+    pub path: String,
+    /// Path pattern of the route that matched the request
+    pub resource: Option<String>,
+    /// Host of the request, when the route matched it by host
+    pub domain: Option<String>,
+    pub path_parameters: HashMap<String, String>,
+    pub body: Option<String>,
+    pub is_base64_encoded: bool,
+    /// Identity of the caller, for requests signed with SigV4
+    pub iam_identity: Option<ApiGatewayRequestAuthorizerIamDescription>,
+    /// Context that the route's authorizer adds to the request
+    pub authorizer: Option<AuthorizerContext>,
+}
+
+/// HTTP response that a function returns, in any payload format.
+pub(super) struct HttpResponse {
+    pub status_code: i64,
+    pub headers: HeaderMap,
+    pub multi_value_headers: HeaderMap,
+    pub cookies: Vec<String>,
+    pub body: Option<LambdaBody>,
+    pub is_base64_encoded: bool,
+}
+
+/// Serialize the request with the payload format that the route expects.
+pub(super) fn build_event(
+    format: PayloadFormat,
+    event: HttpEvent<'_>,
+) -> Result<String, ServerError> {
+    let result = match format {
+        PayloadFormat::V2 => serde_json::to_string(&v2_event(event)),
+        PayloadFormat::V1 => serde_json::to_string(&v1_event(event)),
+        PayloadFormat::Alb => serde_json::to_string(&alb_event(event)),
+    };
+    result.map_err(ServerError::SerializationError)
+}
+
+/// Deserialize the function's response with the payload format that the route expects.
+pub(super) fn decode_response(
+    format: PayloadFormat,
+    body: &[u8],
+) -> Result<HttpResponse, ServerError> {
+    let response = match format {
+        PayloadFormat::V2 => {
+            let resp: ApiGatewayV2httpResponse = serde_json::from_slice(body)?;
+            HttpResponse {
+                status_code: resp.status_code,
+                headers: resp.headers,
+                multi_value_headers: resp.multi_value_headers,
+                cookies: resp.cookies,
+                body: resp.body,
+                is_base64_encoded: resp.is_base64_encoded,
+            }
+        }
+        PayloadFormat::V1 => {
+            let resp: ApiGatewayProxyResponse = serde_json::from_slice(body)?;
+            HttpResponse {
+                status_code: resp.status_code,
+                headers: resp.headers,
+                multi_value_headers: resp.multi_value_headers,
+                cookies: Vec::new(),
+                body: resp.body,
+                is_base64_encoded: resp.is_base64_encoded,
+            }
+        }
+        PayloadFormat::Alb => {
+            let resp: AlbTargetGroupResponse = serde_json::from_slice(body)?;
+            HttpResponse {
+                status_code: resp.status_code,
+                headers: resp.headers,
+                multi_value_headers: resp.multi_value_headers,
+                cookies: Vec::new(),
+                body: resp.body,
+                is_base64_encoded: resp.is_base64_encoded,
+            }
+        }
+    };
+
+    Ok(response)
+}
+
+pub(super) fn query_string_parameters(parts: &Parts) -> QueryMap {
+    parts
+        .uri
+        .query()
+        .unwrap_or_default()
+        .parse::<QueryMap>()
+        .unwrap_or_default()
+}
+
+fn user_agent(headers: &HeaderMap) -> String {
+    headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("cargo-lambda")
+        .to_string()
+}
+
+/// Domain name of the request context. Requests that matched
+/// a route by host use that host, like API Gateway custom domains.
+fn domain_name(event: &HttpEvent<'_>) -> String {
+    event.domain.as_deref().unwrap_or("localhost").to_string()
+}
+
+/// First label of the domain name, or the function name
+/// for requests that didn't match a route by host.
+fn domain_prefix(event: &HttpEvent<'_>) -> String {
+    match event.domain.as_deref().and_then(|d| d.split('.').next()) {
+        Some(prefix) => prefix.to_string(),
+        None => event.function_name.to_string(),
+    }
+}
+
+fn v2_event(event: HttpEvent<'_>) -> ApiGatewayV2httpRequest {
+    let parts = event.parts;
+    let headers = &parts.headers;
+    let time = Utc::now();
+
+    let cookies = headers.get("cookie").map(|c| {
+        c.to_str()
+            .unwrap_or_default()
+            .split("; ")
+            .map(|s| s.trim().to_string())
+            .collect()
+    });
+
+    let request_context = ApiGatewayV2httpRequestContext {
+        stage: Some("$default".into()),
+        route_key: Some("$default".into()),
+        request_id: Some(event.request_id.into()),
+        domain_name: Some(domain_name(&event)),
+        domain_prefix: Some(domain_prefix(&event)),
+        http: ApiGatewayV2httpRequestContextHttpDescription {
+            method: parts.method.clone(),
+            path: Some(event.path.clone()),
+            protocol: Some("http".into()),
+            source_ip: Some("127.0.0.1".into()),
+            user_agent: Some("cargo-lambda".into()),
+        },
+        time: Some(time.format("%d/%b/%Y:%T %z").to_string()),
+        time_epoch: time.timestamp(),
+        account_id: None,
+        authorizer: v2_authorizer(event.iam_identity, event.authorizer),
+        authentication: None,
+        apiid: None,
+    };
+
+    ApiGatewayV2httpRequest {
+        version: Some("2.0".into()),
+        route_key: Some("$default".into()),
+        raw_path: Some(event.path),
+        raw_query_string: parts.uri.query().map(String::from),
+        headers: headers.clone(),
+        body: event.body,
+        request_context,
+        cookies,
+        query_string_parameters: query_string_parameters(parts),
+        is_base64_encoded: event.is_base64_encoded,
+        path_parameters: event.path_parameters,
+        ..Default::default()
+    }
+}
+
+/// Authorizer information in the request context of HTTP APIs.
+fn v2_authorizer(
+    iam: Option<ApiGatewayRequestAuthorizerIamDescription>,
+    authorizer: Option<AuthorizerContext>,
+) -> Option<ApiGatewayRequestAuthorizer> {
+    if iam.is_none() && authorizer.is_none() {
+        return None;
+    }
+
+    let mut description = ApiGatewayRequestAuthorizer {
+        iam,
+        ..Default::default()
+    };
+    match authorizer {
+        Some(AuthorizerContext::Jwt { claims, scopes }) => {
+            // HTTP APIs send every claim as a string.
+            let claims = claims
+                .into_iter()
+                .map(|(name, value)| match value {
+                    Value::String(value) => (name, value),
+                    value => (name, value.to_string()),
+                })
+                .collect();
+            description.jwt = Some(ApiGatewayRequestAuthorizerJwtDescription { claims, scopes });
+        }
+        Some(AuthorizerContext::Lambda { context, .. }) => description.fields = context,
+        None => {}
+    }
+    Some(description)
+}
+
+/// Authorizer information in the request context of REST APIs.
+fn v1_authorizer(authorizer: Option<AuthorizerContext>) -> ApiGatewayRequestAuthorizer {
+    let fields = match authorizer {
+        Some(AuthorizerContext::Jwt { claims, .. }) => {
+            HashMap::from([("claims".to_string(), Value::Object(claims))])
+        }
+        Some(AuthorizerContext::Lambda {
+            principal_id,
+            mut context,
+        }) => {
+            if let Some(principal_id) = principal_id {
+                context.insert("principalId".into(), Value::String(principal_id));
+            }
+            context
+        }
+        None => HashMap::new(),
+    };
+
+    ApiGatewayRequestAuthorizer {
+        fields,
+        ..Default::default()
+    }
+}
+
+/// Event that API Gateway REST APIs send with the payload format version 1.0:
+/// https://docs.aws.amazon.com/apigateway/latest/developerguide/set-up-lambda-proxy-integrations.html#api-gateway-simple-proxy-for-lambda-input-format
+fn v1_event(event: HttpEvent<'_>) -> ApiGatewayProxyRequest {
+    let parts = event.parts;
+    let headers = &parts.headers;
+    let time = Utc::now();
+    let (domain_name, domain_prefix) = (domain_name(&event), domain_prefix(&event));
+    let resource = event.resource.unwrap_or_else(|| event.path.clone());
+    let query_string_parameters = query_string_parameters(parts);
+    let iam = event.iam_identity.unwrap_or_default();
+
+    let request_context = ApiGatewayProxyRequestContext {
+        account_id: Some(LOCAL_ACCOUNT_ID.into()),
+        resource_id: Some(event.function_name.into()),
+        stage: Some("$default".into()),
+        domain_name: Some(domain_name),
+        domain_prefix: Some(domain_prefix),
+        request_id: Some(event.request_id.into()),
+        protocol: Some("HTTP/1.1".into()),
+        identity: ApiGatewayRequestIdentity {
+            source_ip: Some("127.0.0.1".into()),
+            user_agent: Some(user_agent(headers)),
+            account_id: iam.account_id,
+            access_key: iam.access_key,
+            caller: iam.caller_id,
+            user: iam.user_id,
+            user_arn: iam.user_arn,
+            ..Default::default()
+        },
+        resource_path: Some(resource.clone()),
+        path: Some(event.path.clone()),
+        http_method: parts.method.clone(),
+        request_time: Some(time.format("%d/%b/%Y:%T %z").to_string()),
+        request_time_epoch: time.timestamp_millis(),
+        authorizer: v1_authorizer(event.authorizer),
+        ..Default::default()
+    };
+
+    ApiGatewayProxyRequest {
+        resource: Some(resource),
+        path: Some(event.path),
+        http_method: parts.method.clone(),
+        headers: headers.clone(),
+        multi_value_headers: headers.clone(),
+        query_string_parameters: query_string_parameters.clone(),
+        multi_value_query_string_parameters: query_string_parameters,
+        path_parameters: event.path_parameters,
+        request_context,
+        body: event.body,
+        is_base64_encoded: event.is_base64_encoded,
+        ..Default::default()
+    }
+}
+
+/// Event that Application Load Balancers send to their Lambda targets:
+/// https://docs.aws.amazon.com/elasticloadbalancing/latest/application/lambda-functions.html#receive-event-from-load-balancer
+fn alb_event(event: HttpEvent<'_>) -> AlbTargetGroupRequest {
+    let parts = event.parts;
+    let region = function_region(None);
+    let target_group_arn = format!(
+        "arn:aws:elasticloadbalancing:{region}:{LOCAL_ACCOUNT_ID}:targetgroup/{}/0000000000000000",
+        event.function_name
+    );
+
+    AlbTargetGroupRequest {
+        http_method: parts.method.clone(),
+        path: Some(event.path),
+        query_string_parameters: query_string_parameters(parts),
+        headers: parts.headers.clone(),
+        request_context: AlbTargetGroupRequestContext {
+            elb: ElbContext {
+                target_group_arn: Some(target_group_arn),
+            },
+        },
+        is_base64_encoded: event.is_base64_encoded,
+        body: event.body,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn event(parts: &Parts) -> HttpEvent<'_> {
+        HttpEvent {
+            parts,
+            function_name: "get-user",
+            request_id: "request-id",
+            path: "/users/1".into(),
+            resource: Some("/users/{id}".into()),
+            domain: None,
+            path_parameters: HashMap::from([("id".into(), "1".into())]),
+            body: Some("hello".into()),
+            is_base64_encoded: false,
+            iam_identity: None,
+            authorizer: None,
+        }
+    }
+
+    #[test]
+    fn test_build_events() {
+        let (parts, _) = Request::get("/users/1?active=true")
+            .header("user-agent", "curl")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let v1: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::V1, event(&parts)).unwrap()).unwrap();
+        assert_eq!(v1["resource"], "/users/{id}");
+        assert_eq!(v1["path"], "/users/1");
+        assert_eq!(v1["httpMethod"], "GET");
+        assert_eq!(v1["pathParameters"]["id"], "1");
+        assert_eq!(v1["queryStringParameters"]["active"], "true");
+        assert_eq!(v1["multiValueHeaders"]["user-agent"][0], "curl");
+        assert_eq!(v1["requestContext"]["resourcePath"], "/users/{id}");
+        assert_eq!(v1["requestContext"]["identity"]["userAgent"], "curl");
+        assert_eq!(v1["body"], "hello");
+
+        let alb: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::Alb, event(&parts)).unwrap()).unwrap();
+        assert_eq!(alb["path"], "/users/1");
+        assert_eq!(alb["httpMethod"], "GET");
+        assert!(
+            alb["requestContext"]["elb"]["targetGroupArn"]
+                .as_str()
+                .unwrap()
+                .contains(":targetgroup/get-user/")
+        );
+
+        let v2: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::V2, event(&parts)).unwrap()).unwrap();
+        assert_eq!(v2["version"], "2.0");
+        assert_eq!(v2["rawPath"], "/users/1");
+        assert_eq!(v2["requestContext"]["domainName"], "localhost");
+        assert_eq!(v2["requestContext"]["domainPrefix"], "get-user");
+
+        let domain_event = HttpEvent {
+            domain: Some("acme.example.com".into()),
+            ..event(&parts)
+        };
+        let v2: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::V2, domain_event).unwrap()).unwrap();
+        assert_eq!(v2["requestContext"]["domainName"], "acme.example.com");
+        assert_eq!(v2["requestContext"]["domainPrefix"], "acme");
+
+        let jwt = AuthorizerContext::Jwt {
+            claims: serde_json::Map::from_iter([
+                ("sub".to_string(), Value::from("user-1")),
+                ("exp".to_string(), Value::from(2000)),
+            ]),
+            scopes: None,
+        };
+        let jwt_event = |parts| HttpEvent {
+            authorizer: Some(jwt.clone()),
+            ..event(parts)
+        };
+        let v2: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::V2, jwt_event(&parts)).unwrap())
+                .unwrap();
+        assert_eq!(
+            v2["requestContext"]["authorizer"]["jwt"]["claims"]["sub"],
+            "user-1"
+        );
+        assert_eq!(
+            v2["requestContext"]["authorizer"]["jwt"]["claims"]["exp"],
+            "2000"
+        );
+        let v1: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::V1, jwt_event(&parts)).unwrap())
+                .unwrap();
+        assert_eq!(v1["requestContext"]["authorizer"]["claims"]["exp"], 2000);
+
+        let lambda_event = HttpEvent {
+            authorizer: Some(AuthorizerContext::Lambda {
+                principal_id: Some("user-1".into()),
+                context: HashMap::from([("tenant".to_string(), Value::from("acme"))]),
+            }),
+            ..event(&parts)
+        };
+        let v1: serde_json::Value =
+            serde_json::from_str(&build_event(PayloadFormat::V1, lambda_event).unwrap()).unwrap();
+        assert_eq!(v1["requestContext"]["authorizer"]["principalId"], "user-1");
+        assert_eq!(v1["requestContext"]["authorizer"]["tenant"], "acme");
+    }
+
+    #[test]
+    fn test_decode_responses() {
+        let body = br#"{"statusCode":201,"headers":{"x-foo":"bar"},"multiValueHeaders":{"x-bar":["a","b"]},"body":"created","isBase64Encoded":false}"#;
+        let resp = decode_response(PayloadFormat::V1, body).unwrap();
+        assert_eq!(201, resp.status_code);
+        assert_eq!("bar", resp.headers["x-foo"]);
+        assert_eq!(2, resp.multi_value_headers.get_all("x-bar").iter().count());
+
+        let body = br#"{"statusCode":404,"statusDescription":"404 Not Found","headers":{"x-foo":"bar"},"body":"missing"}"#;
+        let resp = decode_response(PayloadFormat::Alb, body).unwrap();
+        assert_eq!(404, resp.status_code);
+        assert!(matches!(resp.body, Some(LambdaBody::Text(ref s)) if s == "missing"));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{error::ServerError, state::Reservation};
+use aws_smithy_eventstream::frame::write_message_to;
+use aws_smithy_types::event_stream::{Header, HeaderValue, Message};
+use axum::body::Body;
+use base64::{Engine as _, engine::general_purpose as b64};
+use bytes::Bytes;
+use futures_util::stream;
+use http_body_util::BodyExt;
+use hyper::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+/// Trailer that the runtime sends when a function fails in the middle of a stream.
+pub(crate) const ERROR_TYPE_TRAILER: &str = "lambda-runtime-function-error-type";
+/// Base64 encoded body of the error that interrupted a stream.
+pub(crate) const ERROR_BODY_TRAILER: &str = "lambda-runtime-function-error-body";
+
+/// Last event in a response stream:
+/// https://docs.aws.amazon.com/lambda/latest/api/API_InvokeWithResponseStreamCompleteEvent.html
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct InvokeComplete {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_details: Option<String>,
+}
+
+/// Error that the function reports through the runtime API.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportedError {
+    error_type: Option<String>,
+    error_message: Option<String>,
+}
+
+impl InvokeComplete {
+    fn failed(error_type: Option<&str>, body: &[u8]) -> InvokeComplete {
+        let reported = serde_json::from_slice::<ReportedError>(body).ok();
+
+        let error_code = error_type
+            .map(String::from)
+            .or_else(|| reported.as_ref().and_then(|r| r.error_type.clone()))
+            .unwrap_or_else(|| "Unhandled".into());
+        let error_details = reported
+            .and_then(|r| r.error_message)
+            .unwrap_or_else(|| String::from_utf8_lossy(body).to_string());
+
+        InvokeComplete {
+            error_code: Some(error_code),
+            error_details: Some(error_details),
+        }
+    }
+
+    /// Read the error that a function reported in the trailers of its stream, if any.
+    fn from_trailers(trailers: &HeaderMap) -> Option<InvokeComplete> {
+        let error_type = trailers.get(ERROR_TYPE_TRAILER)?.to_str().ok();
+        let body = trailers
+            .get(ERROR_BODY_TRAILER)
+            .and_then(|b| b64::STANDARD.decode(b.as_bytes()).ok())
+            .unwrap_or_default();
+
+        Some(InvokeComplete::failed(error_type, &body))
+    }
+}
+
+/// Wrap the function's response in the AWS event stream encoding that
+/// `InvokeWithResponseStream` uses. Every chunk of the response is sent as
+/// a `PayloadChunk` event, and the stream ends with an `InvokeComplete` event.
+///
+/// The reservation is held until the stream ends, because the function
+/// is processing the invocation until then.
+pub(super) fn event_stream(status: StatusCode, body: Body, reservation: Reservation) -> Body {
+    if status != StatusCode::OK {
+        let body = async move {
+            let body = axum::body::to_bytes(body, usize::MAX)
+                .await
+                .map_err(ServerError::DataDeserialization)?;
+            invoke_complete(&InvokeComplete::failed(None, &body))
+        };
+        return Body::from_stream(stream::once(body));
+    }
+
+    let stream = stream::unfold(Some((body, reservation)), |state| async move {
+        let (mut body, reservation) = state?;
+
+        loop {
+            match body.frame().await {
+                None => {
+                    let message = invoke_complete(&InvokeComplete::default());
+                    return Some((message, None));
+                }
+                Some(Err(error)) => {
+                    tracing::error!(?error, "failed to read the response stream");
+                    let complete = InvokeComplete {
+                        error_code: Some("Runtime.StreamError".into()),
+                        error_details: Some(error.to_string()),
+                    };
+                    return Some((invoke_complete(&complete), None));
+                }
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) if data.is_empty() => continue,
+                    Ok(data) => {
+                        let message = payload_chunk(data);
+                        return Some((message, Some((body, reservation))));
+                    }
+                    Err(frame) => {
+                        let complete = frame.trailers_ref().and_then(InvokeComplete::from_trailers);
+                        if let Some(complete) = complete {
+                            return Some((invoke_complete(&complete), None));
+                        }
+                    }
+                },
+            }
+        }
+    });
+
+    Body::from_stream(stream)
+}
+
+fn payload_chunk(data: Bytes) -> Result<Bytes, ServerError> {
+    encode_event("PayloadChunk", "application/octet-stream", data)
+}
+
+fn invoke_complete(complete: &InvokeComplete) -> Result<Bytes, ServerError> {
+    let payload = serde_json::to_vec(complete)?;
+    encode_event("InvokeComplete", "application/json", payload.into())
+}
+
+fn encode_event(
+    event_type: &'static str,
+    content_type: &'static str,
+    payload: Bytes,
+) -> Result<Bytes, ServerError> {
+    let header = |name: &'static str, value: &'static str| {
+        Header::new(name, HeaderValue::String(value.into()))
+    };
+    let message = Message::new(payload)
+        .add_header(header(":event-type", event_type))
+        .add_header(header(":content-type", content_type))
+        .add_header(header(":message-type", "event"));
+
+    let mut buffer = Vec::new();
+    write_message_to(&message, &mut buffer)?;
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_smithy_eventstream::frame::read_message_from;
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+
+    async fn decode(body: Body) -> Vec<(String, Bytes)> {
+        let mut bytes = body.collect().await.unwrap().to_bytes();
+
+        let mut messages = Vec::new();
+        while !bytes.is_empty() {
+            let message = read_message_from(&mut bytes).unwrap();
+            let event_type = message
+                .headers()
+                .iter()
+                .find(|h| h.name().as_str() == ":event-type")
+                .and_then(|h| h.value().as_string().ok())
+                .unwrap()
+                .as_str()
+                .to_string();
+            messages.push((event_type, message.payload().clone()));
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_event_stream() {
+        let body = Body::new(StreamBody::new(stream::iter(vec![
+            Ok::<_, axum::Error>(Frame::data(Bytes::from("hello "))),
+            Ok(Frame::data(Bytes::new())),
+            Ok(Frame::data(Bytes::from("world"))),
+        ])));
+
+        let messages = decode(event_stream(StatusCode::OK, body, Reservation::Unlimited)).await;
+        assert_eq!(
+            messages,
+            vec![
+                ("PayloadChunk".into(), Bytes::from("hello ")),
+                ("PayloadChunk".into(), Bytes::from("world")),
+                ("InvokeComplete".into(), Bytes::from("{}")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_errors() {
+        let error_body = b64::STANDARD.encode(r#"{"errorType":"Oops","errorMessage":"boom"}"#);
+        let mut trailers = HeaderMap::new();
+        trailers.insert(ERROR_TYPE_TRAILER, "Oops".parse().unwrap());
+        trailers.insert(ERROR_BODY_TRAILER, error_body.parse().unwrap());
+
+        let body = Body::new(StreamBody::new(stream::iter(vec![
+            Ok::<_, axum::Error>(Frame::data(Bytes::from("partial"))),
+            Ok(Frame::trailers(trailers)),
+        ])));
+
+        let messages = decode(event_stream(StatusCode::OK, body, Reservation::Unlimited)).await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[1].1,
+            Bytes::from(r#"{"ErrorCode":"Oops","ErrorDetails":"boom"}"#)
+        );
+
+        let body = Body::from(r#"{"errorType":"Runtime.ExitError","errorMessage":"exited"}"#);
+        let messages = decode(event_stream(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            body,
+            Reservation::Unlimited,
+        ))
+        .await;
+        assert_eq!(
+            messages,
+            vec![(
+                "InvokeComplete".into(),
+                Bytes::from(r#"{"ErrorCode":"Runtime.ExitError","ErrorDetails":"exited"}"#)
+            )]
+        );
+    }
+}
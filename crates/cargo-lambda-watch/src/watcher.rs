@@ -1,30 +1,50 @@
-use crate::{error::ServerError, requests::NextEvent, state::ExtensionCache};
+use crate::{
+    error::ServerError,
+    logs::LogFormatter,
+    state::{RuntimeState, environment_function_name},
+    telemetry,
+};
 use cargo_lambda_metadata::{
-    cargo::load_metadata,
-    config::{ConfigOptions, load_config_without_cli_flags},
+    cargo::{load_metadata, watch::ReloadStrategy},
+    config::{Config, ConfigOptions, load_config_without_cli_flags},
 };
 // use cargo_lambda_metadata::cargo::function_environment_metadata;
+use build::FunctionBuild;
+use extensions::{ExtensionCommand, restart_extensions};
 use ignore::create_filter;
 use ignore_files::IgnoreFile;
 use std::{collections::HashMap, convert::Infallible, path::PathBuf, sync::Arc, time::Duration};
 use tracing::{debug, error, trace};
 use watchexec::{
     ErrorHook, Watchexec,
-    action::{Action, Outcome, PreSpawn},
+    action::{Action, Outcome, PostSpawn, PreSpawn},
     command::Command,
     config::{InitConfig, RuntimeConfig},
     error::RuntimeError,
-    event::{Event, Priority, ProcessEnd},
+    event::{Event, Priority, ProcessEnd, Source, Tag},
     handler::SyncFnHandler,
     signal::source::MainSignal,
 };
 
+pub(crate) mod build;
+pub(crate) mod env;
+pub(crate) mod extensions;
 pub(crate) mod ignore;
+pub(crate) mod memory;
+
+/// Metadata key that marks the events sent to restart a function's
+/// process without recompiling it.
+const RESTART_REASON_METADATA: &str = "cargo-lambda-restart-reason";
+
+/// Metadata key that marks the events sent to reload a function's process
+/// after another execution environment builds the function's new code.
+const RELOAD_METADATA: &str = "cargo-lambda-reload";
 
 #[derive(Clone, Debug, Default)]
 pub(crate) struct WatcherConfig {
     pub runtime_api: String,
     pub name: String,
+    pub environment: String,
     pub bin_name: Option<String>,
     pub base: PathBuf,
     pub manifest_path: PathBuf,
@@ -33,6 +53,20 @@ pub(crate) struct WatcherConfig {
     pub only_lambda_apis: bool,
     pub env: HashMap<String, String>,
     pub wait: bool,
+    pub extensions: Vec<ExtensionCommand>,
+    /// What happens to the invocations in flight when the function reloads.
+    /// `None` stops the function right away
+    pub reload_strategy: Option<ReloadStrategy>,
+    /// Directory where the function's command runs, the current directory by default
+    pub working_dir: Option<PathBuf>,
+    /// Paths that restart the function when they change, the project's directory by default.
+    /// An empty list never restarts the function
+    pub watch_paths: Option<Vec<PathBuf>>,
+    /// Build of a function in the workspace. The environment builds the function
+    /// before it starts the function's binary, instead of the watcher's command
+    pub build: Option<FunctionBuild>,
+    /// Whether the environment runs the code that the function's first environment builds
+    pub replica: bool,
 }
 
 impl WatcherConfig {
@@ -48,19 +82,41 @@ impl WatcherConfig {
 pub(crate) async fn new(
     cmd: Command,
     wc: WatcherConfig,
-    ext_cache: ExtensionCache,
+    state: RuntimeState,
 ) -> Result<Arc<Watchexec>, ServerError> {
     let init = crate::watcher::init();
-    let runtime = crate::watcher::runtime(cmd, wc, ext_cache).await?;
+    let runtime = crate::watcher::runtime(cmd, wc, state).await?;
 
-    let wx = Watchexec::new(init, runtime).map_err(ServerError::WatcherError)?;
+    let wx = Watchexec::new(init, runtime).map_err(|e| ServerError::WatcherError(Box::new(e)))?;
     wx.send_event(Event::default(), Priority::Urgent)
         .await
-        .map_err(ServerError::WatcherError)?;
+        .map_err(|e| ServerError::WatcherError(Box::new(e)))?;
 
     Ok(wx)
 }
 
+/// Event that stops the function's process and starts it again.
+pub(crate) fn restart_event(reason: &str) -> Event {
+    let mut event = Event {
+        tags: vec![Tag::Source(Source::Internal)],
+        metadata: Default::default(),
+    };
+    event
+        .metadata
+        .insert(RESTART_REASON_METADATA.into(), vec![reason.into()]);
+    event
+}
+
+/// Event that reloads the function's process with the function's new code.
+pub(crate) fn reload_event() -> Event {
+    let mut event = Event {
+        tags: vec![Tag::Source(Source::Internal)],
+        metadata: Default::default(),
+    };
+    event.metadata.insert(RELOAD_METADATA.into(), Vec::new());
+    event
+}
+
 fn init() -> InitConfig {
     let mut config = InitConfig::default();
     config.on_error(SyncFnHandler::from(
@@ -75,6 +131,13 @@ fn init() -> InitConfig {
                 RuntimeError::FsWatcher { .. } | RuntimeError::EventChannelTrySend { .. } => {
                     err.elevate()
                 }
+                // The function's process didn't start, usually because the function failed to build.
+                RuntimeError::Handler {
+                    ctx: "action pre-spawn",
+                    err,
+                } => {
+                    error!(error = %err, "failed to start the function");
+                }
                 e => {
                     error!(error = ?e, "internal error watching your project");
                 }
@@ -90,17 +153,32 @@ fn init() -> InitConfig {
 async fn runtime(
     cmd: Command,
     wc: WatcherConfig,
-    ext_cache: ExtensionCache,
+    state: RuntimeState,
 ) -> Result<RuntimeConfig, ServerError> {
     let mut config = RuntimeConfig::default();
 
-    config.pathset([wc.base.clone()]);
     config.commands(vec![cmd]);
 
-    config.filterer(create_filter(&wc.base, &wc.ignore_files, wc.ignore_changes).await?);
+    match &wc.watch_paths {
+        // Paths that are watched explicitly are not filtered by the project's ignore files,
+        // they can be build artifacts, like prebuilt binaries in the target directory.
+        Some(paths) => {
+            if !wc.ignore_changes {
+                config.pathset(paths.clone());
+            }
+        }
+        None => {
+            config.pathset([wc.base.clone()]);
+            config.filterer(create_filter(&wc.base, &wc.ignore_files, wc.ignore_changes).await?);
+        }
+    }
 
     config.action_throttle(Duration::from_secs(3));
 
+    let action_state = state.clone();
+    let action_environment = wc.environment.clone();
+    let reload_strategy = wc.reload_strategy;
+    let action_build = wc.build.is_some();
     config.on_action(move |action: Action| {
         let signals: Vec<MainSignal> = action.events.iter().flat_map(|e| e.signals()).collect();
         let has_paths = action
@@ -117,22 +195,37 @@ async fn runtime(
             .next()
             .unwrap_or_default();
 
+        let restart = action
+            .events
+            .iter()
+            .any(|e| e.metadata.contains_key(RESTART_REASON_METADATA));
+
+        let reload = action
+            .events
+            .iter()
+            .any(|e| e.metadata.contains_key(RELOAD_METADATA));
+
         debug!(
             ?action,
             ?signals,
             has_paths,
             empty_event,
+            restart,
+            reload,
             "watcher action received"
         );
 
-        let ext_cache = ext_cache.clone();
+        let state = action_state.clone();
+        let environment = action_environment.clone();
         async move {
             if signals.contains(&MainSignal::Terminate) {
+                state.processes.stopping(&environment).await;
                 action.outcome(Outcome::both(Outcome::Stop, Outcome::Exit));
                 return Ok(());
             }
 
             if signals.contains(&MainSignal::Interrupt) {
+                state.processes.stopping(&environment).await;
                 action.outcome(Outcome::both(Outcome::Stop, Outcome::Exit));
                 return Ok(());
             }
@@ -166,15 +259,41 @@ async fn runtime(
                         _ => {}
                     };
 
+                    // Fail the invocations in a different task, because restarting
+                    // the process sends a new event to this watcher.
+                    tokio::spawn(async move {
+                        let reason = exit_reason(status);
+                        if let Some(failure) = state.processes.exited(&environment, reason).await {
+                            state.fail_environment(&environment, failure).await;
+                        }
+                    });
+
                     action.outcome(Outcome::DoNothing);
                     return Ok(());
                 }
             }
 
-            if !empty_event {
-                let event = NextEvent::shutdown("recompiling function");
-                ext_cache.send_event(event).await?;
+            if !empty_event && !restart {
+                if let Some(strategy) = reload_strategy {
+                    state.prepare_reload(&environment, strategy).await;
+                }
+
+                // The function's other environments reload with the code that this one builds.
+                if !reload {
+                    let function_name = environment_function_name(&environment);
+                    state
+                        .ext_cache
+                        .shutdown_function("recompiling function", function_name)
+                        .await;
+                    state.processes.clear_extension_binaries().await;
+                    if action_build {
+                        state.processes.reload_after_build(&environment).await;
+                    } else {
+                        state.processes.reload_replicas(&environment).await;
+                    }
+                }
             }
+            state.processes.stopping(&environment).await;
             let when_running = Outcome::both(Outcome::Stop, Outcome::Start);
             action.outcome(Outcome::if_running(when_running, Outcome::Start));
 
@@ -182,41 +301,155 @@ async fn runtime(
         }
     });
 
+    let function_name = wc.name.clone();
+    let environment = wc.environment.clone();
+    let post_spawn_state = state.clone();
+    config.on_post_spawn(move |postspawn: PostSpawn| {
+        let name = function_name.clone();
+        let environment = environment.clone();
+        let state = post_spawn_state.clone();
+
+        async move {
+            let pid = postspawn.id;
+            state.processes.spawned(&environment, pid).await;
+
+            let init_state = state.clone();
+            let init_environment = environment.clone();
+            tokio::spawn(async move {
+                memory::wait_for_function_start(pid).await;
+                init_state
+                    .processes
+                    .init_started(&init_environment, pid)
+                    .await;
+            });
+
+            let settings = state.functions.get(&name).await;
+            if settings.enforce_memory {
+                memory::enforce_memory_limit(state, environment, postspawn.id, settings.memory);
+            }
+
+            Ok::<(), ServerError>(())
+        }
+    });
+
     config.on_pre_spawn(move |prespawn: PreSpawn| {
         let name = wc.name.clone();
         let runtime_api = wc.runtime_api.clone();
         let manifest_path = wc.manifest_path.clone();
         let bin_name = wc.bin_name.clone();
         let base_env = wc.env.clone();
+        let task_root = wc.base.clone();
+        let environment = wc.environment.clone();
+        let extensions = wc.extensions.clone();
+        let working_dir = wc.working_dir.clone();
+        let build = wc.build.clone();
+        let replica = wc.replica;
+        let state = state.clone();
 
         async move {
             trace!("loading watch environment metadata");
 
-            let new_env = reload_env(&manifest_path, &bin_name);
+            let config = reload_config(&manifest_path, &bin_name);
+            let new_env = config.as_ref().map(reload_env).unwrap_or_default();
+            let settings = match &config {
+                Some(config) => state.functions.update(&name, config).await,
+                None => state.functions.get(&name).await,
+            };
+
+            let lambda_env = env::lambda_environment(&name, &settings, config.as_ref(), &task_root);
+
+            let binary = match &build {
+                Some(build) => match build.build().await {
+                    Ok(binary) => {
+                        state
+                            .processes
+                            .build_finished(&environment, binary.clone())
+                            .await;
+                        Some(binary)
+                    }
+                    Err(error) => {
+                        let failure = state
+                            .processes
+                            .build_failed(&environment, error.to_string())
+                            .await;
+                        state.fail_environment(&environment, failure).await;
+                        return Err(error);
+                    }
+                },
+                None => None,
+            };
+
+            if !extensions.is_empty() {
+                let mut extension_env = lambda_env.clone().into_iter().collect::<HashMap<_, _>>();
+                extension_env.extend(base_env.clone());
+                extension_env.extend(new_env.clone());
+                restart_extensions(
+                    &state,
+                    &environment,
+                    &extensions,
+                    &extension_env,
+                    &runtime_api,
+                    !replica,
+                )
+                .await;
+            }
 
             if let Some(mut command) = prespawn.command().await {
+                if let (Some(binary), Some(build)) = (&binary, &build) {
+                    let mut run = tokio::process::Command::new(binary);
+                    run.args(&build.run_args);
+                    *command = run;
+                }
+                if let Some(working_dir) = &working_dir {
+                    command.current_dir(working_dir);
+                }
                 command
-                    .env("AWS_LAMBDA_FUNCTION_VERSION", "1")
-                    .env("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "4096")
+                    .envs(lambda_env)
                     .envs(base_env)
                     .envs(new_env)
                     .env("AWS_LAMBDA_RUNTIME_API", &runtime_api)
                     .env("AWS_LAMBDA_FUNCTION_NAME", &name);
+
+                telemetry::capture_output(
+                    &mut command,
+                    &state.telemetry,
+                    &state.processes,
+                    &environment,
+                    LogFormatter::new(&settings.logging),
+                )?;
             }
 
-            Ok::<(), Infallible>(())
+            Ok::<(), ServerError>(())
         }
     });
 
     Ok(config)
 }
 
-fn reload_env(manifest_path: &PathBuf, bin_name: &Option<String>) -> HashMap<String, String> {
+/// Reason of a process' exit, with the same message that Lambda reports.
+fn exit_reason(status: Option<ProcessEnd>) -> String {
+    match status {
+        Some(ProcessEnd::ExitError(code)) => {
+            format!("Runtime exited with error: exit status {code}")
+        }
+        Some(ProcessEnd::ExitSignal(signal)) => {
+            format!("Runtime exited with error: signal: {signal}")
+        }
+        Some(ProcessEnd::ExitStop(code)) | Some(ProcessEnd::Exception(code)) => {
+            format!("Runtime exited with error: {code}")
+        }
+        Some(ProcessEnd::Success) | Some(ProcessEnd::Continued) | None => {
+            "Runtime exited without providing a reason".to_string()
+        }
+    }
+}
+
+pub(crate) fn reload_config(manifest_path: &PathBuf, bin_name: &Option<String>) -> Option<Config> {
     let metadata = match load_metadata(manifest_path) {
         Ok(metadata) => metadata,
         Err(e) => {
             error!("failed to reload metadata: {}", e);
-            return HashMap::new();
+            return None;
         }
     };
 
@@ -224,14 +457,16 @@ fn reload_env(manifest_path: &PathBuf, bin_name: &Option<String>) -> HashMap<Str
         name: bin_name.clone(),
         ..Default::default()
     };
-    let config = match load_config_without_cli_flags(&metadata, &options) {
-        Ok(config) => config,
+    match load_config_without_cli_flags(&metadata, &options) {
+        Ok(config) => Some(config),
         Err(e) => {
             error!("failed to reload config: {}", e);
-            return HashMap::new();
+            None
         }
-    };
+    }
+}
 
+fn reload_env(config: &Config) -> HashMap<String, String> {
     match config.watch.lambda_environment(&config.env) {
         Ok(env) => env,
         Err(e) => {
@@ -0,0 +1,202 @@
+use crate::error::ServerError;
+use cargo_options::{Build as BuildOptions, Run as CargoOptions};
+use serde_json::Value;
+use std::{path::PathBuf, process::Stdio};
+use tokio::process::Command;
+use tracing::debug;
+use watchexec::command::Command as WatchCommand;
+
+/// `cargo build` command that compiles a function in the workspace,
+/// and the arguments that the function's binary runs with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct FunctionBuild {
+    prog: String,
+    args: Vec<String>,
+    /// Name of the binary that the build produces,
+    /// `None` when the package has only one binary
+    target: Option<String>,
+    /// Arguments after `--` in the command line, that `cargo run` passes to the binary
+    pub run_args: Vec<String>,
+}
+
+impl FunctionBuild {
+    /// Command to build a function, with the same options as `cargo run`.
+    pub(crate) fn new(bin_name: Option<&str>, cargo_options: &CargoOptions) -> FunctionBuild {
+        let mut options = build_options(cargo_options);
+        options.packages.clone_from(&cargo_options.packages);
+        options.bin.clone_from(&cargo_options.bin);
+        options.example.clone_from(&cargo_options.example);
+        if let Some(bin_name) = bin_name {
+            options.bin.push(bin_name.to_string());
+        }
+
+        let target = match (options.bin.as_slice(), options.example.as_slice()) {
+            ([bin], []) => Some(bin.clone()),
+            ([], [example]) => Some(example.clone()),
+            _ => None,
+        };
+
+        let cmd = options.command();
+        FunctionBuild {
+            prog: cmd.get_program().to_string_lossy().to_string(),
+            args: cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect(),
+            target,
+            run_args: cargo_options.args.clone(),
+        }
+    }
+
+    /// Command that the watcher starts. The watcher runs the function's binary
+    /// instead, after it builds the function.
+    pub(crate) fn command(&self) -> WatchCommand {
+        WatchCommand::Exec {
+            prog: self.prog.clone(),
+            args: self.args.clone(),
+        }
+    }
+
+    /// Compile the function, and return the path to its binary.
+    /// Cargo's output for humans is printed in the terminal as it is.
+    pub(crate) async fn build(&self) -> Result<PathBuf, ServerError> {
+        debug!(target = ?self.target, "building function");
+        // `Command::output` would capture stderr too, so the child is awaited instead.
+        let output = Command::new(&self.prog)
+            .args(&self.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| ServerError::BuildFunction(e.to_string()))?
+            .wait_with_output()
+            .await
+            .map_err(|e| ServerError::BuildFunction(e.to_string()))?;
+
+        if !output.status.success() {
+            return Err(ServerError::BuildFunction(format!(
+                "cargo exited with {}",
+                output.status
+            )));
+        }
+
+        artifact_executable(&output.stdout, self.target.as_deref()).ok_or_else(|| {
+            ServerError::BuildFunction("cargo didn't report the function's binary".into())
+        })
+    }
+}
+
+/// Options of `cargo build` with the same profile, features, and
+/// manifest as the options of `cargo run`, without selecting any target.
+pub(crate) fn build_options(cargo_options: &CargoOptions) -> BuildOptions {
+    let mut options = BuildOptions {
+        common: cargo_options.common.clone(),
+        manifest_path: cargo_options.manifest_path.clone(),
+        release: cargo_options.release,
+        ignore_rust_version: cargo_options.ignore_rust_version,
+        ..Default::default()
+    };
+    // Cargo reports the path of the binaries in its JSON messages,
+    // and it still prints the diagnostics for humans.
+    options.common.message_format = vec!["json-render-diagnostics".into()];
+    options
+}
+
+/// Path of the binary that Cargo built for a target, from the JSON messages of the build.
+/// Without a target name, the build must produce only one binary.
+pub(crate) fn artifact_executable(messages: &[u8], name: Option<&str>) -> Option<PathBuf> {
+    let mut executables = messages
+        .split(|b| *b == b'\n')
+        .filter_map(|line| serde_json::from_slice::<Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter(|message| name.is_none_or(|name| message["target"]["name"] == name))
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from));
+
+    let executable = executables.next()?;
+    match (name, executables.next()) {
+        (None, Some(_)) => None,
+        _ => Some(executable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_options::CommonOptions;
+
+    #[test]
+    fn test_function_build() {
+        let cargo_options = CargoOptions {
+            common: CommonOptions {
+                quiet: true,
+                ..Default::default()
+            },
+            packages: vec!["basic-lambda".into()],
+            args: vec!["--verbose".into()],
+            release: true,
+            ..Default::default()
+        };
+
+        // Quiet builds still report the binary in their JSON messages.
+        let build = FunctionBuild::new(Some("basic-lambda"), &cargo_options);
+        assert_eq!(build.args[0], "build");
+        assert!(build.args.contains(&"--quiet".to_string()));
+        assert!(
+            build
+                .args
+                .windows(2)
+                .any(|a| a == ["--message-format", "json-render-diagnostics"])
+        );
+        assert!(
+            build
+                .args
+                .windows(2)
+                .any(|a| a == ["--bin", "basic-lambda"])
+        );
+        assert!(
+            build
+                .args
+                .windows(2)
+                .any(|a| a == ["--package", "basic-lambda"])
+        );
+        assert!(build.args.contains(&"--release".to_string()));
+        assert!(!build.args.contains(&"--verbose".to_string()));
+        assert_eq!(build.target.as_deref(), Some("basic-lambda"));
+        assert_eq!(build.run_args, vec!["--verbose"]);
+
+        let build = FunctionBuild::new(None, &cargo_options);
+        assert!(!build.args.contains(&"--bin".to_string()));
+        assert_eq!(build.target, None);
+    }
+
+    #[test]
+    fn test_artifact_executable() {
+        let messages = [
+            r#"{"reason":"compiler-artifact","target":{"name":"serde"},"executable":null}"#,
+            r#"{"reason":"compiler-message","message":{"rendered":"warning: Finished `dev` profile"}}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"logs-extension"},"executable":"/project/target/debug/logs-extension"}"#,
+            r#"{"reason":"build-finished","success":true}"#,
+        ]
+        .join("\n");
+
+        assert_eq!(
+            Some(PathBuf::from("/project/target/debug/logs-extension")),
+            artifact_executable(messages.as_bytes(), Some("logs-extension"))
+        );
+        assert_eq!(
+            Some(PathBuf::from("/project/target/debug/logs-extension")),
+            artifact_executable(messages.as_bytes(), None)
+        );
+        assert_eq!(
+            None,
+            artifact_executable(messages.as_bytes(), Some("serde"))
+        );
+
+        let messages = [
+            r#"{"reason":"compiler-artifact","target":{"name":"first"},"executable":"/project/target/debug/first"}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"second"},"executable":"/project/target/debug/second"}"#,
+        ]
+        .join("\n");
+        assert_eq!(None, artifact_executable(messages.as_bytes(), None));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::state::FunctionSettings;
+use cargo_lambda_metadata::{config::Config, lambda::LogFormat};
+use cargo_lambda_remote::DEFAULT_REGION;
+use chrono::Utc;
+use std::{collections::BTreeMap, path::Path};
+use uuid::Uuid;
+
+/// Version that the emulator reports for every function.
+pub(crate) const FUNCTION_VERSION: &str = "1";
+
+/// Account id used in the ARNs of the local resources.
+pub(crate) const LOCAL_ACCOUNT_ID: &str = "000000000000";
+
+/// Handler name that Lambda reports for functions that use custom runtimes.
+pub(crate) const FUNCTION_HANDLER: &str = "bootstrap";
+
+/// Directory where Lambda installs the runtime, separate from the function's code.
+const RUNTIME_DIR: &str = "/var/runtime";
+
+/// Environment variables that Lambda sets for a function.
+///
+/// The variables in the function's metadata, and the ones passed with
+/// the `--env-var` and `--env-file` flags, override these values.
+/// Lambda doesn't set `AWS_EXECUTION_ENV` for the `provided` runtimes.
+/// See the list of reserved variables in the Lambda documentation:
+/// https://docs.aws.amazon.com/lambda/latest/dg/configuration-envvars.html#configuration-envvars-runtime
+pub(crate) fn lambda_environment(
+    name: &str,
+    settings: &FunctionSettings,
+    config: Option<&Config>,
+    task_root: &Path,
+) -> BTreeMap<String, String> {
+    let region = function_region(config);
+    let task_root = task_root.to_string_lossy().to_string();
+
+    let mut env = BTreeMap::new();
+    env.insert("AWS_REGION".into(), region.clone());
+    env.insert("AWS_DEFAULT_REGION".into(), region);
+    env.insert("AWS_LAMBDA_FUNCTION_NAME".into(), name.into());
+    env.insert(
+        "AWS_LAMBDA_FUNCTION_VERSION".into(),
+        FUNCTION_VERSION.into(),
+    );
+    env.insert(
+        "AWS_LAMBDA_FUNCTION_MEMORY_SIZE".into(),
+        settings.memory.to_string(),
+    );
+    env.insert("AWS_LAMBDA_INITIALIZATION_TYPE".into(), "on-demand".into());
+    env.insert(
+        "AWS_LAMBDA_LOG_FORMAT".into(),
+        settings.logging.log_format().to_string(),
+    );
+    if settings.logging.log_format() == LogFormat::Json {
+        env.insert(
+            "AWS_LAMBDA_LOG_LEVEL".into(),
+            settings.logging.application_log_level().to_string(),
+        );
+    }
+    env.insert("AWS_LAMBDA_LOG_GROUP_NAME".into(), log_group_name(name));
+    env.insert("AWS_LAMBDA_LOG_STREAM_NAME".into(), log_stream_name());
+    env.insert("AWS_XRAY_CONTEXT_MISSING".into(), "LOG_ERROR".into());
+    env.insert("LAMBDA_TASK_ROOT".into(), task_root);
+    env.insert("LAMBDA_RUNTIME_DIR".into(), RUNTIME_DIR.into());
+    env.insert("TZ".into(), ":UTC".into());
+    env.insert("_HANDLER".into(), FUNCTION_HANDLER.into());
+
+    env
+}
+
+/// Region where the function runs. It uses the region in the deploy
+/// configuration, and falls back to the region in the environment.
+pub(crate) fn function_region(config: Option<&Config>) -> String {
+    config
+        .and_then(|c| c.deploy.remote_config.as_ref())
+        .and_then(|r| r.region.clone())
+        .or_else(|| std::env::var("AWS_REGION").ok())
+        .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+        .unwrap_or_else(|| DEFAULT_REGION.to_string())
+}
+
+fn log_group_name(name: &str) -> String {
+    format!("/aws/lambda/{name}")
+}
+
+/// Lambda creates a new log stream for every execution environment.
+fn log_stream_name() -> String {
+    format!(
+        "{}/[{FUNCTION_VERSION}]{}",
+        Utc::now().format("%Y/%m/%d"),
+        Uuid::new_v4().simple()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_lambda_remote::RemoteConfig;
+
+    #[test]
+    fn test_lambda_environment() {
+        let mut config = Config::default();
+        config.deploy.remote_config = Some(RemoteConfig {
+            region: Some("eu-west-1".into()),
+            ..Default::default()
+        });
+        let settings = FunctionSettings {
+            memory: 512,
+            ..Default::default()
+        };
+
+        let env = lambda_environment(
+            "basic-lambda",
+            &settings,
+            Some(&config),
+            Path::new("/tmp/basic-lambda"),
+        );
+
+        assert_eq!("eu-west-1", env["AWS_REGION"]);
+        assert_eq!("eu-west-1", env["AWS_DEFAULT_REGION"]);
+        assert!(!env.contains_key("AWS_EXECUTION_ENV"));
+        assert_eq!("basic-lambda", env["AWS_LAMBDA_FUNCTION_NAME"]);
+        assert_eq!("512", env["AWS_LAMBDA_FUNCTION_MEMORY_SIZE"]);
+        assert_eq!("/aws/lambda/basic-lambda", env["AWS_LAMBDA_LOG_GROUP_NAME"]);
+        assert!(env["AWS_LAMBDA_LOG_STREAM_NAME"].contains("/[1]"));
+        assert_eq!("/tmp/basic-lambda", env["LAMBDA_TASK_ROOT"]);
+        assert_eq!("/var/runtime", env["LAMBDA_RUNTIME_DIR"]);
+        assert_eq!("bootstrap", env["_HANDLER"]);
+    }
+}
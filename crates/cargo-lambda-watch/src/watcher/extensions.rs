@@ -0,0 +1,214 @@
+use super::build::{artifact_executable, build_options};
+use crate::{error::ServerError, state::RuntimeState, telemetry};
+use cargo_options::Run as CargoOptions;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::process::{Child, Command};
+use tracing::{debug, error, warn};
+
+/// How long the function waits for its extensions to register
+/// before it starts, like Lambda's extension init phase.
+const EXTENSION_INIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Extension that the emulator starts next to every function process.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ExtensionCommand {
+    pub name: String,
+    source: ExtensionSource,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ExtensionSource {
+    /// Prebuilt binary
+    Binary(PathBuf),
+    /// Binary in the workspace, and the `cargo build` command that compiles it
+    Workspace { prog: String, args: Vec<String> },
+}
+
+impl ExtensionCommand {
+    /// Command to start an extension. Paths to existing files are executed as
+    /// prebuilt binaries. Any other value is the name of a binary in the workspace,
+    /// that's compiled with `cargo build`, with the same options as functions.
+    pub(crate) fn new(extension: &str, cargo_options: &CargoOptions) -> ExtensionCommand {
+        let path = Path::new(extension);
+        if path.is_file() {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| extension.to_string());
+
+            return ExtensionCommand {
+                name,
+                source: ExtensionSource::Binary(path.to_path_buf()),
+            };
+        }
+
+        let mut options = build_options(cargo_options);
+        options.bin = vec![extension.to_string()];
+        let cmd = options.command();
+
+        ExtensionCommand {
+            name: extension.to_string(),
+            source: ExtensionSource::Workspace {
+                prog: cmd.get_program().to_string_lossy().to_string(),
+                args: cmd
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().to_string())
+                    .collect(),
+            },
+        }
+    }
+
+    /// Compile the extension if it's in the workspace, and return the path to its binary.
+    async fn build(&self) -> Result<PathBuf, ServerError> {
+        let (prog, args) = match &self.source {
+            ExtensionSource::Binary(path) => return Ok(path.clone()),
+            ExtensionSource::Workspace { prog, args } => (prog, args),
+        };
+
+        debug!(extension = %self.name, "building extension");
+        // `Command::output` would capture stderr too, so the child is awaited instead.
+        let output = Command::new(prog)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| ServerError::SpawnExtension(self.name.clone(), e))?
+            .wait_with_output()
+            .await
+            .map_err(|e| ServerError::SpawnExtension(self.name.clone(), e))?;
+
+        match artifact_executable(&output.stdout, Some(&self.name)) {
+            Some(binary) if output.status.success() => Ok(binary),
+            _ => Err(ServerError::BuildExtension(self.name.clone())),
+        }
+    }
+
+    /// Start the extension's binary with the same environment as the function,
+    /// and the runtime API of the function's execution environment.
+    /// The process is killed when the returned handle is dropped.
+    fn spawn(
+        &self,
+        state: &RuntimeState,
+        binary: &Path,
+        environment: &str,
+        env: &HashMap<String, String>,
+        runtime_api: &str,
+    ) -> Result<Child, ServerError> {
+        let mut command = Command::new(binary);
+        command
+            .envs(env)
+            .env("AWS_LAMBDA_RUNTIME_API", runtime_api)
+            .kill_on_drop(true);
+
+        telemetry::capture_extension_output(&mut command, &state.telemetry, environment)
+            .and_then(|_| command.spawn())
+            .map_err(|e| ServerError::SpawnExtension(self.name.clone(), e))
+    }
+}
+
+/// Stop the extensions running in an execution environment, and start them again
+/// before the function's process starts, like Lambda does when it initializes an environment.
+/// The function's process starts after the extensions register, or after the init timeout.
+///
+/// Only the execution environment that builds the function builds its extensions, when their
+/// binaries are outdated. The function's other environments wait for those binaries.
+pub(crate) async fn restart_extensions(
+    state: &RuntimeState,
+    environment: &str,
+    extensions: &[ExtensionCommand],
+    env: &HashMap<String, String>,
+    runtime_api: &str,
+    build: bool,
+) {
+    state.processes.stop_extensions(environment).await;
+    state.ext_cache.unregister_environment(environment).await;
+    state.telemetry.unsubscribe_environment(environment).await;
+
+    if build {
+        for extension in extensions {
+            if state.processes.has_extension_binary(&extension.name).await {
+                continue;
+            }
+
+            let binary = match extension.build().await {
+                Ok(binary) => Some(binary),
+                Err(error) => {
+                    error!(?error, environment, "failed to build extension");
+                    None
+                }
+            };
+            state
+                .processes
+                .set_extension_binary(&extension.name, binary)
+                .await;
+        }
+    }
+
+    let mut processes = Vec::with_capacity(extensions.len());
+    for extension in extensions {
+        let Some(binary) = state.processes.extension_binary(&extension.name).await else {
+            continue;
+        };
+
+        debug!(extension = %extension.name, environment, "starting extension");
+        match extension.spawn(state, &binary, environment, env, runtime_api) {
+            Ok(child) => processes.push(child),
+            Err(error) => error!(?error, environment, "failed to start extension"),
+        }
+    }
+
+    let started = processes.len();
+    state.processes.set_extensions(environment, processes).await;
+
+    let registration = state.ext_cache.wait_for_registration(environment, started);
+    if tokio::time::timeout(EXTENSION_INIT_TIMEOUT, registration)
+        .await
+        .is_err()
+    {
+        warn!(
+            environment,
+            "the extensions didn't register before the init timeout, starting the function"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_command() {
+        let cargo_options = CargoOptions {
+            packages: vec!["basic-lambda".into()],
+            bin: vec!["basic-lambda".into()],
+            args: vec!["--verbose".into()],
+            release: true,
+            ..Default::default()
+        };
+
+        let command = ExtensionCommand::new("logs-extension", &cargo_options);
+        assert_eq!(command.name, "logs-extension");
+        let ExtensionSource::Workspace { args, .. } = &command.source else {
+            panic!("expected a workspace extension");
+        };
+        assert_eq!(args[0], "build");
+        assert!(args.windows(2).any(|a| a == ["--bin", "logs-extension"]));
+        assert!(args.contains(&"--release".to_string()));
+        assert!(!args.contains(&"basic-lambda".to_string()));
+        assert!(!args.contains(&"--verbose".to_string()));
+
+        let binary = std::env::current_exe().unwrap();
+        let command = ExtensionCommand::new(&binary.to_string_lossy(), &cargo_options);
+        assert_eq!(command.source, ExtensionSource::Binary(binary.clone()));
+        assert_eq!(
+            command.name,
+            binary.file_name().unwrap().to_string_lossy().to_string()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{requests::FunctionError, state::RuntimeState};
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// How often the memory used by a function's process is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the emulator checks whether the function's binary started.
+const START_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Stop the function's process when it uses more memory than the function
+/// has available, and fail the invocation that it was running, like Lambda does.
+pub(crate) fn enforce_memory_limit(
+    state: RuntimeState,
+    environment: String,
+    pid: u32,
+    memory: u32,
+) {
+    if cfg!(target_os = "linux") {
+        tokio::spawn(watch_process_memory(state, environment, pid, memory));
+    } else {
+        warn!(
+            ?environment,
+            "memory limits are only enforced on Linux, the function can use all the memory available"
+        );
+    }
+}
+
+async fn watch_process_memory(state: RuntimeState, environment: String, pid: u32, memory: u32) {
+    let limit = u64::from(memory) * 1024 * 1024;
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let Ok(name) = std::fs::read_to_string(format!("/proc/{pid}/comm")) else {
+            // The process is gone, the next process gets its own watcher.
+            return;
+        };
+
+        // `cargo run` replaces its own process with the function's binary
+        // after the build completes. Cargo's memory doesn't count towards the limit.
+        if is_build_process(name.trim()) {
+            continue;
+        }
+
+        let Ok(status) = std::fs::read_to_string(format!("/proc/{pid}/status")) else {
+            return;
+        };
+
+        if let Some(used) = resident_memory(&status) {
+            if used > limit {
+                error!(
+                    ?environment,
+                    used_mb = used / 1024 / 1024,
+                    memory_mb = memory,
+                    "function ran out of memory"
+                );
+                break;
+            }
+        }
+    }
+
+    if let Some(req_id) = state.processes.invocation(&environment).await {
+        let message =
+            format!("RequestId: {req_id} Error: Runtime exited with error: signal: killed");
+        let error = FunctionError::new("Runtime.OutOfMemory", &message);
+        state.fail_invocation(&req_id, error).await;
+    }
+
+    state.restart_environment(&environment, "FAILURE").await;
+}
+
+/// Wait until `cargo run` replaces its own process with the function's binary,
+/// which is when the function starts initializing. It returns right away
+/// on systems where the process cannot be inspected.
+pub(crate) async fn wait_for_function_start(pid: u32) {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+
+    loop {
+        let Ok(name) = std::fs::read_to_string(format!("/proc/{pid}/comm")) else {
+            return;
+        };
+        if !is_build_process(name.trim()) {
+            return;
+        }
+
+        tokio::time::sleep(START_POLL_INTERVAL).await;
+    }
+}
+
+/// Peak memory, in bytes, that the function's process has used since it started.
+pub(crate) fn max_memory_used(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    peak_memory(&status)
+}
+
+fn is_build_process(name: &str) -> bool {
+    matches!(name, "cargo" | "rustup")
+}
+
+/// Extract the resident set size, in bytes, from the content of `/proc/<pid>/status`.
+fn resident_memory(status: &str) -> Option<u64> {
+    status_memory(status, "VmRSS:")
+}
+
+/// Extract the peak resident set size, in bytes, from the content of `/proc/<pid>/status`.
+fn peak_memory(status: &str) -> Option<u64> {
+    status_memory(status, "VmHWM:")
+}
+
+fn status_memory(status: &str, field: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kb = line
+        .trim_start_matches(field)
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resident_memory() {
+        let status = "Name:\tbasic-lambda\nVmPeak:\t  123456 kB\nVmHWM:\t    4096 kB\nVmRSS:\t    2048 kB\nThreads:\t4\n";
+        assert_eq!(Some(2048 * 1024), resident_memory(status));
+        assert_eq!(Some(4096 * 1024), peak_memory(status));
+
+        let status = "Name:\tbasic-lambda\nState:\tZ (zombie)\n";
+        assert_eq!(None, resident_memory(status));
+        assert_eq!(None, peak_memory(status));
+    }
+}
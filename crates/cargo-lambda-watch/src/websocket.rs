@@ -0,0 +1,626 @@
+use crate::{
+    error::ServerError,
+    requests::Action,
+    state::{RefRuntimeState, RuntimeState},
+    trigger_router::schedule_invocation,
+    watcher::env::LOCAL_ACCOUNT_ID,
+};
+use aws_lambda_events::apigw::{
+    ApiGatewayRequestIdentity, ApiGatewayWebsocketProxyRequest,
+    ApiGatewayWebsocketProxyRequestContext,
+};
+use axum::{
+    Router,
+    body::Body,
+    extract::{
+        Extension, State,
+        ws::{
+            CloseFrame, Message, WebSocket, WebSocketUpgrade,
+            close_code::{ABNORMAL, NORMAL, STATUS},
+        },
+    },
+    http::{HeaderMap, Request, Response, StatusCode, Uri, header},
+    response::IntoResponse,
+    routing::get,
+};
+use base64::{Engine as _, engine::general_purpose as b64};
+use cargo_lambda_metadata::cargo::watch::WebSocketApi;
+use chrono::{DateTime, Utc};
+use http_body_util::BodyExt;
+use query_map::QueryMap;
+use serde_json::{Value, json};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{
+    Mutex,
+    mpsc::{self, Sender, UnboundedReceiver, UnboundedSender},
+};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+pub(crate) mod api;
+
+/// Stage of the local WebSocket API, in events and in the `@connections` endpoint.
+pub(crate) const WEBSOCKET_STAGE: &str = "local";
+
+const API_ID: &str = "local";
+const SOURCE_IP: &str = "127.0.0.1";
+
+/// Maximum size of the messages that clients send, like API Gateway's 128 KB quota.
+const MAX_MESSAGE_SIZE: usize = 128 * 1024;
+
+const CONNECT_ROUTE: &str = "$connect";
+const DISCONNECT_ROUTE: &str = "$disconnect";
+const DEFAULT_ROUTE: &str = "$default";
+
+/// Connections that clients have open with the WebSocket API, by connection id.
+#[derive(Clone, Default)]
+pub(crate) struct WebSocketConnections {
+    inner: Arc<Mutex<HashMap<String, Connection>>>,
+}
+
+struct Connection {
+    sender: UnboundedSender<Message>,
+    info: ConnectionInfo,
+}
+
+/// Information that the `@connections` API returns about a connection.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionInfo {
+    pub connected_at: DateTime<Utc>,
+    pub last_active_at: DateTime<Utc>,
+    pub source_ip: String,
+    pub user_agent: Option<String>,
+}
+
+impl WebSocketConnections {
+    async fn insert(&self, id: &str, sender: UnboundedSender<Message>, info: ConnectionInfo) {
+        let mut inner = self.inner.lock().await;
+        inner.insert(id.to_string(), Connection { sender, info });
+    }
+
+    async fn remove(&self, id: &str) {
+        self.inner.lock().await.remove(id);
+    }
+
+    /// Record activity from the client on a connection.
+    async fn touch(&self, id: &str) {
+        if let Some(connection) = self.inner.lock().await.get_mut(id) {
+            connection.info.last_active_at = Utc::now();
+        }
+    }
+
+    pub(crate) async fn info(&self, id: &str) -> Option<ConnectionInfo> {
+        let inner = self.inner.lock().await;
+        inner.get(id).map(|connection| connection.info.clone())
+    }
+
+    /// Send a message to a client. It returns false when the client is not connected.
+    pub(crate) async fn send(&self, id: &str, message: Message) -> bool {
+        let mut inner = self.inner.lock().await;
+        let Some(connection) = inner.get_mut(id) else {
+            return false;
+        };
+        connection.info.last_active_at = Utc::now();
+        connection.sender.send(message).is_ok()
+    }
+}
+
+/// Routes of the WebSocket API, and of its `@connections` API,
+/// only when the emulator has a WebSocket API configured.
+pub(crate) fn routes(websocket: Option<&WebSocketApi>) -> Router<RefRuntimeState> {
+    match websocket {
+        Some(websocket) if websocket.path.starts_with('/') => {
+            api::routes().route(&websocket.path, get(connect_handler))
+        }
+        Some(websocket) => {
+            api::routes().route(&format!("/{}", websocket.path), get(connect_handler))
+        }
+        None => Router::new(),
+    }
+}
+
+/// Details of the request that opened a connection, that the events include.
+#[derive(Clone)]
+struct ConnectionContext {
+    connection_id: String,
+    connected_at: DateTime<Utc>,
+    domain_name: String,
+    headers: HeaderMap,
+    query: QueryMap,
+}
+
+impl ConnectionContext {
+    fn user_agent(&self) -> Option<String> {
+        self.headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    }
+
+    fn event(&self, route_key: &str, event_type: &str) -> ApiGatewayWebsocketProxyRequest {
+        let time = Utc::now();
+        let request_id = Uuid::new_v4().to_string();
+
+        let request_context = ApiGatewayWebsocketProxyRequestContext {
+            account_id: Some(LOCAL_ACCOUNT_ID.to_string()),
+            stage: Some(WEBSOCKET_STAGE.to_string()),
+            request_id: Some(request_id.clone()),
+            extended_request_id: Some(request_id),
+            identity: ApiGatewayRequestIdentity {
+                source_ip: Some(SOURCE_IP.to_string()),
+                user_agent: self.user_agent(),
+                ..Default::default()
+            },
+            apiid: Some(API_ID.to_string()),
+            connected_at: self.connected_at.timestamp_millis(),
+            connection_id: Some(self.connection_id.clone()),
+            domain_name: Some(self.domain_name.clone()),
+            event_type: Some(event_type.to_string()),
+            message_direction: Some("IN".to_string()),
+            request_time: Some(time.format("%d/%b/%Y:%T %z").to_string()),
+            request_time_epoch: time.timestamp_millis(),
+            route_key: Some(route_key.to_string()),
+            ..Default::default()
+        };
+
+        ApiGatewayWebsocketProxyRequest {
+            request_context,
+            ..Default::default()
+        }
+    }
+
+    fn connect_event(&self) -> ApiGatewayWebsocketProxyRequest {
+        ApiGatewayWebsocketProxyRequest {
+            headers: self.headers.clone(),
+            multi_value_headers: self.headers.clone(),
+            query_string_parameters: self.query.clone(),
+            multi_value_query_string_parameters: self.query.clone(),
+            ..self.event(CONNECT_ROUTE, "CONNECT")
+        }
+    }
+
+    fn disconnect_event(
+        &self,
+        status_code: u16,
+        reason: String,
+    ) -> ApiGatewayWebsocketProxyRequest {
+        let mut event = ApiGatewayWebsocketProxyRequest {
+            headers: self.headers.clone(),
+            multi_value_headers: self.headers.clone(),
+            ..self.event(DISCONNECT_ROUTE, "DISCONNECT")
+        };
+        event.request_context.disconnect_status_code = Some(status_code as i64);
+        event.request_context.disconnect_reason = Some(reason);
+        event
+    }
+}
+
+/// Open a connection, after the `$connect` route accepts it.
+async fn connect_handler(
+    State(state): State<RefRuntimeState>,
+    Extension(cmd_tx): Extension<Sender<Action>>,
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Response<Body>, ServerError> {
+    let Some(websocket) = state.websocket_api.clone() else {
+        return respond_with_status(StatusCode::NOT_FOUND);
+    };
+
+    let context = ConnectionContext {
+        connection_id: b64::URL_SAFE.encode(&Uuid::new_v4().as_bytes()[..8]),
+        connected_at: Utc::now(),
+        domain_name: headers
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("localhost")
+            .to_string(),
+        query: uri.query().unwrap_or_default().parse().unwrap_or_default(),
+        headers,
+    };
+
+    let mut ws = ws
+        .max_message_size(MAX_MESSAGE_SIZE)
+        .max_frame_size(MAX_MESSAGE_SIZE);
+    if let Some(function) = websocket.routes.get(CONNECT_ROUTE) {
+        let response = match invoke_route(&state, &cmd_tx, function, &context.connect_event()).await
+        {
+            Ok(response) => response,
+            Err(error) => {
+                error!(%error, connection_id = ?context.connection_id, "failed to invoke the $connect route");
+                return respond_with_status(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        let status = response
+            .get("statusCode")
+            .and_then(Value::as_u64)
+            .and_then(|code| StatusCode::from_u16(code as u16).ok())
+            .unwrap_or(StatusCode::OK);
+        if !status.is_success() {
+            debug!(connection_id = ?context.connection_id, %status, "the $connect route rejected the connection");
+            return respond_with_status(status);
+        }
+
+        let protocol = response
+            .get("headers")
+            .and_then(Value::as_object)
+            .and_then(|headers| {
+                headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-protocol"))
+            })
+            .and_then(|(_, value)| value.as_str())
+            .map(String::from);
+        if let Some(protocol) = protocol {
+            ws = ws.protocols([protocol]);
+        }
+    }
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let info = ConnectionInfo {
+        connected_at: context.connected_at,
+        last_active_at: context.connected_at,
+        source_ip: SOURCE_IP.to_string(),
+        user_agent: context.user_agent(),
+    };
+    state
+        .websockets
+        .insert(&context.connection_id, sender, info)
+        .await;
+
+    let failed_state = state.clone();
+    let connection_id = context.connection_id.clone();
+    let response = ws
+        .on_failed_upgrade(move |error| {
+            error!(
+                ?error,
+                ?connection_id,
+                "failed to upgrade the WebSocket connection"
+            );
+            tokio::spawn(async move { failed_state.websockets.remove(&connection_id).await });
+        })
+        .on_upgrade(move |socket| serve_connection(state, cmd_tx, context, socket, receiver));
+    Ok(response.into_response())
+}
+
+/// Exchange messages with a client until the connection closes,
+/// and invoke the `$disconnect` route after that.
+async fn serve_connection(
+    state: RefRuntimeState,
+    cmd_tx: Sender<Action>,
+    context: ConnectionContext,
+    mut socket: WebSocket,
+    mut outgoing: UnboundedReceiver<Message>,
+) {
+    let connection_id = &context.connection_id;
+    info!(?connection_id, "WebSocket client connected");
+
+    let (status_code, reason) = loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else {
+                    break (ABNORMAL, String::new());
+                };
+                let close = match &message {
+                    Message::Close(frame) => Some(close_status(frame.as_ref(), NORMAL)),
+                    _ => None,
+                };
+                if let Err(error) = socket.send(message).await {
+                    break (ABNORMAL, error.to_string());
+                }
+                if let Some(close) = close {
+                    break close;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(body))) => {
+                    state.websockets.touch(connection_id).await;
+                    dispatch_message(&state, &cmd_tx, &context, body, false);
+                }
+                Some(Ok(Message::Binary(data))) => {
+                    state.websockets.touch(connection_id).await;
+                    dispatch_message(&state, &cmd_tx, &context, b64::STANDARD.encode(data), true);
+                }
+                // The socket answers pings by itself.
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                Some(Ok(Message::Close(frame))) => {
+                    // Reading again sends the reply to the client's close frame.
+                    let _ = socket.recv().await;
+                    break close_status(frame.as_ref(), STATUS);
+                }
+                Some(Err(error)) => break (ABNORMAL, error.to_string()),
+                None => break (ABNORMAL, "Going away".to_string()),
+            }
+        }
+    };
+
+    state.websockets.remove(connection_id).await;
+    // The client doesn't wait for the `$disconnect` route.
+    drop(socket);
+    info!(?connection_id, status_code, "WebSocket client disconnected");
+
+    let function = state
+        .websocket_api
+        .as_ref()
+        .and_then(|websocket| websocket.routes.get(DISCONNECT_ROUTE));
+    if let Some(function) = function {
+        let event = context.disconnect_event(status_code, reason);
+        if let Err(error) = invoke_route(&state, &cmd_tx, function, &event).await {
+            error!(%error, ?connection_id, "failed to invoke the $disconnect route");
+        }
+    }
+}
+
+/// Status code and reason of a close frame, with a default
+/// status code for frames that don't include one.
+fn close_status(frame: Option<&CloseFrame<'static>>, default: u16) -> (u16, String) {
+    match frame {
+        Some(frame) => (frame.code, frame.reason.to_string()),
+        None => (default, String::new()),
+    }
+}
+
+/// Invoke the route that the message selects, in the background
+/// so the client can keep sending messages.
+fn dispatch_message(
+    state: &RefRuntimeState,
+    cmd_tx: &Sender<Action>,
+    context: &ConnectionContext,
+    body: String,
+    is_base64_encoded: bool,
+) {
+    let Some(websocket) = state.websocket_api.clone() else {
+        return;
+    };
+    let state = state.clone();
+    let cmd_tx = cmd_tx.clone();
+    let context = context.clone();
+
+    tokio::spawn(async move {
+        // Route selection expressions can't inspect binary messages.
+        let route_key = if is_base64_encoded {
+            websocket
+                .routes
+                .get_key_value(DEFAULT_ROUTE)
+                .map(|(key, _)| key.as_str())
+        } else {
+            websocket.route_key(&body)
+        };
+
+        let mut event = context.event(route_key.unwrap_or(DEFAULT_ROUTE), "MESSAGE");
+        event.request_context.message_id = Some(Uuid::new_v4().to_string());
+        event.body = Some(body);
+        event.is_base64_encoded = is_base64_encoded;
+        let request_id = event.request_context.request_id.clone();
+
+        let failure = match route_key.and_then(|key| websocket.routes.get(key)) {
+            None => {
+                debug!(connection_id = ?context.connection_id, "no route matches the message");
+                "Forbidden"
+            }
+            Some(function) => match invoke_route(&state, &cmd_tx, function, &event).await {
+                Ok(_) => return,
+                Err(error) => {
+                    error!(%error, connection_id = ?context.connection_id, "failed to invoke the WebSocket route");
+                    "Internal server error"
+                }
+            },
+        };
+
+        // API Gateway reports failures to the client that sent the message.
+        let message = json!({
+            "message": failure,
+            "connectionId": context.connection_id,
+            "requestId": request_id,
+        });
+        state
+            .websockets
+            .send(&context.connection_id, Message::Text(message.to_string()))
+            .await;
+    });
+}
+
+/// Invoke the function of a route. It returns the function's response,
+/// or a description of the failure.
+async fn invoke_route(
+    state: &RuntimeState,
+    cmd_tx: &Sender<Action>,
+    function: &str,
+    event: &ApiGatewayWebsocketProxyRequest,
+) -> Result<Value, String> {
+    if let Err(binaries) = state.is_function_available(function) {
+        return Err(format!(
+            "the function `{function}` doesn't exist, available functions: {binaries:?}"
+        ));
+    }
+
+    let event = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+    let resp = schedule_invocation(
+        state,
+        cmd_tx,
+        function.to_string(),
+        Request::new(Body::from(event)),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let status = resp
+        .extensions()
+        .get::<StatusCode>()
+        .cloned()
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if status != StatusCode::OK {
+        return Err(format!("the function returned status {status}"));
+    }
+
+    let body = resp
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| e.to_string())?
+        .to_bytes();
+    Ok(serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn respond_with_status(status: StatusCode) -> Result<Response<Body>, ServerError> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(status.canonical_reason().unwrap_or_default()))
+        .map_err(ServerError::ResponseBuild)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_routes_with_trigger_router() {
+        let mut state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::new(),
+            None,
+        );
+        state.websocket_api = Some(Arc::new(WebSocketApi {
+            path: "ws".into(),
+            route_selection_expression: "$request.body.action".into(),
+            routes: Default::default(),
+        }));
+        let state = Arc::new(state);
+        let (cmd_tx, _cmd_rx) = mpsc::channel::<Action>(1);
+        let router = crate::trigger_router::routes()
+            .merge(routes(state.websocket_api.as_deref()))
+            .layer(Extension(cmd_tx))
+            .with_state(state.clone());
+
+        // Requests to the WebSocket path that don't upgrade the connection are rejected.
+        let req = Request::get("/ws").body(Body::empty()).unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = Request::post("/local/@connections/conn-1")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::GONE);
+
+        // The `@connections` API is not available without a WebSocket API.
+        let req = Request::post("/@connections/conn-1")
+            .body(Body::empty())
+            .unwrap();
+        let resp = routes(None).with_state(state).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, "wscat".parse().unwrap());
+        let context = ConnectionContext {
+            connection_id: "L0SM9cOFvHcCIhw=".into(),
+            connected_at: Utc::now(),
+            domain_name: "localhost:9000".into(),
+            headers,
+            query: "token=abc".parse().unwrap(),
+        };
+
+        let event = context.connect_event();
+        let request_context = &event.request_context;
+        assert_eq!(request_context.route_key.as_deref(), Some("$connect"));
+        assert_eq!(request_context.event_type.as_deref(), Some("CONNECT"));
+        assert_eq!(request_context.stage.as_deref(), Some(WEBSOCKET_STAGE));
+        assert_eq!(
+            request_context.domain_name.as_deref(),
+            Some("localhost:9000")
+        );
+        assert_eq!(
+            request_context.identity.user_agent.as_deref(),
+            Some("wscat")
+        );
+        assert_eq!(event.query_string_parameters.first("token"), Some("abc"));
+
+        let event = context.disconnect_event(NORMAL, "bye".into());
+        let request_context = &event.request_context;
+        assert_eq!(request_context.event_type.as_deref(), Some("DISCONNECT"));
+        assert_eq!(request_context.disconnect_status_code, Some(1000));
+        assert_eq!(request_context.disconnect_reason.as_deref(), Some("bye"));
+        assert_eq!(
+            request_context.connection_id.as_deref(),
+            Some("L0SM9cOFvHcCIhw=")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_without_routes() {
+        let mut state = RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::new(),
+            None,
+        );
+        state.websocket_api = Some(Arc::new(WebSocketApi {
+            path: "/ws".into(),
+            route_selection_expression: "$request.body.action".into(),
+            routes: Default::default(),
+        }));
+        let state = Arc::new(state);
+        let (cmd_tx, _cmd_rx) = mpsc::channel::<Action>(1);
+        let app = routes(state.websocket_api.as_deref())
+            .layer(Extension(cmd_tx))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let handshake = "GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        stream.write_all(handshake.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            response.push_str(&line.to_lowercase());
+        }
+        assert!(response.starts_with("http/1.1 101"));
+        assert!(response.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo="));
+        assert_eq!(state.websockets.inner.lock().await.len(), 1);
+
+        // Masked text frame, the mask is all zeros.
+        let mut frame = vec![0x81, 0x80 | 2, 0, 0, 0, 0];
+        frame.extend(b"hi");
+        stream.write_all(&frame).await.unwrap();
+
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], 0x81);
+        let mut payload = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+        let message: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(message["message"], "Forbidden");
+
+        stream.write_all(&[0x88, 0x80, 0, 0, 0, 0]).await.unwrap();
+        let mut close = [0u8; 2];
+        stream.read_exact(&mut close).await.unwrap();
+        assert_eq!(close, [0x88, 0]);
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(state.websockets.inner.lock().await.is_empty());
+    }
+}
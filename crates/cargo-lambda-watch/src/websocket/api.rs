@@ -0,0 +1,198 @@
+use crate::{RefRuntimeState, error::ServerError};
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::{
+        Path, State,
+        ws::{CloseFrame, Message, close_code::NORMAL},
+    },
+    http::StatusCode,
+    response::Response,
+    routing::post,
+};
+use chrono::SecondsFormat;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Management API that functions use to send messages to clients,
+/// like API Gateway's `@connections` API.
+pub(crate) fn routes() -> Router<RefRuntimeState> {
+    let handlers = post(post_to_connection)
+        .get(get_connection)
+        .delete(delete_connection);
+    Router::new()
+        .route("/@connections/:connection_id", handlers.clone())
+        .route("/:stage/@connections/:connection_id", handlers)
+}
+
+/// Send the request body to a client. Bodies that are not valid UTF-8
+/// are sent as binary messages.
+async fn post_to_connection(
+    State(state): State<RefRuntimeState>,
+    Path(params): Path<HashMap<String, String>>,
+    body: Bytes,
+) -> Result<Response<Body>, ServerError> {
+    let connection_id = connection_id(&params);
+    let message = match String::from_utf8(body.to_vec()) {
+        Ok(text) => Message::Text(text),
+        Err(_) => Message::Binary(body.to_vec()),
+    };
+
+    if !state.websockets.send(connection_id, message).await {
+        return respond_with_gone(connection_id);
+    }
+    debug!(?connection_id, "message sent to WebSocket client");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::empty())
+        .map_err(ServerError::ResponseBuild)
+}
+
+async fn get_connection(
+    State(state): State<RefRuntimeState>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Response<Body>, ServerError> {
+    let connection_id = connection_id(&params);
+    let Some(info) = state.websockets.info(connection_id).await else {
+        return respond_with_gone(connection_id);
+    };
+
+    let body = serde_json::json!({
+        "ConnectedAt": info.connected_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "Identity": {
+            "SourceIp": info.source_ip,
+            "UserAgent": info.user_agent,
+        },
+        "LastActiveAt": info.last_active_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(ServerError::ResponseBuild)
+}
+
+/// Close the connection with a client. The connection
+/// invokes the `$disconnect` route after it closes.
+async fn delete_connection(
+    State(state): State<RefRuntimeState>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Response<Body>, ServerError> {
+    let connection_id = connection_id(&params);
+    let close = Message::Close(Some(CloseFrame {
+        code: NORMAL,
+        reason: "".into(),
+    }));
+    if !state.websockets.send(connection_id, close).await {
+        return respond_with_gone(connection_id);
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .map_err(ServerError::ResponseBuild)
+}
+
+fn connection_id(params: &HashMap<String, String>) -> &str {
+    params
+        .get("connection_id")
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+fn respond_with_gone(connection_id: &str) -> Result<Response<Body>, ServerError> {
+    debug!(?connection_id, "the WebSocket connection is gone");
+
+    let body = serde_json::json!({ "message": null });
+    Response::builder()
+        .status(StatusCode::GONE)
+        .header("content-type", "application/json")
+        .header("x-amzn-errortype", "GoneException")
+        .body(Body::from(body.to_string()))
+        .map_err(ServerError::ResponseBuild)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state::RuntimeState, websocket::ConnectionInfo};
+    use chrono::Utc;
+    use http_body_util::BodyExt;
+    use std::{collections::HashSet, sync::Arc};
+    use tokio::sync::mpsc;
+
+    fn params(connection_id: &str) -> Path<HashMap<String, String>> {
+        Path(HashMap::from([(
+            "connection_id".to_string(),
+            connection_id.to_string(),
+        )]))
+    }
+
+    #[tokio::test]
+    async fn test_connections_api() {
+        let state = Arc::new(RuntimeState::new(
+            "127.0.0.1:9000".parse().unwrap(),
+            None,
+            "Cargo.toml".into(),
+            false,
+            HashSet::new(),
+            None,
+        ));
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let info = ConnectionInfo {
+            connected_at: Utc::now(),
+            last_active_at: Utc::now(),
+            source_ip: "127.0.0.1".into(),
+            user_agent: Some("wscat".into()),
+        };
+        state.websockets.insert("conn-1", sender, info).await;
+
+        let resp = post_to_connection(State(state.clone()), params("conn-1"), Bytes::from("hello"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(receiver.recv().await, Some(Message::Text("hello".into())));
+
+        let resp = post_to_connection(
+            State(state.clone()),
+            params("conn-1"),
+            Bytes::from_static(&[0xFF, 0x00]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            receiver.recv().await,
+            Some(Message::Binary(vec![0xFF, 0x00]))
+        );
+
+        let resp = get_connection(State(state.clone()), params("conn-1"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["Identity"]["UserAgent"], "wscat");
+        assert!(body["ConnectedAt"].as_str().unwrap().ends_with('Z'));
+
+        let resp = delete_connection(State(state.clone()), params("conn-1"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            receiver.recv().await,
+            Some(Message::Close(Some(CloseFrame {
+                code: NORMAL,
+                reason: "".into(),
+            })))
+        );
+
+        let resp = post_to_connection(State(state.clone()), params("conn-2"), Bytes::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::GONE);
+        assert_eq!(resp.headers()["x-amzn-errortype"], "GoneException");
+    }
+}